@@ -1,8 +1,7 @@
 //! Generate a flat scene, nothing more.
 
-use terr::{mesh::SampleMesh, unbounded::Flat};
-use std::f32::consts;
-use nalgebra::{Point3, UnitQuaternion, Vector3};
+use terr::{mesh::{SampleMesh, UpAxis}, unbounded::Flat};
+use nalgebra::{Point3, Vector3};
 use kiss3d::{window::Window, light::Light};
 
 fn main() {
@@ -10,12 +9,11 @@ fn main() {
     window.set_light(Light::StickToCamera);
     
     let surface = Flat::new(0f32);
-    let mesh = surface.sample_mesh((-50., -50.), (100., 100.), (1, 1));
+    let mesh = surface.sample_mesh((-50., -50.), (100., 100.), (1, 1), UpAxis::Y);
     
-    let mut quad = window.add_trimesh(mesh, Vector3::from_element(1.0));
+    let mut quad = window.add_trimesh(mesh.into(), Vector3::from_element(1.0));
     quad.enable_backface_culling(false);
     quad.set_color(0.75, 0.65, 0.4);
-    quad.set_local_rotation(UnitQuaternion::from_euler_angles(-consts::FRAC_PI_2, 0., 0.));
     
     let mut camera = kiss3d::camera::ArcBall::new(Point3::new(0., 1., 15.), Point3::new(0., 0., 0.));
     
@@ -4,6 +4,7 @@
 //! result, run it again!
 
 use terr::heightmap::{Heightmap, diamond_square};
+use terr::mesh::UpAxis;
 use nalgebra::*;
 use kiss3d::{window::Window, light::Light};
 use rand::prelude::*;
@@ -32,22 +33,13 @@ fn main() {
     let distr = Normal::new(0.0, scale).unwrap();
     diamond_square(&mut heightmap, 0, &mut rng, distr).unwrap();
     
-    let mut quad = heightmap.to_trimesh();
-    for p in &mut quad.coords {
-        // Quad is created with z=height, but y is up in kiss3d's camera.
-        // We must rotate all three coords to keep the right side up.
-        let temp = p.z;
-        p.z = p.x;
-        p.x = p.y;
-        p.y = temp;
-    }
-    quad.recompute_normals();
+    let quad = heightmap.to_trimesh(UpAxis::Y);
     
-    let mut quad = window.add_trimesh(quad, Vector3::from_element(1.0));
+    let mut quad = window.add_trimesh(quad.into(), Vector3::from_element(1.0));
     quad.enable_backface_culling(false);
     quad.set_color(0.75, 0.65, 0.4);
     
-    let mut camera = kiss3d::camera::ArcBall::new(Point3::new(50., 50., 0.), Point3::new(50., 0., 50.));
+    let mut camera = kiss3d::camera::ArcBall::new(Point3::new(0., 50., -50.), Point3::new(50., 0., -50.));
     
     while window.render_with_camera(&mut camera) {
     }
@@ -0,0 +1,35 @@
+//! Generate cellular terrain from Worley noise plus Perlin noise.
+
+use terr::{heightmap::Heightmap, unbounded::{Perlin, Worley, WorleyMode}};
+use terr::mesh::UpAxis;
+use nalgebra::{Point3, Vector3};
+use kiss3d::{window::Window, light::Light};
+use rand::thread_rng;
+use rand_distr::{Distribution, UnitCircle};
+
+fn main() {
+    let mut window = Window::new("Terr: worley");
+    window.set_light(Light::StickToCamera);
+    
+    let mut rng = thread_rng();
+    
+    let cells = 256;
+    let worley = Worley::new(0.05, WorleyMode::F2MinusF1, &mut rng);
+    let mut heightmap = Heightmap::new_flat((cells, cells), (100.0, 100.0));
+    heightmap.add_surface(&worley, 10.0);
+    
+    let sampler = || UnitCircle.sample(&mut rng);
+    let perlin = Perlin::new(0.2, 256, sampler).unwrap();
+    heightmap.add_surface(&perlin, 3.0);
+    
+    let quad = heightmap.to_trimesh(UpAxis::Y);
+    
+    let mut quad = window.add_trimesh(quad.into(), Vector3::from_element(1.0));
+    quad.enable_backface_culling(false);
+    quad.set_color(0.75, 0.65, 0.4);
+    
+    let mut camera = kiss3d::camera::ArcBall::new(Point3::new(0., 50., -50.), Point3::new(50., 0., -50.));
+    
+    while window.render_with_camera(&mut camera) {
+    }
+}
@@ -0,0 +1,512 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Spatial queries over terrain features
+//! 
+//! A [`FeatureIndex`] holds named features — points (settlements, springs),
+//! polylines (rivers, roads, cliff lines) and polygons (lakes, forests) — in
+//! a bounding volume hierarchy, answering gameplay questions such as "the
+//! nearest river within 500 m" or "all features in view" without scanning
+//! every feature.
+//! 
+//! [`StableIds`] assigns feature IDs which survive regeneration with minor
+//! parameter changes, so that game content may be keyed to features.
+//! 
+//! ```
+//! use terr::features::{Feature, FeatureIndex, FeatureShape};
+//! 
+//! let index = FeatureIndex::new(vec![
+//!     Feature::new("river", "Avon", FeatureShape::Polyline(vec![(0.0, 0.0), (1000.0, 0.0)])),
+//!     Feature::new("settlement", "Bath", FeatureShape::Point((300.0, 200.0))),
+//! ]);
+//! let hit = index.nearest((400.0, 150.0), 500.0, Some("river")).unwrap();
+//! assert_eq!(index.feature(hit.feature).name, "Avon");
+//! assert_eq!(hit.point, (400.0, 0.0));
+//! ```
+
+use std::cmp::Ordering;
+use nalgebra::{convert, try_convert, RealField};
+use crate::raster::point_in_polygon;
+
+/// Geometry of a [`Feature`], in world coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureShape<F> {
+    /// A single point
+    Point((F, F)),
+    /// An open polyline of at least one point
+    Polyline(Vec<(F, F)>),
+    /// A closed polygon (the last point connects to the first), including
+    /// its interior
+    Polygon(Vec<(F, F)>),
+}
+
+/// A named terrain feature
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature<F> {
+    /// Feature kind, e.g. `"river"`, `"lake"`, `"cliff"` or `"settlement"`
+    pub kind: String,
+    /// Feature name
+    pub name: String,
+    /// Geometry
+    pub shape: FeatureShape<F>,
+}
+
+impl<F> Feature<F> {
+    /// Construct
+    pub fn new(kind: &str, name: &str, shape: FeatureShape<F>) -> Self {
+        Feature { kind: kind.to_string(), name: name.to_string(), shape }
+    }
+}
+
+impl<F: RealField> Feature<F> {
+    /// A representative point of the feature
+    /// 
+    /// This is the point itself, the length-weighted centroid of a polyline
+    /// or the area centroid of a polygon (falling back to the mean vertex if
+    /// degenerate). It moves little under small changes to the shape or its
+    /// sampling. Panics on an empty shape.
+    pub fn anchor(&self) -> (F, F) {
+        let mean = |points: &[(F, F)]| {
+            let n: F = convert(points.len() as f64);
+            let sum = points.iter().fold((F::zero(), F::zero()), |s, p| (s.0 + p.0, s.1 + p.1));
+            (sum.0 / n, sum.1 / n)
+        };
+        let half: F = convert(0.5);
+        match &self.shape {
+            FeatureShape::Point(p) => *p,
+            FeatureShape::Polyline(points) => {
+                let (mut sum, mut total) = ((F::zero(), F::zero()), F::zero());
+                for w in points.windows(2) {
+                    let len = ((w[1].0 - w[0].0) * (w[1].0 - w[0].0) + (w[1].1 - w[0].1) * (w[1].1 - w[0].1)).sqrt();
+                    sum = (sum.0 + (w[0].0 + w[1].0) * half * len, sum.1 + (w[0].1 + w[1].1) * half * len);
+                    total += len;
+                }
+                if total > F::zero() { (sum.0 / total, sum.1 / total) } else { mean(points) }
+            }
+            FeatureShape::Polygon(points) => {
+                let (mut sum, mut area) = ((F::zero(), F::zero()), F::zero());
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+                    let cross = a.0 * b.1 - b.0 * a.1;
+                    sum = (sum.0 + (a.0 + b.0) * cross, sum.1 + (a.1 + b.1) * cross);
+                    area += cross;
+                }
+                if area.abs() > F::default_epsilon() {
+                    let k = area * convert(3.0);
+                    (sum.0 / k, sum.1 / k)
+                } else {
+                    mean(points)
+                }
+            }
+        }
+    }
+}
+
+/// Result of a distance query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureHit<F> {
+    /// Index of the feature
+    pub feature: usize,
+    /// Nearest point of the feature (the query point if within a polygon)
+    pub point: (F, F),
+    /// Distance from the query point
+    pub distance: F,
+}
+
+// Primitive parts of features
+#[derive(Debug, Clone)]
+enum Prim<F> {
+    Point(usize, (F, F)),
+    Segment(usize, (F, F), (F, F)),
+    // interior of polygon feature
+    Area(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Node<F> {
+    min: (F, F),
+    max: (F, F),
+    // leaf: range of `prims`; else children
+    leaf: bool,
+    a: usize,
+    b: usize,
+}
+
+// Maximum primitives per leaf
+const LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy over terrain features
+/// 
+/// Queries may be restricted to features of one `kind`. Distance queries
+/// report at most one hit per feature: its nearest point.
+#[derive(Debug, Clone)]
+pub struct FeatureIndex<F> {
+    features: Vec<Feature<F>>,
+    prims: Vec<Prim<F>>,
+    nodes: Vec<Node<F>>,
+}
+
+impl<F: RealField> FeatureIndex<F> {
+    /// Build an index over `features`
+    pub fn new(features: Vec<Feature<F>>) -> Self {
+        trace_span!("feature_index", features = features.len());
+        let mut prims = vec![];
+        for (i, f) in features.iter().enumerate() {
+            match &f.shape {
+                FeatureShape::Point(p) => prims.push(Prim::Point(i, *p)),
+                FeatureShape::Polyline(points) => {
+                    if points.len() == 1 {
+                        prims.push(Prim::Point(i, points[0]));
+                    }
+                    prims.extend(points.windows(2).map(|w| Prim::Segment(i, w[0], w[1])));
+                }
+                FeatureShape::Polygon(points) => {
+                    if let (Some(&first), Some(&last)) = (points.first(), points.last()) {
+                        prims.extend(points.windows(2).map(|w| Prim::Segment(i, w[0], w[1])));
+                        prims.push(Prim::Segment(i, last, first));
+                        prims.push(Prim::Area(i));
+                    }
+                }
+            }
+        }
+        let mut index = FeatureIndex { features, prims, nodes: vec![] };
+        if !index.prims.is_empty() {
+            let mut prims = std::mem::take(&mut index.prims);
+            index.build(&mut prims, 0);
+            index.prims = prims;
+        }
+        index
+    }
+    
+    /// All features
+    pub fn features(&self) -> &[Feature<F>] {
+        &self.features
+    }
+    
+    /// Get feature `i`
+    pub fn feature(&self, i: usize) -> &Feature<F> {
+        &self.features[i]
+    }
+    
+    /// The nearest feature within `max_distance` of `p`
+    pub fn nearest(&self, p: (F, F), max_distance: F, kind: Option<&str>) -> Option<FeatureHit<F>> {
+        self.k_nearest(p, 1, max_distance, kind).pop()
+    }
+    
+    /// The `k` nearest features within `max_distance` of `p`, nearest first
+    pub fn k_nearest(&self, p: (F, F), k: usize, max_distance: F, kind: Option<&str>) -> Vec<FeatureHit<F>> {
+        let mut hits = vec![];
+        if k > 0 && !self.nodes.is_empty() {
+            self.search(0, p, max_distance, k, kind, &mut hits);
+        }
+        hits
+    }
+    
+    /// All features within `radius` of `p`, nearest first
+    pub fn within_radius(&self, p: (F, F), radius: F, kind: Option<&str>) -> Vec<FeatureHit<F>> {
+        self.k_nearest(p, usize::MAX, radius, kind)
+    }
+    
+    /// Indices (ascending) of all features intersecting the rectangle from
+    /// `min` to `max`
+    pub fn in_rect(&self, min: (F, F), max: (F, F), kind: Option<&str>) -> Vec<usize> {
+        let mut found = vec![];
+        let mut stack = vec![];
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.min.0 > max.0 || node.min.1 > max.1 || node.max.0 < min.0 || node.max.1 < min.1 {
+                continue;
+            }
+            if !node.leaf {
+                stack.push(node.a);
+                stack.push(node.b);
+                continue;
+            }
+            for prim in &self.prims[node.a..node.b] {
+                let f = prim_feature(prim);
+                if !self.matches(f, kind) {
+                    continue;
+                }
+                let hit = match *prim {
+                    Prim::Point(_, q) => q.0 >= min.0 && q.0 <= max.0 && q.1 >= min.1 && q.1 <= max.1,
+                    Prim::Segment(_, a, b) => segment_in_rect(a, b, min, max),
+                    // rectangle within the polygon (other cases meet an edge)
+                    Prim::Area(_) => point_in_polygon(min, self.polygon(f)),
+                };
+                if hit {
+                    found.push(f);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+    
+    fn matches(&self, feature: usize, kind: Option<&str>) -> bool {
+        kind.map(|k| self.features[feature].kind == k).unwrap_or(true)
+    }
+    
+    fn polygon(&self, feature: usize) -> &[(F, F)] {
+        match &self.features[feature].shape {
+            FeatureShape::Polygon(points) => points,
+            _ => unreachable!(),
+        }
+    }
+    
+    // Build a node over `prims` (with offset `start` into `self.prims`),
+    // returning its index
+    fn build(&mut self, prims: &mut [Prim<F>], start: usize) -> usize {
+        let (mut min, mut max) = ((F::max_value(), F::max_value()), (F::min_value(), F::min_value()));
+        for prim in prims.iter() {
+            let (a, b) = self.bounds(prim);
+            min = (min.0.min(a.0), min.1.min(a.1));
+            max = (max.0.max(b.0), max.1.max(b.1));
+        }
+        let index = self.nodes.len();
+        self.nodes.push(Node { min, max, leaf: true, a: start, b: start + prims.len() });
+        if prims.len() > LEAF_SIZE {
+            // median split along the longer axis
+            let axis_x = max.0 - min.0 >= max.1 - min.1;
+            let centre = |s: &Self, prim: &Prim<F>| {
+                let (a, b) = s.bounds(prim);
+                if axis_x { a.0 + b.0 } else { a.1 + b.1 }
+            };
+            prims.sort_by(|p, q| centre(self, p).partial_cmp(&centre(self, q)).unwrap_or(Ordering::Equal));
+            let mid = prims.len() / 2;
+            let (low, high) = prims.split_at_mut(mid);
+            let a = self.build(low, start);
+            let b = self.build(high, start + mid);
+            let node = &mut self.nodes[index];
+            node.leaf = false;
+            node.a = a;
+            node.b = b;
+        }
+        index
+    }
+    
+    fn bounds(&self, prim: &Prim<F>) -> ((F, F), (F, F)) {
+        match *prim {
+            Prim::Point(_, p) => (p, p),
+            Prim::Segment(_, a, b) => ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1))),
+            Prim::Area(f) => {
+                let init = ((F::max_value(), F::max_value()), (F::min_value(), F::min_value()));
+                self.polygon(f).iter().fold(init, |(min, max), p| {
+                    ((min.0.min(p.0), min.1.min(p.1)), (max.0.max(p.0), max.1.max(p.1)))
+                })
+            }
+        }
+    }
+    
+    // Depth-first search, nearer child first, pruning by the current bound
+    // (`max_distance` or the distance of the `k`-th hit)
+    fn search(&self, n: usize, p: (F, F), max_distance: F, k: usize, kind: Option<&str>,
+        hits: &mut Vec<FeatureHit<F>>)
+    {
+        let bound = |hits: &Vec<FeatureHit<F>>| {
+            if hits.len() >= k { hits[k - 1].distance.min(max_distance) } else { max_distance }
+        };
+        let node = &self.nodes[n];
+        if box_distance(p, node.min, node.max) > bound(hits) {
+            return;
+        }
+        if !node.leaf {
+            let (a, b) = (&self.nodes[node.a], &self.nodes[node.b]);
+            let (first, second) = if box_distance(p, a.min, a.max) <= box_distance(p, b.min, b.max) {
+                (node.a, node.b)
+            } else {
+                (node.b, node.a)
+            };
+            self.search(first, p, max_distance, k, kind, hits);
+            self.search(second, p, max_distance, k, kind, hits);
+            return;
+        }
+        for prim in &self.prims[node.a..node.b] {
+            let f = prim_feature(prim);
+            if !self.matches(f, kind) {
+                continue;
+            }
+            let point = match *prim {
+                Prim::Point(_, q) => q,
+                Prim::Segment(_, a, b) => closest_point(p, a, b),
+                Prim::Area(_) if point_in_polygon(p, self.polygon(f)) => p,
+                Prim::Area(_) => continue,
+            };
+            let d = ((point.0 - p.0) * (point.0 - p.0) + (point.1 - p.1) * (point.1 - p.1)).sqrt();
+            if d > bound(hits) {
+                continue;
+            }
+            if let Some(i) = hits.iter().position(|h| h.feature == f) {
+                if hits[i].distance <= d {
+                    continue;
+                }
+                hits.remove(i);
+            }
+            let at = hits.iter().position(|h| h.distance > d).unwrap_or(hits.len());
+            hits.insert(at, FeatureHit { feature: f, point, distance: d });
+            hits.truncate(k);
+        }
+    }
+}
+
+/// Assignment of feature IDs which are stable across regeneration
+/// 
+/// A new feature's ID is a hash of its kind and the cell (of side `cell`)
+/// containing its [anchor](Feature::anchor): thus a feature whose anchor
+/// stays in the same cell is given the same ID even by a fresh assigner.
+/// Additionally, the assigner remembers assigned IDs: on the next call to
+/// [`StableIds::assign`], each feature whose anchor is within `tolerance` of
+/// a remembered feature of the same kind inherits its ID (nearest pairs
+/// first, one-to-one), even if it crossed a cell boundary. Remembered IDs
+/// may be persisted with [`StableIds::known`] and [`StableIds::with_known`].
+/// 
+/// IDs are computed with a fixed hash function (64-bit FNV-1a), so are
+/// reproducible across platforms and compiler versions.
+#[derive(Debug, Clone)]
+pub struct StableIds<F> {
+    cell: F,
+    tolerance: F,
+    known: Vec<(u64, String, (F, F))>,
+}
+
+impl<F: RealField> StableIds<F> {
+    /// Construct with no remembered features
+    pub fn new(cell: F, tolerance: F) -> Self {
+        Self::with_known(cell, tolerance, vec![])
+    }
+    
+    /// Construct, remembering features as `(id, kind, anchor)`
+    pub fn with_known(cell: F, tolerance: F, known: Vec<(u64, String, (F, F))>) -> Self {
+        assert!(cell > F::zero());
+        StableIds { cell, tolerance, known }
+    }
+    
+    /// Remembered features as `(id, kind, anchor)`
+    pub fn known(&self) -> &[(u64, String, (F, F))] {
+        &self.known
+    }
+    
+    /// Assign an ID to each feature, and remember these (forgetting features
+    /// not matched)
+    /// 
+    /// IDs are unique within the result. The result does not depend on the
+    /// order of `features` (except for features of the same kind with equal
+    /// anchors).
+    pub fn assign(&mut self, features: &[Feature<F>]) -> Vec<u64> {
+        trace_span!("stable_ids", features = features.len());
+        let anchors: Vec<(F, F)> = features.iter().map(|f| f.anchor()).collect();
+        let mut ids: Vec<Option<u64>> = vec![None; features.len()];
+        let mut taken = std::collections::HashSet::new();
+        
+        // inherit remembered IDs, nearest pairs first
+        let index = FeatureIndex::new(self.known.iter()
+            .map(|(_, kind, anchor)| Feature::new(kind, "", FeatureShape::Point(*anchor)))
+            .collect());
+        let mut pairs = vec![];
+        for (i, f) in features.iter().enumerate() {
+            for hit in index.within_radius(anchors[i], self.tolerance, Some(&f.kind)) {
+                pairs.push((hit.distance, i, hit.feature));
+            }
+        }
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let mut used = vec![false; self.known.len()];
+        for (_, i, j) in pairs {
+            let id = self.known[j].0;
+            if ids[i].is_none() && !used[j] && !taken.contains(&id) {
+                ids[i] = Some(id);
+                used[j] = true;
+                taken.insert(id);
+            }
+        }
+        
+        // hash the remainder, in a deterministic order
+        let mut order: Vec<usize> = (0..features.len()).filter(|&i| ids[i].is_none()).collect();
+        order.sort_by(|&a, &b| {
+            features[a].kind.cmp(&features[b].kind).then_with(|| {
+                (anchors[a].0, anchors[a].1).partial_cmp(&(anchors[b].0, anchors[b].1)).unwrap_or(Ordering::Equal)
+            })
+        });
+        for i in order {
+            let cell = |v: F| try_convert::<F, f64>((v / self.cell).floor()).unwrap() as i64;
+            let key = (cell(anchors[i].0), cell(anchors[i].1));
+            let mut salt = 0u32;
+            let id = loop {
+                let id = spatial_hash(&features[i].kind, key, salt);
+                if taken.insert(id) {
+                    break id;
+                }
+                salt += 1;
+            };
+            ids[i] = Some(id);
+        }
+        
+        let ids: Vec<u64> = ids.into_iter().map(|id| id.unwrap()).collect();
+        self.known = features.iter().zip(&ids).zip(&anchors)
+            .map(|((f, id), anchor)| (*id, f.kind.clone(), *anchor))
+            .collect();
+        ids
+    }
+}
+
+// 64-bit FNV-1a hash of kind, cell and salt
+fn spatial_hash(kind: &str, cell: (i64, i64), salt: u32) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    let parts: [&[u8]; 5] = [kind.as_bytes(), &[0], &cell.0.to_le_bytes(), &cell.1.to_le_bytes(), &salt.to_le_bytes()];
+    for &b in parts.iter().flat_map(|p| p.iter()) {
+        h ^= u64::from(b);
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+fn prim_feature<F>(prim: &Prim<F>) -> usize {
+    match *prim {
+        Prim::Point(f, _) | Prim::Segment(f, _, _) | Prim::Area(f) => f,
+    }
+}
+
+fn box_distance<F: RealField>(p: (F, F), min: (F, F), max: (F, F)) -> F {
+    let dx = (min.0 - p.0).max(p.0 - max.0).max(F::zero());
+    let dy = (min.1 - p.1).max(p.1 - max.1).max(F::zero());
+    (dx * dx + dy * dy).sqrt()
+}
+
+// Point of segment a-b nearest to p
+fn closest_point<F: RealField>(p: (F, F), a: (F, F), b: (F, F)) -> (F, F) {
+    let d = (b.0 - a.0, b.1 - a.1);
+    let len2 = d.0 * d.0 + d.1 * d.1;
+    if len2 <= F::zero() {
+        return a;
+    }
+    let t = (((p.0 - a.0) * d.0 + (p.1 - a.1) * d.1) / len2).max(F::zero()).min(F::one());
+    (a.0 + d.0 * t, a.1 + d.1 * t)
+}
+
+// Whether segment a-b meets the rectangle (Liang–Barsky clipping)
+fn segment_in_rect<F: RealField>(a: (F, F), b: (F, F), min: (F, F), max: (F, F)) -> bool {
+    let d = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (F::zero(), F::one());
+    for &(q, r) in &[(-d.0, a.0 - min.0), (d.0, max.0 - a.0), (-d.1, a.1 - min.1), (d.1, max.1 - a.1)] {
+        if q == F::zero() {
+            if r < F::zero() {
+                return false;
+            }
+        } else {
+            let t = r / q;
+            if q < F::zero() {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    t0 <= t1
+}
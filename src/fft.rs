@@ -0,0 +1,116 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Discrete Fourier transforms (internal)
+//! 
+//! Radix-2 Cooley–Tukey for power-of-two lengths, otherwise Bluestein's
+//! algorithm. Transforms are unnormalised.
+
+use nalgebra::{convert, Complex, RealField};
+
+/// Transform `data` in place (forward transform uses `exp(-2πi jk/n)`)
+pub(crate) fn fft<F: RealField>(data: &mut [Complex<F>], inverse: bool) {
+    let n = data.len();
+    if n <= 1 {
+        return;
+    }
+    if n.is_power_of_two() {
+        radix2(data, inverse);
+    } else {
+        bluestein(data, inverse);
+    }
+}
+
+/// Transform a row-major `dim.0 × dim.1` array in place
+pub(crate) fn fft2<F: RealField>(data: &mut [Complex<F>], dim: (usize, usize), inverse: bool) {
+    assert_eq!(data.len(), dim.0 * dim.1);
+    for row in data.chunks_mut(dim.0) {
+        fft(row, inverse);
+    }
+    let mut col = vec![Complex::new(F::zero(), F::zero()); dim.1];
+    for x in 0..dim.0 {
+        for (y, c) in col.iter_mut().enumerate() {
+            *c = data[y * dim.0 + x];
+        }
+        fft(&mut col, inverse);
+        for (y, c) in col.iter().enumerate() {
+            data[y * dim.0 + x] = *c;
+        }
+    }
+}
+
+fn radix2<F: RealField>(data: &mut [Complex<F>], inverse: bool) {
+    let n = data.len();
+    // bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    
+    let sign = if inverse { F::one() } else { -F::one() };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * F::two_pi() / convert(len as f64);
+        let w_len = Complex::new(angle.cos(), angle.sin());
+        for chunk in data.chunks_mut(len) {
+            let mut w = Complex::new(F::one(), F::zero());
+            let (a, b) = chunk.split_at_mut(len / 2);
+            for (u, v) in a.iter_mut().zip(b.iter_mut()) {
+                let t = *v * w;
+                *v = *u - t;
+                *u += t;
+                w *= w_len;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+fn bluestein<F: RealField>(data: &mut [Complex<F>], inverse: bool) {
+    let n = data.len();
+    let m = (2 * n - 1).next_power_of_two();
+    let sign = if inverse { F::one() } else { -F::one() };
+    // chirp w_k = exp(sign * πi k² / n); k² is reduced mod 2n for precision
+    let chirp: Vec<Complex<F>> = (0..n)
+        .map(|k| {
+            let k2 = (k as u64 * k as u64) % (2 * n as u64);
+            let angle = sign * F::pi() * convert(k2 as f64) / convert(n as f64);
+            Complex::new(angle.cos(), angle.sin())
+        })
+        .collect();
+    
+    let zero = Complex::new(F::zero(), F::zero());
+    let mut a = vec![zero; m];
+    for k in 0..n {
+        a[k] = data[k] * chirp[k];
+    }
+    let mut b = vec![zero; m];
+    b[0] = chirp[0].conj();
+    for k in 1..n {
+        b[k] = chirp[k].conj();
+        b[m - k] = chirp[k].conj();
+    }
+    radix2(&mut a, false);
+    radix2(&mut b, false);
+    for (x, y) in a.iter_mut().zip(b.iter()) {
+        *x *= *y;
+    }
+    radix2(&mut a, true);
+    let scale: F = F::one() / convert(m as f64);
+    for k in 0..n {
+        data[k] = a[k] * chirp[k] * scale;
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! GPU compute backend (requires the `gpu` feature)
+//! 
+//! A [`GpuHeightmap`] holds a copy of a `Heightmap<f32>` in GPU memory, on
+//! which noise accumulation and erosion passes may be run as compute shaders
+//! before reading the result back. All operations block until complete.
+//! 
+//! ```no_run
+//! # use terr::{gpu::*, heightmap::Heightmap, unbounded::Perlin};
+//! # fn f(perlin: &Perlin<f32>) -> Result<(), GpuError> {
+//! let ctx = GpuContext::new()?;
+//! let m = Heightmap::new_flat((1025, 1025), (1000.0, 1000.0));
+//! let mut g = GpuHeightmap::upload(&ctx, &m);
+//! g.add_perlin(perlin, 50.0);
+//! g.thermal_erosion(100, 0.7, 0.5);
+//! let m = g.download();
+//! # Ok(()) }
+//! ```
+
+use std::fmt;
+use wgpu::util::DeviceExt;
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use crate::unbounded::Perlin;
+
+/// Error initialising the GPU
+#[derive(Debug)]
+pub enum GpuError {
+    /// No suitable adapter was found
+    NoAdapter,
+    /// The device could not be opened
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpuError::NoAdapter => write!(f, "no suitable GPU adapter found"),
+            GpuError::RequestDevice(e) => write!(f, "failed to open GPU device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+/// A GPU device and queue
+#[derive(Debug)]
+pub struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+}
+
+impl GpuContext {
+    /// Open the default GPU adapter
+    pub fn new() -> Result<Self, GpuError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .ok_or(GpuError::NoAdapter)?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+            .map_err(GpuError::RequestDevice)?;
+        Ok(GpuContext { device, queue })
+    }
+    
+    fn pipeline(&self, source: &str, entry: &str) -> wgpu::ComputePipeline {
+        let module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry),
+            layout: None,
+            module: &module,
+            entry_point: Some(entry),
+            compilation_options: Default::default(),
+            cache: None,
+        })
+    }
+    
+    fn buffer(&self, contents: &[u8], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents, usage })
+    }
+    
+    // Run the pipelines in order, each over the whole grid, with the given
+    // buffers bound in order
+    fn run(&self, dim: (u32, u32), passes: &[(&wgpu::ComputePipeline, &[&wgpu::Buffer])], repeat: u32) {
+        let groups: Vec<_> = passes.iter().map(|(pipeline, buffers)| {
+            let entries: Vec<_> = buffers.iter().enumerate()
+                .map(|(i, b)| wgpu::BindGroupEntry { binding: i as u32, resource: b.as_entire_binding() })
+                .collect();
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        }).collect();
+        
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            for _ in 0..repeat {
+                for ((pipeline, _), group) in passes.iter().zip(groups.iter()) {
+                    pass.set_pipeline(pipeline);
+                    pass.set_bind_group(0, group, &[]);
+                    pass.dispatch_workgroups(dim.0.div_ceil(8), dim.1.div_ceil(8), 1);
+                }
+            }
+        }
+        self.queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// A heightmap in GPU memory
+#[derive(Debug)]
+pub struct GpuHeightmap<'a> {
+    ctx: &'a GpuContext,
+    dim: (u32, u32),
+    size: (f32, f32),
+    heights: wgpu::Buffer,
+}
+
+impl<'a> GpuHeightmap<'a> {
+    /// Upload a heightmap
+    pub fn upload(ctx: &'a GpuContext, m: &Heightmap<f32>) -> Self {
+        let (w, h) = m.dim();
+        let mut bytes = Vec::with_capacity(w as usize * h as usize * 4);
+        for cy in 0..h {
+            for cx in 0..w {
+                bytes.extend_from_slice(&m.get(cx, cy).to_le_bytes());
+            }
+        }
+        let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let heights = ctx.buffer(&bytes, usage);
+        GpuHeightmap { ctx, dim: m.dim(), size: m.size(), heights }
+    }
+    
+    /// Add `amplitude` times Perlin noise (as [`Heightmap::add_surface`])
+    pub fn add_perlin(&mut self, perlin: &Perlin<f32>, amplitude: f32) {
+        trace_span!("gpu_add_perlin", dim = ?self.dim);
+        let (frac_x, frac_y) = self.cell_size();
+        let (periodic, period) = match perlin.period {
+            Some(p) => (1u32, (p.0 as i32, p.1 as i32)),
+            None => (0, (1, 1)),
+        };
+        let mut params = Vec::new();
+        for x in &[self.dim.0, self.dim.1, perlin.mask, periodic] {
+            params.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in &[period.0, period.1] {
+            params.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in &[frac_x, frac_y, perlin.scale, amplitude, 0.0, 0.0] {
+            params.extend_from_slice(&x.to_le_bytes());
+        }
+        let gradients: Vec<u8> = perlin.gradient.iter()
+            .flat_map(|g| g.iter().flat_map(|x| x.to_le_bytes()))
+            .collect();
+        
+        let ctx = self.ctx;
+        let params = ctx.buffer(&params, wgpu::BufferUsages::UNIFORM);
+        let gradients = ctx.buffer(&gradients, wgpu::BufferUsages::STORAGE);
+        let pipeline = ctx.pipeline(include_str!("gpu/perlin.wgsl"), "main");
+        ctx.run(self.dim, &[(&pipeline, &[&params, &self.heights, &gradients])], 1);
+    }
+    
+    /// Run `iterations` of thermal erosion
+    /// 
+    /// Material on slopes steeper than `talus` (rise over run) slides to lower
+    /// neighbours; `rate` (in `(0, 1]`) controls how much of the excess moves
+    /// per iteration.
+    pub fn thermal_erosion(&mut self, iterations: u32, talus: f32, rate: f32) {
+        trace_span!("gpu_thermal_erosion", dim = ?self.dim, iterations);
+        let (frac_x, frac_y) = self.cell_size();
+        let mut params = Vec::new();
+        for x in &[self.dim.0, self.dim.1, 0, 0] {
+            params.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in &[talus * frac_x, talus * frac_y, rate, 0.0] {
+            params.extend_from_slice(&x.to_le_bytes());
+        }
+        
+        let ctx = self.ctx;
+        let params = ctx.buffer(&params, wgpu::BufferUsages::UNIFORM);
+        let flow = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: self.dim.0 as u64 * self.dim.1 as u64 * 16,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let source = include_str!("gpu/thermal.wgsl");
+        let outflow = ctx.pipeline(source, "outflow");
+        let apply = ctx.pipeline(source, "apply");
+        let buffers: &[&wgpu::Buffer] = &[&params, &self.heights, &flow];
+        ctx.run(self.dim, &[(&outflow, buffers), (&apply, buffers)], iterations);
+    }
+    
+    /// Read the heightmap back from the GPU
+    pub fn download(&self) -> Heightmap<f32> {
+        let ctx = self.ctx;
+        let len = self.dim.0 as u64 * self.dim.1 as u64 * 4;
+        let staging = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: len,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = ctx.device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.heights, 0, &staging, 0, len);
+        ctx.queue.submit(Some(encoder.finish()));
+        
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        ctx.device.poll(wgpu::Maintain::Wait);
+        let data: Vec<f32> = {
+            let bytes = slice.get_mapped_range();
+            bytes.chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        };
+        staging.unmap();
+        let mut data = data.into_iter();
+        let grid = Grid::from_fn(self.dim, |_, _| data.next().unwrap());
+        Heightmap::from_grid(grid, self.size)
+    }
+    
+    fn cell_size(&self) -> (f32, f32) {
+        (self.size.0 / (self.dim.0 - 1) as f32, self.size.1 / (self.dim.1 - 1) as f32)
+    }
+}
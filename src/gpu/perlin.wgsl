@@ -0,0 +1,87 @@
+// Accumulate Perlin noise into a heightmap; matches unbounded::Perlin
+
+struct Params {
+    width: u32,
+    height: u32,
+    mask: u32,
+    periodic: u32,
+    period_x: i32,
+    period_y: i32,
+    frac_x: f32,
+    frac_y: f32,
+    scale: f32,
+    amplitude: f32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
+@group(0) @binding(2) var<storage, read> gradients: array<vec2<f32>>;
+
+// Full 64-bit product of two u32, as (lo, hi)
+fn mul32(a: u32, b: u32) -> vec2<u32> {
+    let a0 = a & 0xffffu;
+    let a1 = a >> 16u;
+    let b0 = b & 0xffffu;
+    let b1 = b >> 16u;
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+    let mid = (p00 >> 16u) + (p01 & 0xffffu) + (p10 & 0xffffu);
+    let lo = (p00 & 0xffffu) | (mid << 16u);
+    let hi = p11 + (p01 >> 16u) + (p10 >> 16u) + (mid >> 16u);
+    return vec2<u32>(lo, hi);
+}
+
+// PCG-derived hash of a 64-bit key (lo, hi); see unbounded::hash
+fn hash(key: vec2<u32>) -> u32 {
+    let c = vec2<u32>(0x28cb43bdu, 0xcb45348au);
+    let p = mul32(key.x, c.x);
+    let x = vec2<u32>(p.x, p.y + key.x * c.y + key.y * c.x);
+    let rot = x.y >> 27u;
+    // (x >> 18) ^ x, then >> 27, truncated
+    let t = vec2<u32>((x.x >> 18u) | (x.y << 14u), x.y >> 18u) ^ x;
+    let xsh = (t.x >> 27u) | (t.y << 5u);
+    return (xsh >> rot) | (xsh << ((32u - rot) & 31u));
+}
+
+fn gradient(ix: i32, iy: i32) -> vec2<f32> {
+    var x = ix;
+    var y = iy;
+    if (params.periodic != 0u) {
+        x = ((x % params.period_x) + params.period_x) % params.period_x;
+        y = ((y % params.period_y) + params.period_y) % params.period_y;
+    }
+    // key = (x as u64) + ((y as u64) << 32), with x sign-extended
+    var hi = u32(y);
+    if (x < 0) {
+        hi = hi + 0xffffffffu;
+    }
+    return gradients[hash(vec2<u32>(u32(x), hi)) & params.mask];
+}
+
+fn fade(t: f32) -> f32 {
+    return t * t * (3.0 - 2.0 * t);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let p = vec2<f32>(f32(id.x) * params.frac_x, f32(id.y) * params.frac_y) * params.scale;
+    let p0 = floor(p);
+    let r0 = p - p0;
+    let r1 = r0 - vec2<f32>(1.0, 1.0);
+    let ix = i32(p0.x);
+    let iy = i32(p0.y);
+    
+    let s0 = fade(r0.x);
+    let s1 = fade(r0.y);
+    let a = mix(dot(r0, gradient(ix, iy)), dot(vec2<f32>(r1.x, r0.y), gradient(ix + 1, iy)), s0);
+    let b = mix(dot(vec2<f32>(r0.x, r1.y), gradient(ix, iy + 1)), dot(r1, gradient(ix + 1, iy + 1)), s0);
+    let i = id.y * params.width + id.x;
+    heights[i] = heights[i] + params.amplitude * mix(a, b, s1);
+}
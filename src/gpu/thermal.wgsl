@@ -0,0 +1,76 @@
+// Thermal erosion: material above the talus slope slides to lower
+// neighbours. Two passes avoid write conflicts: `outflow` computes the
+// amount moved to each of four neighbours; `apply` updates heights.
+
+struct Params {
+    width: u32,
+    height: u32,
+    _pad0: u32,
+    _pad1: u32,
+    talus_x: f32,   // maximum stable height difference along x
+    talus_y: f32,   // maximum stable height difference along y
+    rate: f32,
+    _pad2: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
+@group(0) @binding(2) var<storage, read_write> flow: array<vec4<f32>>;
+
+// Neighbour offsets: -x, +x, -y, +y
+fn neighbour(x: u32, y: u32, k: u32) -> vec2<i32> {
+    let offsets = array<vec2<i32>, 4>(vec2<i32>(-1, 0), vec2<i32>(1, 0), vec2<i32>(0, -1), vec2<i32>(0, 1));
+    return vec2<i32>(i32(x), i32(y)) + offsets[k];
+}
+
+fn inside(c: vec2<i32>) -> bool {
+    return c.x >= 0 && c.y >= 0 && c.x < i32(params.width) && c.y < i32(params.height);
+}
+
+@compute @workgroup_size(8, 8)
+fn outflow(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let i = id.y * params.width + id.x;
+    let h = heights[i];
+    var excess = vec4<f32>(0.0);
+    var max_excess = 0.0;
+    for (var k = 0u; k < 4u; k = k + 1u) {
+        let c = neighbour(id.x, id.y, k);
+        if (inside(c)) {
+            let talus = select(params.talus_y, params.talus_x, k < 2u);
+            let d = h - heights[u32(c.y) * params.width + u32(c.x)] - talus;
+            if (d > 0.0) {
+                excess[k] = d;
+                max_excess = max(max_excess, d);
+            }
+        }
+    }
+    let total = excess.x + excess.y + excess.z + excess.w;
+    if (total > 0.0) {
+        // move at most half the largest excess, split proportionally
+        flow[i] = excess * (params.rate * 0.5 * max_excess / total);
+    } else {
+        flow[i] = vec4<f32>(0.0);
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn apply(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let i = id.y * params.width + id.x;
+    let out = flow[i];
+    var h = heights[i] - (out.x + out.y + out.z + out.w);
+    // incoming: neighbour k sends towards us in the opposite direction
+    let opposite = array<u32, 4>(1u, 0u, 3u, 2u);
+    for (var k = 0u; k < 4u; k = k + 1u) {
+        let c = neighbour(id.x, id.y, k);
+        if (inside(c)) {
+            h = h + flow[u32(c.y) * params.width + u32(c.x)][opposite[k]];
+        }
+    }
+    heights[i] = h;
+}
@@ -0,0 +1,363 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Topological export of generated structures
+//! 
+//! A [`WorldGraph`] collects structural data — province adjacency, river
+//! networks, roads and sites — as nodes and edges located on a heightmap, for
+//! consumption by gameplay systems or export to external tools as JSON or
+//! GraphML.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use nalgebra::{convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::drainage::{accumulation, downstream};
+use crate::heightmap::{Heightmap, ProvinceMap};
+use crate::io::escape;
+
+/// A node of a [`WorldGraph`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphNode<F> {
+    /// Node kind, e.g. `"province"` or `"confluence"`
+    pub kind: String,
+    /// Heightmap vertex
+    pub vertex: (u32, u32),
+    /// World position `(x, y, height)`
+    pub position: (F, F, F),
+    /// Named numeric attributes
+    pub attributes: Vec<(String, F)>,
+}
+
+/// An edge of a [`WorldGraph`]
+/// 
+/// Edges are directed from `from` to `to` where this is meaningful (e.g.
+/// downstream along rivers), otherwise direction may be ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphEdge<F> {
+    /// Edge kind, e.g. `"border"` or `"river"`
+    pub kind: String,
+    /// Index of the source node
+    pub from: usize,
+    /// Index of the target node
+    pub to: usize,
+    /// Length of the edge (along the terrain where known)
+    pub length: F,
+    /// Named numeric attributes
+    pub attributes: Vec<(String, F)>,
+}
+
+/// A graph of world structures located on a heightmap
+/// 
+/// Structures of different kinds may be added to the same graph; nodes are
+/// shared where structures meet at the same vertex (e.g. a road ending at a
+/// settlement).
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldGraph<F> {
+    /// Nodes
+    pub nodes: Vec<GraphNode<F>>,
+    /// Edges
+    pub edges: Vec<GraphEdge<F>>,
+}
+
+impl<F: RealField> Default for WorldGraph<F> {
+    fn default() -> Self {
+        WorldGraph::new()
+    }
+}
+
+impl<F: RealField> WorldGraph<F> {
+    /// Construct an empty graph
+    pub fn new() -> Self {
+        WorldGraph { nodes: vec![], edges: vec![] }
+    }
+    
+    /// Add a node at vertex `(cx, cy)` of `m`, returning its index
+    pub fn add_node(&mut self, m: &Heightmap<F>, kind: &str, vertex: (u32, u32)) -> usize {
+        let (x, y) = m.coord_of(vertex.0, vertex.1);
+        self.nodes.push(GraphNode {
+            kind: kind.to_string(),
+            vertex,
+            position: (x, y, m.get(vertex.0, vertex.1)),
+            attributes: vec![],
+        });
+        self.nodes.len() - 1
+    }
+    
+    /// Get the index of the first node at `vertex`, if any
+    pub fn node_at(&self, vertex: (u32, u32)) -> Option<usize> {
+        self.nodes.iter().position(|n| n.vertex == vertex)
+    }
+    
+    /// Get the node at `vertex`, adding a node of the given `kind` if none exists
+    pub fn node_at_or_add(&mut self, m: &Heightmap<F>, kind: &str, vertex: (u32, u32)) -> usize {
+        match self.node_at(vertex) {
+            Some(i) => i,
+            None => self.add_node(m, kind, vertex),
+        }
+    }
+    
+    /// Add an edge, returning its index
+    pub fn add_edge(&mut self, kind: &str, from: usize, to: usize, length: F) -> usize {
+        assert!(from < self.nodes.len() && to < self.nodes.len());
+        self.edges.push(GraphEdge { kind: kind.to_string(), from, to, length, attributes: vec![] });
+        self.edges.len() - 1
+    }
+    
+    /// Iterate over `(edge, neighbour)` index pairs of edges at `node`
+    pub fn neighbours(&self, node: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges.iter().enumerate().filter_map(move |(i, e)| {
+            if e.from == node {
+                Some((i, e.to))
+            } else if e.to == node {
+                Some((i, e.from))
+            } else {
+                None
+            }
+        })
+    }
+    
+    /// Add a path (e.g. a road or trail) over vertices of `m`
+    /// 
+    /// A single edge of kind `kind` joins the end vertices of `path`, with
+    /// length measured along the path (including height differences). End
+    /// nodes are shared with existing nodes at the same vertex, otherwise
+    /// added with kind `"junction"`. Returns the edge index, or `None` if
+    /// `path` has fewer than two vertices.
+    pub fn add_path(&mut self, m: &Heightmap<F>, kind: &str, path: &[(u32, u32)]) -> Option<usize> {
+        if path.len() < 2 {
+            return None;
+        }
+        let length = path_length(m, path);
+        let a = self.node_at_or_add(m, "junction", path[0]);
+        let b = self.node_at_or_add(m, "junction", path[path.len() - 1]);
+        Some(self.add_edge(kind, a, b, length))
+    }
+    
+    /// Add provinces and their adjacency
+    /// 
+    /// Each province is a node of kind `"province"` at its seed, with
+    /// attributes `id` and `area`; adjacent provinces are joined by edges of
+    /// kind `"border"` with length the distance between seeds and attribute
+    /// `border` (the approximate length of the shared border). Returns the node
+    /// index of each province.
+    pub fn add_provinces(&mut self, m: &Heightmap<F>, provinces: &ProvinceMap) -> Vec<usize> {
+        let cell = m.cell_size();
+        let n = provinces.seeds.len();
+        let mut area = vec![F::zero(); n];
+        let mut border = HashMap::new();
+        let dim = m.dim();
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                if let Some(a) = provinces.ids.get(cx, cy) {
+                    area[a as usize] += cell.0 * cell.1;
+                    if cx + 1 < dim.0 {
+                        if let Some(b) = provinces.ids.get(cx + 1, cy).filter(|b| *b != a) {
+                            *border.entry((a.min(b), a.max(b))).or_insert_with(F::zero) += cell.1;
+                        }
+                    }
+                    if cy + 1 < dim.1 {
+                        if let Some(b) = provinces.ids.get(cx, cy + 1).filter(|b| *b != a) {
+                            *border.entry((a.min(b), a.max(b))).or_insert_with(F::zero) += cell.0;
+                        }
+                    }
+                }
+            }
+        }
+        
+        let nodes: Vec<usize> = provinces.seeds.iter().enumerate().map(|(i, seed)| {
+            let node = self.add_node(m, "province", *seed);
+            self.nodes[node].attributes.push(("id".to_string(), convert(i as f64)));
+            self.nodes[node].attributes.push(("area".to_string(), area[i]));
+            node
+        }).collect();
+        for (a, b) in provinces.edges() {
+            let (na, nb) = (nodes[a as usize], nodes[b as usize]);
+            let (pa, pb) = (self.nodes[na].position, self.nodes[nb].position);
+            let length = ((pb.0 - pa.0) * (pb.0 - pa.0) + (pb.1 - pa.1) * (pb.1 - pa.1)).sqrt();
+            let e = self.add_edge("border", na, nb, length);
+            let shared = border.get(&(a, b)).cloned().unwrap_or_else(F::zero);
+            self.edges[e].attributes.push(("border".to_string(), shared));
+        }
+        nodes
+    }
+    
+    /// Add the river network of `m`
+    /// 
+    /// River vertices are those with catchment area (see
+    /// [`Heightmap::flow_accumulation`]) at least `min_area`. Nodes are added
+    /// at sources (`"source"`), confluences (`"confluence"`) and the ends of
+    /// rivers at pits or the map edge (`"mouth"`), each with attribute
+    /// `catchment`. Edges of kind `"river"` are directed downstream, with
+    /// attribute `discharge` (the catchment area at the downstream end).
+    pub fn add_rivers(&mut self, m: &Heightmap<F>, min_area: F) {
+        let dim = m.dim();
+        let acc = accumulation(m, false);
+        let next = downstream(m, false);
+        let is_river = |c: (u32, u32)| acc.get(c.0, c.1) >= min_area;
+        let mut inflows = Grid::new(dim, 0u32);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                if let Some(n) = next.get(cx, cy).filter(|_| is_river((cx, cy))) {
+                    inflows.set(n.0, n.1, inflows.get(n.0, n.1) + 1);
+                }
+            }
+        }
+        let kind = |c: (u32, u32)| match (inflows.get(c.0, c.1), next.get(c.0, c.1)) {
+            (_, None) => Some("mouth"),
+            (0, _) => Some("source"),
+            (1, _) => None,
+            _ => Some("confluence"),
+        };
+        let node = |g: &mut Self, c: (u32, u32), kind: &str| match g.nodes.iter().position(|n| {
+            n.vertex == c && (n.kind == "source" || n.kind == "confluence" || n.kind == "mouth")
+        }) {
+            Some(i) => i,
+            None => {
+                let i = g.add_node(m, kind, c);
+                g.nodes[i].attributes.push(("catchment".to_string(), acc.get(c.0, c.1)));
+                i
+            }
+        };
+        
+        // Trace downstream from each source or confluence to the next node
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let start = (cx, cy);
+                let start_kind = match kind(start) {
+                    Some(k) if is_river(start) && k != "mouth" => k,
+                    _ => continue,
+                };
+                let mut path = vec![start];
+                let mut c = start;
+                let end_kind = loop {
+                    c = next.get(c.0, c.1).unwrap();
+                    path.push(c);
+                    if let Some(k) = kind(c) {
+                        break k;
+                    }
+                };
+                let a = node(self, start, start_kind);
+                let b = node(self, c, end_kind);
+                let e = self.add_edge("river", a, b, path_length(m, &path));
+                self.edges[e].attributes.push(("discharge".to_string(), acc.get(c.0, c.1)));
+            }
+        }
+    }
+    
+    /// Serialise as JSON
+    /// 
+    /// The result is an object with `nodes` and `edges` arrays; each node has
+    /// `kind`, `vertex`, `position` and `attributes` (an object), each edge
+    /// `kind`, `from`, `to`, `length` and `attributes`.
+    pub fn to_json(&self) -> String {
+        let attrs = |a: &[(String, F)]| {
+            let items: Vec<String> = a.iter().map(|(k, v)| format!("\"{}\": {}", escape(k), v)).collect();
+            format!("{{{}}}", items.join(", "))
+        };
+        let mut s = String::new();
+        writeln!(s, "{{").unwrap();
+        writeln!(s, "  \"nodes\": [").unwrap();
+        for (i, n) in self.nodes.iter().enumerate() {
+            let sep = if i + 1 < self.nodes.len() { "," } else { "" };
+            writeln!(s, "    {{ \"kind\": \"{}\", \"vertex\": [{}, {}], \"position\": [{}, {}, {}], \"attributes\": {} }}{}",
+                escape(&n.kind), n.vertex.0, n.vertex.1, n.position.0, n.position.1, n.position.2,
+                attrs(&n.attributes), sep).unwrap();
+        }
+        writeln!(s, "  ],").unwrap();
+        writeln!(s, "  \"edges\": [").unwrap();
+        for (i, e) in self.edges.iter().enumerate() {
+            let sep = if i + 1 < self.edges.len() { "," } else { "" };
+            writeln!(s, "    {{ \"kind\": \"{}\", \"from\": {}, \"to\": {}, \"length\": {}, \"attributes\": {} }}{}",
+                escape(&e.kind), e.from, e.to, e.length, attrs(&e.attributes), sep).unwrap();
+        }
+        writeln!(s, "  ]").unwrap();
+        writeln!(s, "}}").unwrap();
+        s
+    }
+    
+    /// Serialise as GraphML
+    /// 
+    /// Node and edge kinds, positions, lengths and all attribute names used
+    /// are declared as GraphML keys. The graph is declared directed.
+    pub fn to_graphml(&self) -> String {
+        let mut node_keys: Vec<&str> = vec![];
+        for n in &self.nodes {
+            for (k, _) in &n.attributes {
+                if !node_keys.contains(&k.as_str()) {
+                    node_keys.push(k);
+                }
+            }
+        }
+        let mut edge_keys: Vec<&str> = vec![];
+        for e in &self.edges {
+            for (k, _) in &e.attributes {
+                if !edge_keys.contains(&k.as_str()) {
+                    edge_keys.push(k);
+                }
+            }
+        }
+        
+        let mut s = String::new();
+        writeln!(s, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(s, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">").unwrap();
+        writeln!(s, "  <key id=\"kind\" for=\"all\" attr.name=\"kind\" attr.type=\"string\"/>").unwrap();
+        for k in &["x", "y", "z"] {
+            writeln!(s, "  <key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"double\"/>", k).unwrap();
+        }
+        writeln!(s, "  <key id=\"length\" for=\"edge\" attr.name=\"length\" attr.type=\"double\"/>").unwrap();
+        for (i, k) in node_keys.iter().enumerate() {
+            writeln!(s, "  <key id=\"n{}\" for=\"node\" attr.name=\"{}\" attr.type=\"double\"/>", i, xml_escape(k)).unwrap();
+        }
+        for (i, k) in edge_keys.iter().enumerate() {
+            writeln!(s, "  <key id=\"e{}\" for=\"edge\" attr.name=\"{}\" attr.type=\"double\"/>", i, xml_escape(k)).unwrap();
+        }
+        writeln!(s, "  <graph edgedefault=\"directed\">").unwrap();
+        for (i, n) in self.nodes.iter().enumerate() {
+            writeln!(s, "    <node id=\"n{}\">", i).unwrap();
+            writeln!(s, "      <data key=\"kind\">{}</data>", xml_escape(&n.kind)).unwrap();
+            writeln!(s, "      <data key=\"x\">{}</data>", n.position.0).unwrap();
+            writeln!(s, "      <data key=\"y\">{}</data>", n.position.1).unwrap();
+            writeln!(s, "      <data key=\"z\">{}</data>", n.position.2).unwrap();
+            for (k, v) in &n.attributes {
+                let key = node_keys.iter().position(|n| n == k).unwrap();
+                writeln!(s, "      <data key=\"n{}\">{}</data>", key, v).unwrap();
+            }
+            writeln!(s, "    </node>").unwrap();
+        }
+        for e in &self.edges {
+            writeln!(s, "    <edge source=\"n{}\" target=\"n{}\">", e.from, e.to).unwrap();
+            writeln!(s, "      <data key=\"kind\">{}</data>", xml_escape(&e.kind)).unwrap();
+            writeln!(s, "      <data key=\"length\">{}</data>", e.length).unwrap();
+            for (k, v) in &e.attributes {
+                let key = edge_keys.iter().position(|n| n == k).unwrap();
+                writeln!(s, "      <data key=\"e{}\">{}</data>", key, v).unwrap();
+            }
+            writeln!(s, "    </edge>").unwrap();
+        }
+        writeln!(s, "  </graph>").unwrap();
+        writeln!(s, "</graphml>").unwrap();
+        s
+    }
+}
+
+// Length of a path over vertices, including height differences
+fn path_length<F: RealField>(m: &Heightmap<F>, path: &[(u32, u32)]) -> F {
+    let point = |c: (u32, u32)| {
+        let (x, y) = m.coord_of(c.0, c.1);
+        (x, y, m.get(c.0, c.1))
+    };
+    path.windows(2).map(|w| {
+        let (a, b) = (point(w[0]), point(w[1]));
+        ((b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1) + (b.2 - a.2) * (b.2 - a.2)).sqrt()
+    }).fold(F::zero(), |a, b| a + b)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,112 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Grids of auxiliary per-vertex data
+//! 
+//! Simulations and analyses over a [`Heightmap`](crate::heightmap::Heightmap)
+//! often produce data other than heights (depths, masks, intensities).
+//! These are stored in a [`Grid`] using the same vertex indexing as the
+//! heightmap.
+
+use std::mem::size_of;
+use crate::memory::MemoryUsage;
+
+/// A grid of `dim.0 × dim.1` values
+/// 
+/// Values are indexed by vertex `(cx, cy)` and stored in row-major order
+/// (as in a `Heightmap`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<T> {
+    dim: (u32, u32),
+    data: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    /// Construct a grid with all values set to `value`
+    pub fn new(dim: (u32, u32), value: T) -> Self {
+        let data = vec![value; dim.0 as usize * dim.1 as usize];
+        Grid { dim, data }
+    }
+}
+
+impl<T> Grid<T> {
+    /// Construct a grid by evaluating `f(cx, cy)` for each vertex
+    pub fn from_fn<G: FnMut(u32, u32) -> T>(dim: (u32, u32), mut f: G) -> Self {
+        let mut data = Vec::with_capacity(dim.0 as usize * dim.1 as usize);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                data.push(f(cx, cy));
+            }
+        }
+        Grid { dim, data }
+    }
+    
+    /// Get the grid dimension
+    #[inline]
+    pub fn dim(&self) -> (u32, u32) {
+        self.dim
+    }
+    
+    /// Access the data as a row-major slice
+    #[inline]
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+    
+    /// Access the data as a mutable row-major slice
+    #[inline]
+    pub fn data_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+    
+    /// Unwrap the row-major data
+    pub fn into_data(self) -> Vec<T> {
+        self.data
+    }
+    
+    /// Construct a new grid by applying `f` to each value
+    pub fn map<U, G: FnMut(&T) -> U>(&self, f: G) -> Grid<U> {
+        Grid {
+            dim: self.dim,
+            data: self.data.iter().map(f).collect(),
+        }
+    }
+    
+    #[inline]
+    fn index(&self, cx: u32, cy: u32) -> usize {
+        assert!(cx < self.dim.0);
+        assert!(cy < self.dim.1);
+        (cx as usize) + (cy as usize) * (self.dim.0 as usize)
+    }
+}
+
+impl<T: Copy> Grid<T> {
+    /// Get value at the given vertex.
+    /// 
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    #[inline]
+    pub fn get(&self, cx: u32, cy: u32) -> T {
+        self.data[self.index(cx, cy)]
+    }
+    
+    /// Set value at the given vertex.
+    /// 
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    #[inline]
+    pub fn set(&mut self, cx: u32, cy: u32, val: T) {
+        let i = self.index(cx, cy);
+        self.data[i] = val;
+    }
+}
+
+/// Values are counted by their size only (any heap memory they own is ignored)
+impl<T> MemoryUsage for Grid<T> {
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.data.capacity() * size_of::<T>()
+    }
+}
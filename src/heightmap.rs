@@ -9,20 +9,108 @@
 //! Functionality based on heightmaps
 
 use nalgebra as na;
-use na::{convert, try_convert, DMatrix, Dynamic, Vector3, RealField, geometry::{Point2, Point3}};
-use ncollide3d::procedural::{TriMesh, IndexBuffer};
-use ncollide3d::shape::HeightField;
+use na::{convert, try_convert, RealField};
 
-use crate::unbounded::UnboundedSurface;
+use crate::grid::Grid;
+use crate::memory::MemoryUsage;
+use crate::mesh::{AttributedMesh, MicroDetail, TerrainMesh, UpAxis};
+use crate::unbounded::{Curve, UnboundedSurface, Terrace};
+use crate::units::Units;
+use meshing::MeshBuilder;
 
+pub use audio::{AmbientSound, AudioEmitter, AudioZoneParams, AudioZones};
+pub use caves::{CaveEntrance, CaveFinder};
+pub use connectivity::{CarvedPass, ConnectivityReport};
+pub use contours::Contour;
+pub use creep::CreepParams;
+pub use crossings::{Crossing, CrossingKind, RouteAnalysis, RoutePlan};
+pub use curvature::CurvatureKind;
+pub use deposition::DepositionParams;
 pub use displacement::{midpoint_displacement, diamond_square};
+pub use erosion::{ErosionParams, ErosionSession, ErosionTask, FlowFields};
+pub use estuary::{Estuary, EstuaryLayout};
+pub use evolution::{EvolutionParams, LandscapeEvolution};
+pub use farmland::{Farmland, FieldLayout, FieldPattern};
 pub use fault::fault_displacement;
+pub use fetch::FetchMap;
+pub use harbour::{dredge_channel, Harbour, HarbourLayout, QuayWall};
+pub use karst::{KarstLayout, KarstParams, Sinkhole};
+pub use fire::{BurnState, FireParams, FireSim};
+pub use fluid::{FluidParams, FluidSim};
+pub use landslide::{Landslide, MassMovement};
+pub use layered::LayeredErosionParams;
+pub use meander::{MeanderParams, MeanderSim};
+pub use meshing::MeshTask;
+pub use periglacial::{GroundPattern, PatternedGround, PatternedGroundParams};
+pub use mips::Reduction;
+pub use provinces::{ProvinceMap, Provinces};
+pub use settlement::{Lot, Settlement, SettlementLayout};
+pub use shoreline::{ShoreParams, ShoreSegment, ShoreType};
+pub use regional::{blend_biomes, regional_fbm, RegionParams};
+pub use rills::RillParams;
+pub use sampling::PositionSampler;
+pub use spectral::spectral_synthesis;
+pub use stitch::SeamBlend;
+pub use surface::{EdgeMode, HeightmapSurface};
+pub use strata::Strata;
+pub use tiled::{ChunkSource, LoadTask, TiledHeightmap};
+pub use trails::{TrailParams, TrailSim};
+pub use view::HeightmapView;
+pub use travel::{CostField, TravelParams};
+pub use traversal::{Ray, RayCells, RayHit};
 pub use voronoi::Voronoi;
 
+mod audio;
+mod caves;
+mod connectivity;
+mod contours;
+mod creep;
+mod crossings;
+mod curvature;
+mod deposition;
 mod displacement;
+mod erosion;
+mod estuary;
+mod evolution;
+pub(crate) mod drainage;
+mod farmland;
 mod fault;
+mod fetch;
+pub mod filter;
+mod fire;
+mod geometry;
+mod harbour;
+mod karst;
+mod fluid;
+mod landslide;
+mod layered;
+mod meander;
+mod meshing;
+mod mips;
+mod normals;
+mod periglacial;
+mod provinces;
+mod regional;
+mod rills;
+mod sampling;
+mod search;
+mod settlement;
+mod shoreline;
+mod spectral;
+mod stitch;
+mod strata;
+mod surface;
+mod tiled;
+mod tin;
+mod trails;
+mod travel;
+mod traversal;
+mod view;
 mod voronoi;
+#[cfg(feature = "ncollide3d")]
 mod ncollide_impls;
+#[cfg(any(feature = "parry3d", feature = "rapier3d"))]
+mod parry_impls;
 
 /// A heightmap represents a (terrian) surface via a grid of height offsets.
 /// 
@@ -60,6 +148,12 @@ impl<F: RealField> Heightmap<F> {
         self.size
     }
     
+    /// Get the distance between adjacent vertices along each axis
+    #[inline]
+    pub fn cell_size(&self) -> (F, F) {
+        self.len_frac
+    }
+    
     /// Get the coordinates of the given vertex
     #[inline]
     pub fn coord_of(&self, cx: u32, cy: u32) -> (F, F) {
@@ -83,6 +177,38 @@ impl<F: RealField> Heightmap<F> {
         None
     }
     
+    /// Find the vertex nearest the given point, if the point is on the map
+    pub fn nearest_vertex(&self, x: F, y: F) -> Option<(u32, u32)> {
+        if F::zero() <= x && x <= self.size.0 && F::zero() <= y && y <= self.size.1 {
+            let cx = try_convert::<_, f64>((x / self.len_frac.0).round()).unwrap() as u32;
+            let cy = try_convert::<_, f64>((y / self.len_frac.1).round()).unwrap() as u32;
+            return Some((cx.min(self.dim.0 - 1), cy.min(self.dim.1 - 1)));
+        }
+        None
+    }
+    
+    /// Get the height at coordinate `(x, y)`, if on the map
+    /// 
+    /// Heights are interpolated linearly over the triangles used by
+    /// [`Heightmap::to_trimesh`] and ray casting: each cell is split along the
+    /// diagonal from vertex `(cx, cy)` to `(cx + 1, cy + 1)`. The result thus
+    /// lies exactly on the mesh surface.
+    pub fn height_at(&self, x: F, y: F) -> Option<F> {
+        if x < F::zero() || y < F::zero() || x > self.size.0 || y > self.size.1 {
+            return None;
+        }
+        let ((cx, cy), tx, ty) = self.bilinear(x, y);
+        let h00 = self.get(cx, cy);
+        let h11 = self.get(cx + 1, cy + 1);
+        Some(if tx >= ty {
+            let h10 = self.get(cx + 1, cy);
+            h00 + (h10 - h00) * tx + (h11 - h10) * ty
+        } else {
+            let h01 = self.get(cx, cy + 1);
+            h00 + (h01 - h00) * ty + (h11 - h01) * tx
+        })
+    }
+    
     /// Get `(min, max)` altitudes
     #[inline]
     pub fn range(&self) -> (F, F) {
@@ -109,6 +235,73 @@ impl<F: RealField> Heightmap<F> {
         self.range = (self.range.0.min(val), self.range.1.max(val));
         self.data[(cx as usize) + (cy as usize) * (self.dim.0 as usize)] = val;
     }
+    
+    /// Estimate the gradient `(dh/dx, dh/dy)` at the given vertex.
+    /// 
+    /// Uses central differences, or one-sided differences on the edges.
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    pub fn gradient_at(&self, cx: u32, cy: u32) -> (F, F) {
+        let diff = |c: u32, len: u32, get: &dyn Fn(u32) -> F, step: F| {
+            let c0 = if c > 0 { c - 1 } else { c };
+            let c1 = if c + 1 < len { c + 1 } else { c };
+            if c0 == c1 {
+                return F::zero();
+            }
+            (get(c1) - get(c0)) / (convert::<_, F>((c1 - c0) as f64) * step)
+        };
+        let dx = diff(cx, self.dim.0, &|x| self.get(x, cy), self.len_frac.0);
+        let dy = diff(cy, self.dim.1, &|y| self.get(cx, y), self.len_frac.1);
+        (dx, dy)
+    }
+    
+    /// Estimate the slope (magnitude of the gradient) at the given vertex.
+    /// 
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    #[inline]
+    pub fn slope_at(&self, cx: u32, cy: u32) -> F {
+        let (dx, dy) = self.gradient_at(cx, cy);
+        (dx * dx + dy * dy).sqrt()
+    }
+    
+    // Iterate over the (up to 8) vertices adjacent to (cx, cy).
+    fn neighbours(&self, cx: u32, cy: u32) -> impl Iterator<Item = (u32, u32)> {
+        let dim = self.dim;
+        (-1i64..=1).flat_map(move |dy| (-1i64..=1).map(move |dx| (dx, dy)))
+            .filter(|&d| d != (0, 0))
+            .map(move |(dx, dy)| (cx as i64 + dx, cy as i64 + dy))
+            .filter(move |&(x, y)| {
+                x >= 0 && y >= 0 && x < dim.0 as i64 && y < dim.1 as i64
+            })
+            .map(|(x, y)| (x as u32, y as u32))
+    }
+    
+    // Bilinear interpolation of height at the given coordinates, clamped to
+    // the map bounds
+    fn interpolate(&self, x: F, y: F) -> F {
+        let ((cx, cy), tx, ty) = self.bilinear(x, y);
+        let h0 = self.get(cx, cy) + (self.get(cx + 1, cy) - self.get(cx, cy)) * tx;
+        let h1 = self.get(cx, cy + 1) + (self.get(cx + 1, cy + 1) - self.get(cx, cy + 1)) * tx;
+        h0 + (h1 - h0) * ty
+    }
+    
+    // Cell containing coordinate (x, y), clamped to the map, with fractional
+    // position within the cell
+    fn bilinear(&self, x: F, y: F) -> ((u32, u32), F, F) {
+        let fx = (x / self.len_frac.0).max(F::zero());
+        let fy = (y / self.len_frac.1).max(F::zero());
+        let cx = (try_convert::<_, f64>(fx.floor()).unwrap() as u32).min(self.dim.0 - 2);
+        let cy = (try_convert::<_, f64>(fy.floor()).unwrap() as u32).min(self.dim.1 - 2);
+        let tx = (fx - convert(cx as f64)).min(F::one());
+        let ty = (fy - convert(cy as f64)).min(F::one());
+        ((cx, cy), tx, ty)
+    }
+    
+    // Horizontal distance between two vertices
+    fn distance(&self, a: (u32, u32), b: (u32, u32)) -> F {
+        let dx = convert::<_, F>((a.0 as f64 - b.0 as f64).abs()) * self.len_frac.0;
+        let dy = convert::<_, F>((a.1 as f64 - b.1 as f64).abs()) * self.len_frac.1;
+        (dx * dx + dy * dy).sqrt()
+    }
 }
 
 // constructors
@@ -128,18 +321,21 @@ impl<F: RealField> Heightmap<F> {
     
     /// Construct a new Heightmap using the given evaluation function and with
     /// the given `dim` and `size`.
-    pub fn from_surface(dim: (u32, u32), size: (F, F), surface: &dyn UnboundedSurface<F>) -> Self {
+    /// 
+    /// With the `rayon` feature, rows are filled in parallel.
+    pub fn from_surface(dim: (u32, u32), size: (F, F), surface: &(dyn UnboundedSurface<F> + Sync)) -> Self {
+        trace_span!("from_surface", dim = ?dim);
+        let mut m = Heightmap::new_flat(dim, size);
+        m.fill_rows(|_, x, y, h| *h = surface.get(x, y));
+        m
+    }
+    
+    /// Construct a new Heightmap from a grid of heights with the given `size`.
+    pub fn from_grid(grid: Grid<F>, size: (F, F)) -> Self {
+        let dim = grid.dim();
         let x_frac: F = size.0 / convert((dim.0 - 1) as f64);
         let y_frac: F = size.1 / convert((dim.1 - 1) as f64);
-        let mut data = Vec::with_capacity(dim.0 as usize * dim.1 as usize);
-        for iy in 0..dim.1 {
-            let y = convert::<_, F>(iy as f64) * y_frac;
-            for ix in 0..dim.0 {
-                let x = convert::<_, F>(ix as f64) * x_frac;
-                data.push(surface.get(x, y));
-            }
-        }
-        
+        let data = grid.into_data();
         Heightmap {
             dim,
             len_frac: (x_frac, y_frac),
@@ -149,86 +345,285 @@ impl<F: RealField> Heightmap<F> {
         }
     }
     
-    pub fn add_surface(&mut self, surface: &dyn UnboundedSurface<F>, mult: F) {
-        for iy in 0..self.dim.1 {
-            for ix in 0..self.dim.0 {
-                let (x, y) = self.coord_of(ix, iy);
-                let h = self.get(ix, iy);
-                self.set(ix, iy, h + mult * surface.get(x, y));
+    /// Add `mult` times the given surface to the heights.
+    /// 
+    /// With the `rayon` feature, rows are filled in parallel.
+    pub fn add_surface(&mut self, surface: &(dyn UnboundedSurface<F> + Sync), mult: F) {
+        trace_span!("add_surface", dim = ?self.dim);
+        self.fill_rows(|_, x, y, h| *h += mult * surface.get(x, y));
+    }
+    
+    /// Add `mult` times the given surface to the heights, weighted per vertex
+    /// by `mask` (of the same dimensions)
+    /// 
+    /// This applies detail to selected regions only, for example
+    /// [`Cracks`](crate::unbounded::Cracks) to dry lakebeds. With the `rayon`
+    /// feature, rows are filled in parallel.
+    pub fn add_surface_masked(&mut self, surface: &(dyn UnboundedSurface<F> + Sync), mult: F, mask: &Grid<F>) {
+        trace_span!("add_surface_masked", dim = ?self.dim);
+        assert_eq!(mask.dim(), self.dim);
+        self.fill_rows(|(cx, cy), x, y, h| {
+            let weight = mask.get(cx, cy);
+            if weight != F::zero() {
+                *h += mult * weight * surface.get(x, y);
+            }
+        });
+    }
+    
+    // Apply f((cx, cy), x, y, &mut h) to all vertices (by rows, in parallel if
+    // enabled)
+    fn fill_rows<G: Fn((u32, u32), F, F, &mut F) + Sync>(&mut self, f: G) {
+        let (width, frac) = (self.dim.0 as usize, self.len_frac);
+        let fill_row = |(iy, row): (usize, &mut [F])| {
+            let y = convert::<_, F>(iy as f64) * frac.1;
+            for (ix, h) in row.iter_mut().enumerate() {
+                f((ix as u32, iy as u32), convert::<_, F>(ix as f64) * frac.0, y, h);
             }
+        };
+        #[cfg(feature = "rayon")] {
+            use rayon::prelude::*;
+            self.data.par_chunks_mut(width).enumerate().for_each(fill_row);
+        }
+        #[cfg(not(feature = "rayon"))] {
+            self.data.chunks_mut(width).enumerate().for_each(fill_row);
         }
         self.range = range(&self.data);
     }
 }
 
-// conversions
+// transforms
 impl<F: RealField> Heightmap<F> {
-    // Convert to a HeightField
-    pub fn to_heightfield(&self) -> HeightField<F> {
-        let rows = Dynamic::new(self.dim.1 as usize);
-        let cols = Dynamic::new(self.dim.0 as usize);
-        let heights = DMatrix::from_row_slice_generic(rows, cols, &self.data[..]);
-        let scale = Vector3::new(self.size.0, convert::<f64, F>(1.0), self.size.1);
-        HeightField::new(heights, scale)
+    /// Apply a function to every height.
+    pub fn map<G: FnMut(F) -> F>(&mut self, mut f: G) {
+        for h in self.data.iter_mut() {
+            *h = f(*h);
+        }
+        self.range = range(&self.data);
     }
+    
+    /// Rescale heights linearly to span `new_min` to `new_max`.
+    /// 
+    /// The range is first recomputed from the data (since [`Heightmap::set`]
+    /// only ever grows it). A flat map is set to `new_min`.
+    pub fn normalize(&mut self, new_min: F, new_max: F) {
+        let (min, max) = range(&self.data);
+        if max > min {
+            let scale = (new_max - new_min) / (max - min);
+            self.map(|h| new_min + (h - min) * scale);
+        } else {
+            self.map(|_| new_min);
+        }
+    }
+    
+    /// Limit heights to the range `min` to `max`.
+    pub fn clamp(&mut self, min: F, max: F) {
+        assert!(min <= max);
+        self.map(|h| h.max(min).min(max));
+    }
+    
+    /// Apply a [`Terrace`] transform to every height.
+    /// 
+    /// For example, `m.terrace(&Terrace::new(m.range().0, m.range().1, 8, 0.2))`
+    /// quantises the map to 8 levels.
+    pub fn terrace(&mut self, terrace: &Terrace<F>) {
+        self.map(|h| terrace.apply(h));
+    }
+    
+    /// Remap every height through a [`Curve`].
+    pub fn remap(&mut self, curve: &Curve<F>) {
+        self.map(|h| curve.apply(h));
+    }
+    
+    /// Scale the horizontal size and heights by the given factors.
+    /// 
+    /// To scale heights for visualisation only, prefer scaling the mesh (see
+    /// [`exaggerate`](crate::mesh::exaggerate)), since simulations assume equal
+    /// horizontal and vertical units.
+    pub fn scale(&mut self, horizontal: F, vertical: F) {
+        self.size = (self.size.0 * horizontal, self.size.1 * horizontal);
+        self.len_frac = (self.len_frac.0 * horizontal, self.len_frac.1 * horizontal);
+        self.map(|h| h * vertical);
+    }
+    
+    /// Convert the size and heights from units `from` to `to`.
+    pub fn convert_units(&mut self, from: Units, to: Units) {
+        let horizontal = convert(from.horizontal.factor_to(to.horizontal));
+        let vertical = convert(from.vertical.factor_to(to.vertical));
+        self.scale(horizontal, vertical);
+    }
+}
 
-    // Use naive conversion of heightmap to a `TriMesh`.
-    // 
-    // This approach does not cull any vertices, so the result may have a
-    // very high triangle count.
-    pub fn to_trimesh(&self) -> TriMesh<F> {
-        let one: F = na::one();
-        let (x_divs, y_divs) = (self.dim.0 - 1, self.dim.1 - 1);
-        
-        // code adapted from ncollide::procedural::unit_quad:
-        let (x_step, y_step) = self.len_frac;
-        let tx_step = one / convert(x_divs as f64);
-        let ty_step = one / convert(y_divs as f64);
-
-        let mut vertices = Vec::new();
-        let mut triangles = Vec::new();
-        let mut tex_coords = Vec::new();
-
-        // create the vertices
-        for iy in 0..self.dim.1 {
-            for ix in 0..self.dim.0 {
-                let fy: F = convert(iy as f64);
-                let fx: F = convert(ix as f64);
+/// Interpolation used when resampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Height of the nearest vertex
+    Nearest,
+    /// Bilinear interpolation of the four surrounding vertices
+    Bilinear,
+    /// Bicubic (Catmull-Rom) interpolation of the sixteen surrounding
+    /// vertices: smooth, but may overshoot the input range near sharp changes
+    Bicubic,
+}
 
-                let v = Point3::new(fx * x_step, fy * y_step, self.get(iy, ix));
-                vertices.push(v);
-                tex_coords.push(Point2::new(one - fx * tx_step, one - fy * ty_step))
-            }
+// conversions
+impl<F: RealField> Heightmap<F> {
+    /// Resample to a new grid dimension, keeping the same size
+    /// 
+    /// Since vertices lie on the map edges, edge heights are preserved. This
+    /// converts between the `2ⁿ + 1` dimensions of e.g. [`diamond_square`]
+    /// and other sizes.
+    pub fn resample(&self, dim: (u32, u32), interpolation: Interpolation) -> Heightmap<F> {
+        trace_span!("resample", from = ?self.dim, to = ?dim);
+        let mut m = Heightmap::new_flat(dim, self.size);
+        match interpolation {
+            Interpolation::Nearest => m.fill_rows(|_, x, y, h| {
+                let ((cx, cy), tx, ty) = self.bilinear(x, y);
+                let half = convert(0.5);
+                *h = self.get(cx + (tx >= half) as u32, cy + (ty >= half) as u32);
+            }),
+            Interpolation::Bilinear => m.fill_rows(|_, x, y, h| *h = self.interpolate(x, y)),
+            Interpolation::Bicubic => m.fill_rows(|_, x, y, h| *h = self.interpolate_cubic(x, y)),
         }
-
-        // create triangles
-        let ws = self.dim.0;
-        
-        let dl_triangle = |iy: u32, ix: u32| -> Point3<u32> {
-            Point3::new((iy + 1) * ws + ix, iy * ws + ix, (iy + 1) * ws + ix + 1)
+        m
+    }
+    
+    // Catmull-Rom interpolation of height at the given coordinates, clamped
+    // to the map bounds. Vertices beyond the edges are extrapolated linearly.
+    fn interpolate_cubic(&self, x: F, y: F) -> F {
+        let ((cx, cy), tx, ty) = self.bilinear(x, y);
+        let (w, h) = (self.dim.0 as i64, self.dim.1 as i64);
+        let row = |iy: u32| {
+            let p = |ix: i64| match ix {
+                -1 => self.get(0, iy) * convert(2.0) - self.get(1, iy),
+                ix if ix == w => self.get(ix as u32 - 1, iy) * convert(2.0) - self.get(ix as u32 - 2, iy),
+                ix => self.get(ix as u32, iy),
+            };
+            let cx = cx as i64;
+            catmull_rom(p(cx - 1), p(cx), p(cx + 1), p(cx + 2), tx)
         };
-
-        let ur_triangle = |iy: u32, ix: u32| -> Point3<u32> {
-            Point3::new(iy * ws + ix, iy * ws + (ix + 1), (iy + 1) * ws + ix + 1)
+        let q = |iy: i64| match iy {
+            -1 => row(0) * convert(2.0) - row(1),
+            iy if iy == h => row(iy as u32 - 1) * convert(2.0) - row(iy as u32 - 2),
+            iy => row(iy as u32),
         };
-
-        for iy in 0..y_divs {
-            for ix in 0..x_divs {
-                // build two triangles...
-                triangles.push(dl_triangle(iy, ix));
-                triangles.push(ur_triangle(iy, ix));
-            }
-        }
-
-        let mut mesh = TriMesh::new(
-            vertices,
-            None,
-            Some(tex_coords),
-            Some(IndexBuffer::Unified(triangles)),
-        );
-        mesh.recompute_normals();
+        let cy = cy as i64;
+        catmull_rom(q(cy - 1), q(cy), q(cy + 1), q(cy + 2), ty)
+    }
+    
+    // Use naive conversion of heightmap to a `TerrainMesh`, with the `up`
+    // axis convention.
+    // 
+    // This approach does not cull any vertices, so the result may have a
+    // very high triangle count; see `crate::mesh::simplify` and
+    // `Heightmap::to_tin`.
+    pub fn to_trimesh(&self, up: UpAxis) -> TerrainMesh<F> {
+        let mut mesh = self.build_trimesh(1, None, None);
+        mesh.orient(up);
         mesh
     }
+    
+    /// Convert to a decimated `TerrainMesh`
+    /// 
+    /// Only every `step`-th vertex along each axis is used, plus the last, so
+    /// that the mesh covers the whole map; thus a `1025²` map with `step = 4`
+    /// yields a `257²` vertex mesh. Edge vertices of meshes with power-of-two
+    /// steps coincide with vertices of all finer meshes (see
+    /// [`crate::mesh::lod`]). The mesh is Z-up (see [`TerrainMesh::orient`]).
+    pub fn to_trimesh_lod(&self, step: u32) -> TerrainMesh<F> {
+        assert!(step > 0);
+        trace_span!("build_trimesh", dim = ?self.dim, step);
+        let mut builder = MeshBuilder::new(self, 1, step, None, None);
+        while builder.step() {}
+        builder.finish()
+    }
+    
+    /// Convert to a `TerrainMesh`, omitting hole cells
+    /// 
+    /// `holes` has one entry per cell, i.e. dimension `dim - (1, 1)`; both
+    /// triangles of each cell marked `true` are omitted (for example to place
+    /// cave entrances; see [`CaveFinder`]). Vertices are retained. The mesh
+    /// is Z-up (see [`TerrainMesh::orient`]).
+    pub fn to_trimesh_with_holes(&self, holes: &Grid<bool>) -> TerrainMesh<F> {
+        assert_eq!(holes.dim(), (self.dim.0 - 1, self.dim.1 - 1));
+        self.build_trimesh(1, Some(holes), None)
+    }
+    
+    /// Convert to a `TerrainMesh` with sub-cell detail
+    /// 
+    /// Each cell is split into `subdivs × subdivs` quads. Vertex heights are
+    /// interpolated bilinearly from the heightmap, then displaced by `detail`.
+    /// The mesh is Z-up (see [`TerrainMesh::orient`]).
+    pub fn to_trimesh_detailed<S: UnboundedSurface<F>>(&self, subdivs: u32, detail: &MicroDetail<F, S>)
+        -> TerrainMesh<F>
+    {
+        assert!(subdivs > 0);
+        self.build_trimesh(subdivs, None, Some(&|x, y| detail.displacement(x, y)))
+    }
+    
+    /// Bake per-vertex grids into the attributes of a mesh
+    /// 
+    /// `mesh` should be a Z-up mesh generated from this heightmap (e.g. via
+    /// [`Heightmap::to_trimesh`] or [`Heightmap::to_trimesh_detailed`]); the
+    /// result may be reoriented afterwards with [`TerrainMesh::orient`]. Each
+    /// grid must have the same dimension as the heightmap and is interpolated
+    /// bilinearly at each mesh vertex. `colors` are RGBA; `channels` are
+    /// arbitrary named scalars such as splat weights, wetness or occlusion.
+    pub fn bake_attributes(&self, mesh: TerrainMesh<F>, colors: Option<&Grid<[f32; 4]>>, channels: &[(&str, &Grid<F>)])
+        -> AttributedMesh<F>
+    {
+        trace_span!("bake_attributes", vertices = mesh.positions.len());
+        let cells: Vec<_> = mesh.positions.iter().map(|p| self.bilinear(p.x, p.y)).collect();
+        let colors = colors.map(|grid| {
+            assert_eq!(grid.dim(), self.dim);
+            cells.iter().map(|&((cx, cy), tx, ty)| {
+                let tx = try_convert::<_, f64>(tx).unwrap() as f32;
+                let ty = try_convert::<_, f64>(ty).unwrap() as f32;
+                let (c00, c10) = (grid.get(cx, cy), grid.get(cx + 1, cy));
+                let (c01, c11) = (grid.get(cx, cy + 1), grid.get(cx + 1, cy + 1));
+                let mut c = [0.0; 4];
+                for i in 0..4 {
+                    let c0 = c00[i] + (c10[i] - c00[i]) * tx;
+                    let c1 = c01[i] + (c11[i] - c01[i]) * tx;
+                    c[i] = c0 + (c1 - c0) * ty;
+                }
+                c
+            }).collect()
+        });
+        let channels = channels.iter().map(|&(name, grid)| {
+            assert_eq!(grid.dim(), self.dim);
+            let values = cells.iter().map(|&((cx, cy), tx, ty)| {
+                let v0 = grid.get(cx, cy) + (grid.get(cx + 1, cy) - grid.get(cx, cy)) * tx;
+                let v1 = grid.get(cx, cy + 1) + (grid.get(cx + 1, cy + 1) - grid.get(cx, cy + 1)) * tx;
+                v0 + (v1 - v0) * ty
+            }).collect();
+            (name.to_string(), values)
+        }).collect();
+        AttributedMesh { mesh, colors, channels, tangents: None }
+    }
+    
+    fn build_trimesh(&self, subdivs: u32, holes: Option<&Grid<bool>>, detail: Option<&dyn Fn(F, F) -> F>)
+        -> TerrainMesh<F>
+    {
+        trace_span!("build_trimesh", dim = ?self.dim, subdivs);
+        let mut builder = MeshBuilder::new(self, subdivs, 1, holes, detail);
+        while builder.step() {}
+        builder.finish()
+    }
+}
+
+impl<F: RealField> MemoryUsage for Heightmap<F> {
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.capacity() * std::mem::size_of::<F>()
+    }
+}
+
+// Catmull-Rom spline through p1 (t = 0) and p2 (t = 1)
+fn catmull_rom<F: RealField>(p0: F, p1: F, p2: F, p3: F, t: F) -> F {
+    let half: F = convert(0.5);
+    let a = p1 * convert(3.0) - p0 - p2 * convert(3.0) + p3;
+    let b = p0 + p0 - p1 * convert(5.0) + p2 * convert(4.0) - p3;
+    let c = p2 - p0;
+    p1 + half * t * (c + t * (b + t * a))
 }
 
 // calculate (min, max) of data
@@ -0,0 +1,206 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::BinaryHeap;
+use nalgebra::{convert, try_convert, RealField};
+use super::{drainage::accumulation, filter::box_blur, search::Entry, Heightmap};
+use crate::grid::Grid;
+
+/// Type of ambient sound source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmbientSound {
+    /// Waves on the shore
+    Surf,
+    /// Flowing water
+    River,
+    /// Wind in trees, birdsong
+    Forest,
+    /// Wind over exposed ground
+    Wind,
+}
+
+/// Ambient audio zone extraction parameters
+/// 
+/// See [`AudioZoneParams::extract`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioZoneParams<F> {
+    /// Sea level; `None` for no sea (and thus no surf)
+    pub sea_level: Option<F>,
+    /// Width of the surf zone each side of the shoreline
+    pub shore_width: F,
+    /// Catchment area at which flow becomes an audible stream
+    pub river_area: F,
+    /// Distance from a stream over which it is audible
+    pub river_range: F,
+    /// Radius of the surroundings against which ridge exposure is measured
+    pub exposure_radius: F,
+    /// Height above the mean of the surroundings giving full wind exposure
+    pub exposure_relief: F,
+    /// Spacing of emitter points
+    pub emitter_spacing: F,
+}
+
+/// A point sound source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioEmitter<F> {
+    /// Type of sound
+    pub sound: AmbientSound,
+    /// Horizontal position
+    pub position: (F, F),
+    /// Height of the ground (or sea level) at the emitter
+    pub height: F,
+    /// Relative intensity, in `(0, 1]`
+    pub intensity: F,
+}
+
+/// Ambient audio zones of a heightmap
+/// 
+/// Each zone is a weight per vertex, in `[0, 1]`, suitable for blending
+/// ambient loops by listener position; emitters are points for positional
+/// sounds, at most one of each type per square of side `emitter_spacing`.
+#[derive(Debug, Clone)]
+pub struct AudioZones<F> {
+    /// Proximity to the shoreline
+    pub shore: Grid<F>,
+    /// Proximity to streams and rivers
+    pub river: Grid<F>,
+    /// Forest density (from the vegetation grid)
+    pub forest: Grid<F>,
+    /// Exposure of ridges and summits to wind
+    pub wind: Grid<F>,
+    /// Point sources
+    pub emitters: Vec<AudioEmitter<F>>,
+}
+
+impl<F: RealField> AudioZoneParams<F> {
+    /// Extract zones and emitters from `m`
+    /// 
+    /// `vegetation`, if given, is a density in `[0, 1]` per vertex; otherwise
+    /// the forest zone is empty. Zones other than `shore` are zero below sea
+    /// level. Streams are found by flow accumulation over land; river
+    /// emitters are placed on the largest stream in each square, with
+    /// intensity increasing with catchment area. Other emitters are placed at
+    /// the vertex of greatest weight in each square, where that is at least
+    /// one half.
+    pub fn extract(&self, m: &Heightmap<F>, vegetation: Option<&Grid<F>>) -> AudioZones<F> {
+        trace_span!("audio_zones", dim = ?m.dim);
+        if let Some(v) = vegetation {
+            assert_eq!(v.dim(), m.dim);
+        }
+        let (zero, one) = (F::zero(), F::one());
+        let dim = m.dim;
+        let land = |cx: u32, cy: u32| self.sea_level.map(|s| m.get(cx, cy) > s).unwrap_or(true);
+        let falloff = |d: F, range: F| if range > zero { (one - d / range).max(zero) } else { zero };
+        
+        let shore = match self.sea_level {
+            Some(_) => {
+                let coast = |cx: u32, cy: u32| {
+                    let l = land(cx, cy);
+                    m.neighbours(cx, cy).any(|(nx, ny)| (nx == cx || ny == cy) && land(nx, ny) != l)
+                };
+                let dist = distance_field(m, coast, self.shore_width);
+                dist.map(|d| falloff(*d, self.shore_width))
+            }
+            None => Grid::new(dim, zero),
+        };
+        
+        let area = accumulation(m, false);
+        let stream = |cx: u32, cy: u32| land(cx, cy) && area.get(cx, cy) >= self.river_area;
+        let dist = distance_field(m, stream, self.river_range);
+        let river = Grid::from_fn(dim, |cx, cy| {
+            if land(cx, cy) { falloff(dist.get(cx, cy), self.river_range) } else { zero }
+        });
+        
+        let forest = Grid::from_fn(dim, |cx, cy| match vegetation {
+            Some(v) if land(cx, cy) => v.get(cx, cy).max(zero).min(one),
+            _ => zero,
+        });
+        
+        let mut mean = m.clone();
+        box_blur(&mut mean, self.exposure_radius, 1);
+        let wind = Grid::from_fn(dim, |cx, cy| {
+            if !land(cx, cy) || self.exposure_relief <= zero {
+                return zero;
+            }
+            ((m.get(cx, cy) - mean.get(cx, cy)) / self.exposure_relief).max(zero).min(one)
+        });
+        
+        // emitters: best vertex per square
+        let mut emitters = vec![];
+        let block = |len: F| try_convert::<F, f64>(self.emitter_spacing / len).unwrap().round().max(1.0) as u32;
+        let (bw, bh) = (block(m.len_frac.0), block(m.len_frac.1));
+        let max_area = area.data().iter().fold(zero, |a, v| a.max(*v));
+        let strength = Grid::from_fn(dim, |cx, cy| {
+            if stream(cx, cy) { (area.get(cx, cy) / max_area).sqrt() } else { zero }
+        });
+        let sources = [
+            (AmbientSound::Surf, &shore),
+            (AmbientSound::River, &strength),
+            (AmbientSound::Forest, &forest),
+            (AmbientSound::Wind, &wind),
+        ];
+        let threshold: F = convert(0.5);
+        for by in (0..dim.1).step_by(bh as usize) {
+            for bx in (0..dim.0).step_by(bw as usize) {
+                for &(sound, weight) in &sources {
+                    let mut best = (zero, (bx, by));
+                    for cy in by..(by + bh).min(dim.1) {
+                        for cx in bx..(bx + bw).min(dim.0) {
+                            let w = weight.get(cx, cy);
+                            if w > best.0 {
+                                best = (w, (cx, cy));
+                            }
+                        }
+                    }
+                    let (w, c) = best;
+                    if w > zero && (sound == AmbientSound::River || w >= threshold) {
+                        let h = m.get(c.0, c.1);
+                        emitters.push(AudioEmitter {
+                            sound,
+                            position: m.coord_of(c.0, c.1),
+                            height: self.sea_level.map(|s| h.max(s)).unwrap_or(h),
+                            intensity: w,
+                        });
+                    }
+                }
+            }
+        }
+        
+        AudioZones { shore, river, forest, wind, emitters }
+    }
+}
+
+// Approximate distance (over 8-connected vertices) from the nearest source
+// vertex, up to `max` (beyond which distances are `F::max_value()`)
+fn distance_field<F: RealField, S: Fn(u32, u32) -> bool>(m: &Heightmap<F>, source: S, max: F) -> Grid<F> {
+    let w = m.dim.0 as usize;
+    let mut dist = Grid::new(m.dim, F::max_value());
+    let mut heap = BinaryHeap::new();
+    for cy in 0..m.dim.1 {
+        for cx in 0..m.dim.0 {
+            if source(cx, cy) {
+                dist.set(cx, cy, F::zero());
+                heap.push(Entry { cost: F::zero(), index: cy as usize * w + cx as usize });
+            }
+        }
+    }
+    while let Some(Entry { cost, index }) = heap.pop() {
+        let c = ((index % w) as u32, (index / w) as u32);
+        if cost > dist.get(c.0, c.1) {
+            continue;
+        }
+        for n in m.neighbours(c.0, c.1) {
+            let d = cost + m.distance(c, n);
+            if d <= max && d < dist.get(n.0, n.1) {
+                dist.set(n.0, n.1, d);
+                heap.push(Entry { cost: d, index: n.1 as usize * w + n.0 as usize });
+            }
+        }
+    }
+    dist
+}
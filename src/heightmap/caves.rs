@@ -0,0 +1,155 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Cave entrance placement parameters
+/// 
+/// Candidate entrances are vertices on steep faces (slope at least
+/// `min_slope`) above `water_level`, optionally restricted to faces looking in
+/// a given direction. Candidates are scored by slope plus a bonus for valley
+/// heads (concave hollows), then selected greedily by score subject to a
+/// minimum spacing.
+#[derive(Debug, Clone, Copy)]
+pub struct CaveFinder<F> {
+    /// Minimum slope (rise over run) of the face
+    pub min_slope: F,
+    /// Entrances must lie above this height
+    pub water_level: F,
+    /// If given, the allowed range `(from, to)` of facing directions, in
+    /// radians counter-clockwise from the x-axis; the range may wrap
+    pub aspect: Option<(F, F)>,
+    /// Weight of the valley-head (concavity) term relative to slope
+    pub valley_weight: F,
+    /// Minimum distance between entrances
+    pub min_spacing: F,
+    /// Maximum number of entrances
+    pub max_entrances: usize,
+    /// Cells with centre within this distance of an entrance are reserved as
+    /// holes
+    pub hole_radius: F,
+}
+
+/// A placed cave entrance
+#[derive(Debug, Clone, Copy)]
+pub struct CaveEntrance<F> {
+    /// Vertex index of the entrance
+    pub vertex: (u32, u32),
+    /// World coordinate `(x, y, height)`
+    pub position: (F, F, F),
+    /// Unit vector in the direction the entrance faces (downslope)
+    pub facing: (F, F),
+    /// Placement score (higher is better)
+    pub score: F,
+}
+
+impl<F: RealField> CaveFinder<F> {
+    /// Score all candidate vertices of `m`
+    /// 
+    /// Returns candidates in order of decreasing score, without spacing.
+    pub fn candidates(&self, m: &Heightmap<F>) -> Vec<CaveEntrance<F>> {
+        let (w, h) = m.cell_size();
+        let cell = (w * w + h * h).sqrt();
+        let two_pi = F::two_pi();
+        let mut result = Vec::new();
+        for cy in 0..m.dim().1 {
+            for cx in 0..m.dim().0 {
+                let z = m.get(cx, cy);
+                let slope = m.slope_at(cx, cy);
+                if z <= self.water_level || slope < self.min_slope || slope == F::zero() {
+                    continue;
+                }
+                let (gx, gy) = m.gradient_at(cx, cy);
+                let facing = (-gx / slope, -gy / slope);
+                if let Some((from, to)) = self.aspect {
+                    let angle = facing.1.atan2(facing.0);
+                    let wrap = |a: F| a - (a / two_pi).floor() * two_pi;
+                    if wrap(angle - from) > wrap(to - from) {
+                        continue;
+                    }
+                }
+                
+                // Concavity: how far the vertex lies below its neighbours
+                let mut sum = F::zero();
+                let mut n = 0;
+                for c in m.neighbours(cx, cy) {
+                    sum += m.get(c.0, c.1);
+                    n += 1;
+                }
+                let concavity = (sum / convert(n as f64) - z) / cell;
+                let score = slope + self.valley_weight * concavity.max(F::zero());
+                
+                let (x, y) = m.coord_of(cx, cy);
+                result.push(CaveEntrance {
+                    vertex: (cx, cy),
+                    position: (x, y, z),
+                    facing,
+                    score,
+                });
+            }
+        }
+        result.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        result
+    }
+    
+    /// Select entrances on `m`
+    /// 
+    /// Returns at most `max_entrances` entrances, in order of decreasing
+    /// score, each at least `min_spacing` from all others.
+    pub fn find(&self, m: &Heightmap<F>) -> Vec<CaveEntrance<F>> {
+        let d2 = self.min_spacing * self.min_spacing;
+        let mut chosen: Vec<CaveEntrance<F>> = Vec::new();
+        for c in self.candidates(m) {
+            if chosen.len() >= self.max_entrances {
+                break;
+            }
+            let clear = chosen.iter().all(|e| {
+                let (dx, dy) = (e.position.0 - c.position.0, e.position.1 - c.position.1);
+                dx * dx + dy * dy >= d2
+            });
+            if clear {
+                chosen.push(c);
+            }
+        }
+        chosen
+    }
+    
+    /// Reserve hole cells for `entrances`
+    /// 
+    /// The result has one entry per cell of `m` (dimension `dim - (1, 1)`) and
+    /// may be passed to [`Heightmap::to_trimesh_with_holes`]. The cell
+    /// "behind" each entrance (upslope of its vertex) is always reserved.
+    pub fn holes(&self, m: &Heightmap<F>, entrances: &[CaveEntrance<F>]) -> Grid<bool> {
+        let dim = (m.dim().0 - 1, m.dim().1 - 1);
+        let (w, h) = m.cell_size();
+        let half: F = convert(0.5);
+        let r2 = self.hole_radius * self.hole_radius;
+        let mut holes = Grid::from_fn(dim, |cx, cy| {
+            let x = (convert::<_, F>(cx as f64) + half) * w;
+            let y = (convert::<_, F>(cy as f64) + half) * h;
+            entrances.iter().any(|e| {
+                let (dx, dy) = (e.position.0 - x, e.position.1 - y);
+                dx * dx + dy * dy <= r2
+            })
+        });
+        for e in entrances {
+            // Upslope is opposite to facing; cells start at their lower corner
+            let (vx, vy) = e.vertex;
+            let cx = if e.facing.0 > F::zero() { vx.checked_sub(1) } else { Some(vx) };
+            let cy = if e.facing.1 > F::zero() { vy.checked_sub(1) } else { Some(vy) };
+            if let (Some(cx), Some(cy)) = (cx, cy) {
+                if cx < dim.0 && cy < dim.1 {
+                    holes.set(cx, cy, true);
+                }
+            }
+        }
+        holes
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{range, Heightmap, TravelParams, search::cost_field};
+use crate::grid::Grid;
+
+// Cost of carving per unit of height removed, relative to travel cost
+const CARVE_WEIGHT: f64 = 100.0;
+// Fraction of the permitted height difference used when carving, leaving a
+// margin for rounding
+const CARVE_MARGIN: f64 = 0.999;
+
+/// A pass carved by [`Heightmap::ensure_connected`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarvedPass<F> {
+    /// The key point connected
+    pub point: (u32, u32),
+    /// The carved route, from a previously reachable vertex to `point`
+    pub path: Vec<(u32, u32)>,
+    /// Modified vertices with their previous and new heights
+    pub changes: Vec<((u32, u32), F, F)>,
+}
+
+/// Result of [`Heightmap::ensure_connected`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectivityReport<F> {
+    /// Passes carved, in order
+    pub passes: Vec<CarvedPass<F>>,
+    /// Key points which could not be connected
+    pub unreachable: Vec<(u32, u32)>,
+}
+
+impl<F> ConnectivityReport<F> {
+    /// True if all key points are connected
+    pub fn is_connected(&self) -> bool {
+        self.unreachable.is_empty()
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Ensure all `points` are mutually reachable, carving passes as required
+    /// 
+    /// Reachability is as for [`Heightmap::reachable`]. Each key point not
+    /// reachable from the first is connected by the route requiring the least
+    /// carving (traded against route length), along which the terrain is
+    /// lowered by the minimum amount such that every step is passable. Passes
+    /// thus cut through saddles and ridges; terrain is never raised, so
+    /// impassable water cannot be crossed (nor is terrain carved below the
+    /// water level). Points which cannot be connected are reported.
+    pub fn ensure_connected(&mut self, points: &[(u32, u32)], params: &TravelParams<F>) -> ConnectivityReport<F> {
+        trace_span!("ensure_connected", dim = ?self.dim, points = points.len());
+        let mut report = ConnectivityReport { passes: vec![], unreachable: vec![] };
+        let start = match points.first() {
+            Some(&p) => p,
+            None => return report,
+        };
+        let mut reached = self.reachable(&[start], params);
+        for (k, &p) in points.iter().enumerate().skip(1) {
+            if reached.get(p.0, p.1) {
+                continue;
+            }
+            if let Some(pass) = self.carve_pass(p, &reached, params) {
+                let now = self.reachable(&[start], params);
+                // carving must not disconnect any previous point
+                if points[..=k].iter().all(|q| now.get(q.0, q.1) || !reached.get(q.0, q.1) && *q != p) {
+                    reached = now;
+                    report.passes.push(pass);
+                    continue;
+                }
+                // undo a pass which did not help
+                self.undo(&pass.changes);
+            }
+            report.unreachable.push(p);
+        }
+        report
+    }
+    
+    fn undo(&mut self, changes: &[((u32, u32), F, F)]) {
+        for &(c, old, _) in changes {
+            self.set(c.0, c.1, old);
+        }
+        self.range = range(&self.data);
+    }
+    
+    // Carve a pass from the region `reached` to `p`
+    fn carve_pass(&mut self, p: (u32, u32), reached: &Grid<bool>, params: &TravelParams<F>)
+        -> Option<CarvedPass<F>>
+    {
+        // Search for the route of least cost, where steps steeper than
+        // permitted cost the height to be carved.
+        let weight: F = convert(CARVE_WEIGHT);
+        let m: &Heightmap<F> = self;
+        let (cost, next) = cost_field(m, &[p], |a, b| {
+            let l = m.distance(a, b);
+            let dh = (m.get(b.0, b.1) - m.get(a.0, a.1)).abs();
+            let excess = params.max_rise(l).map(|max| (dh - max).max(F::zero())).unwrap_or(F::zero());
+            Some(params.flat_cost(m, b, l, dh / l)? + excess * weight)
+        });
+        let w = self.dim.0 as usize;
+        let from = (0..cost.len())
+            .filter(|&i| reached.data()[i] && cost[i] < F::max_value())
+            .min_by(|&i, &j| cost[i].partial_cmp(&cost[j]).unwrap())?;
+        let mut path = vec![from];
+        while next[*path.last().unwrap()] != usize::MAX {
+            path.push(next[*path.last().unwrap()]);
+        }
+        let path: Vec<_> = path.into_iter().map(|i| ((i % w) as u32, (i / w) as u32)).collect();
+        
+        // Lower the path to the largest profile below the terrain within the
+        // permitted height difference of each step.
+        let margin: F = convert(CARVE_MARGIN);
+        let rise: Vec<_> = path.windows(2)
+            .map(|s| params.max_rise(self.distance(s[0], s[1])).map(|r| r * margin).unwrap_or(F::max_value()))
+            .collect();
+        let mut h: Vec<_> = path.iter().map(|c| self.get(c.0, c.1)).collect();
+        for i in 1..h.len() {
+            h[i] = h[i].min(h[i - 1] + rise[i - 1]);
+        }
+        for i in (0..h.len() - 1).rev() {
+            h[i] = h[i].min(h[i + 1] + rise[i]);
+        }
+        let mut changes = vec![];
+        for (&c, &new) in path.iter().zip(h.iter()) {
+            let old = self.get(c.0, c.1);
+            if new < old {
+                if params.is_water(new) && !params.is_water(old) && params.water_cost.is_none() {
+                    self.undo(&changes);
+                    return None;
+                }
+                self.set(c.0, c.1, new);
+                changes.push((c, old, new));
+            }
+        }
+        Some(CarvedPass { point: p, path, changes })
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{BTreeMap, BTreeSet};
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+
+// Edge of the vertex grid: from vertex (x, y) along the x (0) or y (1) axis
+type EdgeKey = (u32, u32, u8);
+
+/// A contour line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour<F> {
+    /// Elevation
+    pub level: F,
+    /// Polyline (world coordinates). Closed contours have their first point
+    /// repeated at the end; others end on the edge of the map.
+    pub points: Vec<(F, F)>,
+}
+
+impl<F> Contour<F> {
+    /// True if the contour is a closed loop
+    pub fn is_closed(&self) -> bool where F: PartialEq {
+        self.points.len() > 2 && self.points.first() == self.points.last()
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Extract contour lines at every multiple of `interval` within the
+    /// height range
+    /// 
+    /// Contours are traced with marching squares over the vertex grid,
+    /// interpolating linearly along cell edges; saddle cells are resolved by
+    /// the mean height of the cell. Contours are ordered by level, from
+    /// lowest. Each contour separates heights above its level (on the left,
+    /// looking along the line with `y` up) from those at or below.
+    pub fn contours(&self, interval: F) -> Vec<Contour<F>> {
+        trace_span!("contours", dim = ?self.dim);
+        assert!(interval > F::zero());
+        let first = (self.range.0 / interval).ceil();
+        let mut contours = vec![];
+        let mut k = first;
+        while k * interval < self.range.1 {
+            contours.extend(self.contour(k * interval));
+            k += F::one();
+        }
+        contours
+    }
+    
+    /// Extract contour lines at a single `level`
+    /// 
+    /// See [`Heightmap::contours`].
+    pub fn contour(&self, level: F) -> Vec<Contour<F>> {
+        let offset = self.len_frac.0.min(self.len_frac.1) * convert(0.1);
+        trace_contour(self, level).into_iter().map(|mut points| {
+            // orient by comparing heights either side of the first segment
+            let (a, b) = (points[0], points[1]);
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let len = (dx * dx + dy * dy).sqrt();
+            if len > F::zero() {
+                let (nx, ny) = (-dy / len * offset, dx / len * offset);
+                let mid = ((a.0 + b.0) * convert(0.5), (a.1 + b.1) * convert(0.5));
+                if self.interpolate(mid.0 + nx, mid.1 + ny) < self.interpolate(mid.0 - nx, mid.1 - ny) {
+                    points.reverse();
+                }
+            }
+            Contour { level, points }
+        }).collect()
+    }
+}
+
+// Trace the contour at `level` as polylines (marching squares). Closed
+// loops have their first point repeated at the end.
+pub(super) fn trace_contour<F: RealField>(m: &Heightmap<F>, level: F) -> Vec<Vec<(F, F)>> {
+    let dim = m.dim();
+    let above = |cx, cy| m.get(cx, cy) > level;
+    
+    // Marching squares: connect crossed edges within each cell
+    let mut links: BTreeMap<EdgeKey, Vec<EdgeKey>> = BTreeMap::new();
+    let mut link = |a: EdgeKey, b: EdgeKey| {
+        links.entry(a).or_default().push(b);
+        links.entry(b).or_default().push(a);
+    };
+    for cy in 0..dim.1 - 1 {
+        for cx in 0..dim.0 - 1 {
+            let corners = [above(cx, cy), above(cx + 1, cy), above(cx + 1, cy + 1), above(cx, cy + 1)];
+            // bottom, right, top, left
+            let edges = [(cx, cy, 0), (cx + 1, cy, 1), (cx, cy + 1, 0), (cx, cy, 1)];
+            let crossed: Vec<_> = (0..4).filter(|&i| corners[i] != corners[(i + 1) % 4]).collect();
+            match crossed.len() {
+                2 => link(edges[crossed[0]], edges[crossed[1]]),
+                4 => {
+                    let centre = (m.get(cx, cy) + m.get(cx + 1, cy) + m.get(cx + 1, cy + 1)
+                        + m.get(cx, cy + 1)) * convert(0.25) > level;
+                    // join the high corners through a high centre
+                    if corners[0] != centre {
+                        link(edges[3], edges[0]);
+                        link(edges[1], edges[2]);
+                    } else {
+                        link(edges[0], edges[1]);
+                        link(edges[2], edges[3]);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    
+    let point = |e: EdgeKey| {
+        let (x0, y0) = (e.0, e.1);
+        let (x1, y1) = if e.2 == 0 { (x0 + 1, y0) } else { (x0, y0 + 1) };
+        let (h0, h1) = (m.get(x0, y0), m.get(x1, y1));
+        let t = (level - h0) / (h1 - h0);
+        let (a, b) = (m.coord_of(x0, y0), m.coord_of(x1, y1));
+        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+    };
+    
+    // Chain links, starting with open ends, then closed loops
+    let mut lines = vec![];
+    let mut visited = BTreeSet::new();
+    let starts: Vec<EdgeKey> = links.iter().filter(|(_, l)| l.len() == 1).map(|(k, _)| *k)
+        .chain(links.keys().cloned())
+        .collect();
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut line = vec![point(start)];
+        visited.insert(start);
+        let mut prev = None;
+        let mut cur = start;
+        loop {
+            let next = links[&cur].iter().find(|&&n| Some(n) != prev && !visited.contains(&n));
+            match next {
+                Some(&n) => {
+                    line.push(point(n));
+                    visited.insert(n);
+                    prev = Some(cur);
+                    cur = n;
+                }
+                None => {
+                    // close loops
+                    if cur != start && links[&cur].contains(&start) && links[&start].len() == 2 {
+                        line.push(point(start));
+                    }
+                    break;
+                }
+            }
+        }
+        if line.len() > 1 {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
@@ -0,0 +1,112 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{range, Heightmap};
+use crate::grid::Grid;
+
+/// Hillslope diffusion (soil creep) parameters
+/// 
+/// Soil moves downslope with flux `q = -D ∇h` (linear diffusion), rounding
+/// ridge crests and hilltops into convex caps. Given a `critical_slope` `Sc`,
+/// flux is instead `q = -D ∇h / (1 - (|∇h| / Sc)²)` (Roering et al., 1999):
+/// nearly linear on gentle slopes but increasing rapidly towards `Sc`, giving
+/// convex hilltops above planar hillslopes near the critical angle. (The
+/// factor is capped at 50, i.e. flux is limited on slopes at or beyond `Sc`.)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CreepParams<F> {
+    /// Diffusivity `D` (area per unit time)
+    pub diffusivity: F,
+    /// Critical slope (rise over run) for non-linear creep; `None` for linear
+    pub critical_slope: Option<F>,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Apply soil creep over `duration`
+    /// 
+    /// Material is conserved: no flux crosses the map edges. Returns the
+    /// change in height of each vertex.
+    pub fn creep(&mut self, params: &CreepParams<F>, duration: F) -> Grid<F> {
+        trace_span!("creep", dim = ?self.dim);
+        let initial = self.data.clone();
+        diffuse(self, params, duration, false);
+        let w = self.dim.0 as usize;
+        Grid::from_fn(self.dim, |cx, cy| {
+            let i = cy as usize * w + cx as usize;
+            self.data[i] - initial[i]
+        })
+    }
+}
+
+// Diffuse over `duration` with explicit sub-steps. Edges are closed or, if
+// `fixed_edges`, held at their current height.
+pub(super) fn diffuse<F: RealField>(m: &mut Heightmap<F>, params: &CreepParams<F>, duration: F, fixed_edges: bool) {
+    let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+    let (dx, dy) = m.len_frac;
+    let (ix, iy) = (F::one() / (dx * dx), F::one() / (dy * dy));
+    let max_factor: F = convert(50.0);
+    let factor = |slope: F| match params.critical_slope {
+        Some(sc) => {
+            let r = slope / sc;
+            (F::one() / (F::one() - r * r).max(F::one() / max_factor)).min(max_factor)
+        }
+        None => F::one(),
+    };
+    // flux across edge between vertex i and i + 1 (qx) or i + w (qy)
+    let mut qx = vec![F::zero(); w * h];
+    let mut qy = vec![F::zero(); w * h];
+    let mut remaining = duration;
+    while remaining > F::zero() {
+        let mut kmax = F::zero();
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * w + x;
+                if x + 1 < w {
+                    let s = (m.data[i + 1] - m.data[i]) / dx;
+                    let k = params.diffusivity * factor(s.abs());
+                    kmax = kmax.max(k);
+                    qx[i] = -k * s;
+                }
+                if y + 1 < h {
+                    let s = (m.data[i + w] - m.data[i]) / dy;
+                    let k = params.diffusivity * factor(s.abs());
+                    kmax = kmax.max(k);
+                    qy[i] = -k * s;
+                }
+            }
+        }
+        if kmax <= F::zero() {
+            break;
+        }
+        let dt = remaining.min(convert::<_, F>(0.45) / (kmax * (ix + iy)));
+        for y in 0..h {
+            for x in 0..w {
+                if fixed_edges && (x == 0 || y == 0 || x + 1 == w || y + 1 == h) {
+                    continue;
+                }
+                let i = y * w + x;
+                let mut div = F::zero();
+                if x + 1 < w {
+                    div += qx[i] / dx;
+                }
+                if x > 0 {
+                    div -= qx[i - 1] / dx;
+                }
+                if y + 1 < h {
+                    div += qy[i] / dy;
+                }
+                if y > 0 {
+                    div -= qy[i - w] / dy;
+                }
+                m.data[i] -= div * dt;
+            }
+        }
+        remaining -= dt;
+    }
+    m.range = range(&m.data);
+}
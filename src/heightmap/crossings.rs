@@ -0,0 +1,156 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use super::Heightmap;
+
+/// Kind of crossing needed along a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossingKind {
+    /// The route passes above the terrain (valley or water)
+    Bridge,
+    /// The route passes through the terrain (ridge)
+    Tunnel,
+}
+
+/// A suggested bridge or tunnel
+#[derive(Debug, Clone, Copy)]
+pub struct Crossing<F> {
+    /// Bridge or tunnel
+    pub kind: CrossingKind,
+    /// First portal (bridge abutment or tunnel entrance) `(x, y, height)`
+    pub start: (F, F, F),
+    /// Second portal `(x, y, height)`
+    pub end: (F, F, F),
+    /// Distance along the route from `start` to `end`
+    pub length: F,
+    /// Maximum vertical distance between route and terrain: the clearance
+    /// below a bridge or the cover above a tunnel
+    pub max_depth: F,
+}
+
+/// The result of analysing a route
+#[derive(Debug, Clone)]
+pub struct RouteAnalysis<F> {
+    /// Sampled route profile: `(x, y, terrain height, route height)`
+    pub profile: Vec<(F, F, F, F)>,
+    /// Suggested crossings, in order along the route
+    pub crossings: Vec<Crossing<F>>,
+}
+
+/// Route planning constraints
+/// 
+/// A route following the terrain is limited to `max_grade`. Where the
+/// grade-limited route lies more than `max_fill` above the terrain a bridge is
+/// suggested; where it lies more than `max_cut` below the terrain a tunnel is
+/// suggested. Other deviations are assumed to be handled by embankments and
+/// cuttings.
+#[derive(Debug, Clone, Copy)]
+pub struct RoutePlan<F> {
+    /// Maximum grade (rise over run) of the route
+    pub max_grade: F,
+    /// Maximum height of an embankment before a bridge is required
+    pub max_fill: F,
+    /// Maximum depth of a cutting before a tunnel is required
+    pub max_cut: F,
+    /// If given, the route must stay at least `deck_clearance` above this
+    /// water level
+    pub water_level: Option<F>,
+    /// Clearance above water
+    pub deck_clearance: F,
+}
+
+impl<F: RealField> RoutePlan<F> {
+    /// Analyse a route given as a polyline (world coordinates) over `m`
+    /// 
+    /// The route is sampled at intervals of (approximately) the heightmap's
+    /// cell size.
+    pub fn analyse(&self, m: &Heightmap<F>, path: &[(F, F)]) -> RouteAnalysis<F> {
+        let step = m.cell_size().0.min(m.cell_size().1);
+        let mut samples = Vec::new();   // (x, y, distance along route)
+        let mut dist = F::zero();
+        for w in path.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let len = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+            let n = (try_convert::<_, f64>((len / step).ceil()).unwrap() as usize).max(1);
+            for i in 0..n {
+                let t = convert::<_, F>(i as f64) / convert(n as f64);
+                samples.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, dist + len * t));
+            }
+            dist += len;
+        }
+        if let Some(&b) = path.last() {
+            samples.push((b.0, b.1, dist));
+        }
+        
+        let terrain: Vec<F> = samples.iter().map(|s| m.interpolate(s.0, s.1)).collect();
+        let n = samples.len();
+        
+        // Minimum route height (above water), then clamp to the grade limit
+        // in both directions.
+        let min_h = self.water_level.map(|w| w + self.deck_clearance);
+        let mut route: Vec<F> = terrain.iter()
+            .map(|&h| match min_h {
+                Some(w) => h.max(w),
+                None => h,
+            })
+            .collect();
+        for i in 1..n {
+            let d = (samples[i].2 - samples[i - 1].2) * self.max_grade;
+            route[i] = route[i].max(route[i - 1] - d).min(route[i - 1] + d);
+        }
+        for i in (0..n.saturating_sub(1)).rev() {
+            let d = (samples[i + 1].2 - samples[i].2) * self.max_grade;
+            route[i] = route[i].max(route[i + 1] - d).min(route[i + 1] + d);
+        }
+        
+        // Group runs of samples needing a crossing
+        let kind_of = |i: usize| {
+            let over = route[i] - terrain[i];
+            if over > self.max_fill || min_h.map(|w| terrain[i] < w).unwrap_or(false) {
+                Some(CrossingKind::Bridge)
+            } else if -over > self.max_cut {
+                Some(CrossingKind::Tunnel)
+            } else {
+                None
+            }
+        };
+        let mut crossings: Vec<Crossing<F>> = Vec::new();
+        let mut i = 0;
+        while i < n {
+            let kind = match kind_of(i) {
+                Some(k) => k,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            // Portals are at the last/first samples not needing a crossing
+            let first = i.saturating_sub(1);
+            let mut max_depth = F::zero();
+            while i < n && kind_of(i) == Some(kind) {
+                max_depth = max_depth.max((route[i] - terrain[i]).abs());
+                i += 1;
+            }
+            let last = i.min(n - 1);
+            let portal = |j: usize| (samples[j].0, samples[j].1, route[j]);
+            crossings.push(Crossing {
+                kind,
+                start: portal(first),
+                end: portal(last),
+                length: samples[last].2 - samples[first].2,
+                max_depth,
+            });
+        }
+        
+        let profile = (0..n)
+            .map(|i| (samples[i].0, samples[i].1, terrain[i], route[i]))
+            .collect();
+        RouteAnalysis { profile, crossings }
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Type of surface curvature
+/// 
+/// All kinds are positive where the surface is convex (ridges, hilltops,
+/// slope crests) and negative where concave (valleys, hollows, slope feet).
+/// Units are inverse length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurvatureKind {
+    /// Curvature of contour lines: positive on spurs and ridges, where flow
+    /// diverges; negative in hollows and valleys, where flow converges
+    Plan,
+    /// Curvature in the direction of steepest descent: positive where slopes
+    /// steepen downhill (flow accelerates), negative where they flatten
+    /// (flow decelerates and deposits sediment)
+    Profile,
+    /// Mean curvature of the surface, independent of slope direction
+    Mean,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Estimate the curvature at the given vertex
+    /// 
+    /// Derivatives are estimated by central differences over the 3×3
+    /// neighbourhood, shifted inwards on the edges. Plan and profile
+    /// curvature are zero on flat ground.
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    pub fn curvature_at(&self, cx: u32, cy: u32, kind: CurvatureKind) -> F {
+        let (p, q) = self.gradient_at(cx, cy);
+        let (dx, dy) = self.len_frac;
+        // centre of the difference stencil, if the map is wide enough
+        let centre = |c: u32, len: u32| if len < 3 { None } else { Some(c.max(1).min(len - 2)) };
+        let (ox, oy) = (centre(cx, self.dim.0), centre(cy, self.dim.1));
+        let r = ox.map(|x| (self.get(x - 1, cy) - self.get(x, cy) - self.get(x, cy) + self.get(x + 1, cy)) / (dx * dx));
+        let t = oy.map(|y| (self.get(cx, y - 1) - self.get(cx, y) - self.get(cx, y) + self.get(cx, y + 1)) / (dy * dy));
+        let s = match (ox, oy) {
+            (Some(x), Some(y)) => {
+                (self.get(x + 1, y + 1) - self.get(x - 1, y + 1) - self.get(x + 1, y - 1) + self.get(x - 1, y - 1))
+                    / (convert::<_, F>(4.0) * dx * dy)
+            }
+            _ => F::zero(),
+        };
+        let (r, t) = (r.unwrap_or_else(F::zero), t.unwrap_or_else(F::zero));
+        
+        let g2 = p * p + q * q;
+        let one = F::one();
+        match kind {
+            CurvatureKind::Plan | CurvatureKind::Profile if g2 <= F::default_epsilon() => F::zero(),
+            CurvatureKind::Plan => -(r * q * q - convert::<_, F>(2.0) * s * p * q + t * p * p) / (g2 * g2.sqrt()),
+            CurvatureKind::Profile => {
+                let w = one + g2;
+                -(r * p * p + convert::<_, F>(2.0) * s * p * q + t * q * q) / (g2 * w * w.sqrt())
+            }
+            CurvatureKind::Mean => {
+                let w = one + g2;
+                -((one + q * q) * r - convert::<_, F>(2.0) * p * q * s + (one + p * p) * t)
+                    / (convert::<_, F>(2.0) * w * w.sqrt())
+            }
+        }
+    }
+    
+    /// Get the curvature of each vertex
+    /// 
+    /// See [`Heightmap::curvature_at`].
+    pub fn curvature_map(&self, kind: CurvatureKind) -> Grid<F> {
+        trace_span!("curvature_map", dim = ?self.dim);
+        Grid::from_fn(self.dim, |cx, cy| self.curvature_at(cx, cy, kind))
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use rand::Rng;
+use super::{range, Heightmap};
+use crate::grid::Grid;
+
+/// Parameters of sediment deposition
+#[derive(Debug, Clone, Copy)]
+pub struct DepositionParams<F> {
+    /// Height of the water surface, if any; sediment entering water builds a
+    /// delta, otherwise an alluvial fan
+    pub water_level: Option<F>,
+    /// Slope (rise over run) below which sediment settles on land: the
+    /// gradient of fans and delta plains
+    pub fan_slope: F,
+    /// Slope below which sediment settles under water: the gradient of the
+    /// delta front
+    pub foreset_slope: F,
+    /// Height of each deposited parcel of sediment (smaller parcels give
+    /// smoother results at greater cost)
+    pub parcel: F,
+    /// Concentration of flow: each parcel moves to a lower neighbour with
+    /// probability proportional to its excess slope to the power of `focus`.
+    /// Small values spread sediment widely; large values follow the steepest
+    /// descent, forming fewer, longer distributaries.
+    pub focus: F,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Deposit sediment at river mouths and canyon outlets
+    /// 
+    /// Each source is a vertex and a volume of sediment, delivered as parcels
+    /// of height `params.parcel` over one cell. Each parcel moves downhill
+    /// while the slope exceeds the threshold (`fan_slope` on land,
+    /// `foreset_slope` under water), choosing randomly between neighbours
+    /// steep enough, then settles. Fans thus build cones at `fan_slope`, while
+    /// deltas build a platform up to the water level, fronted by a steep
+    /// slope. As deposits raise active channels, flow switches to lower
+    /// routes, forming distributaries. Parcels reaching the map edge are lost.
+    /// 
+    /// Returns the thickness of deposited sediment.
+    pub fn deposit<R: Rng + ?Sized>(&mut self, sources: &[((u32, u32), F)], params: &DepositionParams<F>, rng: &mut R)
+        -> Grid<F>
+    {
+        trace_span!("deposit", dim = ?self.dim, sources = sources.len());
+        assert!(params.parcel > F::zero());
+        let cell = self.len_frac.0 * self.len_frac.1;
+        let mut thickness = Grid::new(self.dim, F::zero());
+        let mut weights = Vec::with_capacity(8);
+        for &(source, volume) in sources {
+            let parcels = try_convert::<F, f64>(volume / (cell * params.parcel)).unwrap().round() as u64;
+            for _ in 0..parcels {
+                let mut c = source;
+                loop {
+                    let h = self.get(c.0, c.1);
+                    let threshold = match params.water_level {
+                        Some(level) if h < level => params.foreset_slope,
+                        _ => params.fan_slope,
+                    };
+                    weights.clear();
+                    let mut total = F::zero();
+                    for n in self.neighbours(c.0, c.1) {
+                        let excess = (h - self.get(n.0, n.1)) / self.distance(c, n) - threshold;
+                        if excess > F::zero() {
+                            let w = excess.powf(params.focus);
+                            total += w;
+                            weights.push((n, w));
+                        }
+                    }
+                    if weights.is_empty() {
+                        self.data[(c.1 * self.dim.0 + c.0) as usize] += params.parcel;
+                        thickness.set(c.0, c.1, thickness.get(c.0, c.1) + params.parcel);
+                        break;
+                    }
+                    let mut r = total * convert(rng.gen::<f64>());
+                    c = weights.last().unwrap().0;
+                    for &(n, w) in &weights {
+                        if r < w {
+                            c = n;
+                            break;
+                        }
+                        r -= w;
+                    }
+                    if c.0 == 0 || c.1 == 0 || c.0 == self.dim.0 - 1 || c.1 == self.dim.1 - 1 {
+                        break;
+                    }
+                }
+            }
+        }
+        self.range = range(&self.data);
+        thickness
+    }
+}
@@ -43,6 +43,7 @@ pub fn midpoint_displacement<F, R: Rng, D: Distribution<F>>(
         distr: D) -> Result<(), Error>
 where F: RealField + Copy
 {
+    trace_span!("midpoint_displacement", dim = ?m.dim());
     let dim = m.dim();
     if dim.0 != dim.1 {
         return Err(Error::NotSquare);
@@ -124,6 +125,7 @@ pub fn diamond_square<F, R: Rng, D: Distribution<F>>(
 where F: RealField + Copy
 {
     #![allow(non_snake_case)]
+    trace_span!("diamond_square", dim = ?m.dim());
     
     let dim = m.dim();
     if dim.0 != dim.1 {
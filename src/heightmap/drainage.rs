@@ -0,0 +1,64 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Single-direction (D8) drainage
+
+use std::cmp::Ordering;
+use nalgebra::RealField;
+use super::Heightmap;
+use crate::grid::Grid;
+
+impl<F: RealField> Heightmap<F> {
+    /// Get the upstream catchment area of each vertex
+    /// 
+    /// Flow from each vertex is routed to the neighbour of steepest descent
+    /// (D8); flow ends at pits and map edges. The area includes the vertex's
+    /// own cell. Rivers may be identified as vertices whose catchment exceeds
+    /// some threshold.
+    pub fn flow_accumulation(&self) -> Grid<F> {
+        accumulation(self, false)
+    }
+}
+
+// Neighbour of steepest descent of each vertex (ascent, if `invert`)
+pub(crate) fn downstream<F: RealField>(m: &Heightmap<F>, invert: bool) -> Grid<Option<(u32, u32)>> {
+    let height = |c: (u32, u32)| if invert { -m.get(c.0, c.1) } else { m.get(c.0, c.1) };
+    Grid::from_fn(m.dim(), |cx, cy| {
+        let h = height((cx, cy));
+        let (x, y) = m.coord_of(cx, cy);
+        let mut best = (F::zero(), None);
+        for n in m.neighbours(cx, cy) {
+            let (nx, ny) = m.coord_of(n.0, n.1);
+            let d = ((nx - x) * (nx - x) + (ny - y) * (ny - y)).sqrt();
+            let slope = (h - height(n)) / d;
+            if slope > best.0 {
+                best = (slope, Some(n));
+            }
+        }
+        best.1
+    })
+}
+
+// Upstream catchment area of each vertex, routing flow as `downstream`
+pub(crate) fn accumulation<F: RealField>(m: &Heightmap<F>, invert: bool) -> Grid<F> {
+    trace_span!("flow_accumulation", dim = ?m.dim());
+    let dim = m.dim();
+    let cell = m.cell_size();
+    let next = downstream(m, invert);
+    let height = |c: (u32, u32)| if invert { -m.get(c.0, c.1) } else { m.get(c.0, c.1) };
+    let mut order: Vec<(u32, u32)> = (0..dim.1).flat_map(|cy| (0..dim.0).map(move |cx| (cx, cy))).collect();
+    order.sort_by(|a, b| height(*b).partial_cmp(&height(*a)).unwrap_or(Ordering::Equal));
+    let mut acc = Grid::new(dim, cell.0 * cell.1);
+    for c in order {
+        if let Some(n) = next.get(c.0, c.1) {
+            let a = acc.get(n.0, n.1) + acc.get(c.0, c.1);
+            acc.set(n.0, n.1, a);
+        }
+    }
+    acc
+}
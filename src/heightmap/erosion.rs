@@ -0,0 +1,467 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use nalgebra::{convert, try_convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+use crate::io::ImageStack;
+use crate::task::{IncrementalTask, Progress};
+
+// File signature of serialised sessions (with format version)
+const MAGIC: &[u8; 8] = b"TERRERO2";
+// Version 1 lacks erodibility
+const MAGIC_V1: &[u8; 8] = b"TERRERO1";
+
+/// Parameters of hydraulic erosion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErosionParams<F> {
+    /// Depth of rain added to every vertex each iteration
+    pub rain: F,
+    /// Sediment capacity per unit of water outflow and slope
+    pub capacity: F,
+    /// Fraction of unused capacity dissolved from the terrain each iteration,
+    /// in `[0, 1]`
+    pub solubility: F,
+    /// Fraction of excess sediment deposited each iteration, in `[0, 1]`
+    pub deposition: F,
+    /// Fraction of water evaporating each iteration, in `[0, 1)`
+    pub evaporation: F,
+}
+
+/// A resumable hydraulic erosion simulation
+/// 
+/// The session owns the terrain and the intermediate state: water depth and
+/// suspended sediment per vertex. Each iteration, rain is added; water flows
+/// towards neighbouring vertices with a lower water surface, carrying its
+/// sediment; flowing water dissolves terrain up to its capacity (proportional
+/// to outflow and slope) while water carrying excess sediment deposits it;
+/// finally some water evaporates. Water does not leave the map.
+/// 
+/// Rock type may vary over the map: an erodibility grid (see
+/// [`ErosionSession::set_erodibility`]) scales dissolution per vertex, thus
+/// resistant rock forms narrow gorges and headlands while soft rock is
+/// carved into wide valleys and bays.
+/// 
+/// Besides the terrain, the water state may be exported for visual effects
+/// with [`ErosionSession::flow_fields`].
+/// 
+/// Long simulations may be spread over frames with [`ErosionSession::run_for`],
+/// cancelled from another thread with [`ErosionSession::run_cancellable`],
+/// and saved and restored across application restarts with
+/// [`ErosionSession::write`] and [`ErosionSession::read`]. A session resumed
+/// after any such interruption gives the same result as one run without.
+#[derive(Debug, Clone)]
+pub struct ErosionSession<F: RealField> {
+    params: ErosionParams<F>,
+    size: (F, F),
+    terrain: Grid<F>,
+    water: Grid<F>,
+    sediment: Grid<F>,
+    erodibility: Option<Grid<F>>,
+    velocity: Grid<(F, F)>,
+    iterations: u64,
+}
+
+/// Water flow state of an [`ErosionSession`]
+/// 
+/// These grids allow renderers to place water effects (streams, waterfalls,
+/// foam, muddy water) consistent with the simulation which shaped the terrain.
+/// Velocity is the mean horizontal displacement of water at each vertex over
+/// the last iteration (world units per iteration): large on steep channels,
+/// e.g. above waterfalls, and zero in pools.
+#[derive(Debug, Clone)]
+pub struct FlowFields<F> {
+    /// Water depth
+    pub depth: Grid<F>,
+    /// Velocity in the `x` direction
+    pub velocity_x: Grid<F>,
+    /// Velocity in the `y` direction
+    pub velocity_y: Grid<F>,
+    /// Sediment concentration (suspended sediment per unit of water depth);
+    /// zero where dry
+    pub concentration: Grid<F>,
+}
+
+impl<F: RealField> FlowFields<F> {
+    /// Add all fields as channels of `stack`
+    /// 
+    /// Channels are named `water_depth`, `velocity_x`, `velocity_y` and
+    /// `sediment`. Velocity components share a range symmetric about zero,
+    /// thus still water maps to the middle of the output range.
+    pub fn add_to<'a>(&'a self, stack: &mut ImageStack<'a, F>) {
+        let max = self.velocity_x.data().iter().chain(self.velocity_y.data())
+            .fold(F::zero(), |a, v| a.max(v.abs()));
+        stack.add("water_depth", &self.depth)
+            .add_with_range("velocity_x", &self.velocity_x, (-max, max))
+            .add_with_range("velocity_y", &self.velocity_y, (-max, max))
+            .add("sediment", &self.concentration);
+    }
+}
+
+impl<F: RealField> ErosionSession<F> {
+    /// Start a session eroding `m`, with no water or sediment
+    pub fn new(m: Heightmap<F>, params: ErosionParams<F>) -> Self {
+        let dim = m.dim();
+        let size = m.size();
+        let terrain = Grid::from_fn(dim, |cx, cy| m.get(cx, cy));
+        ErosionSession {
+            params,
+            size,
+            terrain,
+            water: Grid::new(dim, F::zero()),
+            sediment: Grid::new(dim, F::zero()),
+            erodibility: None,
+            velocity: Grid::new(dim, (F::zero(), F::zero())),
+            iterations: 0,
+        }
+    }
+    
+    /// Get the parameters
+    pub fn params(&self) -> &ErosionParams<F> {
+        &self.params
+    }
+    
+    /// Change the parameters (taking effect from the next iteration)
+    pub fn set_params(&mut self, params: ErosionParams<F>) {
+        self.params = params;
+    }
+    
+    /// Get the erodibility grid, if any
+    pub fn erodibility(&self) -> Option<&Grid<F>> {
+        self.erodibility.as_ref()
+    }
+    
+    /// Set the erodibility of each vertex (taking effect from the next
+    /// iteration)
+    /// 
+    /// Values are non-negative factors on `solubility`: zero for rock which
+    /// does not erode, one for nominal material, greater for soft material.
+    /// The grid may be generated from noise, from [`Strata`](super::Strata)
+    /// indices or painted. `None` is equivalent to erodibility one
+    /// everywhere.
+    pub fn set_erodibility(&mut self, erodibility: Option<Grid<F>>) {
+        if let Some(grid) = erodibility.as_ref() {
+            assert_eq!(grid.dim(), self.terrain.dim());
+        }
+        self.erodibility = erodibility;
+    }
+    
+    /// Get the water velocity of each vertex over the last iteration
+    /// 
+    /// See [`FlowFields`]. This is not serialised: it is zero after
+    /// [`ErosionSession::read`] until the next iteration.
+    pub fn velocity(&self) -> &Grid<(F, F)> {
+        &self.velocity
+    }
+    
+    /// Get water depth, velocity and sediment concentration
+    pub fn flow_fields(&self) -> FlowFields<F> {
+        let dim = self.water.dim();
+        let concentration = Grid::from_fn(dim, |cx, cy| {
+            let w = self.water.get(cx, cy);
+            if w > F::zero() { self.sediment.get(cx, cy) / w } else { F::zero() }
+        });
+        FlowFields {
+            depth: self.water.clone(),
+            velocity_x: self.velocity.map(|v| v.0),
+            velocity_y: self.velocity.map(|v| v.1),
+            concentration,
+        }
+    }
+    
+    /// Number of iterations run so far
+    pub fn iterations(&self) -> u64 {
+        self.iterations
+    }
+    
+    /// Current terrain heights
+    #[inline]
+    pub fn terrain(&self) -> &Grid<F> {
+        &self.terrain
+    }
+    
+    /// Current water depth
+    #[inline]
+    pub fn water(&self) -> &Grid<F> {
+        &self.water
+    }
+    
+    /// Current suspended sediment (as height)
+    #[inline]
+    pub fn sediment(&self) -> &Grid<F> {
+        &self.sediment
+    }
+    
+    /// Get the current terrain as a heightmap
+    /// 
+    /// Suspended sediment is not included.
+    pub fn to_heightmap(&self) -> Heightmap<F> {
+        Heightmap::from_grid(self.terrain.clone(), self.size)
+    }
+    
+    /// Finish the session, depositing all suspended sediment in place
+    pub fn into_heightmap(self) -> Heightmap<F> {
+        let mut terrain = self.terrain;
+        for (h, s) in terrain.data_mut().iter_mut().zip(self.sediment.data()) {
+            *h += *s;
+        }
+        Heightmap::from_grid(terrain, self.size)
+    }
+    
+    /// Run `n` more iterations
+    pub fn run(&mut self, n: u64) {
+        trace_span!("erosion", dim = ?self.terrain.dim(), n);
+        for _ in 0..n {
+            self.step();
+        }
+    }
+    
+    /// Run up to `n` more iterations, stopping early once `cancel` is set
+    /// 
+    /// `cancel` is checked before each iteration, thus may be set from
+    /// another thread. Returns the number of iterations run.
+    pub fn run_cancellable(&mut self, n: u64, cancel: &AtomicBool) -> u64 {
+        trace_span!("erosion", dim = ?self.terrain.dim(), n);
+        for i in 0..n {
+            if cancel.load(Ordering::Relaxed) {
+                return i;
+            }
+            self.step();
+        }
+        n
+    }
+    
+    /// Run up to `n` more iterations, stopping once `budget` has elapsed
+    /// 
+    /// At least one iteration is run (if `n > 0`). This allows a simulation to
+    /// be spread over frames. Returns the number of iterations run.
+    pub fn run_for(&mut self, n: u64, budget: Duration) -> u64 {
+        trace_span!("erosion", dim = ?self.terrain.dim(), n);
+        let start = Instant::now();
+        for i in 0..n {
+            if i > 0 && start.elapsed() >= budget {
+                return i;
+            }
+            self.step();
+        }
+        n
+    }
+    
+    /// Run a single iteration
+    pub fn step(&mut self) {
+        let p = self.params;
+        let dim = self.terrain.dim();
+        for w in self.water.data_mut() {
+            *w += p.rain;
+        }
+        
+        let surface = Grid::from_fn(dim, |cx, cy| self.terrain.get(cx, cy) + self.water.get(cx, cy));
+        let m = Heightmap::from_grid(surface, self.size);
+        let cell = m.cell_size();
+        let diagonal = (cell.0 * cell.0 + cell.1 * cell.1).sqrt();
+        let half: F = convert(0.5);
+        let mut water = self.water.clone();
+        let mut sediment = self.sediment.clone();
+        let mut velocity = Grid::new(dim, (F::zero(), F::zero()));
+        let mut drops = Vec::with_capacity(8);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let w = self.water.get(cx, cy);
+                if w <= F::zero() {
+                    continue;
+                }
+                let s = m.get(cx, cy);
+                drops.clear();
+                let mut total = F::zero();
+                let mut max_drop = F::zero();
+                let mut slope = F::zero();
+                for n in m.neighbours(cx, cy) {
+                    let drop = s - m.get(n.0, n.1);
+                    if drop > F::zero() {
+                        let dist = match (n.0 != cx, n.1 != cy) {
+                            (true, true) => diagonal,
+                            (true, false) => cell.0,
+                            _ => cell.1,
+                        };
+                        drops.push((n, drop));
+                        total += drop;
+                        max_drop = max_drop.max(drop);
+                        slope = slope.max(drop / dist);
+                    }
+                }
+                // Never move more than half the largest drop, to avoid
+                // oscillation.
+                let out = w.min(max_drop * half);
+                
+                let capacity = p.capacity * out * slope;
+                let carried = self.sediment.get(cx, cy);
+                let change = if carried < capacity {
+                    let solubility = match self.erodibility.as_ref() {
+                        Some(e) => p.solubility * e.get(cx, cy),
+                        None => p.solubility,
+                    };
+                    // do not dig below the lowest neighbour
+                    -(solubility * (capacity - carried)).min(max_drop * half)
+                } else {
+                    p.deposition * (carried - capacity)
+                };
+                let h = self.terrain.get(cx, cy) + change;
+                self.terrain.set(cx, cy, h);
+                let carried = carried - change;
+                
+                if drops.is_empty() {
+                    sediment.set(cx, cy, sediment.get(cx, cy) - change);
+                    continue;
+                }
+                let moved = carried * out / w;
+                water.set(cx, cy, water.get(cx, cy) - out);
+                sediment.set(cx, cy, sediment.get(cx, cy) - change - moved);
+                let mut v = (F::zero(), F::zero());
+                for &(n, drop) in &drops {
+                    let frac = drop / total;
+                    water.set(n.0, n.1, water.get(n.0, n.1) + out * frac);
+                    sediment.set(n.0, n.1, sediment.get(n.0, n.1) + moved * frac);
+                    let offset = (convert::<_, F>(n.0 as f64 - cx as f64), convert::<_, F>(n.1 as f64 - cy as f64));
+                    v = (v.0 + offset.0 * cell.0 * frac, v.1 + offset.1 * cell.1 * frac);
+                }
+                // the fraction out / w of the water moves
+                velocity.set(cx, cy, (v.0 * out / w, v.1 * out / w));
+            }
+        }
+        
+        let keep = F::one() - p.evaporation;
+        for w in water.data_mut() {
+            *w *= keep;
+        }
+        self.water = water;
+        self.sediment = sediment;
+        self.velocity = velocity;
+        self.iterations += 1;
+    }
+    
+    /// Serialise the session (parameters and all state)
+    /// 
+    /// Values are stored as little-endian `f64`.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let dim = self.terrain.dim();
+        let mut buf = Vec::with_capacity(64 + 24 * self.terrain.data().len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&dim.0.to_le_bytes());
+        buf.extend_from_slice(&dim.1.to_le_bytes());
+        buf.extend_from_slice(&self.iterations.to_le_bytes());
+        let p = &self.params;
+        let flag = if self.erodibility.is_some() { F::one() } else { F::zero() };
+        let header = [self.size.0, self.size.1, p.rain, p.capacity, p.solubility, p.deposition, p.evaporation, flag];
+        let grids = [&self.terrain, &self.water, &self.sediment];
+        let grids = grids.iter().copied().chain(self.erodibility.as_ref());
+        for x in header.iter().chain(grids.flat_map(|g| g.data())) {
+            buf.extend_from_slice(&try_convert::<F, f64>(*x).unwrap().to_le_bytes());
+        }
+        writer.write_all(&buf)?;
+        writer.flush()
+    }
+    
+    /// Deserialise a session written by [`ErosionSession::write`]
+    /// 
+    /// Sessions written by earlier versions (without erodibility) are
+    /// supported.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        let v1 = &magic == MAGIC_V1;
+        if &magic != MAGIC && !v1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an erosion session"));
+        }
+        let mut b4 = [0; 4];
+        let mut b8 = [0; 8];
+        reader.read_exact(&mut b4)?;
+        let w = u32::from_le_bytes(b4);
+        reader.read_exact(&mut b4)?;
+        let h = u32::from_le_bytes(b4);
+        let len = (w as usize).checked_mul(h as usize);
+        let len = match len {
+            Some(len) if w >= 2 && h >= 2 => len,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid erosion session dimension")),
+        };
+        reader.read_exact(&mut b8)?;
+        let iterations = u64::from_le_bytes(b8);
+        let mut next = || -> io::Result<F> {
+            reader.read_exact(&mut b8)?;
+            Ok(convert(f64::from_le_bytes(b8)))
+        };
+        let size = (next()?, next()?);
+        let params = ErosionParams {
+            rain: next()?,
+            capacity: next()?,
+            solubility: next()?,
+            deposition: next()?,
+            evaporation: next()?,
+        };
+        let has_erodibility = !v1 && next()? != F::zero();
+        let mut grid = || -> io::Result<Grid<F>> {
+            // the dimension is untrusted: grow as values are read
+            let mut data = Vec::with_capacity(len.min(1 << 16));
+            for _ in 0..len {
+                data.push(next()?);
+            }
+            let mut data = data.into_iter();
+            Ok(Grid::from_fn((w, h), |_, _| data.next().unwrap()))
+        };
+        let terrain = grid()?;
+        let water = grid()?;
+        let sediment = grid()?;
+        let erodibility = if has_erodibility { Some(grid()?) } else { None };
+        let velocity = Grid::new((w, h), (F::zero(), F::zero()));
+        Ok(ErosionSession { params, size, terrain, water, sediment, erodibility, velocity, iterations })
+    }
+}
+
+/// A fixed number of erosion iterations as an [`IncrementalTask`]
+/// 
+/// Each unit of work is one iteration.
+#[derive(Debug, Clone)]
+pub struct ErosionTask<F: RealField> {
+    session: ErosionSession<F>,
+    start: u64,
+    total: u64,
+}
+
+impl<F: RealField> ErosionTask<F> {
+    /// Run `iterations` more iterations of `session`
+    pub fn new(session: ErosionSession<F>, iterations: u64) -> Self {
+        let start = session.iterations();
+        ErosionTask { session, start, total: iterations }
+    }
+    
+    /// Access the session
+    pub fn session(&self) -> &ErosionSession<F> {
+        &self.session
+    }
+    
+    /// Stop, returning the session (which may be resumed later)
+    pub fn into_session(self) -> ErosionSession<F> {
+        self.session
+    }
+}
+
+impl<F: RealField> IncrementalTask for ErosionTask<F> {
+    fn run_for(&mut self, budget: Duration) -> Progress {
+        let remaining = self.total - self.progress().done;
+        if remaining > 0 {
+            self.session.run_for(remaining, budget);
+        }
+        self.progress()
+    }
+    
+    fn progress(&self) -> Progress {
+        Progress { done: self.session.iterations() - self.start, total: self.total }
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{geometry::{arc_lengths, project}, Heightmap};
+use crate::grid::Grid;
+
+/// Estuary carving parameters
+/// 
+/// An estuary is the tidal lower reach of a river, given as a polyline
+/// (world coordinates) from upstream to the river mouth at its last point.
+/// Within `tidal_reach` of the mouth (measured along the river) the channel
+/// widens exponentially from `river_width` to `mouth_width` at the mouth, as
+/// in natural funnel-shaped estuaries. The channel bed is dredged from
+/// `channel_depth` below low water at the mouth, rising to low water at the
+/// limit of the tidal reach.
+/// 
+/// On either side of the channel lie tidal flats, of width proportional to
+/// the channel width (`flat_width` at the mouth). Their surface rises from low
+/// water at the channel edge to high water at their outer edge; terrain within
+/// is lowered or raised to match (as mud deposits fill the drowned valley),
+/// except where it lies below low water.
+#[derive(Debug, Clone)]
+pub struct Estuary<F> {
+    /// River course (world coordinates), ending at the mouth
+    pub river: Vec<(F, F)>,
+    /// Mean sea level
+    pub sea_level: F,
+    /// Difference between high and low water
+    pub tidal_range: F,
+    /// Channel width at the upstream limit of the tidal reach
+    pub river_width: F,
+    /// Channel width at the mouth
+    pub mouth_width: F,
+    /// Length of the estuary, along the river from the mouth
+    pub tidal_reach: F,
+    /// Depth of the channel bed below low water at the mouth
+    pub channel_depth: F,
+    /// Width of the tidal flats on each side of the channel at the mouth
+    pub flat_width: F,
+}
+
+/// The result of carving an estuary
+#[derive(Debug, Clone)]
+pub struct EstuaryLayout {
+    /// Vertices in the tidal channel
+    pub channel: Grid<bool>,
+    /// Vertices of tidal (mud) flats, between low and high water
+    pub mudflat: Grid<bool>,
+}
+
+impl<F: RealField> Estuary<F> {
+    /// Height of low water
+    pub fn low_water(&self) -> F {
+        self.sea_level - self.tidal_range * convert(0.5)
+    }
+    
+    /// Height of high water
+    pub fn high_water(&self) -> F {
+        self.sea_level + self.tidal_range * convert(0.5)
+    }
+    
+    /// Channel width at distance `s` upstream from the mouth
+    pub fn width_at(&self, s: F) -> F {
+        if self.mouth_width <= self.river_width {
+            return self.mouth_width;
+        }
+        // convergence length such that the width reaches river_width at the
+        // tidal limit
+        let length = self.tidal_reach / (self.mouth_width / self.river_width).ln();
+        (self.mouth_width * (-s / length).exp()).max(self.river_width)
+    }
+    
+    /// Carve the estuary into `m`
+    pub fn apply_to(&self, m: &mut Heightmap<F>) -> EstuaryLayout {
+        trace_span!("estuary", dim = ?m.dim());
+        assert!(self.river.len() >= 2);
+        let dim = m.dim();
+        let mut channel = Grid::new(dim, false);
+        let mut mudflat = Grid::new(dim, false);
+        let lengths = arc_lengths(&self.river);
+        let total = *lengths.last().unwrap();
+        let (low, high) = (self.low_water(), self.high_water());
+        let half: F = convert(0.5);
+        
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let p = m.coord_of(cx, cy);
+                // nearest point on the river: (distance², distance from mouth)
+                let mut nearest = (F::max_value(), F::zero());
+                for (i, w) in self.river.windows(2).enumerate() {
+                    let (t, d2) = project(w[0], w[1], p);
+                    if d2 < nearest.0 {
+                        let s = lengths[i] + (lengths[i + 1] - lengths[i]) * t;
+                        nearest = (d2, total - s);
+                    }
+                }
+                let (d, s) = (nearest.0.sqrt(), nearest.1);
+                if s > self.tidal_reach {
+                    continue;
+                }
+                let width = self.width_at(s);
+                let flat = self.flat_width * width / self.mouth_width;
+                let h = m.get(cx, cy);
+                if d <= width * half {
+                    let bed = low - self.channel_depth * (F::one() - s / self.tidal_reach);
+                    channel.set(cx, cy, true);
+                    if h > bed {
+                        m.set(cx, cy, bed);
+                    }
+                } else if d <= width * half + flat {
+                    // open water beyond the flats is left as is
+                    if h >= low {
+                        let t = (d - width * half) / flat;
+                        m.set(cx, cy, low + (high - low) * t);
+                        mudflat.set(cx, cy, true);
+                    }
+                }
+            }
+        }
+        m.range = super::range(&m.data);
+        EstuaryLayout { channel, mudflat }
+    }
+}
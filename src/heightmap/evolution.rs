@@ -0,0 +1,237 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use nalgebra::{convert, RealField};
+use super::{creep::diffuse, drainage::{accumulation, downstream}, range, search::Entry, CreepParams, Heightmap};
+use crate::grid::Grid;
+
+/// Parameters of landscape evolution
+/// 
+/// Units must be consistent: e.g. with lengths in metres and time in years,
+/// `erodibility` has units of `m^(1 - 2 area_exponent) / yr` and
+/// `diffusivity` of `m² / yr`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvolutionParams<F> {
+    /// Duration of each step
+    pub time_step: F,
+    /// Stream power erodibility `K`
+    pub erodibility: F,
+    /// Exponent of catchment area in stream power; typically around 0.5
+    pub area_exponent: F,
+    /// Hillslope diffusivity; zero disables hillslope processes
+    pub diffusivity: F,
+    /// Critical slope for non-linear hillslope diffusion (see
+    /// [`CreepParams`]); `None` for linear
+    pub critical_slope: Option<F>,
+}
+
+/// A long-term landscape evolution model
+/// 
+/// Each step of duration `dt`:
+/// 
+/// 1.  Vertices are raised by `uplift × dt`, given an uplift rate per vertex.
+/// 2.  Rivers incise by stream power, `dh/dt = -K A^m S` for catchment area
+///     `A` and slope `S` towards the downstream vertex. This is solved
+///     implicitly (Braun & Willett, 2013), thus is stable for long steps.
+/// 3.  Hillslopes relax by soil creep, as [`Heightmap::creep`].
+/// 
+/// Edge vertices are outlets held at their initial height (base level); all
+/// other vertices drain to them, flow being routed across depressions. Under
+/// constant uplift the landscape tends to a steady state where erosion
+/// balances uplift everywhere: branching valley networks separated by sharp
+/// ridges, with relief increasing with `U / K`. Use
+/// [`LandscapeEvolution::run_until_steady`] to run until then.
+/// 
+/// `K` may vary over the map via an erodibility grid (see
+/// [`LandscapeEvolution::set_erodibility`]); uplift may be changed between
+/// steps, e.g. to model a pulse of tectonic activity.
+#[derive(Debug, Clone)]
+pub struct LandscapeEvolution<F: RealField> {
+    params: EvolutionParams<F>,
+    terrain: Heightmap<F>,
+    uplift: Grid<F>,
+    erodibility: Option<Grid<F>>,
+    time: F,
+    steps: u64,
+}
+
+impl<F: RealField> LandscapeEvolution<F> {
+    /// Start evolving `m`, given an uplift rate per vertex
+    pub fn new(m: Heightmap<F>, uplift: Grid<F>, params: EvolutionParams<F>) -> Self {
+        assert_eq!(uplift.dim(), m.dim);
+        LandscapeEvolution {
+            params,
+            terrain: m,
+            uplift,
+            erodibility: None,
+            time: F::zero(),
+            steps: 0,
+        }
+    }
+    
+    /// Get the parameters
+    pub fn params(&self) -> &EvolutionParams<F> {
+        &self.params
+    }
+    
+    /// Adjust parameters (taking effect from the next step)
+    pub fn set_params(&mut self, params: EvolutionParams<F>) {
+        self.params = params;
+    }
+    
+    /// Get the uplift rate per vertex
+    pub fn uplift(&self) -> &Grid<F> {
+        &self.uplift
+    }
+    
+    /// Set the uplift rate per vertex (taking effect from the next step)
+    pub fn set_uplift(&mut self, uplift: Grid<F>) {
+        assert_eq!(uplift.dim(), self.terrain.dim);
+        self.uplift = uplift;
+    }
+    
+    /// Get the erodibility grid, if any
+    pub fn erodibility(&self) -> Option<&Grid<F>> {
+        self.erodibility.as_ref()
+    }
+    
+    /// Set the erodibility of each vertex (taking effect from the next step)
+    /// 
+    /// Values are non-negative factors on [`EvolutionParams::erodibility`].
+    /// `None` is equivalent to erodibility one everywhere.
+    pub fn set_erodibility(&mut self, erodibility: Option<Grid<F>>) {
+        if let Some(grid) = erodibility.as_ref() {
+            assert_eq!(grid.dim(), self.terrain.dim);
+        }
+        self.erodibility = erodibility;
+    }
+    
+    /// Get the current terrain
+    pub fn terrain(&self) -> &Heightmap<F> {
+        &self.terrain
+    }
+    
+    /// Consume, yielding the terrain
+    pub fn into_heightmap(self) -> Heightmap<F> {
+        self.terrain
+    }
+    
+    /// Model time elapsed
+    pub fn time(&self) -> F {
+        self.time
+    }
+    
+    /// Number of steps run so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    
+    /// Run `n` steps
+    pub fn run(&mut self, n: u64) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+    
+    /// Run until steady state
+    /// 
+    /// Steps are run until the largest rate of height change is at most
+    /// `tolerance`, or `max_steps` have been run. Returns the number of steps
+    /// run.
+    pub fn run_until_steady(&mut self, tolerance: F, max_steps: u64) -> u64 {
+        for n in 0..max_steps {
+            if self.step() <= tolerance {
+                return n + 1;
+            }
+        }
+        max_steps
+    }
+    
+    /// Run one step
+    /// 
+    /// Returns the largest absolute rate of height change over the step.
+    pub fn step(&mut self) -> F {
+        let m = &mut self.terrain;
+        trace_span!("evolution_step", dim = ?m.dim);
+        let p = self.params;
+        let dt = p.time_step;
+        let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+        let interior = |i: usize| (1..w - 1).contains(&(i % w)) && (1..h - 1).contains(&(i / w));
+        let before = m.data.clone();
+        
+        for (i, (v, u)) in m.data.iter_mut().zip(self.uplift.data()).enumerate() {
+            if interior(i) {
+                *v += *u * dt;
+            }
+        }
+        
+        // implicit stream power, downstream first
+        let filled = fill_depressions(m);
+        let area = accumulation(&filled, false);
+        let next = downstream(&filled, false);
+        let mut order: Vec<usize> = (0..m.data.len()).filter(|i| interior(*i)).collect();
+        order.sort_by(|a, b| filled.data[*a].partial_cmp(&filled.data[*b]).unwrap_or(Ordering::Equal));
+        for i in order {
+            let c = ((i % w) as u32, (i / w) as u32);
+            let n = match next.get(c.0, c.1) {
+                Some(n) => n,
+                None => continue,
+            };
+            let k = match self.erodibility.as_ref() {
+                Some(e) => p.erodibility * e.get(c.0, c.1),
+                None => p.erodibility,
+            };
+            let f = k * dt * area.get(c.0, c.1).powf(p.area_exponent) / m.distance(c, n);
+            let (hi, hr) = (m.data[i], m.data[n.1 as usize * w + n.0 as usize]);
+            // no incision within depressions (the receiver may be higher)
+            if hi > hr {
+                m.data[i] = (hi + f * hr) / (F::one() + f);
+            }
+        }
+        
+        if p.diffusivity > F::zero() {
+            let creep = CreepParams { diffusivity: p.diffusivity, critical_slope: p.critical_slope };
+            diffuse(m, &creep, dt, true);
+        }
+        
+        m.range = range(&m.data);
+        self.time += dt;
+        self.steps += 1;
+        m.data.iter().zip(&before).fold(F::zero(), |a, (h1, h0)| a.max((*h1 - *h0).abs())) / dt
+    }
+}
+
+// Raise depressions to their spill height (priority flood from the edges),
+// plus a small gradient so that every vertex drains to an edge
+fn fill_depressions<F: RealField>(m: &Heightmap<F>) -> Heightmap<F> {
+    let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+    let mut filled = m.clone();
+    let mut done = vec![false; w * h];
+    let mut heap = BinaryHeap::new();
+    for (i, d) in done.iter_mut().enumerate() {
+        if i % w == 0 || i % w + 1 == w || i / w == 0 || i / w + 1 == h {
+            *d = true;
+            heap.push(Entry { cost: m.data[i], index: i });
+        }
+    }
+    let lift: F = convert(1e-6);
+    while let Some(Entry { cost, index }) = heap.pop() {
+        for n in m.neighbours((index % w) as u32, (index / w) as u32) {
+            let j = n.1 as usize * w + n.0 as usize;
+            if !done[j] {
+                done[j] = true;
+                let v = filled.data[j].max(cost + (cost.abs() + F::one()) * lift);
+                filled.data[j] = v;
+                heap.push(Entry { cost: v, index: j });
+            }
+        }
+    }
+    filled
+}
@@ -0,0 +1,159 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use nalgebra::{convert, try_convert, RealField};
+use rand::Rng;
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Pattern used to partition land into fields
+#[derive(Debug, Clone, Copy)]
+pub enum FieldPattern<F> {
+    /// Irregular fields: a Voronoi partition over `count` random sites
+    Voronoi {
+        count: usize,
+    },
+    /// Strip fields of the given `width` and `length`, with the long side
+    /// along `angle` (radians, counter-clockwise from the x-axis)
+    Strips {
+        width: F,
+        length: F,
+        angle: F,
+    },
+}
+
+/// Farmland generator
+/// 
+/// Cultivable land is partitioned into fields. On gentle slopes (up to
+/// `max_slope`) each field is flattened towards its mean height; on steeper
+/// land (up to `terrace_slope`) fields are cut into terraced steps.
+/// Hedgerows are raised along field boundaries.
+#[derive(Debug, Clone, Copy)]
+pub struct Farmland<F> {
+    /// Partition pattern
+    pub pattern: FieldPattern<F>,
+    /// Maximum slope of flat (non-terraced) fields
+    pub max_slope: F,
+    /// Maximum slope of terraced fields; steeper land is not cultivated
+    pub terrace_slope: F,
+    /// Height of each terrace step
+    pub terrace_height: F,
+    /// Fraction (in `[0, 1]`) by which flat fields are pulled towards their
+    /// mean height
+    pub flatten: F,
+    /// Height added to hedgerows
+    pub hedge_height: F,
+}
+
+/// Layout of generated fields
+#[derive(Debug, Clone)]
+pub struct FieldLayout {
+    /// Field index of each vertex, or `None` if not cultivated
+    pub fields: Grid<Option<u32>>,
+    /// Whether a hedgerow was placed on each vertex
+    pub hedgerows: Grid<bool>,
+    /// Whether each vertex is on a terraced field
+    pub terraced: Grid<bool>,
+    /// Number of field indices (not all may be used)
+    pub num_fields: u32,
+}
+
+impl<F: RealField> Farmland<F> {
+    /// Generate fields on `m`, modifying its heights
+    pub fn apply_to<R: Rng + ?Sized>(&self, m: &mut Heightmap<F>, rng: &mut R) -> FieldLayout {
+        let dim = m.dim();
+        let slope = Grid::from_fn(dim, |cx, cy| m.slope_at(cx, cy));
+        
+        // Partition
+        let (mut fields, num_fields) = match self.pattern {
+            FieldPattern::Voronoi { count } => {
+                let size = m.size();
+                let sites: Vec<(F, F)> = (0..count).map(|_| {
+                    let x: F = convert(rng.gen::<f64>());
+                    let y: F = convert(rng.gen::<f64>());
+                    (x * size.0, y * size.1)
+                }).collect();
+                let fields = Grid::from_fn(dim, |cx, cy| {
+                    let c = m.coord_of(cx, cy);
+                    let mut best = None;
+                    let mut best_d = F::max_value();
+                    for (i, s) in sites.iter().enumerate() {
+                        let d = (s.0 - c.0).powi(2) + (s.1 - c.1).powi(2);
+                        if d < best_d {
+                            best_d = d;
+                            best = Some(i as u32);
+                        }
+                    }
+                    best
+                });
+                (fields, count as u32)
+            }
+            FieldPattern::Strips { width, length, angle } => {
+                let (sin, cos) = angle.sin_cos();
+                let to_i64 = |x: F| try_convert::<_, f64>(x.floor()).unwrap() as i64;
+                let mut ids = HashMap::new();
+                let fields = Grid::from_fn(dim, |cx, cy| {
+                    let c = m.coord_of(cx, cy);
+                    let u = to_i64((c.0 * cos + c.1 * sin) / length);
+                    let v = to_i64((c.1 * cos - c.0 * sin) / width);
+                    let next = ids.len() as u32;
+                    Some(*ids.entry((u, v)).or_insert(next))
+                });
+                (fields, ids.len() as u32)
+            }
+        };
+        for (f, s) in fields.data_mut().iter_mut().zip(slope.data().iter()) {
+            if *s > self.terrace_slope {
+                *f = None;
+            }
+        }
+        let terraced = Grid::from_fn(dim, |cx, cy| {
+            fields.get(cx, cy).is_some() && slope.get(cx, cy) > self.max_slope
+        });
+        
+        // Hedgerows lie on cultivated vertices adjacent to another field
+        let hedgerows = Grid::from_fn(dim, |cx, cy| {
+            let f = fields.get(cx, cy);
+            f.is_some() && m.neighbours(cx, cy).any(|n| fields.get(n.0, n.1) != f)
+        });
+        
+        // Flatten and terrace fields
+        let mut sum = vec![(F::zero(), 0usize); num_fields as usize];
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                if let Some(f) = fields.get(cx, cy) {
+                    let s = &mut sum[f as usize];
+                    s.0 += m.get(cx, cy);
+                    s.1 += 1;
+                }
+            }
+        }
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let f = match fields.get(cx, cy) {
+                    Some(f) => f as usize,
+                    None => continue,
+                };
+                let mut h = m.get(cx, cy);
+                if terraced.get(cx, cy) {
+                    h = (h / self.terrace_height).floor() * self.terrace_height;
+                } else {
+                    let mean = sum[f].0 / convert(sum[f].1 as f64);
+                    h += (mean - h) * self.flatten;
+                }
+                if hedgerows.get(cx, cy) {
+                    h += self.hedge_height;
+                }
+                m.set(cx, cy, h);
+            }
+        }
+        
+        FieldLayout { fields, hedgerows, terraced, num_fields }
+    }
+}
@@ -65,6 +65,7 @@ pub fn fault_displacement<F, R: Rng, D: Fn(F) -> F>(
         displacement: D)
 where F: RealField + SampleUniform
 {
+    trace_span!("fault_displacement", dim = ?m.dim());
     let half: F = convert(0.5);
     let dim = m.dim();
     let size = m.size();
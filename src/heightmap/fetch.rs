@@ -0,0 +1,140 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Open-water fetch of shoreline cells over a set of wind directions
+/// 
+/// Fetch is the distance of open water over which wind blows before reaching
+/// a point; it governs wave size and thus coastal erosion, beach formation
+/// and the shelter offered by a harbour. Shoreline cells are water vertices
+/// (height at most the water level) with a land vertex among their four
+/// neighbours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchMap<F> {
+    directions: Vec<F>,
+    max_fetch: F,
+    // shoreline cells in row-major order
+    cells: Vec<(u32, u32)>,
+    // fetch per cell, then per direction
+    fetch: Vec<F>,
+}
+
+impl<F: RealField> FetchMap<F> {
+    /// Measure fetch around the coasts of `m`
+    /// 
+    /// Each direction is an angle (radians, anticlockwise from the x axis)
+    /// from which the wind blows; fetch is measured from the cell along this
+    /// direction to the first land. Water reaching the edge of the map is
+    /// assumed to extend to `max_fetch`.
+    /// 
+    /// With the `rayon` feature, cells are measured in parallel.
+    pub fn new(m: &Heightmap<F>, water_level: F, max_fetch: F, directions: &[F]) -> Self {
+        trace_span!("fetch", dim = ?m.dim, directions = directions.len());
+        let (w, h) = m.dim;
+        let water = |x: u32, y: u32| m.get(x, y) <= water_level;
+        let mut cells = vec![];
+        for y in 0..h {
+            for x in 0..w {
+                if water(x, y) && m.neighbours(x, y).any(|(nx, ny)| (nx == x || ny == y) && !water(nx, ny)) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        let dirs: Vec<_> = directions.iter().map(|a| (a.cos(), a.sin())).collect();
+        let measure = |&(x, y): &(u32, u32)| {
+            let p = m.coord_of(x, y);
+            dirs.iter().map(|&d| fetch_ray(m, p, d, water_level, max_fetch)).collect::<Vec<_>>()
+        };
+        #[cfg(feature = "rayon")]
+        let fetch: Vec<Vec<F>> = {
+            use rayon::prelude::*;
+            cells.par_iter().map(measure).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let fetch: Vec<Vec<F>> = cells.iter().map(measure).collect();
+        FetchMap {
+            directions: directions.to_vec(),
+            max_fetch,
+            cells,
+            fetch: fetch.concat(),
+        }
+    }
+    
+    /// The wind directions (angles)
+    pub fn directions(&self) -> &[F] {
+        &self.directions
+    }
+    
+    /// The shoreline cells, in row-major order
+    pub fn cells(&self) -> &[(u32, u32)] {
+        &self.cells
+    }
+    
+    /// Fetch of a cell for each direction, if it is a shoreline cell
+    pub fn fetch(&self, cell: (u32, u32)) -> Option<&[F]> {
+        let n = self.directions.len();
+        let i = self.cells.binary_search_by_key(&(cell.1, cell.0), |c| (c.1, c.0)).ok()?;
+        Some(&self.fetch[i * n..(i + 1) * n])
+    }
+    
+    /// Exposure of a cell, if it is a shoreline cell
+    /// 
+    /// This is the mean fetch, weighted by `weights` (for example the
+    /// frequency or strength of wind from each direction), as a fraction of
+    /// the maximum fetch, thus in the range `[0, 1]`. If `weights` is empty,
+    /// all directions are weighted equally.
+    pub fn exposure(&self, cell: (u32, u32), weights: &[F]) -> Option<F> {
+        assert!(weights.is_empty() || weights.len() == self.directions.len());
+        let fetch = self.fetch(cell)?;
+        let (mut sum, mut total) = (F::zero(), F::zero());
+        for (i, &f) in fetch.iter().enumerate() {
+            let w = weights.get(i).cloned().unwrap_or(F::one());
+            sum += f * w;
+            total += w;
+        }
+        if total <= F::zero() || self.max_fetch <= F::zero() {
+            return Some(F::zero());
+        }
+        Some(sum / (total * self.max_fetch))
+    }
+    
+    /// Exposure of all shoreline cells as a grid (zero elsewhere)
+    /// 
+    /// See [`FetchMap::exposure`].
+    pub fn exposure_grid(&self, dim: (u32, u32), weights: &[F]) -> Grid<F> {
+        let mut grid = Grid::new(dim, F::zero());
+        for &c in &self.cells {
+            grid.set(c.0, c.1, self.exposure(c, weights).unwrap());
+        }
+        grid
+    }
+}
+
+// Distance from `p` along unit direction `dir` to the first land, marching
+// in steps of half a cell
+pub(super) fn fetch_ray<F: RealField>(m: &Heightmap<F>, p: (F, F), dir: (F, F), water_level: F, max_fetch: F) -> F {
+    let step = m.len_frac.0.min(m.len_frac.1) * convert(0.5);
+    let size = m.size;
+    let mut d = step;
+    loop {
+        if d >= max_fetch {
+            return max_fetch;
+        }
+        let (x, y) = (p.0 + dir.0 * d, p.1 + dir.1 * d);
+        if x < F::zero() || y < F::zero() || x > size.0 || y > size.1 {
+            return max_fetch;
+        }
+        if m.interpolate(x, y) > water_level {
+            return d;
+        }
+        d += step;
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Smoothing filters
+//! 
+//! These operate in place, for example to remove high-frequency spikes left
+//! by [`fault_displacement`](super::fault_displacement) or
+//! [`diamond_square`](super::diamond_square), or single-vertex spikes in
+//! imported elevation data ([`median_filter`], [`despike`]). Radii are in
+//! world units; edges are extended (heights beyond the map equal the nearest
+//! edge height) or, for the median filter, windows are truncated.
+
+use nalgebra::{convert, try_convert, RealField};
+use super::{range, Heightmap};
+
+/// Gaussian blur with standard deviation `sigma`
+/// 
+/// The kernel is separable and truncated at `3 sigma`.
+pub fn gaussian_blur<F: RealField>(m: &mut Heightmap<F>, sigma: F) {
+    trace_span!("gaussian_blur", dim = ?m.dim);
+    assert!(sigma >= F::zero());
+    let kernel = |step: F| {
+        let s = try_convert::<F, f64>(sigma / step).unwrap();
+        let r = (3.0 * s).ceil() as usize;
+        let mut k: Vec<F> = (0..=r).map(|i| {
+            if s > 0.0 { convert((-((i * i) as f64) / (2.0 * s * s)).exp()) } else { F::one() }
+        }).collect();
+        let sum = k.iter().skip(1).fold(k[0], |a, &w| a + w + w);
+        for w in &mut k {
+            *w /= sum;
+        }
+        k
+    };
+    let (kx, ky) = (kernel(m.len_frac.0), kernel(m.len_frac.1));
+    convolve(m, &kx, &ky);
+}
+
+/// Box blur: the mean over a square of half-width `radius`
+/// 
+/// Repeated box blurs approximate a Gaussian blur; three passes are usually
+/// indistinguishable.
+pub fn box_blur<F: RealField>(m: &mut Heightmap<F>, radius: F, passes: u32) {
+    trace_span!("box_blur", dim = ?m.dim, passes);
+    assert!(radius >= F::zero());
+    let kernel = |step: F| {
+        let r = try_convert::<F, f64>(radius / step).unwrap().round() as usize;
+        vec![F::one() / convert((2 * r + 1) as f64); r + 1]
+    };
+    let (kx, ky) = (kernel(m.len_frac.0), kernel(m.len_frac.1));
+    for _ in 0..passes {
+        convolve(m, &kx, &ky);
+    }
+}
+
+/// Laplacian smoothing
+/// 
+/// Each iteration moves every vertex a fraction `strength` (in `(0, 1]`) of
+/// the way towards the mean of its (up to 4) axis-aligned neighbours. This
+/// removes spikes and pits quickly while only slowly flattening larger
+/// features.
+pub fn laplacian_smooth<F: RealField>(m: &mut Heightmap<F>, strength: F, iterations: u32) {
+    trace_span!("laplacian_smooth", dim = ?m.dim, iterations);
+    let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+    let mut next = m.data.clone();
+    for _ in 0..iterations {
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * w + x;
+                let mut sum = F::zero();
+                let mut n = 0;
+                for &(ok, j) in &[(x > 0, i.wrapping_sub(1)), (x + 1 < w, i + 1),
+                    (y > 0, i.wrapping_sub(w)), (y + 1 < h, i + w)]
+                {
+                    if ok {
+                        sum += m.data[j];
+                        n += 1;
+                    }
+                }
+                let mean = sum / convert(n as f64);
+                next[i] = m.data[i] + (mean - m.data[i]) * strength;
+            }
+        }
+        std::mem::swap(&mut m.data, &mut next);
+    }
+    m.range = range(&m.data);
+}
+
+/// Median filter over a square of half-width `radius`
+/// 
+/// Unlike blurs, this removes isolated spikes and pits without spreading
+/// them, while preserving sharp edges such as cliffs.
+pub fn median_filter<F: RealField>(m: &mut Heightmap<F>, radius: F) {
+    trace_span!("median_filter", dim = ?m.dim);
+    assert!(radius >= F::zero());
+    let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+    let rx = try_convert::<F, f64>(radius / m.len_frac.0).unwrap().round() as usize;
+    let ry = try_convert::<F, f64>(radius / m.len_frac.1).unwrap().round() as usize;
+    let src = m.data.clone();
+    let mut window = Vec::with_capacity((2 * rx + 1) * (2 * ry + 1));
+    for y in 0..h {
+        for x in 0..w {
+            window.clear();
+            for v in y.saturating_sub(ry)..=(y + ry).min(h - 1) {
+                window.extend_from_slice(&src[v * w + x.saturating_sub(rx)..=v * w + (x + rx).min(w - 1)]);
+            }
+            let mid = window.len() / 2;
+            let (_, median, _) = window.select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap());
+            m.data[y * w + x] = *median;
+        }
+    }
+    m.range = range(&m.data);
+}
+
+/// Remove spikes
+/// 
+/// Each vertex deviating from the mean of its (up to 8) neighbours by more
+/// than `k` times their standard deviation is clamped to that bound. All
+/// vertices are tested against the unmodified map. Returns the number of
+/// vertices clamped.
+pub fn despike<F: RealField>(m: &mut Heightmap<F>, k: F) -> usize {
+    trace_span!("despike", dim = ?m.dim);
+    let src = m.clone();
+    let mut count = 0;
+    for cy in 0..m.dim.1 {
+        for cx in 0..m.dim.0 {
+            let (mut sum, mut sum2, mut n) = (F::zero(), F::zero(), 0);
+            for c in src.neighbours(cx, cy) {
+                let v = src.get(c.0, c.1);
+                sum += v;
+                sum2 += v * v;
+                n += 1;
+            }
+            let nf: F = convert(n as f64);
+            let mean = sum / nf;
+            let sigma = (sum2 / nf - mean * mean).max(F::zero()).sqrt();
+            let h = src.get(cx, cy);
+            let bound = k * sigma;
+            if (h - mean).abs() > bound {
+                let v = if h > mean { mean + bound } else { mean - bound };
+                m.data[(cy * m.dim.0 + cx) as usize] = v;
+                count += 1;
+            }
+        }
+    }
+    m.range = range(&m.data);
+    count
+}
+
+// Convolve with symmetric kernels (given as weights at offsets 0..=r) along
+// each axis
+fn convolve<F: RealField>(m: &mut Heightmap<F>, kx: &[F], ky: &[F]) {
+    let (w, h) = (m.dim.0 as usize, m.dim.1 as usize);
+    let mut tmp = vec![F::zero(); w * h];
+    let clamp = |i: isize, n: usize| i.max(0).min(n as isize - 1) as usize;
+    for y in 0..h {
+        let row = &m.data[y * w..(y + 1) * w];
+        for x in 0..w {
+            let mut sum = row[x] * kx[0];
+            for (d, &k) in kx.iter().enumerate().skip(1) {
+                let d = d as isize;
+                sum += (row[clamp(x as isize - d, w)] + row[clamp(x as isize + d, w)]) * k;
+            }
+            tmp[y * w + x] = sum;
+        }
+    }
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = tmp[y * w + x] * ky[0];
+            for (d, &k) in ky.iter().enumerate().skip(1) {
+                let d = d as isize;
+                sum += (tmp[clamp(y as isize - d, h) * w + x] + tmp[clamp(y as isize + d, h) * w + x]) * k;
+            }
+            m.data[y * w + x] = sum;
+        }
+    }
+    m.range = range(&m.data);
+}
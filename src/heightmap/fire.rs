@@ -0,0 +1,177 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use rand::Rng;
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Burn state of a vertex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BurnState {
+    /// Not (yet) burnt
+    Unburnt,
+    /// Burning, with the given number of steps remaining
+    Burning(u32),
+    /// Burnt out
+    Burnt,
+}
+
+/// Parameters of a wildfire spread simulation
+#[derive(Debug, Clone, Copy)]
+pub struct FireParams<F> {
+    /// Probability that fire spreads to a neighbouring vertex with vegetation
+    /// density `1` on flat ground without wind, per step
+    pub spread: F,
+    /// Slope sensitivity: spread probability is multiplied by
+    /// `exp(slope_factor * s)` where `s` is the slope towards the neighbour
+    /// (positive uphill)
+    pub slope_factor: F,
+    /// Wind sensitivity: spread probability is multiplied by
+    /// `exp(wind_factor * w)` where `w` is the wind component towards the
+    /// neighbour
+    pub wind_factor: F,
+    /// Number of steps a vertex burns before burning out
+    pub burn_time: u32,
+}
+
+/// A stochastic cellular wildfire spread simulation
+/// 
+/// Each step, every burning vertex may ignite each of its unburnt neighbours
+/// with probability depending on the neighbour's vegetation density, the
+/// slope between the two (fire spreads faster uphill) and the wind.
+/// Vertices with zero vegetation density never burn.
+/// 
+/// Reference: Alexandridis et al. 2008,
+/// [doi:10.1016/j.amc.2008.06.046](https://doi.org/10.1016/j.amc.2008.06.046)
+#[derive(Debug, Clone)]
+pub struct FireSim<F> {
+    params: FireParams<F>,
+    vegetation: Grid<F>,
+    wind: Grid<(F, F)>,
+    state: Grid<BurnState>,
+}
+
+impl<F: RealField> FireSim<F> {
+    /// Construct a new simulation
+    /// 
+    /// The `vegetation` density grid (in the range `[0, 1]`) and the `wind`
+    /// field grid (a vector per vertex, in world units) must have the same
+    /// dimension as the heightmap used in [`FireSim::step`].
+    pub fn new(vegetation: Grid<F>, wind: Grid<(F, F)>, params: FireParams<F>) -> Self {
+        assert_eq!(vegetation.dim(), wind.dim());
+        let state = Grid::new(vegetation.dim(), BurnState::Unburnt);
+        FireSim { params, vegetation, wind, state }
+    }
+    
+    /// Ignite the given vertex
+    /// 
+    /// Has no effect if the vertex has no vegetation or is already burnt.
+    pub fn ignite(&mut self, cx: u32, cy: u32) {
+        if self.state.get(cx, cy) == BurnState::Unburnt
+            && self.vegetation.get(cx, cy) > F::zero()
+        {
+            self.state.set(cx, cy, BurnState::Burning(self.params.burn_time));
+        }
+    }
+    
+    /// Current burn state
+    #[inline]
+    pub fn state(&self) -> &Grid<BurnState> {
+        &self.state
+    }
+    
+    /// Number of vertices currently burning
+    pub fn num_burning(&self) -> usize {
+        self.state.data().iter()
+            .filter(|s| matches!(s, BurnState::Burning(_)))
+            .count()
+    }
+    
+    /// Advance the simulation one step
+    /// 
+    /// Returns the number of vertices burning after the step.
+    pub fn step<R: Rng + ?Sized>(&mut self, m: &Heightmap<F>, rng: &mut R) -> usize {
+        assert_eq!(m.dim(), self.state.dim());
+        let dim = m.dim();
+        let mut next = self.state.clone();
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let left = match self.state.get(cx, cy) {
+                    BurnState::Burning(left) => left,
+                    _ => continue,
+                };
+                next.set(cx, cy, if left > 1 {
+                    BurnState::Burning(left - 1)
+                } else {
+                    BurnState::Burnt
+                });
+                
+                let h = m.get(cx, cy);
+                let (wx, wy) = self.wind.get(cx, cy);
+                for n in m.neighbours(cx, cy) {
+                    if next.get(n.0, n.1) != BurnState::Unburnt {
+                        continue;
+                    }
+                    let density = self.vegetation.get(n.0, n.1);
+                    if density <= F::zero() {
+                        continue;
+                    }
+                    let dist = m.distance((cx, cy), n);
+                    let slope = (m.get(n.0, n.1) - h) / dist;
+                    let (c0, c1) = (m.coord_of(cx, cy), m.coord_of(n.0, n.1));
+                    let w = (wx * (c1.0 - c0.0) + wy * (c1.1 - c0.1)) / dist;
+                    let p = self.params.spread * density
+                        * (self.params.slope_factor * slope).exp()
+                        * (self.params.wind_factor * w).exp();
+                    let p = try_convert::<_, f64>(p).unwrap();
+                    if rng.gen_bool(p.clamp(0.0, 1.0)) {
+                        next.set(n.0, n.1, BurnState::Burning(self.params.burn_time));
+                    }
+                }
+            }
+        }
+        self.state = next;
+        self.num_burning()
+    }
+    
+    /// Run until the fire burns out or `max_steps` steps have passed
+    /// 
+    /// Returns the burn state after each step.
+    pub fn run<R: Rng + ?Sized>(&mut self, m: &Heightmap<F>, rng: &mut R, max_steps: usize)
+        -> Vec<Grid<BurnState>>
+    {
+        let mut frames = Vec::new();
+        for _ in 0..max_steps {
+            let burning = self.step(m, rng);
+            frames.push(self.state.clone());
+            if burning == 0 {
+                break;
+            }
+        }
+        frames
+    }
+    
+    /// Fraction of vegetated vertices which have burnt or are burning
+    pub fn burnt_fraction(&self) -> F {
+        let mut vegetated = 0usize;
+        let mut burnt = 0usize;
+        for (s, v) in self.state.data().iter().zip(self.vegetation.data().iter()) {
+            if *v > F::zero() {
+                vegetated += 1;
+                if *s != BurnState::Unburnt {
+                    burnt += 1;
+                }
+            }
+        }
+        if vegetated == 0 {
+            return F::zero();
+        }
+        convert::<_, F>(burnt as f64) / convert(vegetated as f64)
+    }
+}
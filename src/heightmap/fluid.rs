@@ -0,0 +1,183 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Parameters of a fluid spread simulation
+#[derive(Debug, Clone, Copy)]
+pub struct FluidParams<F> {
+    /// Viscosity in the range `[0, 1)`: the fraction of mobile fluid held back
+    /// each step. Water is near `0`; lava is typically `0.5` to `0.9`.
+    pub viscosity: F,
+    /// Yield depth: fluid thinner than this does not flow. This models the
+    /// yield strength of lava; use `0` for water.
+    pub yield_depth: F,
+    /// Fraction of the fluid depth which solidifies each step. Use `0` for
+    /// water.
+    pub solidification: F,
+}
+
+/// A cellular fluid spread simulation (lava flows, floods)
+/// 
+/// Fluid of depth `d` sits on top of the terrain `h` plus any solidified
+/// material `s`. Each step, fluid deeper than the yield depth flows towards
+/// neighbouring vertices with a lower fluid surface `h + s + d`
+/// (proportional to the difference in surface height), then a
+/// fraction of the remaining fluid solidifies. Fluid does not leave the map.
+/// 
+/// The depth grid ([`FluidSim::depth`]) may be captured after each step for
+/// animation; the solidified material ([`FluidSim::solid`]) may be applied to
+/// the heightmap with [`FluidSim::apply_to`].
+#[derive(Debug, Clone)]
+pub struct FluidSim<F> {
+    params: FluidParams<F>,
+    sources: Vec<((u32, u32), F)>,
+    depth: Grid<F>,
+    solid: Grid<F>,
+}
+
+impl<F: RealField> FluidSim<F> {
+    /// Construct a new simulation, with no fluid, over the heightmap `m`
+    pub fn new(m: &Heightmap<F>, params: FluidParams<F>) -> Self {
+        FluidSim {
+            params,
+            sources: Vec::new(),
+            depth: Grid::new(m.dim(), F::zero()),
+            solid: Grid::new(m.dim(), F::zero()),
+        }
+    }
+    
+    /// Add fluid of depth `amount` at the given vertex immediately
+    pub fn add(&mut self, cx: u32, cy: u32, amount: F) {
+        let d = self.depth.get(cx, cy) + amount;
+        self.depth.set(cx, cy, d);
+    }
+    
+    /// Add a source (vent or spring), emitting `rate` depth of fluid each step
+    pub fn add_source(&mut self, cx: u32, cy: u32, rate: F) {
+        self.sources.push(((cx, cy), rate));
+    }
+    
+    /// Remove all sources
+    pub fn clear_sources(&mut self) {
+        self.sources.clear();
+    }
+    
+    /// Current (mobile) fluid depth
+    #[inline]
+    pub fn depth(&self) -> &Grid<F> {
+        &self.depth
+    }
+    
+    /// Depth of solidified material
+    #[inline]
+    pub fn solid(&self) -> &Grid<F> {
+        &self.solid
+    }
+    
+    /// Total volume (as height) of mobile fluid
+    pub fn volume(&self) -> F {
+        self.depth.data().iter().fold(F::zero(), |s, d| s + *d)
+    }
+    
+    /// Advance the simulation one step
+    pub fn step(&mut self, m: &Heightmap<F>) {
+        assert_eq!(m.dim(), self.depth.dim());
+        let dim = m.dim();
+        for &((cx, cy), rate) in &self.sources {
+            let d = self.depth.get(cx, cy) + rate;
+            self.depth.set(cx, cy, d);
+        }
+        
+        let surface = Grid::from_fn(dim, |cx, cy| {
+            m.get(cx, cy) + self.solid.get(cx, cy) + self.depth.get(cx, cy)
+        });
+        let mobility = F::one() - self.params.viscosity;
+        let half: F = convert(0.5);
+        let mut next = self.depth.clone();
+        let mut drops = Vec::with_capacity(8);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let excess = self.depth.get(cx, cy) - self.params.yield_depth;
+                if excess <= F::zero() {
+                    continue;
+                }
+                let s = surface.get(cx, cy);
+                drops.clear();
+                let mut total = F::zero();
+                let mut max_drop = F::zero();
+                for n in m.neighbours(cx, cy) {
+                    let drop = s - surface.get(n.0, n.1);
+                    if drop > F::zero() {
+                        drops.push((n, drop));
+                        total += drop;
+                        max_drop = max_drop.max(drop);
+                    }
+                }
+                if drops.is_empty() {
+                    continue;
+                }
+                // Never move more than half the largest drop, to avoid
+                // oscillation.
+                let out = excess.min(max_drop * half) * mobility;
+                next.set(cx, cy, next.get(cx, cy) - out);
+                for &(n, drop) in &drops {
+                    let d = next.get(n.0, n.1) + out * drop / total;
+                    next.set(n.0, n.1, d);
+                }
+            }
+        }
+        
+        let rate = self.params.solidification;
+        for (d, s) in next.data_mut().iter_mut().zip(self.solid.data_mut().iter_mut()) {
+            let x = *d * rate;
+            *d -= x;
+            *s += x;
+        }
+        self.depth = next;
+    }
+    
+    /// Run `steps` steps, capturing the depth grid every `interval` steps
+    /// 
+    /// This is a convenience wrapper over [`FluidSim::step`] for producing
+    /// animation frames.
+    pub fn run(&mut self, m: &Heightmap<F>, steps: usize, interval: usize) -> Vec<Grid<F>> {
+        trace_span!("fluid", dim = ?m.dim(), steps);
+        let mut frames = Vec::new();
+        for i in 0..steps {
+            self.step(m);
+            if (i + 1) % interval.max(1) == 0 {
+                frames.push(self.depth.clone());
+            }
+        }
+        frames
+    }
+    
+    /// Add the solidified material to the heightmap
+    /// 
+    /// If `include_fluid`, the remaining fluid depth is also added (e.g. to
+    /// solidify everything once a lava flow stops).
+    pub fn apply_to(&self, m: &mut Heightmap<F>, include_fluid: bool) {
+        assert_eq!(m.dim(), self.solid.dim());
+        let dim = m.dim();
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let mut add = self.solid.get(cx, cy);
+                if include_fluid {
+                    add += self.depth.get(cx, cy);
+                }
+                if add != F::zero() {
+                    let h = m.get(cx, cy) + add;
+                    m.set(cx, cy, h);
+                }
+            }
+        }
+    }
+}
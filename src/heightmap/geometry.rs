@@ -0,0 +1,34 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::RealField;
+
+// Parameter of the closest point to `p` on segment `a`–`b`, and its squared
+// distance
+pub(super) fn project<F: RealField>(a: (F, F), b: (F, F), p: (F, F)) -> (F, F) {
+    let (ex, ey) = (b.0 - a.0, b.1 - a.1);
+    let l2 = ex * ex + ey * ey;
+    let t = if l2 > F::zero() {
+        (((p.0 - a.0) * ex + (p.1 - a.1) * ey) / l2).max(F::zero()).min(F::one())
+    } else {
+        F::zero()
+    };
+    let (dx, dy) = (p.0 - a.0 - ex * t, p.1 - a.1 - ey * t);
+    (t, dx * dx + dy * dy)
+}
+
+// Cumulative length along a polyline
+pub(super) fn arc_lengths<F: RealField>(line: &[(F, F)]) -> Vec<F> {
+    let mut s = F::zero();
+    let mut lengths = vec![s];
+    for w in line.windows(2) {
+        s += ((w[1].0 - w[0].0) * (w[1].0 - w[0].0) + (w[1].1 - w[0].1) * (w[1].1 - w[0].1)).sqrt();
+        lengths.push(s);
+    }
+    lengths
+}
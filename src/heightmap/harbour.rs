@@ -0,0 +1,149 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+use crate::raster::{point_in_polygon, segment_distance};
+
+/// Harbour carving parameters
+/// 
+/// A harbour basin is described by a polygon (world coordinates). Inside the
+/// polygon the seabed is dredged to at least `draft` below `water_level`;
+/// where the polygon borders land, a quay wall is raised to `quay_height`
+/// above `water_level`.
+#[derive(Debug, Clone)]
+pub struct Harbour<F> {
+    /// Basin outline (world coordinates)
+    pub polygon: Vec<(F, F)>,
+    /// Height of the water surface
+    pub water_level: F,
+    /// Minimum depth below the water surface inside the basin
+    pub draft: F,
+    /// Height of quay tops above the water surface
+    pub quay_height: F,
+}
+
+/// Alignment data for a quay wall
+/// 
+/// Each wall corresponds to one polygon edge bordering land; dock meshes may
+/// be placed along the segment from `start` to `end`, facing `normal`.
+#[derive(Debug, Clone, Copy)]
+pub struct QuayWall<F> {
+    /// Start of the wall (world coordinates)
+    pub start: (F, F),
+    /// End of the wall (world coordinates)
+    pub end: (F, F),
+    /// Unit normal pointing out of the basin (towards land)
+    pub normal: (F, F),
+    /// Height of the quay top
+    pub top: F,
+    /// Height of the dredged basin floor at the foot of the wall
+    pub bottom: F,
+}
+
+/// The result of carving a harbour
+#[derive(Debug, Clone)]
+pub struct HarbourLayout<F> {
+    /// Vertices inside the basin
+    pub basin: Grid<bool>,
+    /// Vertices raised as part of quay walls
+    pub quay: Grid<bool>,
+    /// Quay walls
+    pub walls: Vec<QuayWall<F>>,
+}
+
+impl<F: RealField> Harbour<F> {
+    /// Carve the harbour into `m`
+    pub fn apply_to(&self, m: &mut Heightmap<F>) -> HarbourLayout<F> {
+        let dim = m.dim();
+        let floor = self.water_level - self.draft;
+        let top = self.water_level + self.quay_height;
+        let basin = Grid::from_fn(dim, |cx, cy| {
+            let c = m.coord_of(cx, cy);
+            point_in_polygon(c, &self.polygon)
+        });
+        
+        // Quay vertices are land vertices adjacent to the basin
+        let quay = Grid::from_fn(dim, |cx, cy| {
+            !basin.get(cx, cy) && m.get(cx, cy) > self.water_level
+                && m.neighbours(cx, cy).any(|n| basin.get(n.0, n.1))
+        });
+        
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                if basin.get(cx, cy) && m.get(cx, cy) > floor {
+                    m.set(cx, cy, floor);
+                } else if quay.get(cx, cy) {
+                    m.set(cx, cy, top);
+                }
+            }
+        }
+        
+        // An edge is a quay wall if land lies just outside its midpoint
+        let mut walls = Vec::new();
+        let n = self.polygon.len();
+        let orientation = signed_area(&self.polygon);
+        let half: F = convert(0.5);
+        let step = m.cell_size().0.max(m.cell_size().1);
+        for i in 0..n {
+            let (a, b) = (self.polygon[i], self.polygon[(i + 1) % n]);
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let len = (dx * dx + dy * dy).sqrt();
+            if len == F::zero() {
+                continue;
+            }
+            // Outward normal: right of the edge for counter-clockwise polygons
+            let mut normal = (dy / len, -dx / len);
+            if orientation < F::zero() {
+                normal = (-normal.0, -normal.1);
+            }
+            let mid = ((a.0 + b.0) * half, (a.1 + b.1) * half);
+            let outside = (mid.0 + normal.0 * step, mid.1 + normal.1 * step);
+            let is_quay = match m.nearest_vertex(outside.0, outside.1) {
+                Some(c) => !basin.get(c.0, c.1) && m.get(c.0, c.1) > self.water_level,
+                None => false,
+            };
+            if is_quay {
+                walls.push(QuayWall { start: a, end: b, normal, top, bottom: floor });
+            }
+        }
+        
+        HarbourLayout { basin, quay, walls }
+    }
+}
+
+/// Dredge a shipping channel along a polyline
+/// 
+/// All vertices within `width / 2` of the polyline `path` (world coordinates)
+/// are lowered to at most `floor`. Returns the mask of affected vertices.
+pub fn dredge_channel<F: RealField>(m: &mut Heightmap<F>, path: &[(F, F)], width: F, floor: F) -> Grid<bool> {
+    let r = width * convert(0.5);
+    let dim = m.dim();
+    let mask = Grid::from_fn(dim, |cx, cy| {
+        let c = m.coord_of(cx, cy);
+        path.windows(2).any(|w| segment_distance(c, w[0], w[1]) <= r)
+    });
+    for cy in 0..dim.1 {
+        for cx in 0..dim.0 {
+            if mask.get(cx, cy) && m.get(cx, cy) > floor {
+                m.set(cx, cy, floor);
+            }
+        }
+    }
+    mask
+}
+
+// Twice the signed area; positive for counter-clockwise polygons
+fn signed_area<F: RealField>(polygon: &[(F, F)]) -> F {
+    let n = polygon.len();
+    (0..n).fold(F::zero(), |s, i| {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        s + a.0 * b.1 - b.0 * a.1
+    })
+}
@@ -0,0 +1,229 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use rand::Rng;
+use super::{range, CaveEntrance, Heightmap};
+use crate::grid::Grid;
+
+/// Karst landscape parameters
+/// 
+/// Sinkholes (dolines) are scattered over limestone regions with radii
+/// following a truncated power law: many small hollows and few large ones.
+/// Most are solution dolines: smooth bowls of depth `depth_ratio × radius`.
+/// A fraction `open_fraction` are collapse dolines: steep-sided pits with a
+/// cave entrance in the wall. Open sinkholes are linked by cave passages (a
+/// minimum spanning tree over passages no longer than `max_passage`).
+#[derive(Debug, Clone, Copy)]
+pub struct KarstParams<F> {
+    /// Mean number of sinkhole candidates per unit area (before masking and
+    /// overlap rejection)
+    pub density: F,
+    /// Minimum radius
+    pub min_radius: F,
+    /// Maximum radius
+    pub max_radius: F,
+    /// Exponent `α` of the radius distribution, `p(r) ∝ r^-α`; typically
+    /// between 2 and 3
+    pub size_exponent: F,
+    /// Ratio of depth to radius
+    pub depth_ratio: F,
+    /// Probability that a sinkhole is an open (collapse) doline
+    pub open_fraction: F,
+    /// Maximum length of a cave passage between open sinkholes
+    pub max_passage: F,
+}
+
+/// A placed sinkhole
+#[derive(Debug, Clone, Copy)]
+pub struct Sinkhole<F> {
+    /// Centre (world coordinates)
+    pub centre: (F, F),
+    /// Radius of the rim
+    pub radius: F,
+    /// Depth below the surrounding terrain
+    pub depth: F,
+    /// Index of the cave entrance of an open (collapse) doline
+    pub entrance: Option<usize>,
+}
+
+/// The result of applying a karst pass
+#[derive(Debug, Clone)]
+pub struct KarstLayout<F> {
+    /// Sinkholes, in order of decreasing radius
+    pub sinkholes: Vec<Sinkhole<F>>,
+    /// Cave entrances of open sinkholes; these may be passed to
+    /// [`CaveFinder::holes`](super::CaveFinder::holes)
+    pub entrances: Vec<CaveEntrance<F>>,
+    /// Cave passages, as pairs of indices into `entrances`
+    pub passages: Vec<(usize, usize)>,
+}
+
+impl<F: RealField> KarstParams<F> {
+    /// Sample a sinkhole radius
+    pub fn sample_radius<R: Rng + ?Sized>(&self, rng: &mut R) -> F {
+        let u: F = convert(rng.gen::<f64>());
+        let e = F::one() - self.size_exponent;
+        if e.abs() < F::default_epsilon() {
+            // α = 1: log-uniform
+            return self.min_radius * (self.max_radius / self.min_radius).powf(u);
+        }
+        let (a, b) = (self.min_radius.powf(e), self.max_radius.powf(e));
+        (a + (b - a) * u).powf(F::one() / e)
+    }
+    
+    /// Apply to `m`
+    /// 
+    /// `limestone`, if given, has one weight in `[0, 1]` per vertex: the
+    /// probability of accepting a sinkhole centred there. Without a mask, the
+    /// whole map is limestone.
+    pub fn apply_to<R: Rng + ?Sized>(&self, m: &mut Heightmap<F>, limestone: Option<&Grid<F>>, rng: &mut R)
+        -> KarstLayout<F>
+    {
+        trace_span!("karst", dim = ?m.dim());
+        assert!(F::zero() < self.min_radius && self.min_radius <= self.max_radius);
+        if let Some(mask) = limestone {
+            assert_eq!(mask.dim(), m.dim());
+        }
+        let size = m.size();
+        let n = try_convert::<F, f64>(self.density * size.0 * size.1).unwrap().round() as usize;
+        let mut radii: Vec<F> = (0..n).map(|_| self.sample_radius(rng)).collect();
+        radii.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        
+        // Place largest first; reject centres within an existing sinkhole and
+        // sinkholes containing an existing centre
+        let mut sinkholes: Vec<Sinkhole<F>> = Vec::new();
+        for radius in radii {
+            let centre: (F, F) = (size.0 * convert(rng.gen::<f64>()), size.1 * convert(rng.gen::<f64>()));
+            if let Some(mask) = limestone {
+                let c = m.nearest_vertex(centre.0, centre.1).unwrap();
+                if convert::<_, F>(rng.gen::<f64>()) >= mask.get(c.0, c.1) {
+                    continue;
+                }
+            }
+            let clear = sinkholes.iter().all(|s| {
+                let (dx, dy) = (s.centre.0 - centre.0, s.centre.1 - centre.1);
+                (dx * dx + dy * dy).sqrt() >= s.radius
+            });
+            if clear {
+                let depth = radius * self.depth_ratio;
+                sinkholes.push(Sinkhole { centre, radius, depth, entrance: None });
+            }
+        }
+        
+        let mut entrances = Vec::new();
+        for s in &mut sinkholes {
+            let open = rng.gen::<f64>() < try_convert::<F, f64>(self.open_fraction).unwrap();
+            carve(m, s, open);
+            if open {
+                // entrance in the steep pit wall, facing the centre
+                let angle: F = F::two_pi() * convert(rng.gen::<f64>());
+                let (cos, sin) = (angle.cos(), angle.sin());
+                let r = s.radius * convert(0.8);
+                let p = (s.centre.0 + cos * r, s.centre.1 + sin * r);
+                // small pits may contain no vertex near the wall
+                let inside = |v: (u32, u32)| {
+                    let (x, y) = m.coord_of(v.0, v.1);
+                    let (dx, dy) = (x - s.centre.0, y - s.centre.1);
+                    dx * dx + dy * dy < s.radius * s.radius
+                };
+                let vertex = m.nearest_vertex(p.0, p.1).filter(|&v| inside(v))
+                    .or_else(|| m.nearest_vertex(s.centre.0, s.centre.1));
+                if let Some(vertex) = vertex {
+                    let (x, y) = m.coord_of(vertex.0, vertex.1);
+                    s.entrance = Some(entrances.len());
+                    entrances.push(CaveEntrance {
+                        vertex,
+                        position: (x, y, m.get(vertex.0, vertex.1)),
+                        facing: (-cos, -sin),
+                        score: s.radius,
+                    });
+                }
+            }
+        }
+        m.range = range(&m.data);
+        
+        let passages = spanning_tree(&entrances, self.max_passage);
+        KarstLayout { sinkholes, entrances, passages }
+    }
+}
+
+// Lower terrain within the sinkhole. Solution dolines are smooth bowls;
+// collapse dolines have a flat floor and steep walls.
+fn carve<F: RealField>(m: &mut Heightmap<F>, s: &Sinkhole<F>, open: bool) {
+    let (w, h) = m.cell_size();
+    let dim = m.dim();
+    let to_index = |v: F, step: F, n: u32| {
+        (try_convert::<F, f64>(v / step).unwrap().max(0.0) as u32).min(n - 1)
+    };
+    let x0 = to_index(s.centre.0 - s.radius, w, dim.0);
+    let x1 = to_index(s.centre.0 + s.radius, w, dim.0) + 1;
+    let y0 = to_index(s.centre.1 - s.radius, h, dim.1);
+    let y1 = to_index(s.centre.1 + s.radius, h, dim.1) + 1;
+    for cy in y0..=y1.min(dim.1 - 1) {
+        for cx in x0..=x1.min(dim.0 - 1) {
+            let (x, y) = m.coord_of(cx, cy);
+            let (dx, dy) = (x - s.centre.0, y - s.centre.1);
+            let t2 = (dx * dx + dy * dy) / (s.radius * s.radius);
+            if t2 >= F::one() {
+                continue;
+            }
+            let profile = if open {
+                F::one() - t2 * t2 * t2 * t2
+            } else {
+                (F::one() - t2) * (F::one() - t2)
+            };
+            let i = (cy * dim.0 + cx) as usize;
+            m.data[i] -= s.depth * profile;
+        }
+    }
+}
+
+// Minimum spanning forest (Prim) over entrances, using edges no longer than
+// `max_length`
+fn spanning_tree<F: RealField>(entrances: &[CaveEntrance<F>], max_length: F) -> Vec<(usize, usize)> {
+    let n = entrances.len();
+    let dist = |a: usize, b: usize| {
+        let (p, q) = (entrances[a].position, entrances[b].position);
+        ((p.0 - q.0) * (p.0 - q.0) + (p.1 - q.1) * (p.1 - q.1)).sqrt()
+    };
+    let mut in_tree = vec![false; n];
+    // best (distance, parent) for each node not yet in the tree
+    let mut best: Vec<Option<(F, usize)>> = vec![None; n];
+    let mut edges = Vec::new();
+    for root in 0..n {
+        if in_tree[root] {
+            continue;
+        }
+        let mut next = Some(root);
+        while let Some(u) = next {
+            in_tree[u] = true;
+            if let Some((_, parent)) = best[u] {
+                edges.push((parent, u));
+            }
+            next = None;
+            let mut min = F::max_value();
+            for v in 0..n {
+                if in_tree[v] {
+                    continue;
+                }
+                let d = dist(u, v);
+                if d <= max_length && best[v].map(|b| d < b.0).unwrap_or(true) {
+                    best[v] = Some((d, u));
+                }
+                if let Some((d, _)) = best[v] {
+                    if d < min {
+                        min = d;
+                        next = Some(v);
+                    }
+                }
+            }
+        }
+    }
+    edges
+}
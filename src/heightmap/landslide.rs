@@ -0,0 +1,242 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, VecDeque};
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+
+/// Parameters of a mass-movement (landslide or avalanche) event
+/// 
+/// An event starts at a *trigger* vertex whose slope exceeds
+/// `critical_slope`. Material fails over the connected unstable region at and
+/// above the trigger (the *scar*), then travels down the line of steepest
+/// descent (the *runout path*) until its energy line meets the terrain, and is
+/// deposited along the lower part of the path.
+/// 
+/// Deposited material settles at the critical slope. Height changes conserve
+/// the total material: the sum of all height changes is zero (up to rounding).
+#[derive(Debug, Clone, Copy)]
+pub struct Landslide<F> {
+    /// Slope (`tan` of the angle of repose) above which material is unstable
+    pub critical_slope: F,
+    /// Maximum depth of material removed from any single vertex
+    pub max_depth: F,
+    /// Runout friction: the ratio `H / L` of fall height to horizontal travel
+    /// distance at which the moving mass comes to rest (Heim's ratio).
+    /// Typical values are `0.2` to `0.6`; smaller values give longer runouts.
+    pub friction: F,
+}
+
+/// The outcome of a mass-movement event
+/// 
+/// This describes the affected region, for example for gameplay or visual
+/// effects triggered by the event.
+#[derive(Debug, Clone)]
+pub struct MassMovement<F> {
+    /// Vertices from which material was removed
+    pub scar: Vec<(u32, u32)>,
+    /// The runout path, starting from the trigger vertex
+    pub path: Vec<(u32, u32)>,
+    /// All modified vertices along with the (signed) change in height
+    pub changes: Vec<((u32, u32), F)>,
+    /// Bounds `(min, max)` of modified vertices (inclusive)
+    pub bounds: ((u32, u32), (u32, u32)),
+    /// Total height of material moved (multiply by the cell area to get a
+    /// volume)
+    pub displaced: F,
+}
+
+impl<F: RealField> Landslide<F> {
+    /// Construct with the given parameters
+    pub fn new(critical_slope: F, max_depth: F, friction: F) -> Self {
+        Landslide { critical_slope, max_depth, friction }
+    }
+    
+    /// List all unstable vertices, steepest first
+    pub fn unstable(&self, m: &Heightmap<F>) -> Vec<(u32, u32)> {
+        let dim = m.dim();
+        let mut list = Vec::new();
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let s = m.slope_at(cx, cy);
+                if s > self.critical_slope {
+                    list.push((s, (cx, cy)));
+                }
+            }
+        }
+        list.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        list.into_iter().map(|(_, c)| c).collect()
+    }
+    
+    /// Trigger an event at vertex `(cx, cy)` and apply it to `m`
+    /// 
+    /// Returns `None` (without modifying `m`) if the vertex is stable.
+    pub fn trigger(&self, m: &mut Heightmap<F>, cx: u32, cy: u32) -> Option<MassMovement<F>> {
+        if m.slope_at(cx, cy) <= self.critical_slope {
+            return None;
+        }
+        let cell_len = (m.cell_size().0 + m.cell_size().1) * convert(0.5);
+        
+        // Find the scar: the connected region of unstable vertices at or
+        // above the trigger.
+        let h_trigger = m.get(cx, cy);
+        let mut scar = vec![(cx, cy)];
+        let mut visited = vec![false; m.data.len()];
+        let index = |c: (u32, u32)| (c.0 as usize) + (c.1 as usize) * (m.dim.0 as usize);
+        visited[index((cx, cy))] = true;
+        let mut queue: VecDeque<(u32, u32)> = scar.iter().cloned().collect();
+        while let Some(c) = queue.pop_front() {
+            for n in m.neighbours(c.0, c.1) {
+                if visited[index(n)] {
+                    continue;
+                }
+                visited[index(n)] = true;
+                if m.get(n.0, n.1) >= h_trigger && m.slope_at(n.0, n.1) > self.critical_slope {
+                    scar.push(n);
+                    queue.push_back(n);
+                }
+            }
+        }
+        
+        // Compute removal before modifying anything, so that slopes are
+        // evaluated on the original surface.
+        let mut changes = Vec::new();
+        let mut displaced = F::zero();
+        let mut h_top = h_trigger;
+        for &c in &scar {
+            let excess = (m.slope_at(c.0, c.1) - self.critical_slope) * cell_len;
+            let d = excess.min(self.max_depth);
+            h_top = h_top.max(m.get(c.0, c.1));
+            displaced += d;
+            changes.push((c, -d));
+        }
+        for &(c, d) in &changes {
+            let h = m.get(c.0, c.1) + d;
+            m.set(c.0, c.1, h);
+        }
+        
+        // Trace the runout path down the line of steepest descent until the
+        // energy line from the top of the scar meets the terrain.
+        let mut path = vec![(cx, cy)];
+        let mut travel = F::zero();
+        let mut c = (cx, cy);
+        loop {
+            let h = m.get(c.0, c.1);
+            let mut next = None;
+            let mut best = F::zero();
+            for n in m.neighbours(c.0, c.1) {
+                let drop = (h - m.get(n.0, n.1)) / m.distance(c, n);
+                if drop > best {
+                    best = drop;
+                    next = Some(n);
+                }
+            }
+            let n = match next {
+                Some(n) => n,
+                None => break,
+            };
+            travel += m.distance(c, n);
+            if h_top - m.get(n.0, n.1) < self.friction * travel {
+                break;
+            }
+            path.push(n);
+            c = n;
+        }
+        
+        // Deposit along the part of the path below the critical slope, with
+        // weight increasing towards the end of the path.
+        let mut weights: Vec<(usize, F)> = path.iter().enumerate().skip(1)
+            .filter(|(_, c)| m.slope_at(c.0, c.1) <= self.critical_slope)
+            .map(|(i, _)| (i, convert(i as f64)))
+            .collect();
+        if weights.is_empty() {
+            let last = path.len() - 1;
+            weights.push((last, F::one()));
+        }
+        let sum = weights.iter().fold(F::zero(), |s, w| s + w.1);
+        let mut deposit = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (i, w) in weights {
+            let c = path[i];
+            let d = displaced * w / sum;
+            let h = m.get(c.0, c.1) + d;
+            m.set(c.0, c.1, h);
+            *deposit.entry(c).or_insert_with(F::zero) += d;
+            queue.push_back(c);
+        }
+        
+        // Let the deposited material settle at the angle of repose.
+        let half: F = convert(0.5);
+        let mut steps = 0;
+        let max_steps = 64 * m.data.len();
+        while let Some(c) = queue.pop_front() {
+            steps += 1;
+            if steps > max_steps {
+                break;
+            }
+            let h = m.get(c.0, c.1);
+            let mut lowest = None;
+            let mut best = self.critical_slope;
+            for n in m.neighbours(c.0, c.1) {
+                let s = (h - m.get(n.0, n.1)) / m.distance(c, n);
+                if s > best {
+                    best = s;
+                    lowest = Some(n);
+                }
+            }
+            if let Some(n) = lowest {
+                let excess = (best - self.critical_slope) * m.distance(c, n) * half;
+                let available = *deposit.get(&c).unwrap_or(&F::zero());
+                let d = excess.min(available);
+                if d > F::zero() {
+                    m.set(c.0, c.1, h - d);
+                    let hn = m.get(n.0, n.1) + d;
+                    m.set(n.0, n.1, hn);
+                    *deposit.entry(c).or_insert_with(F::zero) -= d;
+                    *deposit.entry(n).or_insert_with(F::zero) += d;
+                    queue.push_back(n);
+                    queue.push_back(c);
+                }
+            }
+        }
+        for (c, d) in changes.into_iter() {
+            *deposit.entry(c).or_insert_with(F::zero) += d;
+        }
+        let mut changes: Vec<_> = deposit.into_iter().collect();
+        changes.sort_by_key(|&((x, y), _)| (y, x));
+        
+        let mut bounds = ((cx, cy), (cx, cy));
+        for &(c, _) in &changes {
+            (bounds.0).0 = (bounds.0).0.min(c.0);
+            (bounds.0).1 = (bounds.0).1.min(c.1);
+            (bounds.1).0 = (bounds.1).0.max(c.0);
+            (bounds.1).1 = (bounds.1).1.max(c.1);
+        }
+        
+        Some(MassMovement { scar, path, changes, bounds, displaced })
+    }
+    
+    /// Repeatedly trigger events at the steepest unstable vertex
+    /// 
+    /// At most `max_events` events are simulated. Returns the list of events.
+    pub fn trigger_unstable(&self, m: &mut Heightmap<F>, max_events: usize) -> Vec<MassMovement<F>> {
+        trace_span!("landslides", dim = ?m.dim(), max_events);
+        let mut events = Vec::new();
+        while events.len() < max_events {
+            match self.unstable(m).first() {
+                Some(&(cx, cy)) => {
+                    if let Some(event) = self.trigger(m, cx, cy) {
+                        events.push(event);
+                    }
+                }
+                None => break,
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::Ordering;
+use nalgebra::{convert, RealField};
+use super::{drainage::{accumulation, downstream}, range, Heightmap, Strata};
+use crate::grid::Grid;
+
+/// Parameters of erosion through layers of differing hardness
+/// 
+/// Rock hardness varies with elevation: `strata` defines horizontal layers
+/// and `hardness` gives the (relative, positive) hardness of each layer by
+/// index. Each iteration:
+/// 
+/// 1.  Streams incise by stream power: a vertex with catchment area `A` and
+///     slope `S` is lowered by `incision × A^area_exponent × S / hardness`.
+/// 2.  Slopes fail: where the slope from a vertex to its steepest lower
+///     neighbour exceeds `stable_slope × hardness`, a fraction `collapse` of
+///     the excess moves downhill.
+/// 
+/// Hardness is that of the layer at the vertex's current elevation, divided
+/// by the vertex's `erodibility` if given. Hard caprock thus holds flat tops
+/// and steep cliffs while soft layers beneath are stripped back, forming
+/// escarpments, benches where hard layers outcrop, and isolated mesas, buttes
+/// and (at fine resolution) hoodoos. An erodibility grid adds lateral
+/// variation (e.g. intrusions, faulted or weathered zones): valleys widen
+/// through soft ground and narrow to gorges through hard.
+#[derive(Debug, Clone)]
+pub struct LayeredErosionParams<F> {
+    /// Layer geometry
+    pub strata: Strata<F>,
+    /// Hardness of each layer (by index); one per layer of `strata`
+    pub hardness: Vec<F>,
+    /// Stream incision rate (for hardness 1)
+    pub incision: F,
+    /// Exponent of catchment area in stream power; typically around 0.5
+    pub area_exponent: F,
+    /// Stable slope (rise over run) for hardness 1
+    pub stable_slope: F,
+    /// Fraction of excess height moved per iteration by slope failure, in
+    /// `(0, 1]`
+    pub collapse: F,
+    /// Optional positive erodibility per vertex (one is nominal), e.g.
+    /// generated from noise or painted
+    pub erodibility: Option<Grid<F>>,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Erode through hard and soft layers
+    /// 
+    /// Returns the net lowering of each vertex (negative where material has
+    /// been deposited).
+    pub fn erode_layered(&mut self, params: &LayeredErosionParams<F>, iterations: u32) -> Grid<F> {
+        trace_span!("erode_layered", dim = ?self.dim, iterations);
+        assert_eq!(params.hardness.len(), params.strata.num_layers());
+        assert!(params.hardness.iter().all(|h| *h > F::zero()));
+        if let Some(e) = params.erodibility.as_ref() {
+            assert_eq!(e.dim(), self.dim);
+        }
+        let hardness = |cx: u32, cy: u32, h: F| {
+            let layer = params.hardness[params.strata.layer_at(h) as usize];
+            match params.erodibility.as_ref() {
+                Some(e) => layer / e.get(cx, cy),
+                None => layer,
+            }
+        };
+        let initial = self.data.clone();
+        let half: F = convert(0.5);
+        let mut order: Vec<(u32, u32)> = (0..self.dim.1)
+            .flat_map(|cy| (0..self.dim.0).map(move |cx| (cx, cy)))
+            .collect();
+        let mut delta = vec![F::zero(); self.data.len()];
+        for _ in 0..iterations {
+            // streams: lowest first, so downstream heights are final
+            let area = accumulation(self, false);
+            let next = downstream(self, false);
+            order.sort_by(|a, b| self.get(a.0, a.1).partial_cmp(&self.get(b.0, b.1)).unwrap_or(Ordering::Equal));
+            for &(cx, cy) in &order {
+                if let Some(n) = next.get(cx, cy) {
+                    let h = self.get(cx, cy);
+                    let fall = h - self.get(n.0, n.1);
+                    let slope = fall / self.distance((cx, cy), n);
+                    let d = params.incision * area.get(cx, cy).powf(params.area_exponent) * slope / hardness(cx, cy, h);
+                    self.data[(cy * self.dim.0 + cx) as usize] = h - d.min(fall * convert(0.9));
+                }
+            }
+            
+            // slope failure, applied simultaneously
+            for cy in 0..self.dim.1 {
+                for cx in 0..self.dim.0 {
+                    let h = self.get(cx, cy);
+                    let mut steepest = (F::zero(), (cx, cy), F::zero());
+                    for n in self.neighbours(cx, cy) {
+                        let dist = self.distance((cx, cy), n);
+                        let slope = (h - self.get(n.0, n.1)) / dist;
+                        if slope > steepest.0 {
+                            steepest = (slope, n, dist);
+                        }
+                    }
+                    let (slope, n, dist) = steepest;
+                    let stable = params.stable_slope * hardness(cx, cy, h);
+                    if slope > stable {
+                        let moved = (slope - stable) * dist * half * params.collapse;
+                        delta[(cy * self.dim.0 + cx) as usize] -= moved;
+                        delta[(n.1 * self.dim.0 + n.0) as usize] += moved;
+                    }
+                }
+            }
+            for (h, d) in self.data.iter_mut().zip(delta.iter_mut()) {
+                *h += *d;
+                *d = F::zero();
+            }
+        }
+        self.range = range(&self.data);
+        let w = self.dim.0 as usize;
+        Grid::from_fn(self.dim, |cx, cy| {
+            let i = cy as usize * w + cx as usize;
+            initial[i] - self.data[i]
+        })
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use rand::Rng;
+use super::{geometry::{arc_lengths, project}, range, Heightmap};
+use crate::grid::Grid;
+
+// Weights of local and upstream curvature in bend migration (Howard and
+// Knutson): the negative local term and larger upstream term make bends grow
+// and migrate downstream
+const LOCAL_WEIGHT: f64 = -1.0;
+const UPSTREAM_WEIGHT: f64 = 2.5;
+// Minimum number of nodes between the ends of a neck for a cutoff
+const MIN_LOOP_NODES: usize = 4;
+
+/// Parameters of meander evolution
+#[derive(Debug, Clone, Copy)]
+pub struct MeanderParams<F> {
+    /// Channel width; the centre line is resampled with this node spacing
+    pub width: F,
+    /// Channel depth below the floodplain
+    pub depth: F,
+    /// Bank erodibility: nominal migration per unit time is
+    /// `migration_rate * width * curvature`
+    pub migration_rate: F,
+    /// Length scale over which upstream curvature affects migration
+    /// (typically a few channel widths)
+    pub memory: F,
+    /// Cutoff occurs where two parts of the channel, separated by a loop,
+    /// approach within this distance (typically about one channel width)
+    pub cutoff_distance: F,
+    /// Scale of random lateral node displacement per unit time, relative to
+    /// the width: small perturbations (e.g. `0.01`) grow into new bends,
+    /// sustaining meandering as bends migrate out of the reach
+    pub noise: F,
+}
+
+/// Meandering river planform evolution
+/// 
+/// A low-gradient river reach is modelled as a centre line whose nodes migrate
+/// sideways according to local and upstream curvature (the kinematic model of
+/// Howard and Knutson, "Sufficient conditions for river meandering", 1984,
+/// [doi:10.1029/WR020i011p01656](https://doi.org/10.1029/WR020i011p01656)).
+/// Bends thus grow and migrate downstream; where a neck closes, the loop is
+/// cut off, leaving an oxbow lake. The ends of the reach are fixed.
+/// 
+/// The channel is carved into the heightmap to its bed: the initial river's
+/// terrain profile less the channel depth, taken at the nearest point of the
+/// initial river and made non-increasing downstream. Vertices abandoned by the
+/// migrating channel are filled to the floodplain level (the initial river's
+/// terrain profile, likewise), except oxbows, which remain as lakes.
+#[derive(Debug, Clone)]
+pub struct MeanderSim<F> {
+    params: MeanderParams<F>,
+    nodes: Vec<(F, F)>,
+    // initial river with bed height at each point
+    valley: Vec<((F, F), F)>,
+    oxbows: Vec<Vec<(F, F)>>,
+    time: F,
+    // bed height of each vertex covered by the channel at the last step
+    bed: Grid<Option<F>>,
+    lake: Grid<bool>,
+}
+
+impl<F: RealField> MeanderSim<F> {
+    /// Construct with an initial river centre line (world coordinates, from
+    /// upstream to downstream)
+    pub fn new(m: &Heightmap<F>, river: &[(F, F)], params: MeanderParams<F>) -> Self {
+        assert!(river.len() >= 2 && params.width > F::zero());
+        let mut min = F::max_value();
+        let valley = river.iter().map(|&p| {
+            min = min.min(m.interpolate(p.0, p.1) - params.depth);
+            (p, min)
+        }).collect();
+        let mut sim = MeanderSim {
+            params,
+            nodes: vec![],
+            valley,
+            oxbows: vec![],
+            time: F::zero(),
+            bed: Grid::new(m.dim(), None),
+            lake: Grid::new(m.dim(), false),
+        };
+        sim.nodes = resample(river, params.width);
+        sim
+    }
+    
+    /// The river centre line
+    #[inline]
+    pub fn river(&self) -> &[(F, F)] {
+        &self.nodes
+    }
+    
+    /// Centre lines of cut-off loops (oxbow lakes), oldest first
+    #[inline]
+    pub fn oxbows(&self) -> &[Vec<(F, F)>] {
+        &self.oxbows
+    }
+    
+    /// Simulated time
+    #[inline]
+    pub fn time(&self) -> F {
+        self.time
+    }
+    
+    /// Oxbow lake vertices
+    #[inline]
+    pub fn lakes(&self) -> &Grid<bool> {
+        &self.lake
+    }
+    
+    /// Advance by time `dt`, updating the carved channel in `m`
+    /// 
+    /// For stability, migration within a step should be small relative to
+    /// the channel width. Returns the number of cutoffs.
+    pub fn step<R: Rng + ?Sized>(&mut self, m: &mut Heightmap<F>, rng: &mut R, dt: F) -> usize {
+        let p = self.params;
+        let n = self.nodes.len();
+        if n >= 3 {
+            // nominal migration from curvature (positive to the left)
+            let mut nominal = vec![F::zero(); n];
+            for (i, w) in self.nodes.windows(3).enumerate() {
+                nominal[i + 1] = p.migration_rate * p.width * curvature(w[0], w[1], w[2]);
+            }
+            // weighted with exponentially decaying upstream nominal migration
+            let decay = (-p.width / p.memory).exp();
+            let (local, upstream): (F, F) = (convert(LOCAL_WEIGHT), convert(UPSTREAM_WEIGHT));
+            let mut mean = F::zero();
+            let mut moved = self.nodes.clone();
+            for i in 1..n - 1 {
+                mean = mean * decay + nominal[i - 1] * (F::one() - decay);
+                let rate = local * nominal[i] + upstream * mean;
+                // bends migrate outwards, away from the centre of curvature
+                let (a, b) = (self.nodes[i - 1], self.nodes[i + 1]);
+                let (tx, ty) = (b.0 - a.0, b.1 - a.1);
+                let len = (tx * tx + ty * ty).sqrt();
+                if len > F::zero() {
+                    let noise = p.noise * p.width * dt.sqrt() * convert(rng.gen_range(-1.0, 1.0));
+                    let d = (noise - rate * dt) / len;
+                    moved[i] = clamp(m, (moved[i].0 - ty * d, moved[i].1 + tx * d));
+                }
+            }
+            self.nodes = resample(&moved, p.width);
+        }
+        self.time += dt;
+        
+        let cutoffs = self.cut_off(m);
+        self.carve(m);
+        cutoffs
+    }
+    
+    /// Simulate `steps` steps of `dt`, returning the number of cutoffs
+    pub fn run<R: Rng + ?Sized>(&mut self, m: &mut Heightmap<F>, rng: &mut R, dt: F, steps: usize) -> usize {
+        trace_span!("meander", steps);
+        (0..steps).map(|_| self.step(m, rng, dt)).sum()
+    }
+    
+    // Cut off loops whose necks have closed, marking their channels as lakes
+    fn cut_off(&mut self, m: &Heightmap<F>) -> usize {
+        let d2 = self.params.cutoff_distance * self.params.cutoff_distance;
+        let mut count = 0;
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let a = self.nodes[i];
+            let j = (i + MIN_LOOP_NODES..self.nodes.len()).rev().find(|&j| {
+                let b = self.nodes[j];
+                (b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1) < d2
+            });
+            if let Some(j) = j {
+                let beds = self.beds();
+                let oxbow: Vec<_> = self.nodes.drain(i + 1..j).collect();
+                let covered = self.coverage(m, &oxbow, &beds[i + 1..j]);
+                for (lake, c) in self.lake.data_mut().iter_mut().zip(covered.data()) {
+                    *lake |= c.is_some();
+                }
+                self.oxbows.push(oxbow);
+                count += 1;
+            }
+            i += 1;
+        }
+        count
+    }
+    
+    // Bed height at each node of the reach: that of the nearest point of the
+    // initial river, made non-increasing downstream
+    fn beds(&self) -> Vec<F> {
+        let mut min = F::max_value();
+        self.nodes.iter().map(|&p| {
+            min = min.min(self.valley_bed(p));
+            min
+        }).collect()
+    }
+    
+    // Bed height of the nearest point of the initial river
+    fn valley_bed(&self, p: (F, F)) -> F {
+        let mut best = (F::max_value(), F::zero());
+        for w in self.valley.windows(2) {
+            let ((a, ha), (b, hb)) = (w[0], w[1]);
+            let (t, d2) = project(a, b, p);
+            if d2 < best.0 {
+                best = (d2, ha + (hb - ha) * t);
+            }
+        }
+        best.1
+    }
+    
+    // Vertices within half the channel width of `line` (with bed heights
+    // `beds`), with the bed height at each
+    fn coverage(&self, m: &Heightmap<F>, line: &[(F, F)], beds: &[F]) -> Grid<Option<F>> {
+        let r = self.params.width * convert(0.5);
+        let (dim, cell) = (m.dim(), m.cell_size());
+        let mut covered = Grid::new(dim, None);
+        let index = |v: F, c: F, max: u32| {
+            let i = (v / c).floor().max(F::zero()).min(convert((max - 1) as f64));
+            try_convert::<F, f64>(i).unwrap() as u32
+        };
+        for k in 0..line.len() {
+            let (a, b) = (line[k], line[(k + 1).min(line.len() - 1)]);
+            let (ha, hb) = (beds[k], beds[(k + 1).min(line.len() - 1)]);
+            let x0 = index(a.0.min(b.0) - r, cell.0, dim.0);
+            let x1 = index(a.0.max(b.0) + r + cell.0, cell.0, dim.0);
+            let y0 = index(a.1.min(b.1) - r, cell.1, dim.1);
+            let y1 = index(a.1.max(b.1) + r + cell.1, cell.1, dim.1);
+            for cy in y0..=y1 {
+                for cx in x0..=x1 {
+                    let (t, d2) = project(a, b, m.coord_of(cx, cy));
+                    if d2 <= r * r {
+                        let h = ha + (hb - ha) * t;
+                        let old = covered.get(cx, cy).unwrap_or(F::max_value());
+                        covered.set(cx, cy, Some(old.min(h)));
+                    }
+                }
+            }
+        }
+        covered
+    }
+    
+    // Carve the channel, filling abandoned channel to the floodplain
+    fn carve(&mut self, m: &mut Heightmap<F>) {
+        let covered = self.coverage(m, &self.nodes, &self.beds());
+        let (w, depth) = (m.dim().0 as usize, self.params.depth);
+        for (i, (new, old)) in covered.data().iter().zip(self.bed.data()).enumerate() {
+            let (cx, cy) = ((i % w) as u32, (i / w) as u32);
+            let h = m.get(cx, cy);
+            match (new, old) {
+                (Some(bed), _) => m.set(cx, cy, h.min(*bed)),
+                (None, Some(_)) if !self.lake.get(cx, cy) => {
+                    let floodplain = self.valley_bed(m.coord_of(cx, cy)) + depth;
+                    m.set(cx, cy, h.max(floodplain));
+                }
+                _ => (),
+            }
+        }
+        for (lake, c) in self.lake.data_mut().iter_mut().zip(covered.data()) {
+            *lake &= c.is_none();
+        }
+        self.bed = covered;
+        m.range = range(&m.data);
+    }
+}
+
+// Signed curvature of the circle through three points (positive turning left)
+fn curvature<F: RealField>(a: (F, F), b: (F, F), c: (F, F)) -> F {
+    let (ux, uy) = (b.0 - a.0, b.1 - a.1);
+    let (vx, vy) = (c.0 - b.0, c.1 - b.1);
+    let (wx, wy) = (c.0 - a.0, c.1 - a.1);
+    let denom = ((ux * ux + uy * uy) * (vx * vx + vy * vy) * (wx * wx + wy * wy)).sqrt();
+    if denom > F::zero() {
+        (ux * vy - uy * vx) * convert(2.0) / denom
+    } else {
+        F::zero()
+    }
+}
+
+// Resample a polyline with (approximately) the given spacing, keeping its ends
+fn resample<F: RealField>(line: &[(F, F)], spacing: F) -> Vec<(F, F)> {
+    let lengths = arc_lengths(line);
+    let total = *lengths.last().unwrap();
+    let n = try_convert::<F, f64>((total / spacing).round()).unwrap().max(1.0) as usize;
+    let step = total / convert(n as f64);
+    let mut k = 0;
+    (0..=n).map(|i| {
+        let s = step * convert(i as f64);
+        while k + 2 < lengths.len() && lengths[k + 1] < s {
+            k += 1;
+        }
+        let (a, b) = (line[k], line[k + 1]);
+        let l = lengths[k + 1] - lengths[k];
+        let t = if l > F::zero() { ((s - lengths[k]) / l).min(F::one()) } else { F::zero() };
+        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+    }).collect()
+}
+
+fn clamp<F: RealField>(m: &Heightmap<F>, p: (F, F)) -> (F, F) {
+    let size = m.size();
+    (p.0.max(F::zero()).min(size.0), p.1.max(F::zero()).min(size.1))
+}
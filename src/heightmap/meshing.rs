@@ -0,0 +1,169 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::time::Duration;
+use nalgebra::{convert, RealField, geometry::{Point2, Point3}};
+use super::Heightmap;
+use crate::grid::Grid;
+use crate::mesh::{TerrainMesh, UpAxis};
+use crate::task::{budgeted, IncrementalTask, Progress};
+
+// Incremental construction of the mesh of a heightmap, one row of vertices
+// (and the row of triangles above it) at a time
+// 
+// Either `subdivs` or `step` must be 1. With `step > 1` only every `step`-th
+// vertex (plus the last) along each axis is used.
+pub(super) struct MeshBuilder<'a, F: RealField> {
+    m: &'a Heightmap<F>,
+    subdivs: u32,
+    step: u32,
+    holes: Option<&'a Grid<bool>>,
+    detail: Option<&'a dyn Fn(F, F) -> F>,
+    divs: (u32, u32),
+    row: u32,
+    vertices: Vec<Point3<F>>,
+    tex_coords: Vec<Point2<F>>,
+    triangles: Vec<Point3<u32>>,
+}
+
+impl<'a, F: RealField> MeshBuilder<'a, F> {
+    pub(super) fn new(m: &'a Heightmap<F>, subdivs: u32, step: u32, holes: Option<&'a Grid<bool>>,
+        detail: Option<&'a dyn Fn(F, F) -> F>) -> Self
+    {
+        debug_assert!(subdivs == 1 || step == 1);
+        let divs = (
+            ((m.dim.0 - 1) * subdivs).div_ceil(step),
+            ((m.dim.1 - 1) * subdivs).div_ceil(step),
+        );
+        let vertices = (divs.0 as usize + 1) * (divs.1 as usize + 1);
+        MeshBuilder {
+            m, subdivs, step, holes, detail, divs,
+            row: 0,
+            vertices: Vec::with_capacity(vertices),
+            tex_coords: Vec::with_capacity(vertices),
+            triangles: Vec::with_capacity(2 * divs.0 as usize * divs.1 as usize),
+        }
+    }
+    
+    // Number of rows
+    fn rows(&self) -> u32 {
+        self.divs.1 + 1
+    }
+    
+    // Build the next row; returns false when there are no more rows
+    pub(super) fn step(&mut self) -> bool {
+        if self.row >= self.rows() {
+            return false;
+        }
+        let (m, subdivs, iy) = (self.m, self.subdivs, self.row);
+        let x_divs = self.divs.0;
+        
+        // code adapted from ncollide::procedural::unit_quad:
+        let one = F::one();
+        let sub: F = convert(subdivs as f64);
+        let (x_step, y_step) = (m.len_frac.0 / sub, m.len_frac.1 / sub);
+        
+        // create the vertices
+        let fy: F = convert(iy as f64);
+        let cy = (iy * self.step).min(m.dim.1 - 1);
+        for ix in 0..=x_divs {
+            let (x, y, mut h) = if subdivs == 1 {
+                let cx = (ix * self.step).min(m.dim.0 - 1);
+                let (x, y) = m.coord_of(cx, cy);
+                (x, y, m.get(cx, cy))
+            } else {
+                let (x, y) = (convert::<_, F>(ix as f64) * x_step, fy * y_step);
+                (x, y, m.interpolate(x, y))
+            };
+            if let Some(d) = self.detail {
+                h += d(x, y);
+            }
+            self.vertices.push(Point3::new(x, y, h));
+            self.tex_coords.push(Point2::new(one - x / m.size.0, one - y / m.size.1))
+        }
+        
+        // create triangles between the previous and this row
+        if iy > 0 {
+            let iy = iy - 1;
+            let ws = x_divs + 1;
+            for ix in 0..x_divs {
+                if self.holes.map(|h| h.get(ix / subdivs, iy / subdivs)).unwrap_or(false) {
+                    continue;
+                }
+                // build two triangles...
+                self.triangles.push(Point3::new((iy + 1) * ws + ix, iy * ws + ix, (iy + 1) * ws + ix + 1));
+                self.triangles.push(Point3::new(iy * ws + ix, iy * ws + (ix + 1), (iy + 1) * ws + ix + 1));
+            }
+        }
+        
+        self.row += 1;
+        self.row < self.rows()
+    }
+    
+    pub(super) fn finish(self) -> TerrainMesh<F> {
+        let mut mesh = TerrainMesh::new(
+            self.vertices,
+            None,
+            Some(self.tex_coords),
+            self.triangles,
+        );
+        mesh.recompute_normals();
+        mesh
+    }
+}
+
+/// Incremental meshing of a heightmap (see [`Heightmap::mesh_task`])
+/// 
+/// Each unit of work is one row of vertices.
+pub struct MeshTask<'a, F: RealField> {
+    builder: MeshBuilder<'a, F>,
+    up: UpAxis,
+}
+
+impl<'a, F: RealField> fmt::Debug for MeshTask<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MeshTask")
+            .field("progress", &self.progress())
+            .field("up", &self.up)
+            .finish()
+    }
+}
+
+impl<'a, F: RealField> MeshTask<'a, F> {
+    /// Get the mesh, completing any remaining work
+    /// 
+    /// The result is identical to [`Heightmap::to_trimesh`].
+    pub fn into_mesh(mut self) -> TerrainMesh<F> {
+        while self.builder.step() {}
+        let mut mesh = self.builder.finish();
+        mesh.orient(self.up);
+        mesh
+    }
+}
+
+impl<'a, F: RealField> IncrementalTask for MeshTask<'a, F> {
+    fn run_for(&mut self, budget: Duration) -> Progress {
+        let builder = &mut self.builder;
+        budgeted(budget, || builder.step());
+        self.progress()
+    }
+    
+    fn progress(&self) -> Progress {
+        Progress { done: self.builder.row as u64, total: self.builder.rows() as u64 }
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Start incremental meshing (see [`crate::task`])
+    /// 
+    /// The resulting mesh is as from [`Heightmap::to_trimesh`].
+    pub fn mesh_task(&self, up: UpAxis) -> MeshTask<'_, F> {
+        MeshTask { builder: MeshBuilder::new(self, 1, 1, None, None), up }
+    }
+}
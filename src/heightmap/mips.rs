@@ -0,0 +1,101 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{range, Heightmap};
+
+/// How heights are combined when downsampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    /// Weighted average (a tent filter), preserving mean height
+    Average,
+    /// Maximum: the coarse surface lies on or above the fine surface
+    Max,
+    /// Minimum: the coarse surface lies on or below the fine surface
+    Min,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Reduce resolution by an integer `factor`, keeping the same size
+    /// 
+    /// Requires that `dim - 1` is divisible by `factor` on both axes; the
+    /// result has dimension `(dim - 1) / factor + 1`, thus its vertices
+    /// coincide with every `factor`-th vertex of this map.
+    /// 
+    /// Each coarse vertex combines all fine vertices closer than `factor`
+    /// steps on both axes. With [`Reduction::Max`] or [`Reduction::Min`] this
+    /// footprint covers all cells adjacent to the coarse vertex, thus the
+    /// bilinearly interpolated coarse surface bounds the fine surface
+    /// everywhere (useful for conservative culling and collision).
+    pub fn downsample(&self, factor: u32, reduction: Reduction) -> Heightmap<F> {
+        trace_span!("downsample", dim = ?self.dim, factor);
+        assert!(factor > 0);
+        assert!((self.dim.0 - 1).is_multiple_of(factor) && (self.dim.1 - 1).is_multiple_of(factor),
+            "downsample: dim - 1 not divisible by factor");
+        let dim = ((self.dim.0 - 1) / factor + 1, (self.dim.1 - 1) / factor + 1);
+        let mut m = Heightmap::new_flat(dim, self.size);
+        let r = factor as i64 - 1;
+        let fw: F = convert(factor as f64);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let (x0, y0) = ((cx * factor) as i64, (cy * factor) as i64);
+                let mut acc = match reduction {
+                    Reduction::Average => F::zero(),
+                    Reduction::Max => F::min_value(),
+                    Reduction::Min => F::max_value(),
+                };
+                let mut weight = F::zero();
+                for y in (y0 - r).max(0)..=(y0 + r).min(self.dim.1 as i64 - 1) {
+                    for x in (x0 - r).max(0)..=(x0 + r).min(self.dim.0 as i64 - 1) {
+                        let h = self.get(x as u32, y as u32);
+                        match reduction {
+                            Reduction::Average => {
+                                let wx = fw - convert(((x - x0).abs()) as f64);
+                                let wy = fw - convert(((y - y0).abs()) as f64);
+                                acc += h * wx * wy;
+                                weight += wx * wy;
+                            }
+                            Reduction::Max => acc = acc.max(h),
+                            Reduction::Min => acc = acc.min(h),
+                        }
+                    }
+                }
+                if reduction == Reduction::Average {
+                    acc /= weight;
+                }
+                m.data[(cy * dim.0 + cx) as usize] = acc;
+            }
+        }
+        m.range = range(&m.data);
+        m
+    }
+    
+    /// Build a chain of progressively half-resolution maps
+    /// 
+    /// The first entry has half the resolution of this map, each following
+    /// entry half that of its predecessor (see [`Heightmap::downsample`]).
+    /// The chain ends when `dim - 1` is odd on either axis or a dimension
+    /// reaches 2; thus a map of dimension `2ⁿ + 1` yields `n` levels, down to
+    /// `2 × 2`.
+    pub fn build_mips(&self, reduction: Reduction) -> Vec<Heightmap<F>> {
+        trace_span!("build_mips", dim = ?self.dim);
+        let halvable = |m: &Heightmap<F>| {
+            m.dim.0 > 2 && m.dim.1 > 2 && (m.dim.0 - 1).is_multiple_of(2) && (m.dim.1 - 1).is_multiple_of(2)
+        };
+        let mut mips: Vec<Heightmap<F>> = vec![];
+        loop {
+            let last = mips.last().unwrap_or(self);
+            if !halvable(last) {
+                break;
+            }
+            let next = last.downsample(2, reduction);
+            mips.push(next);
+        }
+        mips
+    }
+}
@@ -10,13 +10,14 @@
 // Most code adapted from ncollide implementations for
 // ncollide3d::shape::HeightField. Note that we don't use HeightField directly
 // because it assumes a different coordinate system and because of issues
-// with more than ~100x100 points.
+// with more than ~100x100 points (for meshes, see also
+// `crate::mesh::ChunkedMesher`).
 
 use nalgebra as na;
-use na::{convert, RealField, geometry::Point3, Unit};
-use ncollide3d::shape::{Shape, FeatureId, Triangle};
+use na::{convert, DMatrix, Dynamic, RealField, Vector3, geometry::Point3, Unit};
+use ncollide3d::shape::{Shape, FeatureId, HeightField, Triangle};
 use ncollide3d::math::{Isometry, Vector};
-use ncollide3d::query::{Ray, RayCast, RayIntersection, PointQuery};
+use ncollide3d::query::{Ray, RayCast, RayIntersection, PointProjection, PointQuery};
 use ncollide3d::bounding_volume::{self, AABB, BoundingSphere, HasBoundingVolume};
 
 use super::Heightmap;
@@ -25,7 +26,7 @@ use super::Heightmap;
 impl<F: RealField> HasBoundingVolume<F, AABB<F>> for Heightmap<F> {
     #[inline]
     fn bounding_volume(&self, m: &Isometry<F>) -> AABB<F> {
-        self.local_bounding_volume().transform_by(m)
+        bounding_volume::local_aabb(self).transform_by(m)
     }
 
     #[inline]
@@ -37,6 +38,25 @@ impl<F: RealField> HasBoundingVolume<F, AABB<F>> for Heightmap<F> {
     }
 }
 
+impl<F: RealField> HasBoundingVolume<F, BoundingSphere<F>> for Heightmap<F> {
+    #[inline]
+    fn bounding_volume(&self, m: &Isometry<F>) -> BoundingSphere<F> {
+        bounding_volume::local_bounding_sphere(self).transform_by(m)
+    }
+    
+    #[inline]
+    fn local_bounding_volume(&self) -> BoundingSphere<F> {
+        bounding_volume::local_aabb(self).bounding_sphere()
+    }
+}
+
+// Feature identifiers follow ncollide's HeightField: each cell (cx, cy) has
+// two triangles (see `triangles_at`) with index `t = 2 × (cx + cy × (dim.0 - 1))`
+// and `t + 1`; face `t` is the front (upper) side of triangle `t` and face
+// `t + n` its back, where `n` is the number of triangles. Vertex `cx + cy × dim.0`
+// is vertex (cx, cy) and edges `3 × (cx + cy × dim.0) + k` start at this
+// vertex and run in the x direction (k = 0), y direction (k = 1) or along the
+// cell diagonal (k = 2).
 impl<F: RealField> Shape<F> for Heightmap<F> {
     #[inline]
     fn aabb(&self, m: &Isometry<F>) -> AABB<F> {
@@ -49,8 +69,8 @@ impl<F: RealField> Shape<F> for Heightmap<F> {
     }
 
     #[inline]
-    fn bounding_sphere(&self, _m: &Isometry<F>) -> BoundingSphere<F> {
-        unimplemented!()
+    fn bounding_sphere(&self, m: &Isometry<F>) -> BoundingSphere<F> {
+        bounding_volume::bounding_sphere(self, m)
     }
 
     #[inline]
@@ -60,23 +80,60 @@ impl<F: RealField> Shape<F> for Heightmap<F> {
 
     #[inline]
     fn as_point_query(&self) -> Option<&dyn PointQuery<F>> {
-        unimplemented!()
-//         Some(self)
+        Some(self)
     }
     
     fn tangent_cone_contains_dir(
         &self,
-        _fid: FeatureId,
-        _m: &Isometry<F>,
+        fid: FeatureId,
+        m: &Isometry<F>,
         _deformations: Option<&[F]>,
-        _dir: &Unit<Vector<F>>,
+        dir: &Unit<Vector<F>>,
     ) -> bool
     {
-        unimplemented!()
+        // The solid lies below the surface. We find the triangle entered by
+        // moving a short distance from the feature in the horizontal
+        // direction of `dir`, and test `dir` against its upward normal.
+        let ls_dir = m.inverse_transform_vector(dir);
+        let p = match self.feature_point(fid) {
+            Some(p) => p,
+            None => return false,
+        };
+        let h = (ls_dir.x * ls_dir.x + ls_dir.y * ls_dir.y).sqrt();
+        if h <= F::default_epsilon() {
+            return ls_dir.z <= F::zero();
+        }
+        let eps = self.len_frac.0.min(self.len_frac.1) * convert(1e-3);
+        let (x, y) = (p.0 + ls_dir.x / h * eps, p.1 + ls_dir.y / h * eps);
+        if x < F::zero() || y < F::zero() || x > self.size.0 || y > self.size.1 {
+            return false;
+        }
+        let ((cx, cy), tx, ty) = self.bilinear(x, y);
+        let tris = self.triangles_at(cx, cy);
+        let tri = if tx >= ty { tris.0 } else { tris.1 };
+        // triangles_at winds triangles clockwise seen from above
+        match tri.normal() {
+            Some(n) => n.dot(&ls_dir) >= F::zero(),
+            None => false,
+        }
     }
 
-    fn subshape_containing_feature(&self, _id: FeatureId) -> usize {
-        unimplemented!()
+    fn subshape_containing_feature(&self, id: FeatureId) -> usize {
+        let cells = (self.dim.0 as usize - 1, self.dim.1 as usize - 1);
+        let n = 2 * cells.0 * cells.1;
+        let w = self.dim.0 as usize;
+        // the first triangle of the cell at vertex (cx, cy), clamped to the map
+        let cell = |v: usize| 2 * ((v % w).min(cells.0 - 1) + (v / w).min(cells.1 - 1) * cells.0);
+        match id {
+            FeatureId::Face(i) => i % n,
+            FeatureId::Vertex(i) => cell(i),
+            FeatureId::Edge(i) => {
+                let t = cell(i / 3);
+                // an edge in the y direction borders the second triangle
+                if i % 3 == 1 { t + 1 } else { t }
+            }
+            FeatureId::Unknown => 0,
+        }
     }
 }
 
@@ -89,91 +146,89 @@ impl<F: RealField> RayCast<F> for Heightmap<F> {
         solid: bool,
     ) -> Option<RayIntersection<F>>
     {
-        let dim = self.dim;
-        let len_frac = self.len_frac;
+        self.cast_ray(m, ray, solid)
+    }
+}
+
+// The solid is the region below the surface and within the map's horizontal
+// extent; only the surface is considered its boundary.
+impl<F: RealField> PointQuery<F> for Heightmap<F> {
+    #[inline]
+    fn project_point(&self, m: &Isometry<F>, pt: &Point3<F>, solid: bool) -> PointProjection<F> {
+        let ls_pt = m.inverse_transform_point(pt);
+        let inside = self.is_below_surface(&ls_pt);
+        if solid && inside {
+            return PointProjection::new(true, *pt);
+        }
+        let (proj, _) = self.project_local_point(&ls_pt);
+        PointProjection::new(inside, m * proj)
+    }
+
+    #[inline]
+    fn project_point_with_feature(&self, m: &Isometry<F>, pt: &Point3<F>) -> (PointProjection<F>, FeatureId) {
+        let ls_pt = m.inverse_transform_point(pt);
+        let inside = self.is_below_surface(&ls_pt);
+        let (proj, fid) = self.project_local_point(&ls_pt);
+        (PointProjection::new(inside, m * proj), fid)
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Convert to an ncollide `HeightField`
+    pub fn to_heightfield(&self) -> HeightField<F> {
+        let rows = Dynamic::new(self.dim.1 as usize);
+        let cols = Dynamic::new(self.dim.0 as usize);
+        let heights = DMatrix::from_row_slice_generic(rows, cols, &self.data[..]);
+        let scale = Vector3::new(self.size.0, convert::<f64, F>(1.0), self.size.1);
+        HeightField::new(heights, scale)
+    }
+    
+    /// Cast many ncollide rays against the heightmap
+    /// 
+    /// Rays are given in the heightmap's local coordinates. This is an adapter
+    /// over [`Heightmap::ray_hits`], yielding the same intersections as
+    /// `toi_and_normal_with_ray` for each ray.
+    pub fn raycast_many(&self, rays: &[Ray<F>]) -> Vec<Option<RayIntersection<F>>> {
+        let n = 2 * (self.dim.0 as usize - 1) * (self.dim.1 as usize - 1);
+        let rays: Vec<_> = rays.iter().map(|ray| super::Ray::new(ray.origin, ray.dir)).collect();
+        self.ray_hits(&rays, F::max_value()).into_iter().map(|hit| hit.map(|hit| {
+            let face = if hit.from_below { hit.triangle + n } else { hit.triangle };
+            RayIntersection::new(hit.toi, hit.normal, FeatureId::Face(face))
+        })).collect()
+    }
+    
+    pub(super) fn cast_ray(&self, m: &Isometry<F>, ray: &Ray<F>, solid: bool) -> Option<RayIntersection<F>> {
+        let cells = (self.dim.0 - 1, self.dim.1 - 1);
         
-        let aabb = self.local_bounding_volume();
+        let aabb = bounding_volume::local_aabb(self);
         let ls_ray = ray.inverse_transform_by(m);
-        let is_pos = (ls_ray.dir.x > F::zero(), ls_ray.dir.y > F::zero());
         let (min_t, max_t) = aabb.clip_ray_parameters(&ls_ray)?;
         
-        // Algorithm: iterate over all cells along the 2D projection of the ray.
-        // Note that multiple interceptions are possible and we must find the
-        // first, so a guess-and-search method is not appropriate.
-        
+        // Algorithm: traverse all cells along the 2D projection of the ray
+        // (see `ray_cells`), testing the triangles of each. Triangles lie
+        // within their cell, so the first cell with an interception contains
+        // the first interception overall.
         let p = ls_ray.point_at(min_t);
-        let mut cell = self.cell_at_coord(p.x, p.y)?;
-
-        loop {
-            if cell.0 + 1 == dim.0 || cell.1 + 1 == dim.1 {
-                continue;   // on edge, not a cell
-            }
+        let dir = (ls_ray.dir.x, ls_ray.dir.y);
+        let convert = |mut inter: RayIntersection<F>, t| {
+            inter.feature = self.convert_feature(inter.feature, t);
+            inter
+        };
+        for cell in self.ray_cells((p.x, p.y), dir, max_t - min_t) {
             let tris = self.triangles_at(cell.0, cell.1);
-            let inter1 = tris.0.toi_and_normal_with_ray(m, ray, solid);
-            let inter2 = tris.1.toi_and_normal_with_ray(m, ray, solid);
-
+            let t = 2 * (cell.0 as usize + cell.1 as usize * cells.0 as usize);
+            let inter1 = tris.0.toi_and_normal_with_ray(m, ray, solid).map(|inter| convert(inter, t));
+            let inter2 = tris.1.toi_and_normal_with_ray(m, ray, solid).map(|inter| convert(inter, t + 1));
+            
             match (inter1, inter2) {
                 (Some(inter1), Some(inter2)) => {
-                    if inter1.toi < inter2.toi {
-                        return Some(inter1);
-                    } else {
-                        return Some(inter2);
-                    }
-                }
-                (Some(inter), None) => {
-                    return Some(inter);
-                }
-                (None, Some(inter)) => {
-                    return Some(inter);
+                    return Some(if inter1.toi <= inter2.toi { inter1 } else { inter2 });
                 }
+                (Some(inter), None) | (None, Some(inter)) => return Some(inter),
                 (None, None) => {}
             }
-
-            let toi_x = if is_pos.0 {
-                let x = convert::<_, F>((cell.0 + 1) as f64) * len_frac.0;
-                (x - ls_ray.origin.x) / ls_ray.dir.x
-            } else if ls_ray.dir.x < F::zero() {
-                let x = convert::<_, F>((cell.0) as f64) * len_frac.0;
-                (x - ls_ray.origin.x) / ls_ray.dir.x
-            } else {
-                F::max_value()
-            };
-
-            let toi_y = if is_pos.1 {
-                let y = convert::<_, F>((cell.1 + 1) as f64) * len_frac.0;
-                (y - ls_ray.origin.y) / ls_ray.dir.y
-            } else if ls_ray.dir.z < F::zero() {
-                let y = convert::<_, F>((cell.1) as f64) * len_frac.0;
-                (y - ls_ray.origin.y) / ls_ray.dir.y
-            } else {
-                F::max_value()
-            };
-
-            if toi_x > max_t && toi_y > max_t {
-                break;
-            }
-
-            if toi_x >= F::zero() && toi_x < toi_y {
-                if is_pos.0 && cell.0 + 2 < dim.0 {
-                    cell.0 += 1
-                } else if !is_pos.0 && cell.0 > 0 {
-                    cell.0 -= 1
-                } else {
-                    break
-                }
-            } else if toi_y >= F::zero() {
-                if is_pos.1 && cell.1 + 2 < dim.1 {
-                    cell.1 += 1
-                } else if !is_pos.1 && cell.1 > 0 {
-                    cell.1 -= 1
-                } else {
-                    break
-                }
-            } else {
-                break
-            }
         }
-
+        
         None
     }
 }
@@ -189,12 +244,132 @@ impl<F: RealField> Heightmap<F> {
         let (x1, y1) = self.coord_of(cx+1, cy+1);
         
         let p00 = Point3::new(x0, y0, self.get(cx, cy));
-        let p01 = Point3::new(x1, y0, self.get(cx, cy + 1));
-        let p10 = Point3::new(x0, y1, self.get(cx + 1, cy));
+        let p01 = Point3::new(x1, y0, self.get(cx + 1, cy));
+        let p10 = Point3::new(x0, y1, self.get(cx, cy + 1));
         let p11 = Point3::new(x1, y1, self.get(cx + 1, cy + 1));
 
         let tri1 = Triangle::new(p01, p00, p11);
         let tri2 = Triangle::new(p00, p10, p11);
         (tri1, tri2)
     }
+    
+    // Convert a feature of triangle `t` from `triangles_at` to a feature of
+    // the heightmap
+    fn convert_feature(&self, fid: FeatureId, t: usize) -> FeatureId {
+        let w = self.dim.0 as usize;
+        let n = 2 * (w - 1) * (self.dim.1 as usize - 1);
+        let c = t / 2;
+        // vertex (cx, cy) of the cell
+        let v = c % (w - 1) + c / (w - 1) * w;
+        // vertices (a, b, c) and edges (ab, bc, ca) of the triangle, the
+        // latter as (start vertex, direction)
+        let (verts, edges) = if t & 1 == 0 {
+            ([v + 1, v, v + w + 1], [(v, 0), (v, 2), (v + 1, 1)])
+        } else {
+            ([v, v + w, v + w + 1], [(v, 1), (v + w, 0), (v, 2)])
+        };
+        match fid {
+            FeatureId::Vertex(i) => FeatureId::Vertex(verts[i]),
+            FeatureId::Edge(i) => FeatureId::Edge(3 * edges[i].0 + edges[i].1),
+            // triangles_at winds triangles clockwise seen from above, so
+            // ncollide reports the upper side as face 1
+            FeatureId::Face(side) => FeatureId::Face(t + (1 - side) * n),
+            FeatureId::Unknown => FeatureId::Unknown,
+        }
+    }
+    
+    // True if the local point is above the map and below its surface
+    fn is_below_surface(&self, p: &Point3<F>) -> bool {
+        self.height_at(p.x, p.y).map(|h| p.z <= h).unwrap_or(false)
+    }
+    
+    // Project a local point onto the nearest point of the surface
+    // 
+    // Cells are searched in square rings about the cell nearest `p`, until
+    // the ring is further than the best projection found.
+    fn project_local_point(&self, p: &Point3<F>) -> (Point3<F>, FeatureId) {
+        let cells = (self.dim.0 - 1, self.dim.1 - 1);
+        let id = Isometry::identity();
+        let ((x0, y0), _, _) = self.bilinear(p.x.max(F::zero()).min(self.size.0), p.y.max(F::zero()).min(self.size.1));
+        let min_frac = self.len_frac.0.min(self.len_frac.1);
+        let max_r = x0.max(cells.0 - 1 - x0).max(y0).max(cells.1 - 1 - y0);
+        
+        // lower bound on the distance from `p` to the cell's bounding box
+        let bound = |cx: u32, cy: u32| {
+            let (xa, ya) = self.coord_of(cx, cy);
+            let (xb, yb) = self.coord_of(cx + 1, cy + 1);
+            let hs = [self.get(cx, cy), self.get(cx + 1, cy), self.get(cx, cy + 1), self.get(cx + 1, cy + 1)];
+            let (za, zb) = hs.iter().fold((hs[0], hs[0]), |(a, b), h| (a.min(*h), b.max(*h)));
+            let gap = |v: F, a: F, b: F| (a - v).max(v - b).max(F::zero());
+            let (gx, gy, gz) = (gap(p.x, xa, xb), gap(p.y, ya, yb), gap(p.z, za, zb));
+            (gx * gx + gy * gy + gz * gz).sqrt()
+        };
+        
+        let mut best = (F::max_value(), *p, FeatureId::Unknown);
+        let mut ring = vec![];
+        for r in 0..=max_r {
+            if r > 0 && best.0 <= convert::<_, F>((r - 1) as f64) * min_frac {
+                break;
+            }
+            ring.clear();
+            for cy in y0.saturating_sub(r)..=(y0 + r).min(cells.1 - 1) {
+                if cy + r == y0 || cy == y0 + r {
+                    ring.extend((x0.saturating_sub(r)..=(x0 + r).min(cells.0 - 1)).map(|cx| (cx, cy)));
+                } else {
+                    if r <= x0 {
+                        ring.push((x0 - r, cy));
+                    }
+                    if x0 + r < cells.0 {
+                        ring.push((x0 + r, cy));
+                    }
+                }
+            }
+            for &(cx, cy) in &ring {
+                if bound(cx, cy) >= best.0 {
+                    continue;
+                }
+                let tris = self.triangles_at(cx, cy);
+                let t = 2 * (cx as usize + cy as usize * cells.0 as usize);
+                for (i, tri) in [tris.0, tris.1].iter().enumerate() {
+                    let (proj, fid) = tri.project_point_with_feature(&id, p);
+                    let d = na::distance(p, &proj.point);
+                    if d < best.0 {
+                        best = (d, proj.point, self.convert_feature(fid, t + i));
+                    }
+                }
+            }
+        }
+        (best.1, best.2)
+    }
+    
+    // Horizontal position of a feature: a vertex, the midpoint of an edge or
+    // the centroid of a face
+    fn feature_point(&self, fid: FeatureId) -> Option<(F, F)> {
+        let (w, hgt) = (self.dim.0 as usize, self.dim.1 as usize);
+        let cells = (w - 1, hgt - 1);
+        let vertex = |v: usize| {
+            if v < w * hgt { Some(((v % w) as f64, (v / w) as f64)) } else { None }
+        };
+        let (x, y) = match fid {
+            FeatureId::Vertex(i) => vertex(i)?,
+            FeatureId::Edge(i) => {
+                let (x, y) = vertex(i / 3)?;
+                match i % 3 {
+                    0 if x < cells.0 as f64 => (x + 0.5, y),
+                    1 if y < cells.1 as f64 => (x, y + 0.5),
+                    2 if x < cells.0 as f64 && y < cells.1 as f64 => (x + 0.5, y + 0.5),
+                    _ => return None,
+                }
+            }
+            FeatureId::Face(i) => {
+                let t = i % (2 * cells.0 * cells.1);
+                let c = t / 2;
+                let (x, y) = ((c % cells.0) as f64, (c / cells.0) as f64);
+                // centroids of the triangles below and above the diagonal
+                if t % 2 == 0 { (x + 2.0 / 3.0, y + 1.0 / 3.0) } else { (x + 1.0 / 3.0, y + 2.0 / 3.0) }
+            }
+            FeatureId::Unknown => return None,
+        };
+        Some((convert::<_, F>(x) * self.len_frac.0, convert::<_, F>(y) * self.len_frac.1))
+    }
 }
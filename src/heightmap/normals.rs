@@ -0,0 +1,43 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField, Vector3};
+use super::Heightmap;
+use crate::grid::Grid;
+
+impl<F: RealField> Heightmap<F> {
+    /// Bake a normal map of `resolution` texels covering the whole map
+    /// 
+    /// Texels are placed at the corners and evenly between, as vertices
+    /// are; normals are computed from the gradient ([`Heightmap::gradient_at`])
+    /// interpolated bilinearly between vertices. Thus a low-resolution mesh
+    /// (e.g. a distant LOD) textured with this map is lit as the full-detail
+    /// map.
+    /// 
+    /// Normals are unit vectors `(x, y, up)` with heights scaled by
+    /// `strength` (1 for true normals; larger exaggerates relief). The `y`
+    /// component points towards increasing `y` (rows); negate it for
+    /// conventions where texture `v` points the other way. See
+    /// [`write_normal_png`](crate::io::write_normal_png) to encode as RGB.
+    pub fn bake_normal_map(&self, resolution: (u32, u32), strength: F) -> Grid<Vector3<F>> {
+        trace_span!("bake_normal_map", dim = ?self.dim, resolution = ?resolution);
+        assert!(resolution.0 >= 2 && resolution.1 >= 2);
+        let step: (F, F) = (self.size.0 / convert((resolution.0 - 1) as f64),
+            self.size.1 / convert((resolution.1 - 1) as f64));
+        let gradients = Grid::from_fn(self.dim, |cx, cy| self.gradient_at(cx, cy));
+        Grid::from_fn(resolution, |ix, iy| {
+            let (x, y): (F, F) = (convert::<_, F>(ix as f64) * step.0, convert::<_, F>(iy as f64) * step.1);
+            let ((cx, cy), tx, ty) = self.bilinear(x, y);
+            let lerp = |a: (F, F), b: (F, F), t: F| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+            let g0 = lerp(gradients.get(cx, cy), gradients.get(cx + 1, cy), tx);
+            let g1 = lerp(gradients.get(cx, cy + 1), gradients.get(cx + 1, cy + 1), tx);
+            let g = lerp(g0, g1, ty);
+            Vector3::new(-g.0 * strength, -g.1 * strength, F::one()).normalize()
+        })
+    }
+}
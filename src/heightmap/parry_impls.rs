@@ -0,0 +1,118 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Integration with parry3d (and rapier3d). These crates use a newer nalgebra
+// and a fixed `f32` scalar, so conversion is via `f32` values.
+
+use nalgebra::{try_convert, RealField};
+
+use super::Heightmap;
+
+// Build a parry `HeightField` with the given parry crate path (parry3d may
+// also be used via rapier3d's re-export), optionally with rows in reverse
+// order (i.e. mirrored in z)
+macro_rules! heightfield {
+    ($m:expr, $flip:expr, $($parry:ident)::+) => {{
+        use $($parry)::+::na::{DMatrix, Vector3};
+        use $($parry)::+::shape::{HeightField, HeightFieldCellStatus};
+        let (m, flip) = ($m, $flip);
+        let rows = m.dim.1 as usize;
+        let heights = DMatrix::from_fn(rows, m.dim.0 as usize, |i, j| {
+            m.height_f32(j, if flip { rows - 1 - i } else { i })
+        });
+        let size = m.size_f32();
+        let mut hf = HeightField::new(heights, Vector3::new(size.0, 1.0, size.1));
+        // parry splits cells along the other diagonal by default, which
+        // matches ours only when mirrored
+        if !flip {
+            for status in hf.cells_statuses_mut().iter_mut() {
+                *status = HeightFieldCellStatus::ZIGZAG_SUBDIVISION;
+            }
+        }
+        hf
+    }};
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Convert to a parry3d `HeightField` (`parry3d` feature)
+    /// 
+    /// Parry height fields use a Y-up frame centred on the origin: the point
+    /// `(x, y, h)` of this heightmap corresponds to the point
+    /// `(x - size.0 / 2, h, y - size.1 / 2)` of the height field. The same
+    /// triangulation is used, so heights agree exactly.
+    #[cfg(feature = "parry3d")]
+    pub fn to_parry_heightfield(&self) -> parry3d::shape::HeightField {
+        heightfield!(self, false, parry3d)
+    }
+    
+    /// Build a rapier3d collider (`rapier3d` feature)
+    /// 
+    /// The collider uses the same Y-up frame as
+    /// [`TerrainMesh::orient`](crate::mesh::TerrainMesh::orient): the point
+    /// `(x, y, h)` of this heightmap is at `(x, h, -y)`, thus the collider
+    /// matches the mesh from `to_trimesh(UpAxis::Y)`. Its shape is a height
+    /// field with rows in reverse order, translated by
+    /// `(size.0 / 2, 0, -size.1 / 2)`.
+    #[cfg(feature = "rapier3d")]
+    pub fn to_rapier_collider(&self) -> rapier3d::geometry::ColliderBuilder {
+        use rapier3d::geometry::{ColliderBuilder, SharedShape};
+        let hf = heightfield!(self, true, rapier3d::parry);
+        let size = self.size_f32();
+        ColliderBuilder::new(SharedShape::new(hf))
+            .translation(rapier3d::na::Vector3::new(size.0 * 0.5, 0.0, -size.1 * 0.5))
+    }
+    
+    fn height_f32(&self, cx: usize, cy: usize) -> f32 {
+        try_convert::<_, f64>(self.data[cx + cy * self.dim.0 as usize]).unwrap() as f32
+    }
+    
+    fn size_f32(&self) -> (f32, f32) {
+        let size = (try_convert::<_, f64>(self.size.0).unwrap(), try_convert::<_, f64>(self.size.1).unwrap());
+        (size.0 as f32, size.1 as f32)
+    }
+}
+
+/// Ray casting in the heightmap's own (Z-up) frame
+/// 
+/// Cells along the ray are traversed as for [`Heightmap::ray_hit`], testing
+/// their triangles. Hits are reported as face features: the upper side of
+/// triangle `t` (see [`RayHit::triangle`](super::RayHit::triangle)) is face
+/// `t`, and the lower side face `t + n` where `n` is the number of triangles.
+#[cfg(feature = "parry3d")]
+impl parry3d::query::RayCast for Heightmap<f32> {
+    fn cast_local_ray_and_get_normal(&self, ray: &parry3d::query::Ray, max_time_of_impact: f32, solid: bool)
+        -> Option<parry3d::query::RayIntersection>
+    {
+        use parry3d::na::Point3;
+        use parry3d::query::RayIntersection;
+        use parry3d::shape::{FeatureId, Triangle};
+        let (min_t, max_t) = self.clip_to_range(ray.origin.z, ray.dir.z, max_time_of_impact)?;
+        let n = 2 * (self.dim.0 as usize - 1) * (self.dim.1 as usize - 1);
+        
+        let p = ray.point_at(min_t);
+        for cell in self.ray_cells((p.x, p.y), (ray.dir.x, ray.dir.y), max_t - min_t) {
+            let (t, tris) = self.cell_triangles(cell.0, cell.1);
+            let mut best: Option<RayIntersection> = None;
+            for (i, tri) in tris.iter().enumerate() {
+                let [a, b, c] = tri.map(|p| Point3::new(p.x, p.y, p.z));
+                let inter = Triangle::new(a, b, c).cast_local_ray_and_get_normal(ray, max_time_of_impact, solid);
+                if let Some(mut inter) = inter {
+                    if best.is_none_or(|best| inter.time_of_impact < best.time_of_impact) {
+                        let face = if inter.normal.z < 0.0 { t + i + n } else { t + i };
+                        inter.feature = FeatureId::Face(face as u32);
+                        best = Some(inter);
+                    }
+                }
+            }
+            if best.is_some() {
+                return best;
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use rand::Rng;
+use super::{range, Heightmap};
+use crate::grid::Grid;
+use crate::unbounded::{Worley, WorleyMode};
+
+/// Type of periglacial patterned ground
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroundPattern {
+    /// Low-centred ice-wedge polygons: troughs along the polygon edges,
+    /// flanked by low rims
+    IceWedge,
+    /// Sorted stone circles: domes of fine soil ringed by stones
+    StoneCircles,
+}
+
+/// Patterned ground parameters
+/// 
+/// Freeze–thaw cycling over permafrost sorts the ground into polygonal
+/// networks (Worley cells of mean diameter `cell_size`). On slopes the
+/// polygons are drawn out into stripes running downhill: patterns blend from
+/// polygons to stripes as slope increases to `stripe_slope`.
+/// 
+/// The pass is driven by a per-vertex mean annual temperature: patterns fade
+/// in as temperature falls below `freeze_temperature`, reaching full strength
+/// `transition` degrees lower.
+/// 
+/// Patterns are a few metres across and are thus only resolved on maps with
+/// several vertices per cell; on coarser maps use the returned masks for
+/// materials and detail textures.
+#[derive(Debug, Clone, Copy)]
+pub struct PatternedGroundParams<F> {
+    /// Pattern type on flat ground
+    pub pattern: GroundPattern,
+    /// Mean diameter of polygons or circles, and spacing of stripes
+    pub cell_size: F,
+    /// Height of the relief (trough depth or dome height)
+    pub relief: F,
+    /// Temperature below which patterns form
+    pub freeze_temperature: F,
+    /// Temperature range over which patterns fade in
+    pub transition: F,
+    /// Slope (rise over run) at which patterns become stripes
+    pub stripe_slope: F,
+}
+
+/// The result of a patterned ground pass
+#[derive(Debug, Clone)]
+pub struct PatternedGround<F> {
+    /// Strength of patterning (permafrost extent) per vertex, in `[0, 1]`
+    pub strength: Grid<F>,
+    /// Weight of pattern borders per vertex, in `[0, 1]`: stone rings and
+    /// stripes of sorted patterns, troughs of ice-wedge polygons
+    pub borders: Grid<F>,
+}
+
+impl<F: RealField> PatternedGroundParams<F> {
+    /// Apply to `m`, given a temperature per vertex
+    pub fn apply_to<R: Rng + ?Sized>(&self, m: &mut Heightmap<F>, temperature: &Grid<F>, rng: &mut R)
+        -> PatternedGround<F>
+    {
+        trace_span!("patterned_ground", dim = ?m.dim());
+        assert_eq!(temperature.dim(), m.dim());
+        assert!(self.cell_size > F::zero());
+        let worley = Worley::new(F::one() / self.cell_size, WorleyMode::F2MinusF1, rng);
+        let (zero, one, half): (F, F, F) = (F::zero(), F::one(), convert(0.5));
+        let smoothstep = |t: F| {
+            let t = t.max(zero).min(one);
+            t * t * (convert::<_, F>(3.0) - t - t)
+        };
+        let width = self.cell_size * convert(0.12);
+        let dim = m.dim();
+        
+        let mut strength = Grid::new(dim, zero);
+        let mut borders = Grid::new(dim, zero);
+        let mut offsets = Grid::new(dim, zero);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let t = temperature.get(cx, cy);
+                let s = if self.transition > zero {
+                    ((self.freeze_temperature - t) / self.transition).max(zero).min(one)
+                } else if t < self.freeze_temperature {
+                    one
+                } else {
+                    zero
+                };
+                if s == zero {
+                    continue;
+                }
+                let (x, y) = m.coord_of(cx, cy);
+                
+                // polygons: distance from the cell edge
+                let (d1, d2) = worley.distances(x, y);
+                let e = (d2 - d1) * self.cell_size * half;
+                let edge = one - smoothstep(e / width);
+                let (polygon, polygon_border) = match self.pattern {
+                    GroundPattern::IceWedge => {
+                        // trough, then a rim of half the height
+                        let trough = (one - e / width).max(zero);
+                        let rim = if e > width && e < width * convert(3.0) {
+                            ((e - width) / (width + width) * F::pi()).sin() * half
+                        } else {
+                            zero
+                        };
+                        ((rim - trough * trough) * self.relief, edge)
+                    }
+                    GroundPattern::StoneCircles => {
+                        let dome = smoothstep(e / (self.cell_size * convert(0.35)));
+                        (dome * self.relief, edge)
+                    }
+                };
+                
+                // stripes: alternating across the slope
+                let (gx, gy) = m.gradient_at(cx, cy);
+                let slope = (gx * gx + gy * gy).sqrt();
+                let w = if self.stripe_slope > zero {
+                    smoothstep((slope / self.stripe_slope - half) / half)
+                } else {
+                    zero
+                };
+                let (h, border) = if w > zero {
+                    let across = (x * gy - y * gx) / slope;
+                    let c = (across / self.cell_size * F::two_pi()).cos();
+                    let stripe_border = smoothstep((-c - half) / half);
+                    (polygon * (one - w) + c * half * self.relief * w,
+                        polygon_border * (one - w) + stripe_border * w)
+                } else {
+                    (polygon, polygon_border)
+                };
+                strength.set(cx, cy, s);
+                borders.set(cx, cy, border * s);
+                offsets.set(cx, cy, h * s);
+            }
+        }
+        
+        // apply after computing slopes on the unmodified map
+        for (h, d) in m.data.iter_mut().zip(offsets.data()) {
+            *h += *d;
+        }
+        m.range = range(&m.data);
+        PatternedGround { strength, borders }
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::BTreeSet;
+use nalgebra::RealField;
+use rand::Rng;
+use super::Heightmap;
+use super::drainage::accumulation;
+use super::search::grow_regions;
+use crate::grid::Grid;
+use crate::unbounded::UnboundedSurface;
+
+/// Province partitioning parameters
+/// 
+/// Provinces are grown from seed vertices: each vertex belongs to the seed
+/// with the least path cost, where cost is distance measured in warped
+/// coordinates (a noise-warped Voronoi diagram) plus a penalty for crossing
+/// rivers and ridges. Where a border would lie near such a feature, it thus
+/// snaps to the feature.
+#[derive(Debug, Clone, Copy)]
+pub struct Provinces<F> {
+    /// Number of provinces to generate
+    pub count: usize,
+    /// Vertices below this height (e.g. sea) are not assigned a province
+    pub sea_level: Option<F>,
+    /// Vertices whose upstream catchment area is at least this are rivers
+    pub river_area: Option<F>,
+    /// Vertices whose catchment area on the inverted terrain is at least this
+    /// are ridges
+    pub ridge_area: Option<F>,
+    /// Cost (as a distance) of crossing a river or ridge
+    /// 
+    /// Borders within roughly half this distance of a feature snap to it.
+    pub snap_cost: F,
+}
+
+/// The result of partitioning a map into provinces
+#[derive(Debug, Clone)]
+pub struct ProvinceMap {
+    /// Seed vertex of each province
+    pub seeds: Vec<(u32, u32)>,
+    /// Province index of each vertex, if any
+    pub ids: Grid<Option<u32>>,
+    /// For each province, the sorted indices of provinces sharing a border
+    pub adjacency: Vec<Vec<u32>>,
+    /// River and ridge vertices used for snapping
+    pub features: Grid<bool>,
+}
+
+impl ProvinceMap {
+    /// Iterate over adjacent province pairs `(a, b)` with `a < b`
+    pub fn edges(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.adjacency.iter().enumerate().flat_map(|(a, adj)| {
+            adj.iter().filter(move |b| **b > a as u32).map(move |b| (a as u32, *b))
+        })
+    }
+}
+
+impl<F: RealField> Provinces<F> {
+    /// Partition `m` into provinces with randomly placed seeds
+    /// 
+    /// Seeds are placed on land vertices by best-candidate sampling, giving
+    /// roughly even spacing. If `warp` is given, coordinates are displaced by
+    /// `amplitude * warp.get(x, y)` along the x-axis and
+    /// `amplitude * warp.get(y, -x)` along the y-axis.
+    pub fn partition<R: Rng + ?Sized>(&self, m: &Heightmap<F>, warp: Option<(&dyn UnboundedSurface<F>, F)>,
+        rng: &mut R) -> ProvinceMap
+    {
+        let dim = m.dim();
+        let land: Vec<(u32, u32)> = (0..dim.1)
+            .flat_map(|cy| (0..dim.0).map(move |cx| (cx, cy)))
+            .filter(|c| self.is_land(m, *c))
+            .collect();
+        let mut seeds: Vec<(u32, u32)> = Vec::with_capacity(self.count);
+        if !land.is_empty() {
+            let dist2 = |a: (u32, u32), b: (u32, u32)| {
+                let (a, b) = (m.coord_of(a.0, a.1), m.coord_of(b.0, b.1));
+                (a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)
+            };
+            for _ in 0..self.count.min(land.len()) {
+                let mut best = (F::zero(), land[rng.gen_range(0, land.len())]);
+                for _ in 0..10 {
+                    let c = land[rng.gen_range(0, land.len())];
+                    let d = seeds.iter().map(|s| dist2(*s, c)).fold(F::max_value(), |a, b| a.min(b));
+                    if d > best.0 {
+                        best = (d, c);
+                    }
+                }
+                seeds.push(best.1);
+            }
+        }
+        self.partition_with_seeds(m, seeds, warp)
+    }
+    
+    /// Partition `m` into provinces grown from the given seed vertices
+    /// 
+    /// See [`Provinces::partition`]. `count` is ignored.
+    pub fn partition_with_seeds(&self, m: &Heightmap<F>, seeds: Vec<(u32, u32)>,
+        warp: Option<(&dyn UnboundedSurface<F>, F)>) -> ProvinceMap
+    {
+        let dim = m.dim();
+        let warped = Grid::from_fn(dim, |cx, cy| {
+            let (x, y) = m.coord_of(cx, cy);
+            match warp {
+                Some((surface, amplitude)) => {
+                    (x + amplitude * surface.get(x, y), y + amplitude * surface.get(y, -x))
+                }
+                None => (x, y),
+            }
+        });
+        
+        let river = self.river_area.map(|a| (accumulation(m, false), a));
+        let ridge = self.ridge_area.map(|a| (accumulation(m, true), a));
+        let over = |f: &Option<(Grid<F>, F)>, cx, cy| f.as_ref().map(|f| f.0.get(cx, cy) >= f.1).unwrap_or(false);
+        let features = Grid::from_fn(dim, |cx, cy| over(&river, cx, cy) || over(&ridge, cx, cy));
+        
+        let regions = grow_regions(m, &seeds, |a, b| {
+            if !self.is_land(m, a) || !self.is_land(m, b) {
+                return None;
+            }
+            let (pa, pb) = (warped.get(a.0, a.1), warped.get(b.0, b.1));
+            let mut cost = ((pb.0 - pa.0) * (pb.0 - pa.0) + (pb.1 - pa.1) * (pb.1 - pa.1)).sqrt();
+            if features.get(b.0, b.1) && !features.get(a.0, a.1) {
+                cost += self.snap_cost;
+            }
+            Some(cost)
+        });
+        let mut regions = regions.into_iter();
+        let ids = Grid::from_fn(dim, |_, _| regions.next().unwrap());
+        
+        let mut adjacency = vec![BTreeSet::new(); seeds.len()];
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let a = ids.get(cx, cy);
+                for (nx, ny) in [(cx + 1, cy), (cx, cy + 1)] {
+                    if nx >= dim.0 || ny >= dim.1 {
+                        continue;
+                    }
+                    if let (Some(a), Some(b)) = (a, ids.get(nx, ny)) {
+                        if a != b {
+                            adjacency[a as usize].insert(b);
+                            adjacency[b as usize].insert(a);
+                        }
+                    }
+                }
+            }
+        }
+        let adjacency = adjacency.into_iter().map(|s| s.into_iter().collect()).collect();
+        
+        ProvinceMap { seeds, ids, adjacency, features }
+    }
+    
+    fn is_land(&self, m: &Heightmap<F>, c: (u32, u32)) -> bool {
+        self.sea_level.map(|s| m.get(c.0, c.1) >= s).unwrap_or(true)
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+use crate::unbounded::{hash, UnboundedSurface};
+
+/// Generator parameters of a region (e.g. a biome)
+/// 
+/// A [`Grid`] of these varies parameters over a map; it may be derived from a
+/// continuous control grid via [`Grid::map`] and [`RegionParams::lerp`], or
+/// from a biome map via [`blend_biomes`]. See [`regional_fbm`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegionParams<F> {
+    /// Amplitude of the first octave
+    pub amplitude: F,
+    /// Frequency multiplier between octaves (typically around 2)
+    pub lacunarity: F,
+    /// Amplitude multiplier between octaves (typically around 0.5)
+    pub persistence: F,
+    /// Erosion strength, for erosion passes accepting a per-vertex strength
+    /// (see [`Grid::map`] to extract a grid of this value)
+    pub erosion: F,
+}
+
+impl<F: RealField> RegionParams<F> {
+    /// Linearly interpolate between `self` (`t = 0`) and `other` (`t = 1`)
+    /// 
+    /// For example, `control.map(|t| plains.lerp(&mountains, *t))` converts a
+    /// control grid with values in `[0, 1]` to parameters.
+    pub fn lerp(&self, other: &Self, t: F) -> Self {
+        let lerp = |a: F, b: F| a + (b - a) * t;
+        RegionParams {
+            amplitude: lerp(self.amplitude, other.amplitude),
+            lacunarity: lerp(self.lacunarity, other.lacunarity),
+            persistence: lerp(self.persistence, other.persistence),
+            erosion: lerp(self.erosion, other.erosion),
+        }
+    }
+}
+
+/// Convert a biome map to smoothly varying parameters
+/// 
+/// Each vertex of `biomes` is an index into `table`. Parameters are averaged
+/// over a neighbourhood of `radius` vertices (two passes of a box filter,
+/// thus transitions between biomes are smooth over roughly `2 * radius`
+/// vertices).
+pub fn blend_biomes<F: RealField>(biomes: &Grid<usize>, table: &[RegionParams<F>], radius: u32)
+    -> Grid<RegionParams<F>>
+{
+    let dim = biomes.dim();
+    let mut result = Grid::new(dim, RegionParams {
+        amplitude: F::zero(), lacunarity: F::zero(), persistence: F::zero(), erosion: F::zero(),
+    });
+    for (b, params) in table.iter().enumerate() {
+        let mut weight = biomes.map(|i| if *i == b { F::one() } else { F::zero() });
+        if weight.data().iter().all(|w| *w == F::zero()) {
+            continue;
+        }
+        for _ in 0..2 {
+            box_blur(&mut weight, radius);
+        }
+        for (r, w) in result.data_mut().iter_mut().zip(weight.data()) {
+            r.amplitude += params.amplitude * *w;
+            r.lacunarity += params.lacunarity * *w;
+            r.persistence += params.persistence * *w;
+            r.erosion += params.erosion * *w;
+        }
+    }
+    result
+}
+
+// Separable box filter of the given radius, clamped at edges
+fn box_blur<F: RealField>(grid: &mut Grid<F>, radius: u32) {
+    let dim = grid.dim();
+    let r = radius as i64;
+    let mut line = Vec::new();
+    for axis in 0..2 {
+        let (len, lines) = if axis == 0 { (dim.0, dim.1) } else { (dim.1, dim.0) };
+        for l in 0..lines {
+            let at = |i: u32| if axis == 0 { (i, l) } else { (l, i) };
+            line.clear();
+            line.extend((0..len).map(|i| { let c = at(i); grid.get(c.0, c.1) }));
+            let mut sum = F::zero();
+            let clamp = |i: i64| line[i.max(0).min(len as i64 - 1) as usize];
+            for i in -r..=r {
+                sum += clamp(i);
+            }
+            let n: F = convert((2 * r + 1) as f64);
+            for i in 0..len as i64 {
+                let c = at(i as u32);
+                grid.set(c.0, c.1, sum / n);
+                sum += clamp(i + r + 1) - clamp(i - r);
+            }
+        }
+    }
+}
+
+/// Add fractal noise with spatially varying parameters
+/// 
+/// This generates fBm (a sum of `octaves` noise octaves) with the amplitude,
+/// lacunarity and persistence at each vertex taken from `params` (of the same
+/// dimension as `m`), thus e.g. mountains, plains and coasts emerge from a
+/// single pass. Octave `i` has amplitude `amplitude * persistence^i` and
+/// frequency `base_scale * lacunarity^i`.
+/// 
+/// `noise` should have unit frequency and range approximately `[-1, 1]`
+/// (e.g. [`Perlin`](crate::unbounded::Perlin) with `scale = 1`). To avoid the
+/// distortion caused by scaling coordinates with a varying factor, each
+/// octave is interpolated between layers of fixed power-of-two frequency.
+pub fn regional_fbm<F: RealField, S: UnboundedSurface<F>>(m: &mut Heightmap<F>, noise: &S, base_scale: F,
+    octaves: u32, params: &Grid<RegionParams<F>>)
+{
+    trace_span!("regional_fbm", dim = ?m.dim(), octaves);
+    assert_eq!(params.dim(), m.dim());
+    let two: F = convert(2.0);
+    // Offset per layer to decorrelate layers
+    let offset = |n: i32| -> (F, F) {
+        let k = (n as i64 as u64).wrapping_mul(2);
+        let f = |k: u64| convert::<_, F>(f64::from(hash(k)) / f64::from(u32::MAX) * 4096.0);
+        (f(k), f(k + 1))
+    };
+    let mut cache: Vec<(i32, F)> = Vec::new();
+    for cy in 0..m.dim().1 {
+        for cx in 0..m.dim().0 {
+            let (x, y) = m.coord_of(cx, cy);
+            let p = params.get(cx, cy);
+            cache.clear();
+            let mut layer = |n: i32| -> F {
+                if let Some(v) = cache.iter().find(|c| c.0 == n) {
+                    return v.1;
+                }
+                let s = base_scale * two.powi(n);
+                let o = offset(n);
+                let v = noise.get(x * s + o.0, y * s + o.1);
+                cache.push((n, v));
+                v
+            };
+            let log_lac = p.lacunarity.max(F::default_epsilon()).log2();
+            let (mut sum, mut amp) = (F::zero(), p.amplitude);
+            for i in 0..octaves {
+                let k = log_lac * convert(i as f64);
+                let n0 = k.floor();
+                let t = k - n0;
+                let n0 = try_convert::<_, f64>(n0).unwrap() as i32;
+                let v = layer(n0) * (F::one() - t) + layer(n0 + 1) * t;
+                // normalise the variance of the blend of independent layers
+                let norm = ((F::one() - t).powi(2) + t * t).sqrt();
+                sum += amp * v / norm;
+                amp *= p.persistence;
+            }
+            let h = m.get(cx, cy) + sum;
+            m.set(cx, cy, h);
+        }
+    }
+}
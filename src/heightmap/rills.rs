@@ -0,0 +1,103 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::Ordering;
+use nalgebra::{convert, RealField};
+use rand::Rng;
+use super::{drainage::{accumulation, downstream}, range, Heightmap};
+use crate::grid::Grid;
+
+/// Rill and gully carving parameters
+/// 
+/// A fast detail pass: rather than simulating water and sediment (see
+/// [`ErosionSession`](super::ErosionSession)), channels are incised directly
+/// by stream power. Each iteration routes flow over the terrain (D8),
+/// perturbed by random micro-relief of amplitude `roughness` so that flow
+/// paths wander and converge even on smooth slopes, then
+/// lowers each vertex with catchment area `A` at least `min_area` and slope
+/// `S` at least `min_slope` by `erodibility × (A - min_area)^area_exponent × S`,
+/// up to a total of `max_depth`. On steep slopes this cuts dense parallel
+/// rills; over further iterations, flow captured by deeper channels merges
+/// them into gullies.
+#[derive(Debug, Clone, Copy)]
+pub struct RillParams<F> {
+    /// Minimum slope (rise over run) on which channels are cut
+    pub min_slope: F,
+    /// Catchment area at which channels begin
+    pub min_area: F,
+    /// Incision per unit stream power
+    pub erodibility: F,
+    /// Exponent of catchment area in stream power; typically around 0.5
+    pub area_exponent: F,
+    /// Maximum total incision
+    pub max_depth: F,
+    /// Amplitude of random micro-relief used when routing flow
+    pub roughness: F,
+    /// Number of iterations
+    pub iterations: u32,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Carve rills and gullies
+    /// 
+    /// `bare`, if given, has one weight in `[0, 1]` per vertex scaling
+    /// incision, e.g. zero under vegetation or on hard rock. Vertices always
+    /// remain above their downstream neighbour, thus no pits are created.
+    /// 
+    /// Returns the depth of incision.
+    pub fn carve_rills<R: Rng + ?Sized>(&mut self, params: &RillParams<F>, bare: Option<&Grid<F>>, rng: &mut R)
+        -> Grid<F>
+    {
+        trace_span!("carve_rills", dim = ?self.dim, iterations = params.iterations);
+        if let Some(mask) = bare {
+            assert_eq!(mask.dim(), self.dim);
+        }
+        let relief: Vec<F> = (0..self.data.len()).map(|_| params.roughness * convert(rng.gen::<f64>())).collect();
+        let mut incision = Grid::new(self.dim, F::zero());
+        let mut order: Vec<(u32, u32)> = (0..self.dim.1)
+            .flat_map(|cy| (0..self.dim.0).map(move |cx| (cx, cy)))
+            .collect();
+        for _ in 0..params.iterations {
+            let mut rough = self.clone();
+            for (h, r) in rough.data.iter_mut().zip(&relief) {
+                *h += *r;
+            }
+            let area = accumulation(&rough, false);
+            let next = downstream(&rough, false);
+            // lowest first, so downstream heights are final
+            order.sort_by(|a, b| self.get(a.0, a.1).partial_cmp(&self.get(b.0, b.1)).unwrap_or(Ordering::Equal));
+            for &(cx, cy) in &order {
+                let n = match next.get(cx, cy) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let h = self.get(cx, cy);
+                let hn = self.get(n.0, n.1);
+                let slope = (h - hn) / self.distance((cx, cy), n);
+                let a = area.get(cx, cy) - params.min_area;
+                // (routing over micro-relief may lead uphill)
+                if slope < params.min_slope || a <= F::zero() {
+                    continue;
+                }
+                let mut d = params.erodibility * a.powf(params.area_exponent) * slope;
+                if let Some(mask) = bare {
+                    d *= mask.get(cx, cy);
+                }
+                let done = incision.get(cx, cy);
+                // keep some fall to the (already carved) downstream vertex
+                d = d.min(params.max_depth - done).min((h - hn) * convert(0.9));
+                if d > F::zero() {
+                    self.data[(cy * self.dim.0 + cx) as usize] = h - d;
+                    incision.set(cx, cy, done + d);
+                }
+            }
+        }
+        self.range = range(&self.data);
+        incision
+    }
+}
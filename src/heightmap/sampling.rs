@@ -0,0 +1,133 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, RealField};
+use rand::{Rng, distributions::Distribution};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Random position sampler over a heightmap
+/// 
+/// Positions are drawn over the (horizontal) area of the map, optionally
+/// weighted by a per-vertex grid (e.g. spawn suitability) and constrained to
+/// a per-vertex mask. Each cell has weight equal to the mean of its corner
+/// weights and is excluded if any corner is masked out; within a cell
+/// positions are uniform.
+/// 
+/// Construction builds an alias table in `O(n)` for `n` cells, after which
+/// each draw is `O(1)`. Sample via [`Distribution`], e.g.
+/// `rng.sample(&sampler)`, yielding world coordinates `(x, y)`.
+#[derive(Debug, Clone)]
+pub struct PositionSampler<F> {
+    cells: Vec<u32>,
+    prob: Vec<f64>,
+    alias: Vec<u32>,
+    width: u32,
+    cell_size: (F, F),
+}
+
+impl<F: RealField> PositionSampler<F> {
+    /// Sample uniformly over the area of `m`
+    pub fn uniform(m: &Heightmap<F>) -> Self {
+        Self::new(m, None, None).unwrap()
+    }
+    
+    /// Construct a sampler
+    /// 
+    /// `weights` and `mask` (if given) must have the dimension of `m`;
+    /// vertices where `mask` is false are excluded. Weights must be
+    /// non-negative. Returns `None` if the total weight is zero (e.g. all
+    /// cells are masked out).
+    pub fn new(m: &Heightmap<F>, weights: Option<&Grid<F>>, mask: Option<&Grid<bool>>) -> Option<Self> {
+        let dim = m.dim();
+        if let Some(w) = weights {
+            assert_eq!(w.dim(), dim);
+        }
+        if let Some(mask) = mask {
+            assert_eq!(mask.dim(), dim);
+        }
+        trace_span!("position_sampler", dim = ?dim);
+        
+        let corners = |cx, cy| [(cx, cy), (cx + 1, cy), (cx, cy + 1), (cx + 1, cy + 1)];
+        let mut cells = vec![];
+        let mut cell_weights = vec![];
+        for cy in 0..dim.1 - 1 {
+            for cx in 0..dim.0 - 1 {
+                if let Some(mask) = mask {
+                    if !corners(cx, cy).iter().all(|c| mask.get(c.0, c.1)) {
+                        continue;
+                    }
+                }
+                let w = match weights {
+                    Some(w) => corners(cx, cy).iter()
+                        .map(|c| try_convert::<F, f64>(w.get(c.0, c.1)).unwrap())
+                        .sum::<f64>() * 0.25,
+                    None => 1.0,
+                };
+                assert!(w >= 0.0, "negative weight");
+                if w > 0.0 {
+                    cells.push(cy * (dim.0 - 1) + cx);
+                    cell_weights.push(w);
+                }
+            }
+        }
+        if cells.is_empty() {
+            return None;
+        }
+        
+        let (prob, alias) = alias_table(&cell_weights);
+        Some(PositionSampler {
+            cells,
+            prob,
+            alias,
+            width: dim.0 - 1,
+            cell_size: m.cell_size(),
+        })
+    }
+    
+    /// Number of cells which may be sampled
+    pub fn num_cells(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+impl<F: RealField> Distribution<(F, F)> for PositionSampler<F> {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> (F, F) {
+        let i = rng.gen_range(0, self.prob.len());
+        let i = if rng.gen::<f64>() < self.prob[i] { i } else { self.alias[i] as usize };
+        let cell = self.cells[i];
+        let cx = (cell % self.width) as f64 + rng.gen::<f64>();
+        let cy = (cell / self.width) as f64 + rng.gen::<f64>();
+        (convert::<_, F>(cx) * self.cell_size.0, convert::<_, F>(cy) * self.cell_size.1)
+    }
+}
+
+// Build an alias table (Vose's method) for the given non-negative weights
+// with positive sum. Returns the acceptance probability and alias of each
+// entry.
+fn alias_table(weights: &[f64]) -> (Vec<f64>, Vec<u32>) {
+    let n = weights.len();
+    let scale = n as f64 / weights.iter().sum::<f64>();
+    let mut prob: Vec<f64> = weights.iter().map(|w| w * scale).collect();
+    let mut alias: Vec<u32> = (0..n as u32).collect();
+    let (mut small, mut large): (Vec<u32>, Vec<u32>) = (0..n as u32).partition(|&i| prob[i as usize] < 1.0);
+    while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+        small.pop();
+        alias[s as usize] = l;
+        prob[l as usize] -= 1.0 - prob[s as usize];
+        if prob[l as usize] < 1.0 {
+            large.pop();
+            small.push(l);
+        }
+    }
+    // Remaining entries are (up to rounding error) exactly 1
+    for i in small.into_iter().chain(large) {
+        prob[i as usize] = 1.0;
+    }
+    (prob, alias)
+}
@@ -0,0 +1,177 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Graph search over heightmap vertices
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use nalgebra::RealField;
+use super::Heightmap;
+
+// Heap entry ordered by lowest cost first
+pub(super) struct Entry<F> {
+    pub(super) cost: F,
+    pub(super) index: usize,
+}
+
+impl<F: RealField> PartialEq for Entry<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl<F: RealField> Eq for Entry<F> {}
+
+impl<F: RealField> PartialOrd for Entry<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: RealField> Ord for Entry<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+// Find the cheapest path from `start` to `goal` over 8-connected vertices.
+//
+// `cost(a, b)` gives the cost of a step between adjacent vertices; `None`
+// marks the step as impassable. Returns the path (including both ends) and
+// its cost.
+pub(crate) fn shortest_path<F, C>(m: &Heightmap<F>, start: (u32, u32), goal: (u32, u32), mut cost: C)
+    -> Option<(Vec<(u32, u32)>, F)>
+where F: RealField, C: FnMut((u32, u32), (u32, u32)) -> Option<F>
+{
+    let w = m.dim().0 as usize;
+    let index = |c: (u32, u32)| (c.0 as usize) + (c.1 as usize) * w;
+    let vertex = |i: usize| ((i % w) as u32, (i / w) as u32);
+    let len = w * m.dim().1 as usize;
+    let mut dist = vec![F::max_value(); len];
+    let mut prev = vec![usize::MAX; len];
+    let mut heap = BinaryHeap::new();
+    
+    dist[index(start)] = F::zero();
+    heap.push(Entry { cost: F::zero(), index: index(start) });
+    while let Some(Entry { cost: d, index: i }) = heap.pop() {
+        if d > dist[i] {
+            continue;
+        }
+        let c = vertex(i);
+        if c == goal {
+            let mut path = vec![c];
+            let mut j = i;
+            while prev[j] != usize::MAX {
+                j = prev[j];
+                path.push(vertex(j));
+            }
+            path.reverse();
+            return Some((path, d));
+        }
+        for n in m.neighbours(c.0, c.1) {
+            if let Some(step) = cost(c, n) {
+                let j = index(n);
+                let dn = d + step;
+                if dn < dist[j] {
+                    dist[j] = dn;
+                    prev[j] = i;
+                    heap.push(Entry { cost: dn, index: j });
+                }
+            }
+        }
+    }
+    None
+}
+
+// Grow regions from `seeds` over 8-connected vertices, assigning each vertex
+// to the seed of least path cost.
+//
+// `cost(a, b)` is as for `shortest_path`. Returns the seed index of each
+// vertex (in row-major order), or `None` where unreachable.
+pub(crate) fn grow_regions<F, C>(m: &Heightmap<F>, seeds: &[(u32, u32)], mut cost: C) -> Vec<Option<u32>>
+where F: RealField, C: FnMut((u32, u32), (u32, u32)) -> Option<F>
+{
+    let w = m.dim().0 as usize;
+    let index = |c: (u32, u32)| (c.0 as usize) + (c.1 as usize) * w;
+    let vertex = |i: usize| ((i % w) as u32, (i / w) as u32);
+    let len = w * m.dim().1 as usize;
+    let mut dist = vec![F::max_value(); len];
+    let mut region = vec![None; len];
+    let mut heap = BinaryHeap::new();
+    
+    for (r, s) in seeds.iter().enumerate() {
+        let i = index(*s);
+        if region[i].is_none() {
+            dist[i] = F::zero();
+            region[i] = Some(r as u32);
+            heap.push(Entry { cost: F::zero(), index: i });
+        }
+    }
+    while let Some(Entry { cost: d, index: i }) = heap.pop() {
+        if d > dist[i] {
+            continue;
+        }
+        let c = vertex(i);
+        for n in m.neighbours(c.0, c.1) {
+            if let Some(step) = cost(c, n) {
+                let j = index(n);
+                let dn = d + step;
+                if dn < dist[j] {
+                    dist[j] = dn;
+                    region[j] = region[i];
+                    heap.push(Entry { cost: dn, index: j });
+                }
+            }
+        }
+    }
+    region
+}
+
+// Compute the least cost of travel from every vertex to any of `goals` over
+// 8-connected vertices.
+//
+// `cost(a, b)` gives the cost of a step from `a` to `b` (as for
+// `shortest_path`; steps are searched in reverse from the goals). Returns the
+// cost of each vertex (`F::max_value()` where unreachable) and the index of
+// the next vertex towards the nearest goal (`usize::MAX` at goals and where
+// unreachable), both in row-major order.
+pub(crate) fn cost_field<F, C>(m: &Heightmap<F>, goals: &[(u32, u32)], mut cost: C) -> (Vec<F>, Vec<usize>)
+where F: RealField, C: FnMut((u32, u32), (u32, u32)) -> Option<F>
+{
+    let w = m.dim().0 as usize;
+    let index = |c: (u32, u32)| (c.0 as usize) + (c.1 as usize) * w;
+    let vertex = |i: usize| ((i % w) as u32, (i / w) as u32);
+    let len = w * m.dim().1 as usize;
+    let mut dist = vec![F::max_value(); len];
+    let mut next = vec![usize::MAX; len];
+    let mut heap = BinaryHeap::new();
+    
+    for g in goals {
+        let i = index(*g);
+        dist[i] = F::zero();
+        heap.push(Entry { cost: F::zero(), index: i });
+    }
+    while let Some(Entry { cost: d, index: i }) = heap.pop() {
+        if d > dist[i] {
+            continue;
+        }
+        let c = vertex(i);
+        for n in m.neighbours(c.0, c.1) {
+            if let Some(step) = cost(n, c) {
+                let j = index(n);
+                let dn = d + step;
+                if dn < dist[j] {
+                    dist[j] = dn;
+                    next[j] = i;
+                    heap.push(Entry { cost: dn, index: j });
+                }
+            }
+        }
+    }
+    (dist, next)
+}
@@ -0,0 +1,189 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use nalgebra::{convert, try_convert, RealField};
+use super::Heightmap;
+use crate::grid::Grid;
+
+/// Settlement grading parameters
+/// 
+/// The settlement is laid out on a regular street grid around `centre`:
+/// square blocks of 2 × 2 lots each, separated by streets.
+#[derive(Debug, Clone, Copy)]
+pub struct Settlement<F> {
+    /// Centre of the settlement (world coordinates)
+    pub centre: (F, F),
+    /// Radius of the settlement; only lots entirely within this radius are
+    /// placed
+    pub radius: F,
+    /// Side length of each (square) lot
+    pub lot_size: F,
+    /// Width of streets
+    pub street_width: F,
+    /// Lots where the mean slope exceeds this are not built on
+    pub max_slope: F,
+    /// Number of smoothing passes applied to streets
+    pub street_smoothing: u32,
+}
+
+/// A graded building lot
+#[derive(Debug, Clone)]
+pub struct Lot<F> {
+    /// Lot corners (world coordinates, counter-clockwise)
+    pub corners: [(F, F); 4],
+    /// Final (flat) elevation of the lot
+    pub elevation: F,
+    /// Total height of material removed (multiply by the cell area for a
+    /// volume)
+    pub cut: F,
+    /// Total height of material added
+    pub fill: F,
+}
+
+/// The result of grading a settlement
+#[derive(Debug, Clone)]
+pub struct SettlementLayout<F> {
+    /// Placed lots
+    pub lots: Vec<Lot<F>>,
+    /// Index into `lots` for each vertex, if any
+    pub lot_map: Grid<Option<u32>>,
+    /// Street vertices
+    pub streets: Grid<bool>,
+}
+
+impl<F: RealField> Settlement<F> {
+    /// Grade lots and streets on `m`
+    /// 
+    /// Each lot is levelled to the mean height of its vertices, so that cut
+    /// and fill balance within each lot. Streets are smoothed.
+    pub fn apply_to(&self, m: &mut Heightmap<F>) -> SettlementLayout<F> {
+        let dim = m.dim();
+        let period = self.lot_size + self.lot_size + self.street_width;
+        let to_i64 = |x: F| try_convert::<_, f64>(x.floor()).unwrap() as i64;
+        let r2 = self.radius * self.radius;
+        
+        // Classify vertices as street or lot (identified by lot index along
+        // each axis), within the radius.
+        enum Use {
+            Street,
+            Lot(i64, i64),
+        }
+        let classify = |x: F, y: F| -> Option<Use> {
+            let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+            if dx * dx + dy * dy > r2 {
+                return None;
+            }
+            let axis = |d: F| -> Option<i64> {
+                let block = (d / period).floor();
+                let u = d - block * period;
+                if u < self.street_width {
+                    None
+                } else {
+                    let sub = to_i64((u - self.street_width) / self.lot_size).min(1);
+                    Some(to_i64(block) * 2 + sub)
+                }
+            };
+            match (axis(dx), axis(dy)) {
+                (Some(i), Some(j)) => Some(Use::Lot(i, j)),
+                _ => Some(Use::Street),
+            }
+        };
+        
+        let mut streets = Grid::new(dim, false);
+        let mut members: HashMap<(i64, i64), Vec<(u32, u32)>> = HashMap::new();
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                let c = m.coord_of(cx, cy);
+                match classify(c.0, c.1) {
+                    Some(Use::Street) => streets.set(cx, cy, true),
+                    Some(Use::Lot(i, j)) => members.entry((i, j)).or_default().push((cx, cy)),
+                    None => {}
+                }
+            }
+        }
+        
+        // Grade lots, in a deterministic order
+        let mut keys: Vec<(i64, i64)> = members.keys().cloned().collect();
+        keys.sort_by_key(|&(i, j)| (j, i));
+        let size = m.size();
+        let mut lots = Vec::new();
+        let mut lot_map = Grid::new(dim, None);
+        for (i, j) in keys {
+            let cells = &members[&(i, j)];
+            // Lot bounds: lot k on an axis starts after k/2 blocks, a street
+            // and k%2 lots.
+            let start = |k: i64, c: F| {
+                let block: F = convert(k.div_euclid(2) as f64);
+                let sub: F = convert(k.rem_euclid(2) as f64);
+                c + block * period + self.street_width + sub * self.lot_size
+            };
+            let x0 = start(i, self.centre.0);
+            let y0 = start(j, self.centre.1);
+            let (x1, y1) = (x0 + self.lot_size, y0 + self.lot_size);
+            let corners = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+            let inside = corners.iter().all(|&(x, y)| {
+                let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+                x >= F::zero() && y >= F::zero() && x <= size.0 && y <= size.1
+                    && dx * dx + dy * dy <= r2
+            });
+            if !inside {
+                continue;
+            }
+            
+            let n: F = convert(cells.len() as f64);
+            let mut sum = F::zero();
+            let mut slope = F::zero();
+            for &(cx, cy) in cells {
+                sum += m.get(cx, cy);
+                slope += m.slope_at(cx, cy);
+            }
+            if slope / n > self.max_slope {
+                continue;
+            }
+            let elevation = sum / n;
+            let mut cut = F::zero();
+            let mut fill = F::zero();
+            let index = lots.len() as u32;
+            for &(cx, cy) in cells {
+                let d = m.get(cx, cy) - elevation;
+                if d > F::zero() {
+                    cut += d;
+                } else {
+                    fill -= d;
+                }
+                m.set(cx, cy, elevation);
+                lot_map.set(cx, cy, Some(index));
+            }
+            lots.push(Lot { corners, elevation, cut, fill });
+        }
+        
+        // Smooth streets towards the mean of adjacent street and lot vertices
+        for _ in 0..self.street_smoothing {
+            let prev = m.clone();
+            for cy in 0..dim.1 {
+                for cx in 0..dim.0 {
+                    if !streets.get(cx, cy) {
+                        continue;
+                    }
+                    let mut sum = prev.get(cx, cy);
+                    let mut n = 1;
+                    for c in prev.neighbours(cx, cy) {
+                        if streets.get(c.0, c.1) || lot_map.get(c.0, c.1).is_some() {
+                            sum += prev.get(c.0, c.1);
+                            n += 1;
+                        }
+                    }
+                    m.set(cx, cy, sum / convert(n as f64));
+                }
+            }
+        }
+        
+        SettlementLayout { lots, lot_map, streets }
+    }
+}
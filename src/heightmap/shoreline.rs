@@ -0,0 +1,149 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{contours::trace_contour, fetch::fetch_ray, Heightmap};
+
+// Number of directions over which fetch is averaged (over the seaward half
+// circle)
+const FETCH_RAYS: usize = 9;
+
+/// Type of a shoreline segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShoreType {
+    /// Gently sloping shore exposed to waves
+    Beach,
+    /// Steep shore
+    Cliff,
+    /// Gently sloping sheltered shore
+    Marsh,
+    /// Moderately steep shore
+    Rocky,
+}
+
+/// A run of shoreline of one type
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShoreSegment<F> {
+    /// The shore type
+    pub kind: ShoreType,
+    /// Polyline (world coordinates); adjacent segments share end points
+    pub points: Vec<(F, F)>,
+}
+
+/// Shoreline classification parameters
+/// 
+/// The coastline is the contour of `m` at `water_level`. At each point, the
+/// slope of the terrain and the wave exposure, measured as fetch (the mean
+/// distance of open water, over directions facing the sea), determine the
+/// type (see also [`FetchMap`](super::FetchMap)):
+/// 
+/// -   slope at least `cliff_slope`: [`ShoreType::Cliff`]
+/// -   else slope at least `rocky_slope`: [`ShoreType::Rocky`]
+/// -   else fetch below `exposed_fetch`: [`ShoreType::Marsh`]
+/// -   else [`ShoreType::Beach`]
+#[derive(Debug, Clone, Copy)]
+pub struct ShoreParams<F> {
+    /// Height of the water surface
+    pub water_level: F,
+    /// Minimum slope (rise over run) of rocky shores
+    pub rocky_slope: F,
+    /// Minimum slope of cliffs
+    pub cliff_slope: F,
+    /// Minimum fetch of exposed shores
+    pub exposed_fetch: F,
+    /// Maximum fetch measured; water reaching the edge of the map is assumed
+    /// to extend this far
+    pub max_fetch: F,
+}
+
+impl<F: RealField> ShoreParams<F> {
+    /// Classify the shoreline of `m`
+    /// 
+    /// Coastlines are traced with marching squares. Those reaching the edge
+    /// of the map are open polylines; closed coastlines (islands and lakes)
+    /// have their first point repeated at the end of the last segment.
+    pub fn classify(&self, m: &Heightmap<F>) -> Vec<ShoreSegment<F>> {
+        trace_span!("shoreline", dim = ?m.dim());
+        let mut segments = vec![];
+        for mut line in trace_contour(m, self.water_level) {
+            let mut kinds: Vec<_> = line.iter().map(|&p| self.classify_point(m, p)).collect();
+            // start closed loops at a change of type
+            let closed = line.len() > 2 && line.first() == line.last();
+            if closed {
+                line.pop();
+                kinds.pop();
+                if let Some(i) = kinds.iter().position(|&k| k != kinds[0]) {
+                    line.rotate_left(i);
+                    kinds.rotate_left(i);
+                }
+            }
+            let first = line[0];
+            let mut current: Option<ShoreSegment<F>> = None;
+            for (p, kind) in line.into_iter().zip(kinds) {
+                match current {
+                    Some(ref mut s) if s.kind == kind => s.points.push(p),
+                    _ => {
+                        let mut points = vec![];
+                        if let Some(s) = current.take() {
+                            points.push(*s.points.last().unwrap());
+                            segments.push(s);
+                        }
+                        points.push(p);
+                        current = Some(ShoreSegment { kind, points });
+                    }
+                }
+            }
+            if let Some(mut s) = current {
+                if closed {
+                    s.points.push(first);
+                }
+                segments.push(s);
+            }
+        }
+        segments
+    }
+    
+    /// Classify a single point of the coastline (world coordinates)
+    pub fn classify_point(&self, m: &Heightmap<F>, p: (F, F)) -> ShoreType {
+        let (gx, gy) = gradient(m, p);
+        let slope = (gx * gx + gy * gy).sqrt();
+        if slope >= self.cliff_slope {
+            ShoreType::Cliff
+        } else if slope >= self.rocky_slope {
+            ShoreType::Rocky
+        } else if self.fetch(m, p, (-gx, -gy)) < self.exposed_fetch {
+            ShoreType::Marsh
+        } else {
+            ShoreType::Beach
+        }
+    }
+    
+    // Mean fetch at `p` over the half circle around direction `sea`
+    fn fetch(&self, m: &Heightmap<F>, p: (F, F), sea: (F, F)) -> F {
+        let len = (sea.0 * sea.0 + sea.1 * sea.1).sqrt();
+        if len == F::zero() {
+            return self.max_fetch;
+        }
+        let base = sea.1.atan2(sea.0);
+        let mut total = F::zero();
+        for i in 0..FETCH_RAYS {
+            let frac: F = convert((i as f64 + 0.5) / FETCH_RAYS as f64 - 0.5);
+            let angle = base + frac * F::pi();
+            total += fetch_ray(m, p, (angle.cos(), angle.sin()), self.water_level, self.max_fetch);
+        }
+        total / convert(FETCH_RAYS as f64)
+    }
+}
+
+// Gradient of the terrain at a point (central differences over one cell)
+fn gradient<F: RealField>(m: &Heightmap<F>, p: (F, F)) -> (F, F) {
+    let (dx, dy) = m.cell_size();
+    let gx = (m.interpolate(p.0 + dx, p.1) - m.interpolate(p.0 - dx, p.1)) / (dx + dx);
+    let gy = (m.interpolate(p.0, p.1 + dy) - m.interpolate(p.0, p.1 - dy)) / (dy + dy);
+    (gx, gy)
+}
@@ -0,0 +1,68 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, Complex, RealField};
+use super::Heightmap;
+use crate::fft::fft2;
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+/// Generate fractal terrain via spectral synthesis
+/// 
+/// Gaussian white noise is filtered to a power spectrum `P(f) ∝ 1 / f^β`
+/// (`β = roughness_beta`) and transformed to the spatial domain via an
+/// inverse FFT. All existing heights of `m` are replaced; the result has mean
+/// zero and unit standard deviation (use [`Heightmap::map`] to rescale).
+/// 
+/// Useful values of `β` lie in the range 1.5 to 3; larger values give
+/// smoother terrain (`β = 2` corresponds to Brownian surfaces). Unlike
+/// midpoint displacement this has no grid artifacts and any dimension is
+/// supported, though powers of two are fastest.
+/// 
+/// The result is periodic: vertex `(dim.0, cy)` would equal vertex `(0, cy)`,
+/// thus maps tile seamlessly when the last row and column of one map are
+/// followed by the first of the next.
+/// 
+/// Source: [Gal19], section 3.1.3.
+/// 
+/// [Gal19]: https://www.doi.org/10.1111/cgf.13657
+pub fn spectral_synthesis<F: RealField, R: Rng>(m: &mut Heightmap<F>, roughness_beta: F, rng: &mut R) {
+    trace_span!("spectral_synthesis", dim = ?m.dim());
+    let dim = (m.dim().0 as usize, m.dim().1 as usize);
+    let exponent = -roughness_beta * convert(0.25);   // amplitude ∝ (f²)^(-β/4)
+    // Signed frequency of index i along an axis of length n
+    let freq = |i: usize, n: usize| -> F {
+        if i <= n / 2 { convert(i as f64) } else { convert(i as f64 - n as f64) }
+    };
+    
+    let mut data = Vec::with_capacity(dim.0 * dim.1);
+    for y in 0..dim.1 {
+        let fy = freq(y, dim.1) / convert(dim.1 as f64);
+        for x in 0..dim.0 {
+            let fx = freq(x, dim.0) / convert(dim.0 as f64);
+            let f2 = fx * fx + fy * fy;
+            let a = if f2 > F::zero() { f2.powf(exponent) } else { F::zero() };
+            let re: f64 = rng.sample(StandardNormal);
+            let im: f64 = rng.sample(StandardNormal);
+            data.push(Complex::new(a * convert(re), a * convert(im)));
+        }
+    }
+    fft2(&mut data, dim, true);
+    
+    // Take the real part and normalise
+    let n: F = convert(data.len() as f64);
+    let mean = data.iter().fold(F::zero(), |s, c| s + c.re) / n;
+    let var = data.iter().fold(F::zero(), |s, c| s + (c.re - mean).powi(2)) / n;
+    let sd = if var > F::zero() { var.sqrt() } else { F::one() };
+    let mut i = 0;
+    m.map(|_| {
+        let h = (data[i].re - mean) / sd;
+        i += 1;
+        h
+    });
+}
@@ -0,0 +1,82 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{range, Heightmap};
+
+/// How overlapping tiles are combined by [`Heightmap::stitch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeamBlend {
+    /// Equal-weight average of all tiles covering a vertex
+    Average,
+    /// Weighted by distance from the tile's edge (ignoring edges on the
+    /// border of the mosaic), giving smooth transitions across wide overlaps
+    Feather,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Assemble tiles into one map
+    /// 
+    /// Each tile is placed with its first vertex at `offset` (a vertex index
+    /// of the result). All tiles must have the same cell size. The result
+    /// covers all tiles, starting from vertex `(0, 0)`; vertices not covered
+    /// by any tile have height zero. Where tiles overlap (e.g. sharing edge
+    /// vertices), heights are combined according to `blend`.
+    pub fn stitch(tiles: &[((u32, u32), &Heightmap<F>)], blend: SeamBlend) -> Heightmap<F> {
+        assert!(!tiles.is_empty(), "stitch: no tiles");
+        let cell = tiles[0].1.len_frac;
+        let eps = F::default_epsilon().sqrt();
+        let mut dim = (0, 0);
+        for &(offset, tile) in tiles {
+            let c = tile.len_frac;
+            assert!((c.0 - cell.0).abs() <= eps * cell.0 && (c.1 - cell.1).abs() <= eps * cell.1,
+                "stitch: tiles have differing cell sizes");
+            dim = (dim.0.max(offset.0 + tile.dim.0), dim.1.max(offset.1 + tile.dim.1));
+        }
+        trace_span!("stitch", tiles = tiles.len(), dim = ?dim);
+        
+        let len = dim.0 as usize * dim.1 as usize;
+        let mut sum = vec![F::zero(); len];
+        let mut weight = vec![F::zero(); len];
+        for &(offset, tile) in tiles {
+            // distance (in vertices) to an interior edge of the mosaic
+            let margin = |i: u32, off: u32, n: u32, total: u32| {
+                let low = if off == 0 { u32::MAX } else { i };
+                let high = if off + n == total { u32::MAX } else { n - 1 - i };
+                low.min(high)
+            };
+            for cy in 0..tile.dim.1 {
+                let my = margin(cy, offset.1, tile.dim.1, dim.1);
+                for cx in 0..tile.dim.0 {
+                    let w = match blend {
+                        SeamBlend::Average => F::one(),
+                        SeamBlend::Feather => {
+                            let d = margin(cx, offset.0, tile.dim.0, dim.0).min(my);
+                            if d == u32::MAX { F::one() } else { convert(d as f64 + 1.0) }
+                        }
+                    };
+                    let i = (offset.1 + cy) as usize * dim.0 as usize + (offset.0 + cx) as usize;
+                    sum[i] += tile.get(cx, cy) * w;
+                    weight[i] += w;
+                }
+            }
+        }
+        
+        let data: Vec<F> = sum.into_iter().zip(weight).map(|(s, w)| {
+            if w > F::zero() { s / w } else { F::zero() }
+        }).collect();
+        let steps: (F, F) = (convert((dim.0 - 1) as f64), convert((dim.1 - 1) as f64));
+        Heightmap {
+            dim,
+            len_frac: cell,
+            size: (steps.0 * cell.0, steps.1 * cell.1),
+            range: range(&data),
+            data,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::RealField;
+use super::Heightmap;
+use crate::grid::Grid;
+use crate::unbounded::UnboundedSurface;
+
+/// Horizontal rock strata (sedimentary layers)
+/// 
+/// Layers are stacked upwards from elevation `base` with the given
+/// thicknesses, repeating the sequence indefinitely (and also below `base`).
+/// The *index* of a layer is its position in the `thickness` list, for
+/// example to select a colour or material.
+/// 
+/// Layer boundaries may be perturbed by a warp surface (see
+/// [`Strata::indices`]) to avoid perfectly flat bands.
+#[derive(Debug, Clone)]
+pub struct Strata<F> {
+    base: F,
+    thickness: Vec<F>,
+    period: F,
+}
+
+impl<F: RealField> Strata<F> {
+    /// Construct with the given `base` elevation and layer thicknesses
+    /// 
+    /// All thicknesses must be positive; at least one must be given.
+    pub fn new(base: F, thickness: Vec<F>) -> Self {
+        assert!(!thickness.is_empty());
+        assert!(thickness.iter().all(|t| *t > F::zero()));
+        let period = thickness.iter().fold(F::zero(), |s, t| s + *t);
+        Strata { base, thickness, period }
+    }
+    
+    /// Number of layers in the repeating sequence
+    pub fn num_layers(&self) -> usize {
+        self.thickness.len()
+    }
+    
+    /// Get the layer index at the given elevation
+    pub fn layer_at(&self, elevation: F) -> u32 {
+        let rel = elevation - self.base;
+        let mut h = rel - (rel / self.period).floor() * self.period;
+        for (i, t) in self.thickness.iter().enumerate() {
+            if h < *t {
+                return i as u32;
+            }
+            h -= *t;
+        }
+        // only reachable through rounding error
+        (self.thickness.len() - 1) as u32
+    }
+    
+    /// Get the layer index of each vertex of `m`
+    /// 
+    /// If `warp` is given, boundaries are displaced vertically by
+    /// `amplitude * warp.get(x, y)`.
+    pub fn indices(&self, m: &Heightmap<F>, warp: Option<(&dyn UnboundedSurface<F>, F)>) -> Grid<u32> {
+        Grid::from_fn(m.dim(), |cx, cy| {
+            let mut h = m.get(cx, cy);
+            if let Some((surface, amplitude)) = warp {
+                let (x, y) = m.coord_of(cx, cy);
+                h -= amplitude * surface.get(x, y);
+            }
+            self.layer_at(h)
+        })
+    }
+    
+    /// Get the layer index of exposed vertices of `m`
+    /// 
+    /// As [`Strata::indices`], but only vertices with slope at least
+    /// `min_slope` (cliff faces) are assigned a layer.
+    pub fn exposed_indices(&self, m: &Heightmap<F>, warp: Option<(&dyn UnboundedSurface<F>, F)>, min_slope: F)
+        -> Grid<Option<u32>>
+    {
+        let indices = self.indices(m, warp);
+        Grid::from_fn(m.dim(), |cx, cy| {
+            if m.slope_at(cx, cy) >= min_slope {
+                Some(indices.get(cx, cy))
+            } else {
+                None
+            }
+        })
+    }
+    
+    /// Get the elevation of the boundary below the layer at `elevation`, and
+    /// the fractional position `[0, 1)` within the layer
+    /// 
+    /// This may be used by renderers to blend between layers.
+    pub fn position_in_layer(&self, elevation: F) -> (F, F) {
+        let rel = elevation - self.base;
+        let start = (rel / self.period).floor() * self.period;
+        let mut h = rel - start;
+        let mut bottom = self.base + start;
+        for t in self.thickness.iter() {
+            if h < *t {
+                return (bottom, h / *t);
+            }
+            h -= *t;
+            bottom += *t;
+        }
+        // only reachable through rounding error
+        (bottom, F::zero())
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use nalgebra::{convert, RealField};
+use super::Heightmap;
+use crate::unbounded::{BoxedSurface, UnboundedSurface};
+
+/// Behaviour of a [`HeightmapSurface`] beyond the bounds of the heightmap
+pub enum EdgeMode<F: RealField> {
+    /// Extend edge heights outwards
+    Clamp,
+    /// Repeat the map with period equal to its size; the map should be
+    /// tileable with equal heights on opposite edges
+    Wrap,
+    /// Reflect the map at each edge, giving period twice its size
+    Mirror,
+    /// Use another surface (sampled at the same world coordinates)
+    Fallback(BoxedSurface<F>),
+}
+
+impl<F: RealField> fmt::Debug for EdgeMode<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdgeMode::Clamp => f.write_str("Clamp"),
+            EdgeMode::Wrap => f.write_str("Wrap"),
+            EdgeMode::Mirror => f.write_str("Mirror"),
+            EdgeMode::Fallback(_) => f.write_str("Fallback(..)"),
+        }
+    }
+}
+
+/// A heightmap as an [`UnboundedSurface`]
+/// 
+/// Heights are interpolated bilinearly. Vertex `(0, 0)` is placed at world
+/// coordinate `origin`; beyond the map, heights are determined by the
+/// [`EdgeMode`]. This allows existing maps to be composed with other
+/// surfaces, warped or resampled.
+/// 
+/// ```
+/// # use terr::grid::Grid;
+/// # use terr::heightmap::*;
+/// # use terr::unbounded::UnboundedSurface;
+/// let m = Heightmap::from_grid(Grid::from_fn((3, 2), |cx, _| cx as f64), (2.0, 1.0));
+/// let s = HeightmapSurface::new(m, (10.0, 0.0), EdgeMode::Mirror);
+/// assert_eq!(s.get(11.5, 0.5), 1.5);
+/// assert_eq!(s.get(12.5, 0.5), 1.5);
+/// assert_eq!(s.get(9.0, 0.5), 1.0);
+/// ```
+#[derive(Debug)]
+pub struct HeightmapSurface<F: RealField> {
+    map: Heightmap<F>,
+    origin: (F, F),
+    edge: EdgeMode<F>,
+}
+
+impl<F: RealField> HeightmapSurface<F> {
+    /// Construct
+    pub fn new(map: Heightmap<F>, origin: (F, F), edge: EdgeMode<F>) -> Self {
+        HeightmapSurface { map, origin, edge }
+    }
+    
+    /// Access the heightmap
+    pub fn heightmap(&self) -> &Heightmap<F> {
+        &self.map
+    }
+    
+    /// Unwrap, returning the heightmap
+    pub fn into_heightmap(self) -> Heightmap<F> {
+        self.map
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for HeightmapSurface<F> {
+    fn get(&self, x: F, y: F) -> F {
+        let size = self.map.size();
+        let (u, v) = (x - self.origin.0, y - self.origin.1);
+        let (u, v) = match self.edge {
+            EdgeMode::Clamp => (u, v),
+            EdgeMode::Wrap => (wrap(u, size.0), wrap(v, size.1)),
+            EdgeMode::Mirror => (mirror(u, size.0), mirror(v, size.1)),
+            EdgeMode::Fallback(ref surface) => {
+                if u < F::zero() || v < F::zero() || u > size.0 || v > size.1 {
+                    return surface.get(x, y);
+                }
+                (u, v)
+            }
+        };
+        self.map.interpolate(u, v)
+    }
+}
+
+// Reduce t to [0, period)
+fn wrap<F: RealField>(t: F, period: F) -> F {
+    t - (t / period).floor() * period
+}
+
+// Reflect t into [0, period]
+fn mirror<F: RealField>(t: F, period: F) -> F {
+    let t = wrap(t, period * convert(2.0));
+    if t > period { period + period - t } else { t }
+}
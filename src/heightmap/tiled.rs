@@ -0,0 +1,305 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::size_of;
+use std::time::Duration;
+use nalgebra::{convert, try_convert, RealField};
+use super::{Heightmap, Interpolation};
+use crate::memory::{BudgetPolicy, MemoryBudget, MemoryUsage};
+use crate::task::{budgeted, IncrementalTask, Progress};
+use crate::unbounded::{BoxedSurface, UnboundedSurface};
+
+/// Surface used to generate chunks
+pub type ChunkSource<F> = BoxedSurface<F>;
+
+/// A terrain of unbounded extent stored as a grid of [`Heightmap`] chunks
+/// 
+/// Chunk `(i, j)` covers world coordinates from `(i * size.0, j * size.1)` to
+/// `((i + 1) * size.0, (j + 1) * size.1)`, where `size` is the chunk size.
+/// Adjacent chunks share their edge vertices (each stores its own copy), thus
+/// each chunk is a complete `Heightmap` which may be meshed independently.
+/// 
+/// Chunks are generated on demand by sampling a surface at world
+/// coordinates; they may also be replaced (e.g. after editing or when loading
+/// from disk) and unloaded to bound memory usage. A chunk which is unloaded
+/// and later requested is regenerated from the surface, losing any edits.
+/// 
+/// A [`MemoryBudget`] may be set to bound memory usage automatically: when
+/// a new chunk is generated or inserted and the estimated usage exceeds the
+/// budget, the least recently used chunks are unloaded or downsampled.
+/// Downsampled chunks cover the same area at lower resolution, thus may have
+/// a smaller dimension than [`TiledHeightmap::chunk_dim`].
+pub struct TiledHeightmap<F: RealField> {
+    chunk_dim: (u32, u32),
+    chunk_size: (F, F),
+    surface: ChunkSource<F>,
+    chunks: HashMap<(i32, i32), Entry<F>>,
+    clock: u64,
+    budget: Option<MemoryBudget>,
+}
+
+// A chunk and the clock value of its last use
+type Entry<F> = (Heightmap<F>, u64);
+
+impl<F: RealField> fmt::Debug for TiledHeightmap<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TiledHeightmap")
+            .field("chunk_dim", &self.chunk_dim)
+            .field("chunk_size", &self.chunk_size)
+            .field("chunks", &self.chunks.len())
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+impl<F: RealField> TiledHeightmap<F> {
+    /// Construct with no chunks loaded
+    /// 
+    /// Each chunk has `chunk_dim` vertices and covers `chunk_size`.
+    pub fn new(chunk_dim: (u32, u32), chunk_size: (F, F), surface: ChunkSource<F>) -> Self {
+        assert!(chunk_dim.0 >= 2 && chunk_dim.1 >= 2);
+        TiledHeightmap { chunk_dim, chunk_size, surface, chunks: HashMap::new(), clock: 0, budget: None }
+    }
+    
+    /// Set or remove the memory budget
+    /// 
+    /// The budget is enforced immediately. Note that the most recently used
+    /// chunk is never unloaded, thus usage may exceed a very small budget.
+    pub fn set_budget(&mut self, budget: Option<MemoryBudget>) {
+        if let Some(MemoryBudget { policy: BudgetPolicy::Downsample { min_dim }, .. }) = budget {
+            assert!(min_dim >= 2);
+        }
+        self.budget = budget;
+        let newest = self.chunks.iter().max_by_key(|(_, e)| e.1).map(|(c, _)| *c);
+        self.enforce_budget(newest);
+    }
+    
+    /// Get the memory budget
+    pub fn budget(&self) -> Option<MemoryBudget> {
+        self.budget
+    }
+    
+    /// Get the grid dimension of each chunk
+    #[inline]
+    pub fn chunk_dim(&self) -> (u32, u32) {
+        self.chunk_dim
+    }
+    
+    /// Get the size of each chunk
+    #[inline]
+    pub fn chunk_size(&self) -> (F, F) {
+        self.chunk_size
+    }
+    
+    /// Get the world coordinates of the origin of chunk `c`
+    pub fn chunk_origin(&self, c: (i32, i32)) -> (F, F) {
+        let x = convert::<_, F>(c.0 as f64) * self.chunk_size.0;
+        let y = convert::<_, F>(c.1 as f64) * self.chunk_size.1;
+        (x, y)
+    }
+    
+    /// Find the chunk containing world coordinate `(x, y)`
+    pub fn chunk_at_coord(&self, x: F, y: F) -> (i32, i32) {
+        let i = try_convert::<_, f64>((x / self.chunk_size.0).floor()).unwrap() as i32;
+        let j = try_convert::<_, f64>((y / self.chunk_size.1).floor()).unwrap() as i32;
+        (i, j)
+    }
+    
+    /// Get chunk `c`, if loaded
+    pub fn get_chunk(&self, c: (i32, i32)) -> Option<&Heightmap<F>> {
+        self.chunks.get(&c).map(|e| &e.0)
+    }
+    
+    /// Get chunk `c`, generating it if not loaded
+    pub fn chunk(&mut self, c: (i32, i32)) -> &Heightmap<F> {
+        self.chunk_mut(c)
+    }
+    
+    /// Get chunk `c` mutably, generating it if not loaded
+    /// 
+    /// Note that edge vertices are duplicated in adjacent chunks; edits to
+    /// these are not propagated.
+    pub fn chunk_mut(&mut self, c: (i32, i32)) -> &mut Heightmap<F> {
+        if !self.chunks.contains_key(&c) {
+            let (dim, size, origin) = (self.chunk_dim, self.chunk_size, self.chunk_origin(c));
+            let m = Heightmap::from_surface(dim, size, &Offset { surface: &*self.surface, origin });
+            self.chunks.insert(c, (m, 0));
+            self.enforce_budget(Some(c));
+        }
+        self.clock += 1;
+        let entry = self.chunks.get_mut(&c).unwrap();
+        entry.1 = self.clock;
+        &mut entry.0
+    }
+    
+    /// Insert chunk `c`, replacing any existing chunk
+    /// 
+    /// The heightmap must have the chunk dimension and size.
+    pub fn insert(&mut self, c: (i32, i32), m: Heightmap<F>) -> Option<Heightmap<F>> {
+        assert_eq!(m.dim(), self.chunk_dim);
+        assert!(m.size() == self.chunk_size);
+        self.clock += 1;
+        let old = self.chunks.insert(c, (m, self.clock)).map(|e| e.0);
+        self.enforce_budget(Some(c));
+        old
+    }
+    
+    /// Unload chunk `c`, returning it if it was loaded
+    pub fn unload(&mut self, c: (i32, i32)) -> Option<Heightmap<F>> {
+        self.chunks.remove(&c).map(|e| e.0)
+    }
+    
+    /// Iterate over the indices of loaded chunks (in arbitrary order)
+    pub fn loaded(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.chunks.keys().cloned()
+    }
+    
+    /// Ensure all chunks overlapping the rectangle from `start` to
+    /// `start + size` (world coordinates) are loaded
+    pub fn load_region(&mut self, start: (F, F), size: (F, F)) {
+        for c in self.region(start, size) {
+            self.chunk(c);
+        }
+    }
+    
+    /// Incrementally load all chunks overlapping the rectangle from `start`
+    /// to `start + size` (see [`TiledHeightmap::load_region`])
+    /// 
+    /// Chunks already loaded when the task is created are skipped.
+    pub fn load_task(&mut self, start: (F, F), size: (F, F)) -> LoadTask<'_, F> {
+        let mut pending: Vec<_> = self.region(start, size)
+            .filter(|c| !self.chunks.contains_key(c))
+            .collect();
+        // generate in row-major order
+        pending.reverse();
+        let total = pending.len() as u64;
+        LoadTask { tiles: self, pending, total }
+    }
+    
+    // Indices of chunks overlapping a region, in row-major order
+    fn region(&self, start: (F, F), size: (F, F)) -> impl Iterator<Item = (i32, i32)> {
+        let c0 = self.chunk_at_coord(start.0, start.1);
+        let c1 = self.chunk_at_coord(start.0 + size.0, start.1 + size.1);
+        (c0.1..=c1.1).flat_map(move |j| (c0.0..=c1.0).map(move |i| (i, j)))
+    }
+    
+    /// Get the height at world coordinate `(x, y)`, generating the containing
+    /// chunk if necessary
+    /// 
+    /// Heights are interpolated bilinearly between vertices.
+    pub fn height_at(&mut self, x: F, y: F) -> F {
+        let c = self.chunk_at_coord(x, y);
+        let origin = self.chunk_origin(c);
+        self.chunk(c).interpolate(x - origin.0, y - origin.1)
+    }
+}
+
+impl<F: RealField> TiledHeightmap<F> {
+    // Unload or downsample least recently used chunks (other than `keep`)
+    // until within budget
+    fn enforce_budget(&mut self, keep: Option<(i32, i32)>) {
+        let budget = match self.budget {
+            Some(b) => b,
+            None => return,
+        };
+        let mut usage = self.memory_usage();
+        while usage > budget.bytes {
+            let candidates = self.chunks.iter().filter(|(c, _)| Some(**c) != keep);
+            let target = match budget.policy {
+                BudgetPolicy::Evict => None,
+                BudgetPolicy::Downsample { min_dim } => candidates.clone()
+                    .filter(|(_, e)| e.0.dim().0 > min_dim || e.0.dim().1 > min_dim)
+                    .min_by_key(|(_, e)| e.1)
+                    .map(|(c, _)| (*c, min_dim)),
+            };
+            if let Some((c, min_dim)) = target {
+                let m = &mut self.chunks.get_mut(&c).unwrap().0;
+                let dim = m.dim();
+                let half = |d: u32| ((d - 1) / 2 + 1).max(min_dim).min(d);
+                let smaller = m.resample((half(dim.0), half(dim.1)), Interpolation::Bilinear);
+                usage = usage + smaller.memory_usage() - m.memory_usage();
+                *m = smaller;
+                continue;
+            }
+            match candidates.min_by_key(|(_, e)| e.1).map(|(c, _)| *c) {
+                Some(c) => {
+                    let (m, _) = self.chunks.remove(&c).unwrap();
+                    usage -= chunk_usage(&m);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl<F: RealField> MemoryUsage for TiledHeightmap<F> {
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.chunks.values().map(|e| chunk_usage(&e.0)).sum::<usize>()
+    }
+}
+
+/// Incremental loading of chunks (see [`TiledHeightmap::load_task`])
+/// 
+/// Each unit of work is the generation of one chunk.
+pub struct LoadTask<'a, F: RealField> {
+    tiles: &'a mut TiledHeightmap<F>,
+    pending: Vec<(i32, i32)>,
+    total: u64,
+}
+
+impl<'a, F: RealField> fmt::Debug for LoadTask<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LoadTask")
+            .field("progress", &self.progress())
+            .finish()
+    }
+}
+
+impl<'a, F: RealField> LoadTask<'a, F> {
+    /// Access the chunk store
+    pub fn tiles(&self) -> &TiledHeightmap<F> {
+        self.tiles
+    }
+}
+
+impl<'a, F: RealField> IncrementalTask for LoadTask<'a, F> {
+    fn run_for(&mut self, budget: Duration) -> Progress {
+        let (tiles, pending) = (&mut *self.tiles, &mut self.pending);
+        budgeted(budget, || match pending.pop() {
+            Some(c) => {
+                tiles.chunk(c);
+                !pending.is_empty()
+            }
+            None => false,
+        });
+        self.progress()
+    }
+    
+    fn progress(&self) -> Progress {
+        Progress { done: self.total - self.pending.len() as u64, total: self.total }
+    }
+}
+
+// Memory of a chunk including its map entry
+fn chunk_usage<F: RealField>(m: &Heightmap<F>) -> usize {
+    size_of::<((i32, i32), Entry<F>)>() - size_of::<Heightmap<F>>() + m.memory_usage()
+}
+
+// Surface translated to chunk-local coordinates
+struct Offset<'a, S: ?Sized, F> {
+    surface: &'a S,
+    origin: (F, F),
+}
+
+impl<'a, F: RealField, S: UnboundedSurface<F> + ?Sized> UnboundedSurface<F> for Offset<'a, S, F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.surface.get(self.origin.0 + x, self.origin.1 + y)
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Triangulated irregular network by greedy insertion
+//
+// Points are vertex indices, thus all geometric predicates are exact in
+// integer arithmetic.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use nalgebra::{convert, RealField, geometry::{Point2, Point3}};
+use super::Heightmap;
+use crate::mesh::{orient, TerrainMesh, Triangulation};
+
+// Insertion candidate: the point of greatest error in a triangle, ordered by
+// greatest error first
+struct Candidate<F> {
+    error: F,
+    tri: u32,
+    version: u32,
+    point: (u32, u32),
+}
+
+impl<F: RealField> PartialEq for Candidate<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.error == other.error
+    }
+}
+
+impl<F: RealField> Eq for Candidate<F> {}
+
+impl<F: RealField> PartialOrd for Candidate<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: RealField> Ord for Candidate<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error.partial_cmp(&other.error).unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Tin<'a, F: RealField> {
+    m: &'a Heightmap<F>,
+    tri: Triangulation,
+}
+
+impl<'a, F: RealField> Tin<'a, F> {
+    // Find the point of greatest vertical error in triangle `t`
+    fn candidate(&self, t: u32) -> Option<(F, (u32, u32))> {
+        let tri = &self.tri.tris[t as usize];
+        if tri.is_ghost() {
+            return None;
+        }
+        let p = |v: u32| self.tri.points[v as usize];
+        let [a, b, c] = [p(tri.v[0]), p(tri.v[1]), p(tri.v[2])];
+        let h = |p: (i64, i64)| self.m.get(p.0 as u32, p.1 as u32);
+        let (ha, hb, hc) = (h(a), h(b), h(c));
+        let area: F = convert(orient(a, b, c) as f64);
+        let mut best: Option<(F, (u32, u32))> = None;
+        for y in a.1.min(b.1).min(c.1)..=a.1.max(b.1).max(c.1) {
+            for x in a.0.min(b.0).min(c.0)..=a.0.max(b.0).max(c.0) {
+                let q = (x, y);
+                let (wa, wb, wc) = (orient(b, c, q), orient(c, a, q), orient(a, b, q));
+                if wa < 0 || wb < 0 || wc < 0 || q == a || q == b || q == c {
+                    continue;
+                }
+                let plane = (ha * convert(wa as f64) + hb * convert(wb as f64) + hc * convert(wc as f64)) / area;
+                let error = (h(q) - plane).abs();
+                if best.map(|b| error > b.0).unwrap_or(true) {
+                    best = Some((error, (x as u32, y as u32)));
+                }
+            }
+        }
+        best
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Convert to a triangulated irregular network (TIN)
+    /// 
+    /// Starting from the four corners, the vertex of greatest vertical error
+    /// is repeatedly inserted into a Delaunay triangulation until no vertex
+    /// is further than `max_error` from the mesh (the greedy insertion
+    /// algorithm of Garland and Heckbert, "Fast polygonal approximation of
+    /// terrains and height fields", 1995). The result uses a subset of the
+    /// heightmap's vertices and is typically far smaller than the regular
+    /// grid of [`Heightmap::to_trimesh`]. The mesh is Z-up (see
+    /// [`TerrainMesh::orient`]).
+    pub fn to_tin(&self, max_error: F) -> TerrainMesh<F> {
+        assert!(max_error >= F::zero());
+        trace_span!("to_tin", dim = ?self.dim);
+        let (w, h) = ((self.dim.0 - 1) as i64, (self.dim.1 - 1) as i64);
+        let mut tri = Triangulation::new((0, 0), (w, 0), (w, h));
+        tri.insert((0, h)).unwrap();
+        let mut tin = Tin { m: self, tri };
+        
+        let mut heap = BinaryHeap::new();
+        loop {
+            let mut touched = std::mem::take(&mut tin.tri.touched);
+            touched.sort_unstable();
+            touched.dedup();
+            for t in touched {
+                if let Some((error, point)) = tin.candidate(t) {
+                    if error > max_error {
+                        let version = tin.tri.tris[t as usize].version;
+                        heap.push(Candidate { error, tri: t, version, point });
+                    }
+                }
+            }
+            let c = loop {
+                match heap.pop() {
+                    Some(c) if c.version != tin.tri.tris[c.tri as usize].version => continue,
+                    c => break c,
+                }
+            };
+            match c {
+                Some(c) => {
+                    tin.tri.insert_in(c.tri, (c.point.0 as i64, c.point.1 as i64));
+                }
+                None => break,
+            }
+        }
+        
+        let one = F::one();
+        let positions = tin.tri.points.iter().map(|&(x, y)| {
+            let (x, y) = (x as u32, y as u32);
+            let (px, py) = self.coord_of(x, y);
+            Point3::new(px, py, self.get(x, y))
+        }).collect::<Vec<_>>();
+        let uvs = positions.iter().map(|p| Point2::new(one - p.x / self.size.0, one - p.y / self.size.1)).collect();
+        let indices = tin.tri.triangles().map(|t| Point3::new(t[0], t[1], t[2])).collect();
+        let mut mesh = TerrainMesh::new(positions, None, Some(uvs), indices);
+        mesh.recompute_normals();
+        mesh
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use rand::Rng;
+use super::{Heightmap, search::shortest_path};
+use crate::grid::Grid;
+
+/// Parameters of trail formation
+#[derive(Debug, Clone, Copy)]
+pub struct TrailParams<F> {
+    /// Additional cost of walking up or down slopes: a step of length `l` and
+    /// slope `s` costs `l * (1 + slope_cost * |s|)` before trail comfort
+    pub slope_cost: F,
+    /// Maximum reduction of walking cost on established trails, in the range
+    /// `[0, 1)`. The cost is multiplied by `1 - comfort * i / (i + 1)` for
+    /// trail intensity `i`.
+    pub comfort: F,
+    /// Intensity deposited on each vertex of a walked path
+    pub deposit: F,
+    /// Fraction of trail intensity lost after each trip (trails not walked
+    /// eventually fade)
+    pub decay: F,
+    /// Height removed from each vertex of a walked path
+    pub wear: F,
+    /// Fraction (in `[0, 1]`) by which walked vertices are pulled towards the
+    /// mean height of their neighbours on the path
+    pub smoothing: F,
+}
+
+/// An agent-based trail formation simulation
+/// 
+/// Agents repeatedly walk the cheapest route between two random points of
+/// interest. Walking a path deposits trail intensity, making the path cheaper
+/// to walk again, and wears the path into the terrain. Since agents prefer
+/// existing trails, over many trips routes merge into an organic network.
+/// 
+/// This is a simplified form of the *active walker* model of Helbing et al.,
+/// [doi:10.1038/40353](https://doi.org/10.1038/40353).
+#[derive(Debug, Clone)]
+pub struct TrailSim<F> {
+    params: TrailParams<F>,
+    points: Vec<(u32, u32)>,
+    intensity: Grid<F>,
+}
+
+impl<F: RealField> TrailSim<F> {
+    /// Construct with the given points of interest (vertices of `m`)
+    pub fn new(m: &Heightmap<F>, points: Vec<(u32, u32)>, params: TrailParams<F>) -> Self {
+        let intensity = Grid::new(m.dim(), F::zero());
+        TrailSim { params, points, intensity }
+    }
+    
+    /// Trail intensity
+    #[inline]
+    pub fn intensity(&self) -> &Grid<F> {
+        &self.intensity
+    }
+    
+    /// Walk a single trip between vertices `a` and `b`
+    /// 
+    /// Returns the walked path, if any.
+    pub fn walk(&mut self, m: &mut Heightmap<F>, a: (u32, u32), b: (u32, u32)) -> Option<Vec<(u32, u32)>> {
+        let p = self.params;
+        let intensity = &self.intensity;
+        let path = {
+            let hm: &Heightmap<F> = m;
+            shortest_path(hm, a, b, |c, n| {
+                let l = hm.distance(c, n);
+                let s = (hm.get(n.0, n.1) - hm.get(c.0, c.1)).abs() / l;
+                let i = intensity.get(n.0, n.1);
+                let comfort = F::one() - p.comfort * i / (i + F::one());
+                Some(l * (F::one() + p.slope_cost * s) * comfort)
+            })?.0
+        };
+        
+        let decay = F::one() - p.decay;
+        for i in self.intensity.data_mut().iter_mut() {
+            *i *= decay;
+        }
+        let half: F = convert(0.5);
+        let heights: Vec<F> = path.iter().map(|c| m.get(c.0, c.1)).collect();
+        for (i, &c) in path.iter().enumerate() {
+            let x = self.intensity.get(c.0, c.1) + p.deposit;
+            self.intensity.set(c.0, c.1, x);
+            
+            let mut h = heights[i];
+            if i > 0 && i + 1 < path.len() {
+                let mean = (heights[i - 1] + heights[i + 1]) * half;
+                h += (mean - h) * p.smoothing;
+            }
+            m.set(c.0, c.1, h - p.wear);
+        }
+        Some(path)
+    }
+    
+    /// Simulate `trips` trips between random pairs of points of interest
+    pub fn run<R: Rng + ?Sized>(&mut self, m: &mut Heightmap<F>, rng: &mut R, trips: usize) {
+        let n = self.points.len();
+        if n < 2 {
+            return;
+        }
+        for _ in 0..trips {
+            let i = rng.gen_range(0, n);
+            let j = (i + rng.gen_range(1, n)) % n;
+            let (a, b) = (self.points[i], self.points[j]);
+            self.walk(m, a, b);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use nalgebra::RealField;
+use super::{Heightmap, search::cost_field};
+use crate::grid::Grid;
+
+/// Movement costs over terrain
+#[derive(Debug, Clone, Copy)]
+pub struct TravelParams<F> {
+    /// Additional cost of moving up or down slopes: a step of length `l` and
+    /// slope `s` costs `l * (1 + slope_cost * |s|)`
+    pub slope_cost: F,
+    /// If given, steps with a steeper slope are impassable
+    pub max_slope: Option<F>,
+    /// If given, steps between vertices differing in height by more than
+    /// this are impassable
+    pub max_step: Option<F>,
+    /// If given, vertices at or below this height are under water
+    pub water_level: Option<F>,
+    /// Cost multiplier of steps onto vertices under water; if `None`, water
+    /// is impassable
+    pub water_cost: Option<F>,
+}
+
+impl<F: RealField> TravelParams<F> {
+    // Cost of a step from `a` to `b`, if passable
+    pub(super) fn step_cost(&self, m: &Heightmap<F>, a: (u32, u32), b: (u32, u32)) -> Option<F> {
+        let l = m.distance(a, b);
+        let dh = (m.get(b.0, b.1) - m.get(a.0, a.1)).abs();
+        if self.max_rise(l).map(|max| dh > max).unwrap_or(false) {
+            return None;
+        }
+        self.flat_cost(m, b, l, dh / l)
+    }
+    
+    // Cost of a step of length `l` and slope `s` onto `b`, ignoring slope
+    // limits
+    pub(super) fn flat_cost(&self, m: &Heightmap<F>, b: (u32, u32), l: F, s: F) -> Option<F> {
+        let mut cost = l * (F::one() + self.slope_cost * s);
+        if self.is_water(m.get(b.0, b.1)) {
+            cost *= self.water_cost?;
+        }
+        Some(cost)
+    }
+    
+    // Maximum height difference of a step of length `l`, if limited
+    pub(super) fn max_rise(&self, l: F) -> Option<F> {
+        match (self.max_slope.map(|s| s * l), self.max_step) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+    
+    // True if height `h` is under water
+    pub(super) fn is_water(&self, h: F) -> bool {
+        self.water_level.map(|w| h <= w).unwrap_or(false)
+    }
+}
+
+/// A travel cost field and flow field towards one or more goals
+/// 
+/// For every vertex of a heightmap this gives the least cost of travel to the
+/// nearest goal (over 8-connected vertices, via Dijkstra's algorithm) and the
+/// next vertex along the cheapest route. Any number of units may thus be
+/// steered towards the goals by following the flow field, and reachability
+/// tested by whether a cost exists.
+#[derive(Debug, Clone)]
+pub struct CostField<F> {
+    cost: Grid<F>,
+    next: Vec<usize>,
+}
+
+impl<F: RealField> CostField<F> {
+    /// Compute the field of travel over `m` towards `goals` (vertices)
+    pub fn new(m: &Heightmap<F>, goals: &[(u32, u32)], params: &TravelParams<F>) -> Self {
+        Self::with_cost(m, goals, |a, b| params.step_cost(m, a, b))
+    }
+    
+    /// Compute the field with a custom step cost
+    /// 
+    /// `cost(a, b)` gives the cost of a step from vertex `a` to adjacent
+    /// vertex `b`, or `None` if impassable. Costs must be non-negative.
+    pub fn with_cost<C>(m: &Heightmap<F>, goals: &[(u32, u32)], cost: C) -> Self
+        where C: FnMut((u32, u32), (u32, u32)) -> Option<F>
+    {
+        trace_span!("cost_field", dim = ?m.dim(), goals = goals.len());
+        let (cost, next) = cost_field(m, goals, cost);
+        let mut cost = cost.into_iter();
+        let cost = Grid::from_fn(m.dim(), |_, _| cost.next().unwrap());
+        CostField { cost, next }
+    }
+    
+    /// Cost grid
+    /// 
+    /// Unreachable vertices have cost `F::max_value()`.
+    #[inline]
+    pub fn costs(&self) -> &Grid<F> {
+        &self.cost
+    }
+    
+    /// Get the cost of travel from vertex `c` to the nearest goal, if
+    /// reachable
+    pub fn cost(&self, c: (u32, u32)) -> Option<F> {
+        let cost = self.cost.get(c.0, c.1);
+        if cost == F::max_value() { None } else { Some(cost) }
+    }
+    
+    /// True if a goal is reachable from vertex `c`
+    pub fn is_reachable(&self, c: (u32, u32)) -> bool {
+        self.cost(c).is_some()
+    }
+    
+    /// Get the next vertex from `c` towards the nearest goal
+    /// 
+    /// Returns `None` at goals and where unreachable.
+    pub fn next(&self, c: (u32, u32)) -> Option<(u32, u32)> {
+        let w = self.cost.dim().0;
+        let i = self.next[(c.0 + c.1 * w) as usize];
+        if i == usize::MAX {
+            None
+        } else {
+            Some(((i % w as usize) as u32, (i / w as usize) as u32))
+        }
+    }
+    
+    /// Get the flow direction at vertex `c`, as a unit step `(dx, dy)` in
+    /// vertex indices (each in `{-1, 0, 1}`)
+    /// 
+    /// Returns `(0, 0)` at goals and where unreachable.
+    pub fn direction(&self, c: (u32, u32)) -> (i32, i32) {
+        self.next(c)
+            .map(|n| (n.0 as i32 - c.0 as i32, n.1 as i32 - c.1 as i32))
+            .unwrap_or((0, 0))
+    }
+    
+    /// Follow the flow field from `c`, returning the route (including both
+    /// ends) if a goal is reachable
+    pub fn path_from(&self, c: (u32, u32)) -> Option<Vec<(u32, u32)>> {
+        self.cost(c)?;
+        let mut path = vec![c];
+        let mut c = c;
+        while let Some(n) = self.next(c) {
+            path.push(n);
+            c = n;
+        }
+        Some(path)
+    }
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Find the vertices reachable from any of `starts`
+    /// 
+    /// This is a flood fill over 8-connected vertices, where a step is
+    /// traversable if passable under `params` (the slope cost is ignored).
+    /// The result is a mask of the map: for example, a generated map is fully
+    /// traversable if all entries are true.
+    pub fn reachable(&self, starts: &[(u32, u32)], params: &TravelParams<F>) -> Grid<bool> {
+        trace_span!("reachable", dim = ?self.dim, starts = starts.len());
+        let mut mask = Grid::new(self.dim, false);
+        let mut queue = VecDeque::new();
+        for &s in starts {
+            if !mask.get(s.0, s.1) {
+                mask.set(s.0, s.1, true);
+                queue.push_back(s);
+            }
+        }
+        while let Some(c) = queue.pop_front() {
+            for n in self.neighbours(c.0, c.1) {
+                if !mask.get(n.0, n.1) && params.step_cost(self, c, n).is_some() {
+                    mask.set(n.0, n.1, true);
+                    queue.push_back(n);
+                }
+            }
+        }
+        mask
+    }
+}
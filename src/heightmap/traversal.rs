@@ -0,0 +1,254 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, try_convert, Point3, RealField, Vector3};
+use super::Heightmap;
+
+/// A ray in a heightmap's local coordinates
+/// 
+/// Points along the ray are `origin + t × dir` for `t ≥ 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray<F: RealField> {
+    /// Starting point
+    pub origin: Point3<F>,
+    /// Direction (need not be normalised)
+    pub dir: Vector3<F>,
+}
+
+impl<F: RealField> Ray<F> {
+    /// Construct a ray
+    pub fn new(origin: Point3<F>, dir: Vector3<F>) -> Self {
+        Ray { origin, dir }
+    }
+    
+    /// Get the point at parameter `t`
+    pub fn point_at(&self, t: F) -> Point3<F> {
+        self.origin + self.dir * t
+    }
+}
+
+/// The first intersection of a [`Ray`] with a heightmap's surface
+/// 
+/// See [`Heightmap::ray_hit`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit<F: RealField> {
+    /// Ray parameter of the intersection
+    pub toi: F,
+    /// Unit normal of the surface at the intersection, facing the ray origin
+    pub normal: Vector3<F>,
+    /// Index of the triangle hit
+    /// 
+    /// Cell `(cx, cy)` is split along the diagonal from vertex `(cx, cy)` to
+    /// `(cx+1, cy+1)` into triangle `2 × (cx + cy × (dim.0 - 1))`, containing
+    /// vertex `(cx+1, cy)`, and the next, containing vertex `(cx, cy+1)`.
+    pub triangle: usize,
+    /// True if the surface was hit from below
+    pub from_below: bool,
+}
+
+/// Iterator over the cells crossed by a ray
+/// 
+/// See [`Heightmap::ray_cells`].
+#[derive(Debug, Clone)]
+pub struct RayCells<F> {
+    // the next cell to yield, if any
+    cell: Option<(u32, u32)>,
+    cells: (u32, u32),
+    dir: (F, F),
+    // per axis: ray parameter of the next cell boundary and the increment
+    // between boundaries
+    next: (F, F),
+    delta: (F, F),
+    max_t: F,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Iterate over the cells crossed by the horizontal projection of a ray
+    /// 
+    /// The ray is `origin + t × dir` for `0 ≤ t ≤ max_t` (which may be
+    /// `F::max_value()`); only the part over the map is traversed. Cells are
+    /// yielded in order along the ray (Amanatides & Woo), each once. Where the
+    /// ray runs exactly along a cell boundary, only the cells to one side
+    /// are yielded; where it passes exactly through a vertex, one extra cell
+    /// touching the vertex is included.
+    /// A vertical ray (`dir = (0, 0)`) yields the cell containing `origin`.
+    pub fn ray_cells(&self, origin: (F, F), dir: (F, F), max_t: F) -> RayCells<F> {
+        let cells = (self.dim.0 - 1, self.dim.1 - 1);
+        let zero = F::zero();
+        
+        // clip to the map
+        let mut range = (zero, max_t);
+        for &(o, d, size) in &[(origin.0, dir.0, self.size.0), (origin.1, dir.1, self.size.1)] {
+            if d == zero {
+                if o < zero || o > size {
+                    range = (F::one(), zero);
+                }
+            } else {
+                let (t0, t1) = ((zero - o) / d, (size - o) / d);
+                range = (range.0.max(t0.min(t1)), range.1.min(t0.max(t1)));
+            }
+        }
+        let mut iter = RayCells {
+            cell: None,
+            cells,
+            dir,
+            next: (F::max_value(), F::max_value()),
+            delta: (F::max_value(), F::max_value()),
+            max_t: range.1,
+        };
+        if range.0 > range.1 {
+            return iter;
+        }
+        
+        let p = (origin.0 + dir.0 * range.0, origin.1 + dir.1 * range.0);
+        let cell_of = |v: F, frac: F, n: u32| {
+            let i = try_convert::<_, f64>((v / frac).floor()).unwrap();
+            (i.max(0.0) as u32).min(n - 1)
+        };
+        let cell = (cell_of(p.0, self.len_frac.0, cells.0), cell_of(p.1, self.len_frac.1, cells.1));
+        let axis = |o: F, d: F, c: u32, frac: F| {
+            if d > zero {
+                ((convert::<_, F>((c + 1) as f64) * frac - o) / d, frac / d)
+            } else if d < zero {
+                ((convert::<_, F>(c as f64) * frac - o) / d, -frac / d)
+            } else {
+                (F::max_value(), F::max_value())
+            }
+        };
+        let (next_x, delta_x) = axis(origin.0, dir.0, cell.0, self.len_frac.0);
+        let (next_y, delta_y) = axis(origin.1, dir.1, cell.1, self.len_frac.1);
+        iter.cell = Some(cell);
+        iter.next = (next_x, next_y);
+        iter.delta = (delta_x, delta_y);
+        iter
+    }
+    
+    /// Cast a ray against the surface
+    /// 
+    /// The ray is in the heightmap's local coordinates; the first intersection
+    /// with `toi ≤ max_toi` from either side is returned. Cells are traversed
+    /// via [`Heightmap::ray_cells`], thus the cost is proportional to the
+    /// length of the ray over the map.
+    pub fn ray_hit(&self, ray: &Ray<F>, max_toi: F) -> Option<RayHit<F>> {
+        let (min_t, max_t) = self.clip_to_range(ray.origin.z, ray.dir.z, max_toi)?;
+        
+        // Triangles lie within their cell, so the first cell with an
+        // intersection contains the first intersection overall.
+        let p = ray.point_at(min_t);
+        for cell in self.ray_cells((p.x, p.y), (ray.dir.x, ray.dir.y), max_t - min_t) {
+            let (t, [tri1, tri2]) = self.cell_triangles(cell.0, cell.1);
+            let hit1 = ray_triangle(ray, tri1, max_toi).map(|h| (h, t));
+            let hit2 = ray_triangle(ray, tri2, max_toi).map(|h| (h, t + 1));
+            let hit = match (hit1, hit2) {
+                (Some(a), Some(b)) => Some(if (a.0).0 <= (b.0).0 { a } else { b }),
+                (a, b) => a.or(b),
+            };
+            if let Some(((toi, normal), triangle)) = hit {
+                let from_below = normal.z < F::zero();
+                return Some(RayHit { toi, normal, triangle, from_below });
+            }
+        }
+        None
+    }
+    
+    /// Cast many rays against the surface
+    /// 
+    /// This is equivalent to calling [`Heightmap::ray_hit`] for each ray, but
+    /// with the `rayon` feature casts rays in parallel.
+    pub fn ray_hits(&self, rays: &[Ray<F>], max_toi: F) -> Vec<Option<RayHit<F>>> {
+        trace_span!("ray_hits", rays = rays.len());
+        #[cfg(feature = "rayon")] {
+            use rayon::prelude::*;
+            rays.par_iter().map(|ray| self.ray_hit(ray, max_toi)).collect()
+        }
+        #[cfg(not(feature = "rayon"))] {
+            rays.iter().map(|ray| self.ray_hit(ray, max_toi)).collect()
+        }
+    }
+    
+    // Clip the parameter range `[0, max_t]` of a ray with height `z + t × dz`
+    // to the map's height range, if they intersect
+    pub(super) fn clip_to_range(&self, z: F, dz: F, max_t: F) -> Option<(F, F)> {
+        let zero = F::zero();
+        let range = if dz == zero {
+            if z < self.range.0 || z > self.range.1 {
+                return None;
+            }
+            (zero, max_t)
+        } else {
+            let (t0, t1) = ((self.range.0 - z) / dz, (self.range.1 - z) / dz);
+            (t0.min(t1).max(zero), t0.max(t1).min(max_t))
+        };
+        if range.0 <= range.1 { Some(range) } else { None }
+    }
+    
+    // Index and vertices of the first triangle of cell `(cx, cy)` and the
+    // vertices of the second (see `RayHit::triangle`)
+    pub(super) fn cell_triangles(&self, cx: u32, cy: u32) -> (usize, [[Point3<F>; 3]; 2]) {
+        let (x0, y0) = self.coord_of(cx, cy);
+        let (x1, y1) = self.coord_of(cx + 1, cy + 1);
+        let p00 = Point3::new(x0, y0, self.get(cx, cy));
+        let p10 = Point3::new(x1, y0, self.get(cx + 1, cy));
+        let p01 = Point3::new(x0, y1, self.get(cx, cy + 1));
+        let p11 = Point3::new(x1, y1, self.get(cx + 1, cy + 1));
+        let t = 2 * (cx as usize + cy as usize * (self.dim.0 as usize - 1));
+        (t, [[p10, p00, p11], [p00, p01, p11]])
+    }
+}
+
+// Intersect a ray with triangle `[a, b, c]`, from either side, yielding the
+// ray parameter and the unit normal facing the ray origin
+fn ray_triangle<F: RealField>(ray: &Ray<F>, [a, b, c]: [Point3<F>; 3], max_toi: F) -> Option<(F, Vector3<F>)> {
+    let zero = F::zero();
+    let (ab, ac) = (b - a, c - a);
+    let n = ab.cross(&ac);
+    // Solve `origin + t × dir = a + u × ab + v × ac` by Cramer's rule
+    let det = -ray.dir.dot(&n);
+    if det == zero {
+        return None;
+    }
+    let ao = ray.origin - a;
+    let t = ao.dot(&n) / det;
+    let u = ao.dot(&ray.dir.cross(&ac)) / det;
+    let v = ab.dot(&ray.dir.cross(&ao)) / det;
+    if t < zero || t > max_toi || u < zero || v < zero || u + v > F::one() {
+        return None;
+    }
+    let normal = if det > zero { n.normalize() } else { -n.normalize() };
+    Some((t, normal))
+}
+
+impl<F: RealField> Iterator for RayCells<F> {
+    type Item = (u32, u32);
+    
+    fn next(&mut self) -> Option<(u32, u32)> {
+        let cell = self.cell?;
+        let zero = F::zero();
+        // step across the nearer cell boundary; stop at the end of the ray
+        // or the edge of the map (including when parallel to both axes)
+        let step = |c: u32, d: F, n: u32| {
+            if d > zero && c + 1 < n {
+                Some(c + 1)
+            } else if d < zero && c > 0 {
+                Some(c - 1)
+            } else {
+                None
+            }
+        };
+        self.cell = if self.next.0 < self.next.1 {
+            let t = self.next.0;
+            self.next.0 += self.delta.0;
+            if t > self.max_t { None } else { step(cell.0, self.dir.0, self.cells.0).map(|cx| (cx, cell.1)) }
+        } else {
+            let t = self.next.1;
+            self.next.1 += self.delta.1;
+            if t > self.max_t { None } else { step(cell.1, self.dir.1, self.cells.1).map(|cy| (cell.0, cy)) }
+        };
+        Some(cell)
+    }
+}
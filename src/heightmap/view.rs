@@ -0,0 +1,132 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{convert, RealField};
+use super::{range, Heightmap};
+
+/// A read-only rectangular window onto a [`Heightmap`]
+/// 
+/// The view covers `dim` vertices starting at vertex `origin` of the source
+/// map, without copying. Vertex indices and coordinates are local to the
+/// view: vertex `(0, 0)` of the view is at coordinate `(0, 0)`.
+#[derive(Debug, Clone, Copy)]
+pub struct HeightmapView<'a, F> {
+    map: &'a Heightmap<F>,
+    origin: (u32, u32),
+    dim: (u32, u32),
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Borrow a window of `dim` vertices starting at vertex `origin`
+    /// 
+    /// Requires `dim >= (2, 2)` and that the window lies within the map.
+    pub fn view(&self, origin: (u32, u32), dim: (u32, u32)) -> HeightmapView<'_, F> {
+        assert!(dim.0 >= 2 && dim.1 >= 2);
+        assert!(origin.0 + dim.0 <= self.dim.0 && origin.1 + dim.1 <= self.dim.1,
+            "view: window exceeds map bounds");
+        HeightmapView { map: self, origin, dim }
+    }
+    
+    /// Copy a window of `dim` vertices starting at vertex `origin` to a new
+    /// map
+    /// 
+    /// Cell size is preserved. Requirements are as for [`Heightmap::view`].
+    pub fn crop(&self, origin: (u32, u32), dim: (u32, u32)) -> Heightmap<F> {
+        self.view(origin, dim).to_heightmap()
+    }
+}
+
+impl<'a, F: RealField> HeightmapView<'a, F> {
+    /// Get the source map
+    #[inline]
+    pub fn source(&self) -> &'a Heightmap<F> {
+        self.map
+    }
+    
+    /// Get the first vertex of the view, in the source map
+    #[inline]
+    pub fn origin(&self) -> (u32, u32) {
+        self.origin
+    }
+    
+    /// Get the grid dimension
+    #[inline]
+    pub fn dim(&self) -> (u32, u32) {
+        self.dim
+    }
+    
+    /// Get the distance between adjacent vertices along each axis
+    #[inline]
+    pub fn cell_size(&self) -> (F, F) {
+        self.map.len_frac
+    }
+    
+    /// Get the size of the view
+    pub fn size(&self) -> (F, F) {
+        let (w, h): (F, F) = (convert((self.dim.0 - 1) as f64), convert((self.dim.1 - 1) as f64));
+        (w * self.map.len_frac.0, h * self.map.len_frac.1)
+    }
+    
+    /// Get the coordinates of the view's origin in the source map
+    #[inline]
+    pub fn offset(&self) -> (F, F) {
+        self.map.coord_of(self.origin.0, self.origin.1)
+    }
+    
+    /// Get value at the given vertex (local to the view).
+    /// 
+    /// Requires `cx < self.dim().0 && cy < self.dim().1`.
+    #[inline]
+    pub fn get(&self, cx: u32, cy: u32) -> F {
+        assert!(cx < self.dim.0);
+        assert!(cy < self.dim.1);
+        self.map.get(self.origin.0 + cx, self.origin.1 + cy)
+    }
+    
+    /// Get row `cy` of the view as a slice
+    #[inline]
+    pub fn row(&self, cy: u32) -> &'a [F] {
+        assert!(cy < self.dim.1);
+        let start = (self.origin.1 + cy) as usize * self.map.dim.0 as usize + self.origin.0 as usize;
+        &self.map.data[start..start + self.dim.0 as usize]
+    }
+    
+    /// Calculate the `(min, max)` height within the view
+    /// 
+    /// Unlike [`Heightmap::range`], this is not cached.
+    pub fn range(&self) -> (F, F) {
+        let mut r = (F::max_value(), F::min_value());
+        for cy in 0..self.dim.1 {
+            let (min, max) = range(self.row(cy));
+            r = (r.0.min(min), r.1.max(max));
+        }
+        r
+    }
+    
+    /// Borrow a sub-window (relative to this view)
+    pub fn view(&self, origin: (u32, u32), dim: (u32, u32)) -> HeightmapView<'a, F> {
+        assert!(origin.0 + dim.0 <= self.dim.0 && origin.1 + dim.1 <= self.dim.1,
+            "view: window exceeds view bounds");
+        self.map.view((self.origin.0 + origin.0, self.origin.1 + origin.1), dim)
+    }
+    
+    /// Copy the view to a new map
+    pub fn to_heightmap(&self) -> Heightmap<F> {
+        let mut data = Vec::with_capacity(self.dim.0 as usize * self.dim.1 as usize);
+        for cy in 0..self.dim.1 {
+            data.extend_from_slice(self.row(cy));
+        }
+        Heightmap {
+            dim: self.dim,
+            len_frac: self.map.len_frac,
+            size: self.size(),
+            range: range(&data),
+            data,
+        }
+    }
+}
@@ -48,6 +48,7 @@ impl<F: RealField> Voronoi<F> {
     /// 
     /// TODO: optimise (current alg is naive)
     pub fn apply_to<D: FnMut(F, F) -> F>(&self, m: &mut Heightmap<F>, w: &[F], mut dist: D){
+        trace_span!("voronoi", dim = ?m.dim(), points = self.points.len());
         let dim = m.dim();
         let np = self.points.len();
         let nw = w.len().min(np);
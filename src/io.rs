@@ -0,0 +1,209 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Image import and export
+//! 
+//! Use [`load`] to import a heightmap of any supported format.
+//! 
+//! Image formats are enabled by optional features: `png`, `exr`, `geotiff`. Presets for
+//! game engines are also provided (Unity RAW, Unreal Engine PNG tiles).
+//! 
+//! Images are written with vertex `(0, 0)` as the first (top-left) pixel and
+//! rows in order of increasing `cy`.
+//! 
+//! Each format has a streamed variant (`write_*_to`) which writes to an
+//! `io::Write` stream, reading values on demand from a function `f(cx, cy)`
+//! with memory usage bounded by a row (or block of rows). This allows export
+//! of maps too large to hold in memory, e.g. from the loaded chunks of a
+//! [`TiledHeightmap`](crate::heightmap::TiledHeightmap) or direct from a
+//! surface.
+
+use std::fmt::Write;
+use std::io;
+use nalgebra::{try_convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+
+mod asc;
+mod engines;
+mod load;
+mod raw;
+#[cfg(feature = "exr")]
+mod exr;
+#[cfg(feature = "geotiff")]
+mod geotiff;
+#[cfg(feature = "png")]
+mod png;
+
+pub use self::asc::{read_asc, write_asc, write_asc_to, AscInfo, ASC_NODATA};
+pub use self::engines::{write_raw16_to, write_unity_raw};
+pub use self::load::{load, load_with_units, Format, Metadata};
+pub use self::raw::{Endianness, RawFormat};
+#[cfg(feature = "png")]
+pub use self::engines::write_unreal_tiles;
+#[cfg(feature = "exr")]
+pub use self::exr::{read_exr, write_exr, write_exr_to};
+#[cfg(feature = "geotiff")]
+pub use self::geotiff::{read_geotiff, GeoTiffInfo};
+#[cfg(feature = "png")]
+pub use self::png::{read_png, write_normal_png, write_normal_png_to, write_png16, write_png16_to};
+
+/// A stack of aligned analysis grids for export
+/// 
+/// For example, height, slope, wetness and occlusion grids may be collected
+/// and written as a set of aligned images plus a manifest, for consumption by
+/// external texturing tools.
+/// 
+/// Each channel is normalised from a value range (by default the channel's
+/// own minimum and maximum) to the full range of the output format; the
+/// manifest records these ranges so that values may be reconstructed.
+#[derive(Debug, Clone)]
+pub struct ImageStack<'a, F> {
+    dim: (u32, u32),
+    channels: Vec<Channel<'a, F>>,
+}
+
+#[derive(Debug, Clone)]
+struct Channel<'a, F> {
+    name: String,
+    grid: GridRef<'a, F>,
+    range: (F, F),
+}
+
+#[derive(Debug, Clone)]
+enum GridRef<'a, F> {
+    Borrowed(&'a Grid<F>),
+    Owned(Grid<F>),
+}
+
+impl<'a, F: RealField> ImageStack<'a, F> {
+    /// Construct an empty stack for grids of dimension `dim`
+    pub fn new(dim: (u32, u32)) -> Self {
+        ImageStack { dim, channels: vec![] }
+    }
+    
+    /// Add a channel, normalised over its own range
+    /// 
+    /// The `name` is used in file names and should be a simple identifier.
+    pub fn add(&mut self, name: &str, grid: &'a Grid<F>) -> &mut Self {
+        let range = grid_range(grid);
+        self.add_with_range(name, grid, range)
+    }
+    
+    /// Add a channel, normalised over the given `(min, max)` range
+    pub fn add_with_range(&mut self, name: &str, grid: &'a Grid<F>, range: (F, F)) -> &mut Self {
+        assert_eq!(grid.dim(), self.dim);
+        self.channels.push(Channel { name: name.to_string(), grid: GridRef::Borrowed(grid), range });
+        self
+    }
+    
+    /// Add the heights of `m` as a channel, normalised over its range
+    pub fn add_heightmap(&mut self, name: &str, m: &Heightmap<F>) -> &mut Self {
+        assert_eq!(m.dim(), self.dim);
+        let grid = Grid::from_fn(m.dim(), |cx, cy| m.get(cx, cy));
+        self.channels.push(Channel { name: name.to_string(), grid: GridRef::Owned(grid), range: m.range() });
+        self
+    }
+    
+    /// Get the grid dimension
+    pub fn dim(&self) -> (u32, u32) {
+        self.dim
+    }
+    
+    /// Iterate over `(name, grid, range)` of each channel
+    pub fn channels(&self) -> impl Iterator<Item = (&str, &Grid<F>, (F, F))> {
+        self.channels.iter().map(|c| {
+            let grid = match c.grid {
+                GridRef::Borrowed(g) => g,
+                GridRef::Owned(ref g) => g,
+            };
+            (c.name.as_str(), grid, c.range)
+        })
+    }
+    
+    /// Get a JSON manifest describing the stack
+    /// 
+    /// `files` gives the file name of each channel, in order.
+    /// 
+    /// Panics if the number of files does not match the number of channels.
+    pub fn manifest(&self, files: &[String]) -> String {
+        assert_eq!(files.len(), self.channels.len(), "ImageStack::manifest: expected one file per channel");
+        let mut s = String::new();
+        writeln!(s, "{{").unwrap();
+        writeln!(s, "  \"width\": {},", self.dim.0).unwrap();
+        writeln!(s, "  \"height\": {},", self.dim.1).unwrap();
+        writeln!(s, "  \"channels\": [").unwrap();
+        for (i, ((name, _, range), file)) in self.channels().zip(files).enumerate() {
+            let sep = if i + 1 < files.len() { "," } else { "" };
+            writeln!(s, "    {{ \"name\": \"{}\", \"file\": \"{}\", \"min\": {}, \"max\": {} }}{}",
+                escape(name), escape(file), to_f64(range.0), to_f64(range.1), sep).unwrap();
+        }
+        writeln!(s, "  ]").unwrap();
+        writeln!(s, "}}").unwrap();
+        s
+    }
+}
+
+// Normalise v from range to [0, 1], clamped
+fn normalise<F: RealField>(v: F, range: (F, F)) -> f64 {
+    let d = range.1 - range.0;
+    if d > F::zero() {
+        to_f64((v - range.0) / d).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+fn to_f64<F: RealField>(x: F) -> f64 {
+    try_convert::<_, f64>(x).unwrap()
+}
+
+fn grid_range<F: RealField>(grid: &Grid<F>) -> (F, F) {
+    grid.data().iter().fold((F::max_value(), F::min_value()), |(lo, hi), v| (lo.min(*v), hi.max(*v)))
+}
+
+// Error for malformed input
+pub(super) fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Build a grid of row-major `values`, replacing samples failing `is_valid`
+// by the lowest valid value (zero if none). Also returns a mask of valid
+// samples if any were invalid.
+pub(super) fn replace_nodata<V>(dim: (u32, u32), values: &[f64], is_valid: V) -> (Grid<f64>, Option<Grid<bool>>)
+where V: Fn(f64) -> bool
+{
+    let lowest = values.iter().cloned().filter(|v| is_valid(*v)).fold(None, |m: Option<f64>, v| {
+        Some(m.map_or(v, |m| m.min(v)))
+    });
+    let value = |cx: u32, cy: u32| values[cx as usize + cy as usize * dim.0 as usize];
+    let valid = if values.iter().all(|v| is_valid(*v)) {
+        None
+    } else {
+        Some(Grid::from_fn(dim, |cx, cy| is_valid(value(cx, cy))))
+    };
+    let grid = Grid::from_fn(dim, |cx, cy| {
+        let v = value(cx, cy);
+        if is_valid(v) { v } else { lowest.unwrap_or(0.0) }
+    });
+    (grid, valid)
+}
+
+// Escape a string for inclusion in JSON
+pub(crate) fn escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(r, "\\u{:04x}", c as u32).unwrap(),
+            c => r.push(c),
+        }
+    }
+    r
+}
@@ -0,0 +1,158 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use nalgebra::{convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use super::{invalid, replace_nodata, to_f64};
+
+/// No-data value written by [`write_asc`] and [`write_asc_to`]
+pub const ASC_NODATA: f64 = -9999.0;
+
+/// Georeferencing and no-data information of an Esri ASCII grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct AscInfo {
+    /// Cell size `(x, y)`, usually equal
+    pub cellsize: (f64, f64),
+    /// Coordinates of the centre of the lower-left cell, i.e. vertex
+    /// `(0, nrows - 1)`
+    pub origin: (f64, f64),
+    /// The no-data value (`NODATA_value`), if given
+    pub nodata: Option<f64>,
+    /// Mask of valid samples, if any sample had the no-data value
+    /// 
+    /// Invalid samples are replaced by the lowest valid height.
+    pub valid: Option<Grid<bool>>,
+}
+
+/// Read an Esri ASCII grid (`.asc`)
+/// 
+/// Both corner (`xllcorner`) and centre (`xllcenter`) registration are
+/// accepted, as are the non-standard `dx` and `dy` in place of `cellsize`.
+/// Rows are stored from north to south, thus `cy` increases southwards.
+/// Heights and spacing are not converted.
+pub fn read_asc<F: RealField>(path: &Path) -> io::Result<(Heightmap<F>, AscInfo)> {
+    let (grid, info) = parse_asc(BufReader::new(File::open(path)?))?;
+    let dim = grid.dim();
+    let size = ((dim.0 - 1) as f64 * info.cellsize.0, (dim.1 - 1) as f64 * info.cellsize.1);
+    let grid = grid.map(|h| convert::<_, F>(*h));
+    Ok((Heightmap::from_grid(grid, (convert(size.0), convert(size.1))), info))
+}
+
+/// Write a heightmap as an Esri ASCII grid (`.asc`)
+/// 
+/// `origin` is the coordinates of the centre of the lower-left cell (see
+/// [`AscInfo::origin`]). Samples where `valid` is false are written as
+/// [`ASC_NODATA`]. If the vertex spacing differs between axes, the
+/// non-standard `dx` and `dy` are written in place of `cellsize`.
+pub fn write_asc<F: RealField>(path: &Path, m: &Heightmap<F>, origin: (f64, f64), valid: Option<&Grid<bool>>)
+    -> io::Result<()>
+{
+    trace_span!("write_asc", path = %path.display());
+    let dim = m.dim();
+    if let Some(valid) = valid {
+        assert_eq!(valid.dim(), dim);
+    }
+    let cellsize = (to_f64(m.size().0) / (dim.0 - 1) as f64, to_f64(m.size().1) / (dim.1 - 1) as f64);
+    let w = BufWriter::new(File::create(path)?);
+    write_asc_to(w, dim, cellsize, origin, |cx, cy| {
+        match valid {
+            Some(valid) if !valid.get(cx, cy) => None,
+            _ => Some(m.get(cx, cy)),
+        }
+    })
+}
+
+/// Stream an Esri ASCII grid of `dim` samples to `w`
+/// 
+/// Values are read row-by-row from `f(cx, cy)`; `None` is written as
+/// [`ASC_NODATA`]. See [`write_asc`]. `w` is not buffered.
+pub fn write_asc_to<W, F, G>(mut w: W, dim: (u32, u32), cellsize: (f64, f64), origin: (f64, f64), mut f: G)
+    -> io::Result<()>
+where W: Write, F: RealField, G: FnMut(u32, u32) -> Option<F>
+{
+    writeln!(w, "ncols {}", dim.0)?;
+    writeln!(w, "nrows {}", dim.1)?;
+    writeln!(w, "xllcorner {}", origin.0 - 0.5 * cellsize.0)?;
+    writeln!(w, "yllcorner {}", origin.1 - 0.5 * cellsize.1)?;
+    if cellsize.0 == cellsize.1 {
+        writeln!(w, "cellsize {}", cellsize.0)?;
+    } else {
+        writeln!(w, "dx {}", cellsize.0)?;
+        writeln!(w, "dy {}", cellsize.1)?;
+    }
+    writeln!(w, "NODATA_value {}", ASC_NODATA)?;
+    let mut line = String::new();
+    for cy in 0..dim.1 {
+        line.clear();
+        for cx in 0..dim.0 {
+            if cx > 0 {
+                line.push(' ');
+            }
+            match f(cx, cy) {
+                Some(v) => line.push_str(&v.to_string()),
+                None => line.push_str(&ASC_NODATA.to_string()),
+            }
+        }
+        line.push('\n');
+        w.write_all(line.as_bytes())?;
+    }
+    w.flush()
+}
+
+// Parse an ASC grid, replacing no-data samples by the lowest valid height
+pub(super) fn parse_asc<R: BufRead>(reader: R) -> io::Result<(Grid<f64>, AscInfo)> {
+    trace_span!("parse_asc");
+    let mut lines = reader.lines();
+    let mut header = Vec::new();
+    let mut values = Vec::new();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let key = match words.next() {
+            Some(key) => key,
+            None => continue,
+        };
+        if key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let v: f64 = words.next().and_then(|v| v.parse().ok()).ok_or_else(|| invalid("bad ASC header"))?;
+            header.push((key.to_ascii_lowercase(), v));
+        } else {
+            values.extend(line.split_whitespace().map(|v| v.parse::<f64>()));
+            for line in lines.by_ref() {
+                values.extend(line?.split_whitespace().map(|v| v.parse::<f64>()));
+            }
+        }
+    }
+    let get = |k: &str| header.iter().find(|h| h.0 == k).map(|h| h.1);
+    let cols = get("ncols").ok_or_else(|| invalid("ASC: missing ncols"))? as u32;
+    let rows = get("nrows").ok_or_else(|| invalid("ASC: missing nrows"))? as u32;
+    let cellsize = match (get("cellsize"), get("dx"), get("dy")) {
+        (Some(c), _, _) => (c, c),
+        (None, Some(dx), Some(dy)) => (dx, dy),
+        _ => return Err(invalid("ASC: missing cellsize")),
+    };
+    if cols < 2 || rows < 2 {
+        return Err(invalid("heightmap smaller than 2×2"));
+    }
+    let values: Vec<f64> = values.into_iter().collect::<Result<_, _>>().map_err(|_| invalid("ASC: bad value"))?;
+    if values.len() != cols as usize * rows as usize {
+        return Err(invalid("ASC: wrong number of values"));
+    }
+    
+    let origin = match (get("xllcorner"), get("yllcorner"), get("xllcenter"), get("yllcenter")) {
+        (Some(x), Some(y), _, _) => (x + 0.5 * cellsize.0, y + 0.5 * cellsize.1),
+        (_, _, Some(x), Some(y)) => (x, y),
+        _ => (0.0, 0.0),
+    };
+    let nodata = get("nodata_value");
+    let (grid, valid) = replace_nodata((cols, rows), &values, |v| Some(v) != nodata);
+    Ok((grid, AscInfo { cellsize, origin, nodata, valid }))
+}
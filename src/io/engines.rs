@@ -0,0 +1,110 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Export presets for game engines
+//! 
+//! Heightmap units are assumed to be metres.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use nalgebra::RealField;
+use crate::heightmap::{Heightmap, Interpolation};
+use super::{normalise, to_f64};
+
+/// Write a heightmap for import into a Unity terrain
+/// 
+/// The map is resampled to a square power-of-two-plus-one resolution (the
+/// smallest at least as large as `m`, from 33 to 4097) and written to
+/// `{name}.raw` as 16-bit little-endian ("Windows") RAW, normalised over the
+/// height range. The import settings are written to `{name}.json`: the
+/// resolution, the terrain size `(width, height, length)` and the terrain
+/// position height (the minimum height of `m`).
+/// 
+/// Unity's first RAW row lies at `z = 0`, matching `cy = 0`.
+pub fn write_unity_raw<F: RealField>(dir: &Path, name: &str, m: &Heightmap<F>) -> io::Result<()> {
+    trace_span!("write_unity_raw", dir = %dir.display());
+    let largest = m.dim().0.max(m.dim().1) - 1;
+    let res = largest.next_power_of_two().clamp(32, 4096) + 1;
+    let r = m.resample((res, res), Interpolation::Bilinear);
+    let range = m.range();
+    
+    let w = BufWriter::new(File::create(dir.join(format!("{}.raw", name)))?);
+    write_raw16_to(w, (res, res), range, |cx, cy| r.get(cx, cy))?;
+    
+    let size = m.size();
+    let json = format!("{{\n  \"resolution\": {},\n  \"depth\": 16,\n  \"byte_order\": \"little\",\n  \
+        \"terrain_size\": [{}, {}, {}],\n  \"position_y\": {}\n}}\n",
+        res, to_f64(size.0), to_f64(range.1 - range.0), to_f64(size.1), to_f64(range.0));
+    fs::write(dir.join(format!("{}.json", name)), json)
+}
+
+/// Stream 16-bit little-endian RAW data of `dim` samples to `w`
+/// 
+/// Values are read row-by-row from `f(cx, cy)` and normalised over `range`;
+/// only a single row is held in memory. `w` is not buffered.
+pub fn write_raw16_to<W: Write, F: RealField, G: FnMut(u32, u32) -> F>(mut w: W, dim: (u32, u32), range: (F, F),
+    mut f: G) -> io::Result<()>
+{
+    let mut row = Vec::with_capacity(dim.0 as usize * 2);
+    for cy in 0..dim.1 {
+        row.clear();
+        for cx in 0..dim.0 {
+            let v = (normalise(f(cx, cy), range) * 65535.0).round() as u16;
+            row.extend_from_slice(&v.to_le_bytes());
+        }
+        w.write_all(&row)?;
+    }
+    w.flush()
+}
+
+/// Write a heightmap as Unreal Engine Landscape tiles
+/// 
+/// The map is resampled so that it divides exactly into square tiles of
+/// `tile_size` vertices sharing edge vertices; `tile_size` should be a
+/// recommended landscape size such as 127, 253, 505 or 1009. Tiles are
+/// written as 16-bit greyscale PNGs named `{name}_x{i}_y{j}.png`, as expected
+/// by tiled landscape import.
+/// 
+/// Heights are normalised over the height range, with the mid-height at
+/// 32768. Recommended import settings are written to `{name}.json`: the
+/// landscape scale `(x, y, z)` (percent, as entered in the editor) and the
+/// landscape location height (centimetres).
+#[cfg(feature = "png")]
+pub fn write_unreal_tiles<F: RealField>(dir: &Path, name: &str, m: &Heightmap<F>, tile_size: u32)
+    -> io::Result<()>
+{
+    trace_span!("write_unreal_tiles", dir = %dir.display());
+    use super::write_png16_to;
+    
+    assert!(tile_size >= 2);
+    let stride = tile_size - 1;
+    let tiles = (
+        (m.dim().0 - 1).div_ceil(stride),
+        (m.dim().1 - 1).div_ceil(stride),
+    );
+    let dim = (tiles.0 * stride + 1, tiles.1 * stride + 1);
+    let r = m.resample(dim, Interpolation::Bilinear);
+    let range = m.range();
+    for j in 0..tiles.1 {
+        for i in 0..tiles.0 {
+            let w = BufWriter::new(File::create(dir.join(format!("{}_x{}_y{}.png", name, i, j)))?);
+            write_png16_to(w, (tile_size, tile_size), range, |cx, cy| r.get(i * stride + cx, j * stride + cy))?;
+        }
+    }
+    
+    // Unreal heights: cm = (v - 32768) * z_scale / 128; spacing: cm = x_scale
+    let cell = r.cell_size();
+    let span = to_f64(range.1 - range.0) * 100.0;
+    let z_scale = span * 128.0 / 65535.0;
+    let location_z = to_f64(range.0) * 100.0 + span * 32768.0 / 65535.0;
+    let json = format!("{{\n  \"tiles\": [{}, {}],\n  \"tile_size\": {},\n  \
+        \"scale\": [{}, {}, {}],\n  \"location_z\": {}\n}}\n",
+        tiles.0, tiles.1, tile_size, to_f64(cell.0) * 100.0, to_f64(cell.1) * 100.0, z_scale, location_z);
+    fs::write(dir.join(format!("{}.json", name)), json)
+}
@@ -0,0 +1,75 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use nalgebra::{convert, try_convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+
+/// Write heights of `m` as a single-channel (`Y`) 32-bit float EXR image
+/// 
+/// Heights are stored unnormalised; the horizontal size is not stored.
+pub fn write_exr<F: RealField>(path: &Path, m: &Heightmap<F>) -> io::Result<()> {
+    trace_span!("write_exr", path = %path.display());
+    let w = BufWriter::new(File::create(path)?);
+    write_exr_to(w, m.dim(), |cx, cy| m.get(cx, cy))
+}
+
+/// Stream a single-channel (`Y`) 32-bit float EXR image of `dim` pixels to `w`
+/// 
+/// Values are read from `f(cx, cy)` as blocks of rows are compressed (in
+/// parallel), thus memory usage is bounded by the block size rather than the
+/// image size. EXR requires `w` to be seekable; it is not buffered.
+pub fn write_exr_to<W, F, G>(w: W, dim: (u32, u32), f: G) -> io::Result<()>
+where W: Write + Seek, F: RealField, G: Fn(u32, u32) -> F + Sync
+{
+    use exr::prelude::*;
+    let pixels = SpecificChannels::build()
+        .with_channel("Y")
+        .with_pixel_fn(|p| (try_convert::<_, f64>(f(p.x() as u32, p.y() as u32)).unwrap() as f32,));
+    Image::from_channels((dim.0 as usize, dim.1 as usize), pixels)
+        .write()
+        .to_buffered(w)
+        .map_err(to_io_error)
+}
+
+/// Read a heightmap from the `Y` channel of an EXR image
+/// 
+/// Samples of any type are converted to `F`. The horizontal `size` of the map
+/// must be given since EXR does not store it.
+pub fn read_exr<F: RealField>(path: &Path, size: (F, F)) -> io::Result<Heightmap<F>> {
+    trace_span!("read_exr", path = %path.display());
+    use exr::prelude::*;
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .specific_channels()
+        .required("Y")
+        .collect_pixels(
+            |res, _| Grid::new((res.width() as u32, res.height() as u32), F::zero()),
+            |grid: &mut Grid<F>, p, (y,): (f32,)| grid.set(p.x() as u32, p.y() as u32, convert(y as f64)),
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_file(path)
+        .map_err(to_io_error)?;
+    let grid = image.layer_data.channel_data.pixels;
+    if grid.dim().0 < 2 || grid.dim().1 < 2 {
+        return Err(super::invalid("heightmap image smaller than 2×2"));
+    }
+    Ok(Heightmap::from_grid(grid, size))
+}
+
+fn to_io_error(e: exr::error::Error) -> io::Error {
+    match e {
+        exr::error::Error::Io(e) => e,
+        e => super::invalid(&e.to_string()),
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use tiff::{ColorType, TiffError};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use super::{invalid, replace_nodata};
+
+/// Georeferencing and no-data information of a GeoTIFF DEM
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoTiffInfo {
+    /// Pixel spacing `(x, y)` in model units, if stored
+    /// 
+    /// This gives the heightmap `size`; without it a spacing of 1 is used.
+    pub pixel_scale: Option<(f64, f64)>,
+    /// Model coordinates of vertex `(0, 0)` (the centre of the top-left pixel),
+    /// if stored
+    pub origin: Option<(f64, f64)>,
+    /// True if the model is geographic, i.e. coordinates and spacing are in
+    /// degrees of longitude and latitude
+    pub geographic: bool,
+    /// The no-data value (`GDAL_NODATA` tag), if any
+    pub nodata: Option<f64>,
+    /// Mask of valid samples, if any sample had the no-data value
+    /// 
+    /// Invalid samples are replaced by the lowest valid height.
+    pub valid: Option<Grid<bool>>,
+}
+
+/// Read a single-band GeoTIFF DEM
+/// 
+/// Samples of any integer or float type are converted to `f32`. The pixel
+/// spacing is taken from the `ModelPixelScale` tag; since GeoTIFF rows run
+/// from north to south, `cy` increases southwards. Vertical units are not
+/// converted.
+pub fn read_geotiff(path: &Path) -> io::Result<(Heightmap<f32>, GeoTiffInfo)> {
+    trace_span!("read_geotiff", path = %path.display());
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))
+        .map_err(to_io_error)?
+        .with_limits(Limits::unlimited());
+    let dim = decoder.dimensions().map_err(to_io_error)?;
+    match decoder.colortype().map_err(to_io_error)? {
+        ColorType::Gray(_) => (),
+        _ => return Err(invalid("GeoTIFF DEM must have a single band")),
+    }
+    if dim.0 < 2 || dim.1 < 2 {
+        return Err(invalid("heightmap image smaller than 2×2"));
+    }
+    
+    let scale = decoder.find_tag(Tag::ModelPixelScaleTag).map_err(to_io_error)?
+        .map(|v| v.into_f64_vec()).transpose().map_err(to_io_error)?
+        .filter(|s| s.len() >= 2)
+        .map(|s| (s[0], s[1]));
+    let tiepoint = decoder.find_tag(Tag::ModelTiepointTag).map_err(to_io_error)?
+        .map(|v| v.into_f64_vec()).transpose().map_err(to_io_error)?
+        .filter(|t| t.len() >= 6);
+    let keys = decoder.find_tag(Tag::GeoKeyDirectoryTag).map_err(to_io_error)?
+        .map(|v| v.into_u16_vec()).transpose().map_err(to_io_error)?
+        .unwrap_or_default();
+    let nodata = decoder.find_tag(Tag::GdalNodata).map_err(to_io_error)?
+        .map(|v| v.into_string()).transpose().map_err(to_io_error)?
+        .and_then(|s| s.trim_matches(|c: char| c == '\0' || c.is_whitespace()).parse::<f64>().ok());
+    
+    // GeoKeyDirectory: header of 4 values then entries (id, location, count, value)
+    let geo_key = |id: u16| keys.get(4..).and_then(|e| {
+        e.chunks_exact(4).find(|k| k[0] == id && k[1] == 0).map(|k| k[3])
+    });
+    let geographic = geo_key(1024) == Some(2);     // GTModelTypeGeoKey: ModelTypeGeographic
+    let pixel_is_point = geo_key(1025) == Some(2);  // GTRasterTypeGeoKey: RasterPixelIsPoint
+    
+    let origin = match (tiepoint, scale) {
+        (Some(t), Some(s)) => {
+            // pixel (i, j) maps to model (x, y); areas refer to the pixel corner
+            let offset = if pixel_is_point { 0.0 } else { 0.5 };
+            Some((t[3] + (offset - t[0]) * s.0, t[4] - (offset - t[1]) * s.1))
+        }
+        _ => None,
+    };
+    
+    let values: Vec<f64> = match decoder.read_image().map_err(to_io_error)? {
+        DecodingResult::U8(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::U16(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::U32(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::U64(v) => v.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::I8(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::I16(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::I32(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::I64(v) => v.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::F32(v) => v.into_iter().map(f64::from).collect(),
+        DecodingResult::F64(v) => v,
+    };
+    let expected = dim.0 as usize * dim.1 as usize;
+    if values.len() != expected {
+        return Err(invalid(&format!("GeoTIFF: expected {} samples, got {}", expected, values.len())));
+    }
+    
+    let (grid, valid) = replace_nodata(dim, &values, |v| !v.is_nan() && Some(v) != nodata);
+    let grid = grid.map(|v| *v as f32);
+    
+    let spacing = scale.unwrap_or((1.0, 1.0));
+    let size = ((dim.0 - 1) as f32 * spacing.0 as f32, (dim.1 - 1) as f32 * spacing.1 as f32);
+    let info = GeoTiffInfo { pixel_scale: scale, origin, geographic, nodata, valid };
+    Ok((Heightmap::from_grid(grid, size), info))
+}
+
+fn to_io_error(e: TiffError) -> io::Error {
+    match e {
+        TiffError::IoError(e) => e,
+        e => invalid(&e.to_string()),
+    }
+}
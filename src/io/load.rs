@@ -0,0 +1,343 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use nalgebra::{convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use crate::units::{Unit, Units};
+use super::invalid;
+
+/// Heightmap file formats recognised by [`load`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Greyscale PNG (8 or 16 bit); requires the `png` feature
+    Png,
+    /// 16-bit little-endian RAW
+    Raw,
+    /// Esri ASCII grid
+    Asc,
+    /// SRTM HGT tile
+    Hgt,
+    /// Terragen terrain
+    Ter,
+    /// OpenEXR; requires the `exr` feature
+    Exr,
+    /// GeoTIFF DEM; requires the `geotiff` feature
+    GeoTiff,
+}
+
+/// Information about a heightmap loaded by [`load`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// The detected file format
+    pub format: Format,
+    /// Coordinates of the lower-left (south-west) sample, if stored, in the
+    /// file's own reference system (degrees for HGT)
+    pub origin: Option<(f64, f64)>,
+    /// The no-data value, if any samples had this value
+    /// 
+    /// Such samples are replaced by the lowest valid height.
+    pub nodata: Option<f64>,
+    /// True if no vertical scale is known: heights are normalised to `[0, 1]`
+    pub normalised: bool,
+    /// True if no horizontal scale is known: a vertex spacing of 1 is used
+    pub unit_spacing: bool,
+}
+
+/// Load a heightmap, detecting the file format
+/// 
+/// The format is detected from the file header where possible (PNG, EXR,
+/// GeoTIFF, Terragen, ASC), otherwise from the extension (`.hgt`, `.raw` or `.r16`).
+/// Heights and sizes are converted to metres where the format specifies units:
+/// 
+/// -   ASC and EXR: heights are stored unscaled; ASC `cellsize` gives spacing
+///     (see [`read_asc`](super::read_asc))
+/// -   GeoTIFF: heights are stored unscaled; spacing is given in model units
+///     (see [`read_geotiff`](super::read_geotiff))
+/// -   HGT: spacing is derived from the latitude in the file name (e.g.
+///     `N45E006.hgt`), or the equator if this is not recognised
+/// -   Terragen: the `SCAL` and `ALTW` chunks are applied
+/// -   PNG and RAW: a sidecar `{stem}.json` as written by
+///     [`write_unity_raw`](super::write_unity_raw) or
+///     [`write_unreal_tiles`](super::write_unreal_tiles) is applied if present
+///     (for a tile `{name}_x{i}_y{j}`, the sidecar is `{name}.json`);
+///     otherwise heights are normalised. Without a sidecar RAW files must be
+///     square.
+/// 
+/// Vertex `(0, 0)` is the first sample stored in the file (the north-west
+/// corner for ASC and HGT).
+pub fn load<F: RealField>(path: &Path) -> io::Result<(Heightmap<F>, Metadata)> {
+    trace_span!("load", path = %path.display());
+    let bytes = fs::read(path)?;
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+    let format = if bytes.starts_with(b"\x89PNG") {
+        Format::Png
+    } else if bytes.starts_with(&[0x76, 0x2f, 0x31, 0x01]) {
+        Format::Exr
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        Format::GeoTiff
+    } else if bytes.starts_with(b"TERRAGENTERRAIN ") {
+        Format::Ter
+    } else if bytes.len() >= 5 && bytes[..5].eq_ignore_ascii_case(b"ncols") {
+        Format::Asc
+    } else {
+        match ext.as_deref() {
+            Some("hgt") => Format::Hgt,
+            Some("raw") | Some("r16") => Format::Raw,
+            Some("asc") => Format::Asc,
+            _ => return Err(invalid("unrecognised heightmap format")),
+        }
+    };
+    
+    let mut meta = Metadata { format, origin: None, nodata: None, normalised: false, unit_spacing: false };
+    let (grid, size) = match format {
+        Format::Png => load_png(path, &mut meta)?,
+        Format::Raw => load_raw(path, &bytes, &mut meta)?,
+        Format::Asc => load_asc(&bytes, &mut meta)?,
+        Format::Hgt => load_hgt(path, &bytes, &mut meta)?,
+        Format::Ter => load_ter(&bytes)?,
+        Format::Exr => load_exr(path, &mut meta)?,
+        Format::GeoTiff => load_geotiff(path, &mut meta)?,
+    };
+    if grid.dim().0 < 2 || grid.dim().1 < 2 {
+        return Err(invalid("heightmap smaller than 2×2"));
+    }
+    let grid = grid.map(|h| convert::<_, F>(*h));
+    Ok((Heightmap::from_grid(grid, (convert(size.0), convert(size.1))), meta))
+}
+
+/// Load a heightmap as [`load`], converting from the given units where the
+/// format does not specify units
+/// 
+/// The result is in metres. This applies to ASC data (spacing and heights),
+/// EXR and GeoTIFF heights and to unit spacing where no horizontal scale is known (i.e.
+/// each pixel is taken to be one `units.horizontal` apart).
+pub fn load_with_units<F: RealField>(path: &Path, units: Units) -> io::Result<(Heightmap<F>, Metadata)> {
+    let (mut m, meta) = load::<F>(path)?;
+    let horizontal = meta.format == Format::Asc || meta.unit_spacing;
+    let vertical = matches!(meta.format, Format::Asc | Format::Exr | Format::GeoTiff);
+    let from = Units {
+        horizontal: if horizontal { units.horizontal } else { Unit::Metre },
+        vertical: if vertical { units.vertical } else { Unit::Metre },
+    };
+    m.convert_units(from, Units::METRES);
+    Ok((m, meta))
+}
+
+type Loaded = (Grid<f64>, (f64, f64));
+
+#[cfg(feature = "png")]
+fn load_png(path: &Path, meta: &mut Metadata) -> io::Result<Loaded> {
+    let grid = super::read_png(path)?;
+    Ok(apply_sidecar(path, grid, meta))
+}
+
+#[cfg(not(feature = "png"))]
+fn load_png(_: &Path, _: &mut Metadata) -> io::Result<Loaded> {
+    Err(io::Error::other("loading PNG requires the png feature"))
+}
+
+#[cfg(feature = "exr")]
+fn load_exr(path: &Path, meta: &mut Metadata) -> io::Result<Loaded> {
+    let m: Heightmap<f64> = super::read_exr(path, (1.0, 1.0))?;
+    meta.unit_spacing = true;
+    let dim = m.dim();
+    let grid = Grid::from_fn(dim, |cx, cy| m.get(cx, cy));
+    Ok((grid, unit_size(dim)))
+}
+
+#[cfg(not(feature = "exr"))]
+fn load_exr(_: &Path, _: &mut Metadata) -> io::Result<Loaded> {
+    Err(io::Error::other("loading EXR requires the exr feature"))
+}
+
+#[cfg(feature = "geotiff")]
+fn load_geotiff(path: &Path, meta: &mut Metadata) -> io::Result<Loaded> {
+    let (m, info) = super::read_geotiff(path)?;
+    let dim = m.dim();
+    let size = (f64::from(m.size().0), f64::from(m.size().1));
+    meta.unit_spacing = info.pixel_scale.is_none();
+    meta.nodata = info.nodata.filter(|_| info.valid.is_some());
+    // origin is the top-left sample; rows run southwards
+    meta.origin = info.origin.map(|o| (o.0, o.1 - size.1));
+    Ok((Grid::from_fn(dim, |cx, cy| f64::from(m.get(cx, cy))), size))
+}
+
+#[cfg(not(feature = "geotiff"))]
+fn load_geotiff(_: &Path, _: &mut Metadata) -> io::Result<Loaded> {
+    Err(io::Error::other("loading GeoTIFF requires the geotiff feature"))
+}
+
+fn load_raw(path: &Path, bytes: &[u8], meta: &mut Metadata) -> io::Result<Loaded> {
+    let n = bytes.len() / 2;
+    let res = match read_sidecar(path).and_then(|s| json_numbers(&s, "resolution")) {
+        Some(r) => r[0] as usize,
+        None => (n as f64).sqrt().round() as usize,
+    };
+    if res * res != n || !bytes.len().is_multiple_of(2) {
+        return Err(invalid("RAW size does not match a square 16-bit heightmap"));
+    }
+    let dim = (res as u32, res as u32);
+    let grid = Grid::from_fn(dim, |cx, cy| {
+        let i = 2 * (cx as usize + cy as usize * res);
+        f64::from(u16::from_le_bytes([bytes[i], bytes[i + 1]])) / 65535.0
+    });
+    Ok(apply_sidecar(path, grid, meta))
+}
+
+// Apply scaling from a sidecar JSON file to normalised heights
+fn apply_sidecar(path: &Path, mut grid: Grid<f64>, meta: &mut Metadata) -> Loaded {
+    let dim = grid.dim();
+    let json = read_sidecar(path).unwrap_or_default();
+    let unity = (json_numbers(&json, "terrain_size"), json_numbers(&json, "position_y"));
+    let unreal = (json_numbers(&json, "scale"), json_numbers(&json, "location_z"));
+    if let (Some(ts), Some(y)) = unity {
+        if ts.len() == 3 {
+            grid.data_mut().iter_mut().for_each(|h| *h = y[0] + *h * ts[1]);
+            return (grid, (ts[0], ts[2]));
+        }
+    }
+    if let (Some(s), Some(z)) = unreal {
+        if s.len() == 3 {
+            // cm = (v - 32768) * z_scale / 128 + location_z
+            let f = |h: f64| ((h * 65535.0 - 32768.0) * s[2] / 128.0 + z[0]) / 100.0;
+            grid.data_mut().iter_mut().for_each(|h| *h = f(*h));
+            let size = ((dim.0 - 1) as f64 * s[0] / 100.0, (dim.1 - 1) as f64 * s[1] / 100.0);
+            return (grid, size);
+        }
+    }
+    meta.normalised = true;
+    meta.unit_spacing = true;
+    (grid, unit_size(dim))
+}
+
+fn load_asc(bytes: &[u8], meta: &mut Metadata) -> io::Result<Loaded> {
+    let (grid, info) = super::asc::parse_asc(bytes)?;
+    let dim = grid.dim();
+    meta.origin = Some(info.origin);
+    meta.nodata = info.nodata.filter(|_| info.valid.is_some());
+    Ok((grid, ((dim.0 - 1) as f64 * info.cellsize.0, (dim.1 - 1) as f64 * info.cellsize.1)))
+}
+
+fn load_hgt(path: &Path, bytes: &[u8], meta: &mut Metadata) -> io::Result<Loaded> {
+    const VOID: i16 = -32768;
+    const METRES_PER_DEGREE: f64 = 111_195.0;
+    let n = bytes.len() / 2;
+    let res = (n as f64).sqrt().round() as usize;
+    if res * res != n || !bytes.len().is_multiple_of(2) || res < 2 {
+        return Err(invalid("HGT size does not match a square 16-bit tile"));
+    }
+    let mut grid = Grid::from_fn((res as u32, res as u32), |cx, cy| {
+        let i = 2 * (cx as usize + cy as usize * res);
+        f64::from(i16::from_be_bytes([bytes[i], bytes[i + 1]]))
+    });
+    fill_nodata(&mut grid, |h| h == f64::from(VOID), f64::from(VOID), meta);
+    
+    // Tile names give the south-west corner, e.g. N45E006 or S10W073
+    let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_ascii_uppercase();
+    let parse = |s: &str, neg: char| s[1..].parse::<f64>().ok().map(|v| if s.starts_with(neg) { -v } else { v });
+    let lat = name.get(0..3).filter(|s| s.starts_with(['N', 'S'])).and_then(|s| parse(s, 'S'));
+    let lon = name.get(3..7).filter(|s| s.starts_with(['E', 'W'])).and_then(|s| parse(s, 'W'));
+    if let (Some(lat), Some(lon)) = (lat, lon) {
+        meta.origin = Some((lon, lat));
+    }
+    let centre = lat.map(|lat| lat + 0.5).unwrap_or(0.0);
+    Ok((grid, (METRES_PER_DEGREE * centre.to_radians().cos(), METRES_PER_DEGREE)))
+}
+
+fn load_ter(bytes: &[u8]) -> io::Result<Loaded> {
+    let short = || invalid("Terragen: unexpected end of file");
+    let u16_at = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(short);
+    let i16_at = |i: usize| u16_at(i).map(|v| v as i16);
+    let f32_at = |i: usize| bytes.get(i..i + 4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(short);
+    
+    let (mut size, mut xpts, mut ypts) = (None, None, None);
+    let mut scale = (30.0, 30.0, 30.0);
+    let mut i = 16;
+    loop {
+        let tag = bytes.get(i..i + 4).ok_or_else(short)?;
+        i += 4;
+        match tag {
+            b"SIZE" => { size = Some(u16_at(i)? as u32 + 1); i += 4; }
+            b"XPTS" => { xpts = Some(u16_at(i)? as u32); i += 4; }
+            b"YPTS" => { ypts = Some(u16_at(i)? as u32); i += 4; }
+            b"SCAL" => {
+                scale = (f64::from(f32_at(i)?), f64::from(f32_at(i + 4)?), f64::from(f32_at(i + 8)?));
+                i += 12;
+            }
+            b"CRAD" => i += 4,
+            b"CRVM" => i += 4,
+            b"ALTW" => break,
+            _ => return Err(invalid("Terragen: unknown chunk")),
+        }
+    }
+    let size = size.ok_or_else(|| invalid("Terragen: missing SIZE"))?;
+    let dim = (xpts.unwrap_or(size), ypts.unwrap_or(size));
+    let height_scale = f64::from(i16_at(i)?);
+    let base = f64::from(i16_at(i + 2)?);
+    i += 4;
+    if bytes.len() < i + 2 * dim.0 as usize * dim.1 as usize {
+        return Err(short());
+    }
+    // Heights are stored with y increasing, i.e. the first row is the southern edge
+    let grid = Grid::from_fn(dim, |cx, cy| {
+        let j = i + 2 * (cx as usize + cy as usize * dim.0 as usize);
+        let e = f64::from(i16::from_le_bytes([bytes[j], bytes[j + 1]]));
+        (base + e * height_scale / 65536.0) * scale.2
+    });
+    Ok((grid, ((dim.0 - 1) as f64 * scale.0, (dim.1 - 1) as f64 * scale.1)))
+}
+
+// Replace no-data samples by the lowest valid height
+fn fill_nodata<P: Fn(f64) -> bool>(grid: &mut Grid<f64>, is_nodata: P, nodata: f64, meta: &mut Metadata) {
+    let lowest = grid.data().iter().cloned().filter(|h| !is_nodata(*h)).fold(None, |m: Option<f64>, h| {
+        Some(m.map_or(h, |m| m.min(h)))
+    });
+    let mut any = false;
+    for h in grid.data_mut() {
+        if is_nodata(*h) {
+            *h = lowest.unwrap_or(0.0);
+            any = true;
+        }
+    }
+    if any {
+        meta.nodata = Some(nodata);
+    }
+}
+
+fn unit_size(dim: (u32, u32)) -> (f64, f64) {
+    ((dim.0 - 1) as f64, (dim.1 - 1) as f64)
+}
+
+// Read `{stem}.json`, or for an Unreal tile `{name}_x{i}_y{j}` the shared `{name}.json`
+fn read_sidecar(path: &Path) -> Option<String> {
+    fs::read_to_string(path.with_extension("json")).ok().or_else(|| {
+        let stem = path.file_stem()?.to_str()?;
+        let (rest, j) = stem.rsplit_once("_y")?;
+        let (name, i) = rest.rsplit_once("_x")?;
+        let is_index = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_index(i) || !is_index(j) {
+            return None;
+        }
+        fs::read_to_string(path.with_file_name(format!("{}.json", name))).ok()
+    })
+}
+
+// Extract a number or array of numbers from a flat JSON object
+fn json_numbers(json: &str, key: &str) -> Option<Vec<f64>> {
+    let start = json.find(&format!("\"{}\"", key))? + key.len() + 2;
+    let rest = json[start..].trim_start().strip_prefix(':')?.trim_start();
+    let value = match rest.strip_prefix('[') {
+        Some(rest) => &rest[..rest.find(']')?],
+        None => &rest[..rest.find([',', '}', '\n']).unwrap_or(rest.len())],
+    };
+    value.split(',').map(|v| v.trim().parse().ok()).collect()
+}
@@ -0,0 +1,127 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use nalgebra::{convert, RealField, Vector3};
+use crate::grid::Grid;
+use super::{normalise, ImageStack};
+
+impl<'a, F: RealField> ImageStack<'a, F> {
+    /// Write each channel as a 16-bit greyscale PNG, plus a manifest
+    /// 
+    /// Files are written to `dir` as `{prefix}_{name}.png`; the manifest is
+    /// `{prefix}.json`.
+    pub fn write_pngs(&self, dir: &Path, prefix: &str) -> io::Result<()> {
+        let mut files = Vec::new();
+        for (name, grid, range) in self.channels() {
+            let file = format!("{}_{}.png", prefix, name);
+            write_png16(&dir.join(&file), grid, range)?;
+            files.push(file);
+        }
+        fs::write(dir.join(format!("{}.json", prefix)), self.manifest(&files))
+    }
+}
+
+/// Write a grid as a 16-bit greyscale PNG, normalised over `range`
+pub fn write_png16<F: RealField>(path: &Path, grid: &Grid<F>, range: (F, F)) -> io::Result<()> {
+    trace_span!("write_png16", path = %path.display());
+    let w = BufWriter::new(File::create(path)?);
+    write_png16_to(w, grid.dim(), range, |cx, cy| grid.get(cx, cy))
+}
+
+/// Stream a 16-bit greyscale PNG of `dim` pixels to `w`
+/// 
+/// Values are read row-by-row from `f(cx, cy)` and normalised over `range`;
+/// only a single row is held in memory. `w` is not buffered.
+pub fn write_png16_to<W: Write, F: RealField, G: FnMut(u32, u32) -> F>(w: W, dim: (u32, u32), range: (F, F),
+    mut f: G) -> io::Result<()>
+{
+    let mut encoder = png::Encoder::new(w, dim.0, dim.1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer();
+    let mut row = Vec::with_capacity(dim.0 as usize * 2);
+    for cy in 0..dim.1 {
+        row.clear();
+        for cx in 0..dim.0 {
+            let x = (normalise(f(cx, cy), range) * 65535.0).round() as u16;
+            row.extend_from_slice(&x.to_be_bytes());
+        }
+        stream.write_all(&row)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Write unit normals as an 8-bit RGB PNG
+/// 
+/// Components are mapped from `[-1, 1]` to `[0, 255]` (the usual normal
+/// map encoding), thus a flat surface `(0, 0, 1)` is `(128, 128, 255)`.
+/// See [`Heightmap::bake_normal_map`](crate::heightmap::Heightmap::bake_normal_map).
+pub fn write_normal_png<F: RealField>(path: &Path, normals: &Grid<Vector3<F>>) -> io::Result<()> {
+    trace_span!("write_normal_png", path = %path.display());
+    let w = BufWriter::new(File::create(path)?);
+    write_normal_png_to(w, normals)
+}
+
+/// Stream normals as an 8-bit RGB PNG to `w`
+/// 
+/// See [`write_normal_png`]. `w` is not buffered.
+pub fn write_normal_png_to<W: Write, F: RealField>(w: W, normals: &Grid<Vector3<F>>) -> io::Result<()> {
+    let dim = normals.dim();
+    let mut encoder = png::Encoder::new(w, dim.0, dim.1);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer();
+    let mut row = Vec::with_capacity(dim.0 as usize * 3);
+    let one = F::one();
+    for cy in 0..dim.1 {
+        row.clear();
+        for cx in 0..dim.0 {
+            let n = normals.get(cx, cy);
+            for c in n.iter() {
+                row.push((normalise(*c, (-one, one)) * 255.0).round() as u8);
+            }
+        }
+        stream.write_all(&row)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Read a greyscale PNG (of any bit depth) as values normalised to `[0, 1]`
+/// 
+/// Any alpha channel is ignored.
+pub fn read_png<F: RealField>(path: &Path) -> io::Result<Grid<F>> {
+    trace_span!("read_png", path = %path.display());
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        _ => return Err(super::invalid("PNG heightmap must be greyscale")),
+    };
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+    let wide = info.bit_depth == png::BitDepth::Sixteen;
+    let bytes = if wide { 2 } else { 1 };
+    Ok(Grid::from_fn((info.width, info.height), |cx, cy| {
+        let i = cy as usize * info.line_size + cx as usize * channels * bytes;
+        let v = if wide {
+            f64::from(u16::from_be_bytes([data[i], data[i + 1]])) / 65535.0
+        } else {
+            f64::from(data[i]) / 255.0
+        };
+        convert(v)
+    }))
+}
@@ -0,0 +1,117 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{self, Read, Write};
+use nalgebra::{convert, RealField};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use super::{invalid, normalise, to_f64};
+
+/// Sample format of raw heightmap data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// 16-bit unsigned integers (`.r16`), mapped linearly to a height range
+    R16,
+    /// 32-bit floats (`.r32`), stored unscaled
+    R32,
+}
+
+impl RawFormat {
+    /// Bytes per sample
+    pub fn sample_bytes(self) -> usize {
+        match self {
+            RawFormat::R16 => 2,
+            RawFormat::R32 => 4,
+        }
+    }
+}
+
+/// Byte order of raw heightmap data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Little-endian ("Windows" / Intel byte order), as used by most tools
+    Little,
+    /// Big-endian ("Mac" / Motorola byte order)
+    Big,
+}
+
+impl<F: RealField> Heightmap<F> {
+    /// Read a heightmap from raw sample data
+    /// 
+    /// Exactly `dim.0 × dim.1` samples are read in row-major order; an error
+    /// is returned if `reader` has less or more data. R16 samples are mapped
+    /// linearly such that `0` is `range.0` and `65535` is `range.1`; `range`
+    /// is not used for R32 data.
+    pub fn from_raw<R: Read>(mut reader: R, dim: (u32, u32), size: (F, F), format: RawFormat,
+        endianness: Endianness, range: (F, F)) -> io::Result<Self>
+    {
+        assert!(dim.0 >= 2 && dim.1 >= 2);
+        let bytes = format.sample_bytes();
+        let mut row = vec![0; dim.0 as usize * bytes];
+        let mut data = Vec::with_capacity(dim.0 as usize * dim.1 as usize);
+        for _ in 0..dim.1 {
+            reader.read_exact(&mut row).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid("raw data shorter than expected for dim"),
+                _ => e,
+            })?;
+            for b in row.chunks_exact(bytes) {
+                let v = match (format, endianness) {
+                    (RawFormat::R16, Endianness::Little) => f64::from(u16::from_le_bytes([b[0], b[1]])) / 65535.0,
+                    (RawFormat::R16, Endianness::Big) => f64::from(u16::from_be_bytes([b[0], b[1]])) / 65535.0,
+                    (RawFormat::R32, Endianness::Little) => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    (RawFormat::R32, Endianness::Big) => f64::from(f32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+                };
+                data.push(match format {
+                    RawFormat::R16 => range.0 + (range.1 - range.0) * convert(v),
+                    RawFormat::R32 => convert(v),
+                });
+            }
+        }
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid("raw data longer than expected for dim"));
+        }
+        let mut data = data.into_iter();
+        Ok(Heightmap::from_grid(Grid::from_fn(dim, |_, _| data.next().unwrap()), size))
+    }
+    
+    /// Write the heightmap as raw sample data
+    /// 
+    /// Samples are written in row-major order, one row at a time. For R16,
+    /// heights are normalised over `range` (usually [`Heightmap::range`]) and
+    /// clamped; `range` is not used for R32 data.
+    pub fn write_raw<W: Write>(&self, mut writer: W, format: RawFormat, endianness: Endianness, range: (F, F))
+        -> io::Result<()>
+    {
+        let (w, h) = self.dim();
+        let mut row = Vec::with_capacity(w as usize * format.sample_bytes());
+        for cy in 0..h {
+            row.clear();
+            for cx in 0..w {
+                let v = self.get(cx, cy);
+                match format {
+                    RawFormat::R16 => {
+                        let x = (normalise(v, range) * 65535.0).round() as u16;
+                        row.extend_from_slice(&match endianness {
+                            Endianness::Little => x.to_le_bytes(),
+                            Endianness::Big => x.to_be_bytes(),
+                        });
+                    }
+                    RawFormat::R32 => {
+                        let x = to_f64(v) as f32;
+                        row.extend_from_slice(&match endianness {
+                            Endianness::Little => x.to_le_bytes(),
+                            Endianness::Big => x.to_be_bytes(),
+                        });
+                    }
+                }
+            }
+            writer.write_all(&row)?;
+        }
+        writer.flush()
+    }
+}
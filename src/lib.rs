@@ -20,13 +20,44 @@
 //! voxels for `O(n³)` memory usage, and hybrid representations (e.g. a multi-
 //! layered heightfield with local exceptions).
 //! 
-//! Currently this library is limited to single-layer heightfields.
+//! Currently this library is mostly limited to single-layer heightfields;
+//! the [`volume`] module provides a first step towards functional volumetric
+//! representations.
 
 /// Types usable as an approximation of the real numbers, ℝ.
 /// 
 /// Currently this is fixed as `nalgebra::RealField`.
 pub use nalgebra::RealField;
 
+// Enter a span (arguments as for `tracing::info_span!`) until the end of the
+// enclosing scope, emitting a timing event on exit. Without the `tracing`
+// feature this does nothing.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _timed = crate::trace::Timed::new(tracing::info_span!($($arg)*));
+    };
+}
+
+pub mod features;
+pub mod grid;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod graph;
 pub mod unbounded;
 pub mod heightmap;
+pub mod io;
+pub mod memory;
 pub mod mesh;
+pub mod metrics;
+pub mod pipeline;
+pub mod raster;
+pub mod sweep;
+pub mod task;
+pub mod terrain;
+pub mod units;
+pub mod volume;
+
+mod fft;
+#[cfg(feature = "tracing")]
+mod trace;
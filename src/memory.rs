@@ -0,0 +1,80 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Memory accounting
+//! 
+//! The [`MemoryUsage`] trait reports the estimated memory held by
+//! heightmaps, grids, meshes, caches and chunk stores, allowing applications
+//! to monitor terrain memory against their budget. The chunk store
+//! ([`TiledHeightmap`]) may additionally enforce a [`MemoryBudget`].
+//! 
+//! Estimates count the value itself plus the heap buffers it owns (by
+//! capacity); allocator overhead and the internals of hash tables are
+//! approximated or ignored.
+//! 
+//! [`TiledHeightmap`]: crate::heightmap::TiledHeightmap
+
+use std::mem::size_of;
+
+/// Estimated memory usage
+pub trait MemoryUsage {
+    /// Get the estimated memory usage in bytes
+    fn memory_usage(&self) -> usize;
+}
+
+/// What to do when a [`MemoryBudget`] is exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Unload the least recently used chunks
+    Evict,
+    /// Halve the resolution of the least recently used chunks, down to
+    /// `min_dim` vertices along each axis; chunks are unloaded only once all
+    /// are at minimum resolution
+    Downsample {
+        /// Minimum chunk dimension (at least 2)
+        min_dim: u32,
+    },
+}
+
+/// A memory budget for a chunk store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    /// Maximum estimated memory usage in bytes
+    pub bytes: usize,
+    /// Action taken when over budget
+    pub policy: BudgetPolicy,
+}
+
+impl MemoryBudget {
+    /// Budget of `bytes`, evicting chunks when exceeded
+    pub fn evict(bytes: usize) -> Self {
+        MemoryBudget { bytes, policy: BudgetPolicy::Evict }
+    }
+    
+    /// Budget of `bytes`, downsampling chunks to at least `min_dim` when
+    /// exceeded
+    pub fn downsample(bytes: usize, min_dim: u32) -> Self {
+        assert!(min_dim >= 2);
+        MemoryBudget { bytes, policy: BudgetPolicy::Downsample { min_dim } }
+    }
+}
+
+impl<T> MemoryUsage for Vec<T> {
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.capacity() * size_of::<T>()
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for Option<T> {
+    fn memory_usage(&self) -> usize {
+        match self {
+            Some(x) => x.memory_usage() + size_of::<Self>() - size_of::<T>(),
+            None => size_of::<Self>(),
+        }
+    }
+}
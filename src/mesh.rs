@@ -9,12 +9,130 @@
 //! Mesh manipulation
 
 use nalgebra as na;
-use na::{convert, RealField, geometry::{Point2, Point3}};
-use ncollide3d::procedural::IndexBuffer;
+use na::{convert, RealField, Rotation3, Translation3, Unit, Vector2, Vector3, Vector4, geometry::{Point2, Point3}};
+use crate::memory::MemoryUsage;
 use crate::unbounded::UnboundedSurface;
 
-/// Type of tri-mesh used for drawing a terrain
-pub use ncollide3d::procedural::TriMesh;
+pub mod lod;
+mod adaptive;
+mod chunked;
+mod delaunay;
+mod simplify;
+
+pub use chunked::{Aabb, ChunkedMesher, MeshChunk};
+pub use delaunay::from_points;
+pub(crate) use delaunay::{orient, Triangulation};
+pub use simplify::simplify;
+
+/// Vertical axis convention of generated meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpAxis {
+    /// Vertices are `(x, y, height)`: the native convention of heightmaps and
+    /// surfaces
+    #[default]
+    Z,
+    /// Vertices are `(x, height, -y)`, as used by glTF, many engines and
+    /// kiss3d. This is a rotation of the Z-up mesh, thus triangle winding
+    /// (counter-clockwise seen from above) is preserved.
+    Y,
+}
+
+/// A triangle mesh of a terrain
+/// 
+/// This is the output of mesh generation (e.g.
+/// [`Heightmap::to_trimesh`](crate::heightmap::Heightmap::to_trimesh) and
+/// [`SampleMesh`]), using plain vertex and index buffers which may be passed
+/// to any engine or exporter. With the `ncollide3d` feature, this converts
+/// into `ncollide3d::procedural::TriMesh` (e.g. for display with kiss3d).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainMesh<F: RealField> {
+    /// Vertex positions
+    pub positions: Vec<Point3<F>>,
+    /// Per-vertex normals, if any
+    pub normals: Option<Vec<Vector3<F>>>,
+    /// Per-vertex texture coordinates, if any
+    pub uvs: Option<Vec<Point2<F>>>,
+    /// Triangles, as indices into `positions`
+    pub indices: Vec<Point3<u32>>,
+}
+
+impl<F: RealField> TerrainMesh<F> {
+    /// Construct
+    /// 
+    /// If `normals` or `uvs` are given they must have the same length as
+    /// `positions`.
+    pub fn new(positions: Vec<Point3<F>>, normals: Option<Vec<Vector3<F>>>, uvs: Option<Vec<Point2<F>>>,
+        indices: Vec<Point3<u32>>) -> Self
+    {
+        TerrainMesh { positions, normals, uvs, indices }
+    }
+    
+    /// Number of triangles
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len()
+    }
+    
+    /// Set vertex normals to the mean of the normals of adjacent triangles
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![Vector3::zeros(); self.positions.len()];
+        let mut count = vec![0u32; self.positions.len()];
+        for t in &self.indices {
+            let idx = [t.x as usize, t.y as usize, t.z as usize];
+            let (p0, p1, p2) = (self.positions[idx[0]], self.positions[idx[1]], self.positions[idx[2]]);
+            let cross = (p1 - p0).cross(&(p2 - p0));
+            let normal = if cross == Vector3::zeros() { cross } else { cross.normalize() };
+            for i in &idx {
+                normals[*i] += normal;
+                count[*i] += 1;
+            }
+        }
+        for (n, c) in normals.iter_mut().zip(count) {
+            if c > 0 {
+                *n /= convert::<f64, F>(c as f64);
+            }
+        }
+        self.normals = Some(normals);
+    }
+    
+    /// Rotate a Z-up mesh to the `up` axis convention
+    /// 
+    /// Positions and normals are transformed; this does nothing for
+    /// [`UpAxis::Z`].
+    pub fn orient(&mut self, up: UpAxis) {
+        if up == UpAxis::Y {
+            for p in self.positions.iter_mut() {
+                *p = Point3::new(p.x, p.z, -p.y);
+            }
+            if let Some(ref mut normals) = self.normals {
+                for n in normals.iter_mut() {
+                    *n = Vector3::new(n.x, n.z, -n.y);
+                }
+            }
+        }
+    }
+    
+    /// Translate all vertices
+    pub fn translate_by(&mut self, t: &Translation3<F>) {
+        for p in self.positions.iter_mut() {
+            *p = t.transform_point(p);
+        }
+    }
+}
+
+impl<F: RealField> MemoryUsage for TerrainMesh<F> {
+    fn memory_usage(&self) -> usize {
+        self.positions.memory_usage() + self.normals.memory_usage() + self.uvs.memory_usage()
+            + self.indices.memory_usage()
+    }
+}
+
+#[cfg(feature = "ncollide3d")]
+impl<F: RealField> From<TerrainMesh<F>> for ncollide3d::procedural::TriMesh<F> {
+    fn from(mesh: TerrainMesh<F>) -> Self {
+        let indices = ncollide3d::procedural::IndexBuffer::Unified(mesh.indices);
+        ncollide3d::procedural::TriMesh::new(mesh.positions, mesh.normals, mesh.uvs, Some(indices))
+    }
+}
 
 
 /// Sample a mesh on a surface
@@ -23,71 +141,339 @@ pub use ncollide3d::procedural::TriMesh;
 /// but is not always the fastest or most accurate method of constructing a
 /// mesh (check for more specific implementations).
 /// 
+/// Vertex normals are taken from the surface gradient (see
+/// [`UnboundedSurface::get_with_gradient`]) rather than averaged over faces,
+/// thus are smooth even at low subdivision counts.
+/// 
 /// Does not perform any mesh optimisation.
 pub trait SampleMesh<F: RealField> {
-    /// Sample a [`TriMesh`] on the given `surface` over the rectangle from
+    /// Sample a [`TerrainMesh`] on the given `surface` over the rectangle from
     /// `start` to `start + size` with the given number of `subdivs`-isions
-    /// (i.e. with `(subdivs.0 + 1) * (subdivs.1 + 1)` sample points).
-    fn sample_mesh(&self, start: (F, F), size: (F, F), subdivs: (u32, u32)) -> TriMesh<F>;
+    /// (i.e. with `(subdivs.0 + 1) * (subdivs.1 + 1)` sample points), using
+    /// the `up` axis convention.
+    fn sample_mesh(&self, start: (F, F), size: (F, F), subdivs: (u32, u32), up: UpAxis) -> TerrainMesh<F>;
+    
+    /// As [`SampleMesh::sample_mesh`], additionally displacing each vertex by
+    /// `detail`. The mesh is Z-up (see [`TerrainMesh::orient`]).
+    fn sample_mesh_detailed<S: UnboundedSurface<F>>(&self, start: (F, F), size: (F, F), subdivs: (u32, u32),
+        detail: &MicroDetail<F, S>) -> TerrainMesh<F>;
+    
+    /// Sample a [`TerrainMesh`] over the rectangle from `start` to
+    /// `start + size`, subdividing only where the surface is not planar
+    /// 
+    /// Starting from the whole rectangle, each quad is split into four while
+    /// the surface deviates by more than `max_error` from the bilinear patch
+    /// through the quad's corners (tested at its centre and edge midpoints),
+    /// down to at most `max_depth` levels (i.e. the resolution of
+    /// `sample_mesh` with `2^max_depth` subdivisions). Flat regions thus use
+    /// few, large triangles. Quads adjoining smaller neighbours are fanned
+    /// from their centre, such that the mesh has no cracks.
+    /// 
+    /// Features small enough to fall between the test points of a quad may be
+    /// missed. The mesh is Z-up (see [`TerrainMesh::orient`]).
+    fn sample_mesh_adaptive(&self, start: (F, F), size: (F, F), max_error: F, max_depth: u32) -> TerrainMesh<F>;
 }
 
+/// High-frequency detail displacement applied at mesh generation time
+/// 
+/// This adds surface detail beyond the resolution of the source data without
+/// storing it. The displacement at source coordinate `(x, y)` (i.e. the
+/// local coordinate of a heightmap, or the coordinate of a sampled surface) is
+/// `amplitude * surface.get(origin.0 + x, origin.1 + y)`. Since `surface` is
+/// a deterministic function of world position, adjacent meshes (e.g.
+/// heightmap tiles) with correct `origin` receive consistent detail.
+#[derive(Debug, Clone)]
+pub struct MicroDetail<F, S> {
+    /// Detail noise, typically a high-frequency [`Perlin`](crate::unbounded::Perlin)
+    pub surface: S,
+    /// Scale of the displacement
+    pub amplitude: F,
+    /// World position of the local origin
+    pub origin: (F, F),
+}
 
-impl<F: RealField, U: UnboundedSurface<F>> SampleMesh<F> for U {
-    fn sample_mesh(&self, start: (F, F), size: (F, F), subdivs: (u32, u32)) -> TriMesh<F> {
-        let one: F = na::one();
-        let np = (subdivs.0 + 1, subdivs.1 + 1);
-        
-        // code adapted from ncollide::procedural::unit_quad:
-        let tx_step = one / convert(subdivs.0 as f64);
-        let ty_step = one / convert(subdivs.1 as f64);
-        let x_step = tx_step * size.0;
-        let y_step = ty_step * size.1;
-
-        let mut vertices = Vec::new();
-        let mut triangles = Vec::new();
-        let mut tex_coords = Vec::new();
-
-        // create the vertices
-        for iy in 0..np.1 {
-            for ix in 0..np.0 {
-                let fy: F = convert(iy as f64);
-                let fx: F = convert(ix as f64);
-
-                let v = Point3::new(
-                        start.0 + fx * x_step,
-                        start.1 + fy * y_step,
-                        self.get(fy, fx));
-                vertices.push(v);
-                tex_coords.push(Point2::new(one - fx * tx_step, one - fy * ty_step))
-            }
+impl<F: RealField, S: UnboundedSurface<F>> MicroDetail<F, S> {
+    /// Get the displacement at source coordinate `(x, y)`
+    pub fn displacement(&self, x: F, y: F) -> F {
+        self.amplitude * self.surface.get(self.origin.0 + x, self.origin.1 + y)
+    }
+    
+    /// Get the displacement and its gradient at source coordinate `(x, y)`
+    pub fn displacement_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (h, g) = self.surface.get_with_gradient(self.origin.0 + x, self.origin.1 + y);
+        let a = self.amplitude;
+        (a * h, [a * g[0], a * g[1]])
+    }
+}
+
+/// A mesh with baked per-vertex attributes
+/// 
+/// Attribute vectors are indexed like `mesh.positions`. See
+/// [`Heightmap::bake_attributes`](crate::heightmap::Heightmap::bake_attributes).
+#[derive(Debug, Clone)]
+pub struct AttributedMesh<F: RealField> {
+    /// The mesh
+    pub mesh: TerrainMesh<F>,
+    /// Per-vertex RGBA colours, if any
+    pub colors: Option<Vec<[f32; 4]>>,
+    /// Named per-vertex scalar channels
+    pub channels: Vec<(String, Vec<F>)>,
+    /// Per-vertex tangents, if computed (see [`AttributedMesh::compute_tangents`])
+    pub tangents: Option<Vec<Vector4<F>>>,
+}
+
+impl<F: RealField> AttributedMesh<F> {
+    /// Get a channel by name
+    pub fn channel(&self, name: &str) -> Option<&[F]> {
+        self.channels.iter()
+            .find(|c| c.0 == name)
+            .map(|c| &c.1[..])
+    }
+    
+    /// Compute and store tangents (see [`tangents`])
+    pub fn compute_tangents(&mut self) {
+        self.tangents = Some(tangents(&self.mesh));
+    }
+}
+
+/// Compute per-vertex tangents of a mesh
+/// 
+/// Results use the MikkTSpace convention: `xyz` is the unit tangent (the
+/// direction of increasing texture `u`, orthogonal to the vertex normal) and
+/// `w = ±1` gives the handedness, such that the bitangent is
+/// `w * cross(normal, tangent)`. Triangle contributions are weighted by
+/// corner angle as in MikkTSpace, although vertices are not split where
+/// tangent frames diverge; for smooth terrain the results agree closely.
+/// 
+/// If the mesh has no texture coordinates, the planar mapping `(u, v) = (x, y)`
+/// is used, which gives consistent frames for triplanar shading. If the mesh
+/// has no normals, they are computed as by [`TerrainMesh::recompute_normals`].
+pub fn tangents<F: RealField>(mesh: &TerrainMesh<F>) -> Vec<Vector4<F>> {
+    trace_span!("tangents", vertices = mesh.positions.len());
+    let coords = &mesh.positions;
+    let n = coords.len();
+    let triangles = &mesh.indices;
+    let uv = |i: usize| match mesh.uvs {
+        Some(ref uvs) => uvs[i],
+        None => Point2::new(coords[i].x, coords[i].y),
+    };
+    let normals = match mesh.normals {
+        Some(ref normals) => normals.clone(),
+        None => {
+            let mut m = mesh.clone();
+            m.recompute_normals();
+            m.normals.unwrap()
+        }
+    };
+    
+    let zero = Vector3::zeros();
+    let mut tan = vec![zero; n];
+    let mut bitan = vec![zero; n];
+    for t in triangles {
+        let idx = [t.x as usize, t.y as usize, t.z as usize];
+        let (p0, p1, p2) = (coords[idx[0]], coords[idx[1]], coords[idx[2]]);
+        let (w0, w1, w2) = (uv(idx[0]), uv(idx[1]), uv(idx[2]));
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let (d1, d2) = (w1 - w0, w2 - w0);
+        let r = d1.x * d2.y - d2.x * d1.y;
+        if r == F::zero() {
+            continue;
+        }
+        let t = (e1 * d2.y - e2 * d1.y) / r;
+        let b = (e2 * d1.x - e1 * d2.x) / r;
+        for k in 0..3 {
+            let p = coords[idx[k]];
+            let a = coords[idx[(k + 1) % 3]] - p;
+            let c = coords[idx[(k + 2) % 3]] - p;
+            let angle = a.angle(&c);
+            tan[idx[k]] += t * angle;
+            bitan[idx[k]] += b * angle;
+        }
+    }
+    
+    (0..n).map(|i| {
+        let normal = normals[i];
+        let mut t = tan[i] - normal * normal.dot(&tan[i]);
+        if t.norm() <= F::default_epsilon() {
+            // degenerate: use any direction orthogonal to the normal
+            let axis = if normal.x.abs() < convert(0.9) { Vector3::x() } else { Vector3::y() };
+            t = axis - normal * normal.dot(&axis);
         }
+        let t = t.normalize();
+        let w = if normal.cross(&t).dot(&bitan[i]) < F::zero() { -F::one() } else { F::one() };
+        Vector4::new(t.x, t.y, t.z, w)
+    }).collect()
+}
+
+
+/// Scale mesh heights by `factor` for visualisation (vertical exaggeration)
+/// 
+/// Normals, if present, are transformed to remain perpendicular to the
+/// surface. Since the mesh no longer reflects real proportions, this should be
+/// applied to display meshes only, not to data used for physics.
+pub fn exaggerate<F: RealField>(mesh: &mut TerrainMesh<F>, factor: F) {
+    for p in mesh.positions.iter_mut() {
+        p.z *= factor;
+    }
+    if let Some(ref mut normals) = mesh.normals {
+        // normals transform by the inverse transpose: (x, y, z / factor)
+        for n in normals.iter_mut() {
+            n.z /= factor;
+            *n = n.normalize();
+        }
+    }
+}
 
-        // create triangles
-        let ws = np.0;
-        
-        let dl_triangle = |iy: u32, ix: u32| -> Point3<u32> {
-            Point3::new((iy + 1) * ws + ix, iy * ws + ix, (iy + 1) * ws + ix + 1)
-        };
-
-        let ur_triangle = |iy: u32, ix: u32| -> Point3<u32> {
-            Point3::new(iy * ws + ix, iy * ws + (ix + 1), (iy + 1) * ws + ix + 1)
-        };
-
-        for iy in 0..subdivs.1 {
-            for ix in 0..subdivs.0 {
-                // build two triangles...
-                triangles.push(dl_triangle(iy, ix));
-                triangles.push(ur_triangle(iy, ix));
+/// Planetary curvature, for bending far-view meshes over a planet surface
+/// 
+/// Mesh coordinates are taken as distances along the surface from a tangent
+/// point `centre` (typically the viewer's position), with `x` east and `y`
+/// north. [`Curvature::apply`] maps each vertex onto the curved surface, such
+/// that distant terrain drops below the horizon (by approximately
+/// `d² / 2R` at distance `d`).
+/// 
+/// The radius of curvature differs between the east-west and north-south
+/// directions on an ellipsoidal planet; see [`Curvature::earth`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Curvature<F> {
+    /// Radius of curvature in the east-west (`x`) and north-south (`y`) directions
+    pub radius: (F, F),
+    /// Tangent point, in mesh coordinates
+    pub centre: (F, F),
+}
+
+impl<F: RealField> Curvature<F> {
+    /// Curvature of a sphere
+    pub fn sphere(radius: F, centre: (F, F)) -> Self {
+        Curvature { radius: (radius, radius), centre }
+    }
+    
+    /// Curvature of the Earth (WGS 84 ellipsoid, in metres) at the given
+    /// latitude (degrees)
+    pub fn earth(latitude: F, centre: (F, F)) -> Self {
+        let a: F = convert(6_378_137.0);
+        let f: F = convert(1.0 / 298.257_223_563);
+        let e2 = f * (convert::<_, F>(2.0) - f);
+        let s = (latitude * F::pi() / convert(180.0)).sin();
+        let w = F::one() - e2 * s * s;
+        let prime_vertical = a / w.sqrt();
+        let meridional = a * (F::one() - e2) / (w * w.sqrt());
+        Curvature { radius: (prime_vertical, meridional), centre }
+    }
+    
+    /// Bend `mesh` over the curved surface
+    /// 
+    /// Positions and normals are transformed exactly for a surface of the
+    /// radius of curvature in the direction of each vertex from `centre`.
+    pub fn apply(&self, mesh: &mut TerrainMesh<F>) {
+        for i in 0..mesh.positions.len() {
+            let p = mesh.positions[i];
+            let v = Vector2::new(p.x - self.centre.0, p.y - self.centre.1);
+            let d = v.norm();
+            if d == F::zero() {
+                continue;
+            }
+            let dir = v / d;
+            // Euler's theorem: 1/R = cos²θ/R_x + sin²θ/R_y
+            let radius = F::one() / (dir.x * dir.x / self.radius.0 + dir.y * dir.y / self.radius.1);
+            let angle = d / radius;
+            let r = radius + p.z;
+            let horizontal = dir * (r * angle.sin());
+            mesh.positions[i] = Point3::new(
+                self.centre.0 + horizontal.x,
+                self.centre.1 + horizontal.y,
+                r * angle.cos() - radius);
+            if let Some(ref mut normals) = mesh.normals {
+                let axis = Unit::new_normalize(Vector3::new(-dir.y, dir.x, F::zero()));
+                normals[i] = Rotation3::from_axis_angle(&axis, angle) * normals[i];
             }
         }
+    }
+}
+
 
-        let mut mesh = TriMesh::new(
-            vertices,
-            None,
-            Some(tex_coords),
-            Some(IndexBuffer::Unified(triangles)),
-        );
-        mesh.recompute_normals();
+impl<F: RealField, U: UnboundedSurface<F>> SampleMesh<F> for U {
+    fn sample_mesh(&self, start: (F, F), size: (F, F), subdivs: (u32, u32), up: UpAxis) -> TerrainMesh<F> {
+        let mut mesh = sample(self, start, size, subdivs, None);
+        mesh.orient(up);
         mesh
     }
+    
+    fn sample_mesh_detailed<S: UnboundedSurface<F>>(&self, start: (F, F), size: (F, F), subdivs: (u32, u32),
+        detail: &MicroDetail<F, S>) -> TerrainMesh<F>
+    {
+        let detail = |x: F, y: F| detail.displacement_with_gradient(x, y);
+        sample(self, start, size, subdivs, Some(&detail))
+    }
+    
+    fn sample_mesh_adaptive(&self, start: (F, F), size: (F, F), max_error: F, max_depth: u32) -> TerrainMesh<F> {
+        trace_span!("sample_mesh_adaptive", max_depth);
+        adaptive::sample_adaptive(self, start, size, max_error, max_depth)
+    }
+}
+
+// Displacement and gradient at a coordinate
+type DetailFn<'a, F> = &'a dyn Fn(F, F) -> (F, [F; 2]);
+
+fn sample<F: RealField, U: UnboundedSurface<F>>(surface: &U, start: (F, F), size: (F, F), subdivs: (u32, u32),
+    detail: Option<DetailFn<F>>) -> TerrainMesh<F>
+{
+    let one: F = na::one();
+    let np = (subdivs.0 + 1, subdivs.1 + 1);
+    
+    // code adapted from ncollide::procedural::unit_quad:
+    let tx_step = one / convert(subdivs.0 as f64);
+    let ty_step = one / convert(subdivs.1 as f64);
+    let x_step = tx_step * size.0;
+    let y_step = ty_step * size.1;
+    
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    
+    // create the vertices
+    for iy in 0..np.1 {
+        for ix in 0..np.0 {
+            let fy: F = convert(iy as f64);
+            let fx: F = convert(ix as f64);
+            
+            let (x, y) = (start.0 + fx * x_step, start.1 + fy * y_step);
+            let (mut h, mut g) = surface.get_with_gradient(x, y);
+            if let Some(d) = detail {
+                let (dh, dg) = d(x, y);
+                h += dh;
+                g = [g[0] + dg[0], g[1] + dg[1]];
+            }
+            vertices.push(Point3::new(x, y, h));
+            normals.push(Vector3::new(-g[0], -g[1], one).normalize());
+            tex_coords.push(Point2::new(one - fx * tx_step, one - fy * ty_step))
+        }
+    }
+    
+    // create triangles
+    let ws = np.0;
+    
+    let dl_triangle = |iy: u32, ix: u32| -> Point3<u32> {
+        Point3::new((iy + 1) * ws + ix, iy * ws + ix, (iy + 1) * ws + ix + 1)
+    };
+    
+    let ur_triangle = |iy: u32, ix: u32| -> Point3<u32> {
+        Point3::new(iy * ws + ix, iy * ws + (ix + 1), (iy + 1) * ws + ix + 1)
+    };
+    
+    for iy in 0..subdivs.1 {
+        for ix in 0..subdivs.0 {
+            // build two triangles...
+            triangles.push(dl_triangle(iy, ix));
+            triangles.push(ur_triangle(iy, ix));
+        }
+    }
+    
+    TerrainMesh::new(
+        vertices,
+        Some(normals),
+        Some(tex_coords),
+        triangles,
+    )
 }
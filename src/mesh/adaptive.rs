@@ -0,0 +1,160 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Adaptive quadtree sampling of surfaces
+//
+// Quads are addressed on an integer lattice of `2^max_depth` steps per side,
+// thus vertices shared between quads of different depth are identified
+// exactly.
+
+use std::collections::{BTreeSet, HashMap};
+use nalgebra::{convert, RealField, Vector3, geometry::{Point2, Point3}};
+use crate::unbounded::UnboundedSurface;
+use super::TerrainMesh;
+
+type Lattice = (u32, u32);
+
+struct Sampler<'a, F: RealField, U> {
+    surface: &'a U,
+    start: (F, F),
+    step: (F, F),
+    n: u32,
+    cache: HashMap<Lattice, (F, [F; 2])>,
+}
+
+impl<'a, F: RealField, U: UnboundedSurface<F>> Sampler<'a, F, U> {
+    fn coord(&self, p: Lattice) -> (F, F) {
+        let (x, y): (F, F) = (convert(p.0 as f64), convert(p.1 as f64));
+        (self.start.0 + x * self.step.0, self.start.1 + y * self.step.1)
+    }
+    
+    fn sample(&mut self, p: Lattice) -> (F, [F; 2]) {
+        if let Some(&s) = self.cache.get(&p) {
+            return s;
+        }
+        let (x, y) = self.coord(p);
+        let s = self.surface.get_with_gradient(x, y);
+        self.cache.insert(p, s);
+        s
+    }
+    
+    fn h(&mut self, p: Lattice) -> F {
+        self.sample(p).0
+    }
+    
+    // Maximum deviation of the surface from the bilinear patch over the quad
+    // at the centre and edge midpoints
+    fn deviation(&mut self, (x0, y0): Lattice, s: u32) -> F {
+        let (x1, y1, xm, ym) = (x0 + s, y0 + s, x0 + s / 2, y0 + s / 2);
+        let half: F = convert(0.5);
+        let (h00, h10, h01, h11) = (self.h((x0, y0)), self.h((x1, y0)), self.h((x0, y1)), self.h((x1, y1)));
+        let c = (self.h((xm, ym)) - (h00 + h10 + h01 + h11) * half * half).abs();
+        let b = (self.h((xm, y0)) - (h00 + h10) * half).abs();
+        let r = (self.h((x1, ym)) - (h10 + h11) * half).abs();
+        let t = (self.h((xm, y1)) - (h01 + h11) * half).abs();
+        let l = (self.h((x0, ym)) - (h00 + h01) * half).abs();
+        c.max(b).max(r).max(t).max(l)
+    }
+}
+
+// Vertex buffers indexed by lattice point
+struct Builder<F: RealField> {
+    index: HashMap<Lattice, u32>,
+    positions: Vec<Point3<F>>,
+    normals: Vec<Vector3<F>>,
+    uvs: Vec<Point2<F>>,
+    triangles: Vec<Point3<u32>>,
+}
+
+impl<F: RealField> Builder<F> {
+    fn vertex<U: UnboundedSurface<F>>(&mut self, sampler: &mut Sampler<F, U>, p: Lattice) -> u32 {
+        if let Some(&i) = self.index.get(&p) {
+            return i;
+        }
+        let (h, g) = sampler.sample(p);
+        let (x, y) = sampler.coord(p);
+        let one = F::one();
+        let n: F = convert(sampler.n as f64);
+        let (tx, ty): (F, F) = (convert(p.0 as f64), convert(p.1 as f64));
+        self.positions.push(Point3::new(x, y, h));
+        self.normals.push(Vector3::new(-g[0], -g[1], one).normalize());
+        self.uvs.push(Point2::new(one - tx / n, one - ty / n));
+        let i = self.positions.len() as u32 - 1;
+        self.index.insert(p, i);
+        i
+    }
+}
+
+pub(super) fn sample_adaptive<F: RealField, U: UnboundedSurface<F>>(surface: &U, start: (F, F), size: (F, F),
+    max_error: F, max_depth: u32) -> TerrainMesh<F>
+{
+    assert!(max_depth < 32);
+    let n = 1u32 << max_depth;
+    let nf: F = convert(n as f64);
+    let mut sampler = Sampler { surface, start, step: (size.0 / nf, size.1 / nf), n, cache: HashMap::new() };
+    
+    // Subdivide quads (lower corner, side) while not planar
+    let mut leaves = vec![];
+    let mut stack = vec![((0, 0), n)];
+    while let Some((p, s)) = stack.pop() {
+        if s > 1 && sampler.deviation(p, s) > max_error {
+            let h = s / 2;
+            stack.extend_from_slice(&[(p, h), ((p.0 + h, p.1), h), ((p.0, p.1 + h), h), ((p.0 + h, p.1 + h), h)]);
+        } else {
+            leaves.push((p, s));
+        }
+    }
+    leaves.sort_unstable_by_key(|&(p, _)| (p.1, p.0));
+    
+    // Leaf corners, by row and by column, to find vertices on leaf edges
+    let mut rows = BTreeSet::new();
+    let mut cols = BTreeSet::new();
+    for &((x, y), s) in &leaves {
+        for &(cx, cy) in [(x, y), (x + s, y), (x, y + s), (x + s, y + s)].iter() {
+            rows.insert((cy, cx));
+            cols.insert((cx, cy));
+        }
+    }
+    
+    let mut b = Builder {
+        index: HashMap::new(),
+        positions: vec![],
+        normals: vec![],
+        uvs: vec![],
+        triangles: vec![],
+    };
+    for &((x0, y0), s) in &leaves {
+        let (x1, y1) = (x0 + s, y0 + s);
+        let inner = |set: &BTreeSet<Lattice>, a: u32, lo: u32, hi: u32| {
+            set.range((a, lo + 1)..(a, hi)).map(|k| k.1).collect::<Vec<_>>()
+        };
+        // boundary, counter-clockwise from the lower corner
+        let mut boundary = vec![(x0, y0)];
+        boundary.extend(inner(&rows, y0, x0, x1).into_iter().map(|x| (x, y0)));
+        boundary.push((x1, y0));
+        boundary.extend(inner(&cols, x1, y0, y1).into_iter().map(|y| (x1, y)));
+        boundary.push((x1, y1));
+        boundary.extend(inner(&rows, y1, x0, x1).into_iter().rev().map(|x| (x, y1)));
+        boundary.push((x0, y1));
+        boundary.extend(inner(&cols, x0, y0, y1).into_iter().rev().map(|y| (x0, y)));
+        let v: Vec<u32> = boundary.iter().map(|&p| b.vertex(&mut sampler, p)).collect();
+        if v.len() == 4 {
+            // as the regular grid of `sample`
+            b.triangles.push(Point3::new(v[3], v[0], v[2]));
+            b.triangles.push(Point3::new(v[0], v[1], v[2]));
+        } else {
+            // fan from the centre over T-junctions with smaller neighbours
+            let c = b.vertex(&mut sampler, (x0 + s / 2, y0 + s / 2));
+            for i in 0..v.len() {
+                b.triangles.push(Point3::new(c, v[i], v[(i + 1) % v.len()]));
+            }
+        }
+    }
+    
+    TerrainMesh::new(b.positions, Some(b.normals), Some(b.uvs), b.triangles)
+}
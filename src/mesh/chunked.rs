@@ -0,0 +1,199 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::{RealField, Translation3, geometry::{Point2, Point3}};
+use crate::grid::Grid;
+use crate::heightmap::Heightmap;
+use super::{TerrainMesh, UpAxis};
+
+/// An axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb<F: RealField> {
+    /// Minimum coordinates
+    pub mins: Point3<F>,
+    /// Maximum coordinates
+    pub maxs: Point3<F>,
+}
+
+impl<F: RealField> Aabb<F> {
+    /// Get the centre
+    pub fn center(&self) -> Point3<F> {
+        nalgebra::center(&self.mins, &self.maxs)
+    }
+}
+
+/// A mesh of one chunk (see [`ChunkedMesher`])
+#[derive(Debug, Clone)]
+pub struct MeshChunk<F: RealField> {
+    /// Chunk index `(i, j)`
+    pub index: (u32, u32),
+    /// Level of detail (vertex step `2^lod`)
+    pub lod: u32,
+    /// Bounds of the chunk (including all its heightmap vertices)
+    pub aabb: Aabb<F>,
+    /// The mesh, in the coordinates (and texture coordinates) of the whole
+    /// heightmap
+    pub mesh: TerrainMesh<F>,
+}
+
+/// Meshes a heightmap in chunks
+/// 
+/// The heightmap is split into `n × n` chunks of (near) equal size, adjacent
+/// chunks sharing their edge vertices. Each chunk may be meshed with its own
+/// level of detail, decimating vertices as [`Heightmap::to_trimesh_lod`] with
+/// step `2^lod` relative to the chunk origin, thus edges of chunks at the same
+/// level match exactly while edge vertices of a coarser chunk are a subset of
+/// those of a finer neighbour. Each chunk has an [`Aabb`] for frustum culling.
+/// 
+/// Where a finer chunk borders a coarser one, the finer edge has extra
+/// vertices not on the coarser edge, leaving small cracks; enable
+/// [`ChunkedMesher::skirts`] to hide these.
+/// 
+/// Chunking also keeps each mesh small: physics height fields and some
+/// renderers handle large single meshes poorly.
+#[derive(Debug, Clone)]
+pub struct ChunkedMesher<'a, F: RealField> {
+    m: &'a Heightmap<F>,
+    n: u32,
+    up: UpAxis,
+    skirt: Option<F>,
+}
+
+impl<'a, F: RealField> ChunkedMesher<'a, F> {
+    /// Split `m` into `n × n` chunks, meshing with the `up` axis convention
+    /// 
+    /// Requires `0 < n < dim` along each axis.
+    pub fn new(m: &'a Heightmap<F>, n: u32, up: UpAxis) -> Self {
+        assert!(n > 0 && n < m.dim().0 && n < m.dim().1);
+        ChunkedMesher { m, n, up, skirt: None }
+    }
+    
+    /// Add skirts of the given depth to all chunks
+    /// 
+    /// A skirt is a strip of (outward-facing) triangles hanging vertically
+    /// down from each edge of a chunk, hiding cracks between chunks of
+    /// different levels of detail. `depth` should exceed the largest height
+    /// difference between adjacent vertices at the coarsest level used.
+    /// Skirt vertices copy the normal and texture coordinates of the edge
+    /// vertex above.
+    pub fn skirts(mut self, depth: F) -> Self {
+        assert!(depth > F::zero());
+        self.skirt = Some(depth);
+        self
+    }
+    
+    /// Number of chunks along each axis
+    pub fn num_chunks(&self) -> u32 {
+        self.n
+    }
+    
+    // Vertex ranges (inclusive) of chunk `c`
+    fn vertices(&self, c: (u32, u32)) -> ((u32, u32), (u32, u32)) {
+        assert!(c.0 < self.n && c.1 < self.n);
+        let dim = self.m.dim();
+        let bound = |i: u32, d: u32| (u64::from(i) * u64::from(d - 1) / u64::from(self.n)) as u32;
+        ((bound(c.0, dim.0), bound(c.1, dim.1)), (bound(c.0 + 1, dim.0), bound(c.1 + 1, dim.1)))
+    }
+    
+    /// Get the bounding box of chunk `c`
+    pub fn aabb(&self, c: (u32, u32)) -> Aabb<F> {
+        let (v0, v1) = self.vertices(c);
+        let (mut lo, mut hi) = (F::max_value(), F::min_value());
+        for cy in v0.1..=v1.1 {
+            for cx in v0.0..=v1.0 {
+                let h = self.m.get(cx, cy);
+                lo = lo.min(h);
+                hi = hi.max(h);
+            }
+        }
+        if let Some(depth) = self.skirt {
+            lo -= depth;
+        }
+        let (x0, y0) = self.m.coord_of(v0.0, v0.1);
+        let (x1, y1) = self.m.coord_of(v1.0, v1.1);
+        match self.up {
+            UpAxis::Z => Aabb { mins: Point3::new(x0, y0, lo), maxs: Point3::new(x1, y1, hi) },
+            UpAxis::Y => Aabb { mins: Point3::new(x0, lo, -y1), maxs: Point3::new(x1, hi, -y0) },
+        }
+    }
+    
+    /// Mesh chunk `c` with vertex step `2^lod`
+    pub fn mesh_chunk(&self, c: (u32, u32), lod: u32) -> MeshChunk<F> {
+        let (v0, v1) = self.vertices(c);
+        let dim = (v1.0 - v0.0 + 1, v1.1 - v0.1 + 1);
+        let grid = Grid::from_fn(dim, |cx, cy| self.m.get(v0.0 + cx, v0.1 + cy));
+        let (x0, y0) = self.m.coord_of(v0.0, v0.1);
+        let (x1, y1) = self.m.coord_of(v1.0, v1.1);
+        let chunk = Heightmap::from_grid(grid, (x1 - x0, y1 - y0));
+        
+        let step = 1 << lod.min(31);
+        let mut mesh = chunk.to_trimesh_lod(step);
+        if let Some(depth) = self.skirt {
+            add_skirt(&mut mesh, ((dim.0 - 1).div_ceil(step), (dim.1 - 1).div_ceil(step)), depth);
+        }
+        mesh.translate_by(&Translation3::new(x0, y0, F::zero()));
+        // texture coordinates cover the whole heightmap
+        let size = self.m.size();
+        mesh.uvs = Some(mesh.positions.iter()
+            .map(|p| Point2::new(F::one() - p.x / size.0, F::one() - p.y / size.1))
+            .collect());
+        mesh.orient(self.up);
+        MeshChunk { index: c, lod, aabb: self.aabb(c), mesh }
+    }
+    
+    /// Mesh all chunks, selecting the level of detail of each
+    /// 
+    /// `select` is passed the index and bounds of each chunk and returns its
+    /// level of detail, or `None` to skip (cull) the chunk.
+    pub fn mesh_all<S>(&self, mut select: S) -> Vec<MeshChunk<F>>
+        where S: FnMut((u32, u32), &Aabb<F>) -> Option<u32>
+    {
+        trace_span!("chunked_mesh", dim = ?self.m.dim(), n = self.n);
+        let mut chunks = vec![];
+        for j in 0..self.n {
+            for i in 0..self.n {
+                if let Some(lod) = select((i, j), &self.aabb((i, j))) {
+                    chunks.push(self.mesh_chunk((i, j), lod));
+                }
+            }
+        }
+        chunks
+    }
+}
+
+// Add a skirt to a Z-up grid mesh with `divs` quads along each axis (as
+// generated by `Heightmap::to_trimesh_lod`)
+fn add_skirt<F: RealField>(mesh: &mut TerrainMesh<F>, divs: (u32, u32), depth: F) {
+    let ws = divs.0 + 1;
+    let index = |ix: u32, iy: u32| iy * ws + ix;
+    // border loop, counter-clockwise seen from above
+    let mut border = vec![];
+    border.extend((0..divs.0).map(|ix| index(ix, 0)));
+    border.extend((0..divs.1).map(|iy| index(divs.0, iy)));
+    border.extend((1..=divs.0).rev().map(|ix| index(ix, divs.1)));
+    border.extend((1..=divs.1).rev().map(|iy| index(0, iy)));
+    
+    let base = mesh.positions.len() as u32;
+    for &i in &border {
+        let p = mesh.positions[i as usize];
+        mesh.positions.push(Point3::new(p.x, p.y, p.z - depth));
+        if let Some(ref mut normals) = mesh.normals {
+            normals.push(normals[i as usize]);
+        }
+        if let Some(ref mut uvs) = mesh.uvs {
+            uvs.push(uvs[i as usize]);
+        }
+    }
+    let n = border.len() as u32;
+    for k in 0..n {
+        let (a, b) = (border[k as usize], border[((k + 1) % n) as usize]);
+        let (a1, b1) = (base + k, base + (k + 1) % n);
+        mesh.indices.push(Point3::new(a, a1, b));
+        mesh.indices.push(Point3::new(b, a1, b1));
+    }
+}
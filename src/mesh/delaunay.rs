@@ -0,0 +1,341 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Incremental Delaunay triangulation over integer coordinates
+//
+// All geometric predicates are exact in integer arithmetic. The exterior of
+// the convex hull is covered by "ghost" triangles, each joining a hull edge
+// to a vertex at infinity, thus points outside the hull are inserted like any
+// other (as in Shewchuk's Triangle).
+
+use nalgebra::{convert, try_convert, RealField, geometry::{Point2, Point3}};
+use super::TerrainMesh;
+
+/// The vertex at infinity
+pub(crate) const GHOST: u32 = u32::MAX;
+
+// Coordinates of quantised points are in `[0, 2^QUANT_BITS)`; this bounds
+// intermediate values of `incircle` well within `i128`
+const QUANT_BITS: u32 = 24;
+
+// Triangle with counter-clockwise vertices; `n[i]` is the neighbour across
+// the edge opposite `v[i]`
+#[derive(Clone, Copy)]
+pub(crate) struct Tri {
+    pub(crate) v: [u32; 3],
+    n: [u32; 3],
+    pub(crate) version: u32,
+}
+
+impl Tri {
+    // Rotate so that index `i` becomes 0
+    fn rotated(&self, i: usize) -> Tri {
+        let r = |a: [u32; 3]| [a[i], a[(i + 1) % 3], a[(i + 2) % 3]];
+        Tri { v: r(self.v), n: r(self.n), version: self.version }
+    }
+    
+    /// True if this joins a hull edge to the vertex at infinity
+    pub(crate) fn is_ghost(&self) -> bool {
+        self.v.contains(&GHOST)
+    }
+    
+    // Rotate a ghost triangle to the form `[x, y, GHOST]`
+    fn ghost_form(&self) -> Tri {
+        let k = self.v.iter().position(|&v| v == GHOST).unwrap();
+        self.rotated((k + 1) % 3)
+    }
+}
+
+// Twice the signed area of (a, b, c); positive if counter-clockwise
+pub(crate) fn orient(a: (i64, i64), b: (i64, i64), c: (i64, i64)) -> i64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+// Positive if `d` lies inside the circumcircle of counter-clockwise (a, b, c)
+fn incircle(a: (i64, i64), b: (i64, i64), c: (i64, i64), d: (i64, i64)) -> i128 {
+    let (adx, ady) = ((a.0 - d.0) as i128, (a.1 - d.1) as i128);
+    let (bdx, bdy) = ((b.0 - d.0) as i128, (b.1 - d.1) as i128);
+    let (cdx, cdy) = ((c.0 - d.0) as i128, (c.1 - d.1) as i128);
+    (adx * adx + ady * ady) * (bdx * cdy - cdx * bdy)
+        + (bdx * bdx + bdy * bdy) * (cdx * ady - adx * cdy)
+        + (cdx * cdx + cdy * cdy) * (adx * bdy - bdx * ady)
+}
+
+// True if `p`, collinear with `a` and `b`, lies strictly between them
+fn between(a: (i64, i64), b: (i64, i64), p: (i64, i64)) -> bool {
+    let d = (p.0 - a.0) * (b.0 - a.0) + (p.1 - a.1) * (b.1 - a.1);
+    let l = (b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1);
+    d > 0 && d < l
+}
+
+/// A Delaunay triangulation
+/// 
+/// Triangles modified since `touched` was last cleared are listed there.
+pub(crate) struct Triangulation {
+    pub(crate) points: Vec<(i64, i64)>,
+    pub(crate) tris: Vec<Tri>,
+    pub(crate) touched: Vec<u32>,
+    last: u32,
+}
+
+impl Triangulation {
+    /// Start with the counter-clockwise triangle `(a, b, c)`
+    pub(crate) fn new(a: (i64, i64), b: (i64, i64), c: (i64, i64)) -> Self {
+        assert!(orient(a, b, c) > 0);
+        // The ghost across the edge opposite vertex `k` is triangle `k + 1`.
+        let tris = vec![
+            Tri { v: [0, 1, 2], n: [1, 2, 3], version: 0 },
+            Tri { v: [2, 1, GHOST], n: [3, 2, 0], version: 0 },
+            Tri { v: [0, 2, GHOST], n: [1, 3, 0], version: 0 },
+            Tri { v: [1, 0, GHOST], n: [2, 1, 0], version: 0 },
+        ];
+        Triangulation { points: vec![a, b, c], tris, touched: vec![0, 1, 2, 3], last: 0 }
+    }
+    
+    fn point(&self, v: u32) -> (i64, i64) {
+        self.points[v as usize]
+    }
+    
+    fn set(&mut self, t: u32, tri: Tri) {
+        self.tris[t as usize] = Tri { version: self.tris[t as usize].version + 1, ..tri };
+        self.touched.push(t);
+    }
+    
+    fn add(&mut self, tri: Tri) -> u32 {
+        self.tris.push(tri);
+        let t = self.tris.len() as u32 - 1;
+        self.touched.push(t);
+        t
+    }
+    
+    // In triangle `t`, replace neighbour `old` with `new`
+    fn relink(&mut self, t: u32, old: u32, new: u32) {
+        for n in self.tris[t as usize].n.iter_mut() {
+            if *n == old {
+                *n = new;
+            }
+        }
+    }
+    
+    // Find the triangle containing `p` (or a vertex equal to `p`) by walking
+    fn locate(&self, p: (i64, i64)) -> Result<u32, u32> {
+        let mut t = self.last;
+        'walk: loop {
+            let tri = self.tris[t as usize];
+            if tri.is_ghost() {
+                let g = tri.ghost_form();
+                let (x, y) = (self.point(g.v[0]), self.point(g.v[1]));
+                let o = orient(x, y, p);
+                if o > 0 {
+                    return Ok(t);
+                } else if o < 0 {
+                    t = g.n[2];
+                } else if p == x || p == y {
+                    return Err(g.v[if p == x { 0 } else { 1 }]);
+                } else if between(x, y, p) {
+                    return Ok(t);
+                } else if between(x, p, y) {
+                    // beyond y along the hull
+                    t = g.n[0];
+                } else {
+                    t = g.n[1];
+                }
+                continue;
+            }
+            for i in 0..3 {
+                let (a, b) = (self.point(tri.v[(i + 1) % 3]), self.point(tri.v[(i + 2) % 3]));
+                if orient(a, b, p) < 0 {
+                    t = tri.n[i];
+                    continue 'walk;
+                }
+            }
+            if let Some(&v) = tri.v.iter().find(|&&v| self.point(v) == p) {
+                return Err(v);
+            }
+            return Ok(t);
+        }
+    }
+    
+    /// Insert point `p`, returning its index, or the index of an equal
+    /// existing point as an error
+    pub(crate) fn insert(&mut self, p: (i64, i64)) -> Result<u32, u32> {
+        let t = self.locate(p)?;
+        Ok(self.insert_in(t, p))
+    }
+    
+    /// Insert point `p`, lying in (or on the boundary of) triangle `t` and
+    /// not equal to any vertex, returning its index
+    pub(crate) fn insert_in(&mut self, t: u32, q: (i64, i64)) -> u32 {
+        self.points.push(q);
+        let p = self.points.len() as u32 - 1;
+        let tri = self.tris[t as usize];
+        let edge = (0..3).find(|&i| {
+            let (a, b) = (tri.v[(i + 1) % 3], tri.v[(i + 2) % 3]);
+            a != GHOST && b != GHOST && orient(self.point(a), self.point(b), q) == 0
+        });
+        match edge {
+            None => {
+                let [a, b, c] = tri.v;
+                let [na, nb, nc] = tri.n;
+                let t1 = self.add(Tri { v: [p, c, a], n: [nb, 0, t], version: 0 });
+                let t2 = self.add(Tri { v: [p, a, b], n: [nc, t, t1], version: 0 });
+                self.tris[t1 as usize].n[1] = t2;
+                self.set(t, Tri { v: [p, b, c], n: [na, t1, t2], ..tri });
+                self.relink(nb, t, t1);
+                self.relink(nc, t, t2);
+                for &s in [t, t1, t2].iter() {
+                    self.legalize(s);
+                }
+            }
+            Some(i) => {
+                // split `t` and its neighbour `u` across the edge
+                let tri = tri.rotated(i);
+                let [a, b, c] = tri.v;
+                let [u, nb, nc] = tri.n;
+                let ut = self.tris[u as usize];
+                let j = (0..3).find(|&j| ut.n[j] == t).unwrap();
+                let ut = ut.rotated(j);
+                let d = ut.v[0];
+                let tb = self.add(Tri { v: [p, a, b], n: [nc, u, t], version: 0 });
+                let td = self.add(Tri { v: [p, d, c], n: [ut.n[2], t, u], version: 0 });
+                self.relink(nc, t, tb);
+                self.relink(ut.n[2], u, td);
+                self.set(t, Tri { v: [p, c, a], n: [nb, tb, td], ..tri });
+                self.set(u, Tri { v: [p, b, d], n: [ut.n[1], td, tb], ..ut });
+                for &s in [t, tb, u, td].iter() {
+                    self.legalize(s);
+                }
+            }
+        }
+        self.last = t;
+        p
+    }
+    
+    // True if `d` lies within the circumcircle of `t` (for a ghost triangle,
+    // the open half-plane beyond its hull edge plus the open edge)
+    fn in_circle(&self, t: &Tri, d: u32) -> bool {
+        if d == GHOST {
+            return false;
+        }
+        let d = self.point(d);
+        if t.is_ghost() {
+            let g = t.ghost_form();
+            let (x, y) = (self.point(g.v[0]), self.point(g.v[1]));
+            let o = orient(x, y, d);
+            o > 0 || o == 0 && between(x, y, d)
+        } else {
+            incircle(self.point(t.v[0]), self.point(t.v[1]), self.point(t.v[2]), d) > 0
+        }
+    }
+    
+    // Restore the Delaunay property across the edge of `t` opposite its
+    // vertex 0 (the inserted point), flipping recursively
+    fn legalize(&mut self, t: u32) {
+        let mut stack = vec![t];
+        while let Some(t) = stack.pop() {
+            let tri = self.tris[t as usize];
+            let u = tri.n[0];
+            let ut = self.tris[u as usize];
+            let j = (0..3).find(|&j| ut.n[j] == t).unwrap();
+            let ut = ut.rotated(j);
+            let [p, b, c] = tri.v;
+            let d = ut.v[0];
+            if !self.in_circle(&tri, d) {
+                continue;
+            }
+            self.relink(ut.n[1], u, t);
+            self.relink(tri.n[1], t, u);
+            self.set(t, Tri { v: [p, b, d], n: [ut.n[1], u, tri.n[2]], ..tri });
+            self.set(u, Tri { v: [p, d, c], n: [ut.n[2], tri.n[1], t], ..ut });
+            stack.push(t);
+            stack.push(u);
+        }
+    }
+    
+    /// Iterate over the vertices of finite triangles
+    pub(crate) fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.tris.iter().filter(|t| !t.is_ghost()).map(|t| t.v)
+    }
+}
+
+// Interleave the bits of `x` and `y`
+fn morton(x: i64, y: i64) -> u64 {
+    let spread = |v: i64| {
+        let mut v = v as u64 & 0xffff_ffff;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    };
+    spread(x) | (spread(y) << 1)
+}
+
+/// Triangulate scattered elevation samples
+/// 
+/// Each point is `(x, y, h)`; the points are Delaunay-triangulated over
+/// `(x, y)`, covering their convex hull, for example to mesh survey data or
+/// the output of adaptive sampling. The mesh vertices are the input points,
+/// in order; repeated `(x, y)` positions are not referenced (the first is
+/// used). Coordinates are quantised to 24 bits relative to the bounding box
+/// for exact geometric predicates. Texture coordinates span the bounding
+/// box, as for [`crate::heightmap::Heightmap::to_trimesh`].
+/// 
+/// The mesh is Z-up (see [`TerrainMesh::orient`]). It has no triangles if
+/// there are fewer than three non-collinear points.
+pub fn from_points<F: RealField>(points: &[Point3<F>]) -> TerrainMesh<F> {
+    trace_span!("from_points", points = points.len());
+    let f = |x: F| try_convert::<F, f64>(x).unwrap();
+    let mut min = (f64::INFINITY, f64::INFINITY);
+    let mut max = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for p in points {
+        min = (min.0.min(f(p.x)), min.1.min(f(p.y)));
+        max = (max.0.max(f(p.x)), max.1.max(f(p.y)));
+    }
+    let extent = (max.0 - min.0, max.1 - min.1);
+    let scale = ((1u64 << QUANT_BITS) - 1) as f64 / extent.0.max(extent.1);
+    let quant: Vec<(i64, i64)> = points.iter()
+        .map(|p| (((f(p.x) - min.0) * scale).round() as i64, ((f(p.y) - min.1) * scale).round() as i64))
+        .collect();
+    
+    let one = F::one();
+    let uvs = points.iter().map(|p| {
+        let u = if extent.0 > 0.0 { (f(p.x) - min.0) / extent.0 } else { 0.0 };
+        let v = if extent.1 > 0.0 { (f(p.y) - min.1) / extent.1 } else { 0.0 };
+        Point2::new(one - convert(u), one - convert(v))
+    }).collect();
+    
+    // Insert in Morton order, such that each point is usually near the last
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by_key(|&i| (morton(quant[i].0, quant[i].1), i));
+    let mut indices = vec![];
+    let first = order.first().map(|&a| quant[a]);
+    let b = order.iter().position(|&i| Some(quant[i]) != first);
+    let c = b.and_then(|b| (b + 1..order.len())
+        .find(|&c| orient(quant[order[0]], quant[order[b]], quant[order[c]]) != 0));
+    if let (Some(b), Some(c)) = (b, c) {
+        let (a, mut b, mut c) = (order[0], order[b], order[c]);
+        if orient(quant[a], quant[b], quant[c]) < 0 {
+            std::mem::swap(&mut b, &mut c);
+        }
+        let mut tri = Triangulation::new(quant[a], quant[b], quant[c]);
+        let mut map = vec![a, b, c];
+        for &i in order.iter() {
+            if i != a && i != b && i != c && tri.insert(quant[i]).is_ok() {
+                map.push(i);
+            }
+        }
+        indices = tri.triangles()
+            .map(|t| Point3::new(map[t[0] as usize] as u32, map[t[1] as usize] as u32, map[t[2] as usize] as u32))
+            .collect();
+    }
+    
+    let mut mesh = TerrainMesh::new(points.to_vec(), None, Some(uvs), indices);
+    mesh.recompute_normals();
+    mesh
+}
@@ -0,0 +1,99 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Level-of-detail meshes
+//! 
+//! A naive mesh of a large heightmap has far too many triangles to render
+//! (over two million for `1025²` vertices). An [`LodChain`] holds meshes of
+//! the same heightmap at `1×, 2×, 4×, …` vertex decimation, from which a
+//! renderer selects by viewing distance.
+//! 
+//! Level `i` uses every `2^i`-th vertex along each axis plus the last (see
+//! [`Heightmap::to_trimesh_lod`]), thus every edge vertex of a coarser level
+//! is also a vertex of all finer levels. (Where neighbouring chunks use
+//! different levels, finer edges still have extra vertices; hide the
+//! resulting cracks with skirts, see [`ChunkedMesher::skirts`].)
+//! 
+//! [`ChunkedMesher::skirts`]: super::ChunkedMesher::skirts
+
+use nalgebra::{try_convert, RealField};
+use crate::heightmap::Heightmap;
+use crate::memory::MemoryUsage;
+use super::{TerrainMesh, UpAxis};
+
+/// A chain of meshes of decreasing detail
+#[derive(Debug, Clone)]
+pub struct LodChain<F: RealField> {
+    levels: Vec<TerrainMesh<F>>,
+}
+
+impl<F: RealField> LodChain<F> {
+    /// Generate up to `levels` meshes of `m` with the `up` axis convention
+    /// 
+    /// Level `i` has step `2^i`. Generation stops early once a level covers
+    /// the map with a single cell along each axis. Requires `levels > 0`.
+    pub fn new(m: &Heightmap<F>, levels: u32, up: UpAxis) -> Self {
+        assert!(levels > 0);
+        trace_span!("lod_chain", dim = ?m.dim(), levels);
+        let max_divs = (m.dim().0 - 1).max(m.dim().1 - 1);
+        let mut meshes = vec![];
+        for i in 0..levels.min(32) {
+            let mut mesh = m.to_trimesh_lod(1 << i);
+            mesh.orient(up);
+            meshes.push(mesh);
+            if (1 << i) >= max_divs {
+                break;
+            }
+        }
+        LodChain { levels: meshes }
+    }
+    
+    /// Number of levels
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+    
+    /// Get the mesh of level `i` (0 is the most detailed)
+    pub fn level(&self, i: usize) -> &TerrainMesh<F> {
+        &self.levels[i]
+    }
+    
+    /// Vertex step of level `i`
+    pub fn step(i: usize) -> u32 {
+        1 << i
+    }
+    
+    /// Select a level by viewing distance
+    /// 
+    /// Level 0 is used below `distance0`; each doubling of distance beyond
+    /// this selects the next level, up to the coarsest available.
+    pub fn select(&self, distance: F, distance0: F) -> usize {
+        if distance < distance0 {
+            return 0;
+        }
+        let ratio = try_convert::<F, f64>(distance / distance0).unwrap();
+        let level = ratio.log2().floor() as usize + 1;
+        level.min(self.levels.len() - 1)
+    }
+    
+    /// Get the level selected by [`LodChain::select`]
+    pub fn select_mesh(&self, distance: F, distance0: F) -> &TerrainMesh<F> {
+        &self.levels[self.select(distance, distance0)]
+    }
+    
+    /// Take the meshes, most detailed first
+    pub fn into_levels(self) -> Vec<TerrainMesh<F>> {
+        self.levels
+    }
+}
+
+impl<F: RealField> MemoryUsage for LodChain<F> {
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.levels.iter().map(|m| m.memory_usage()).sum::<usize>()
+    }
+}
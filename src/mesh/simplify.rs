@@ -0,0 +1,249 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, BTreeMap};
+use nalgebra::{convert, Matrix4, RealField, Vector3, Vector4, geometry::Point3};
+use super::TerrainMesh;
+
+// Weight of boundary constraint planes: boundaries are effectively only
+// simplified where straight
+const BOUNDARY_WEIGHT: f64 = 1e6;
+// Minimum cosine of the rotation of a triangle's normal by a collapse;
+// larger rotations risk folds and slivers
+const MIN_NORMAL_COS: f64 = 0.5;
+
+// Candidate collapse of vertex `from` into vertex `to`, ordered by lowest
+// cost first. `stamp` is the version of both vertices when computed.
+struct Collapse<F> {
+    cost: F,
+    from: u32,
+    to: u32,
+    stamp: (u32, u32),
+}
+
+impl<F: RealField> PartialEq for Collapse<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl<F: RealField> Eq for Collapse<F> {}
+
+impl<F: RealField> PartialOrd for Collapse<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: RealField> Ord for Collapse<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Simplify a mesh within an error bound
+/// 
+/// Edges are collapsed greedily in order of least quadric error (Garland and
+/// Heckbert, "Surface simplification using quadric error metrics", 1997)
+/// while the error stays within `max_error`, measured as a distance: thus
+/// flat plains collapse to a few large triangles while rough terrain keeps
+/// its detail. Each collapse merges a vertex into a neighbour, so remaining
+/// vertices keep their exact positions, normals and texture coordinates.
+/// Collapses which would fold a triangle over (or rotate it by more than
+/// 60°) or make the mesh non-manifold are skipped.
+/// 
+/// Mesh boundaries are only simplified where straight (in the plane of the
+/// adjacent triangles), thus the outline of the map is preserved; heights
+/// along the boundary may be simplified, so adjacent chunks simplified
+/// separately may not match exactly (see
+/// [`ChunkedMesher::skirts`](super::ChunkedMesher::skirts)).
+pub fn simplify<F: RealField>(mesh: &TerrainMesh<F>, max_error: F) -> TerrainMesh<F> {
+    trace_span!("simplify", triangles = mesh.indices.len());
+    let pos = &mesh.positions;
+    let nv = pos.len();
+    let mut faces: Vec<Option<[u32; 3]>> = mesh.indices.iter().map(|t| Some([t.x, t.y, t.z])).collect();
+    let mut vfaces: Vec<Vec<u32>> = vec![vec![]; nv];
+    for (i, f) in mesh.indices.iter().enumerate() {
+        for &v in [f.x, f.y, f.z].iter() {
+            vfaces[v as usize].push(i as u32);
+        }
+    }
+    
+    // error quadrics: face planes plus boundary constraint planes
+    let mut quadrics = vec![Matrix4::<F>::zeros(); nv];
+    let mut edges = BTreeMap::new();
+    for f in mesh.indices.iter() {
+        let f = [f.x, f.y, f.z];
+        let n = match normal(pos, f).try_normalize(F::zero()) {
+            Some(n) => n,
+            None => continue,
+        };
+        let k = plane_quadric(n, &pos[f[0] as usize]);
+        for i in 0..3 {
+            quadrics[f[i] as usize] += k;
+            let (a, b) = (f[i], f[(i + 1) % 3]);
+            edges.entry((a.min(b), a.max(b))).or_insert((0, n)).0 += 1;
+        }
+    }
+    let weight: F = convert(BOUNDARY_WEIGHT);
+    for (&(a, b), &(count, n)) in edges.iter() {
+        if count == 1 {
+            let e = pos[b as usize] - pos[a as usize];
+            if let Some(c) = e.cross(&n).try_normalize(F::zero()) {
+                let k = plane_quadric(c, &pos[a as usize]) * weight;
+                quadrics[a as usize] += k;
+                quadrics[b as usize] += k;
+            }
+        }
+    }
+    
+    let threshold = max_error * max_error;
+    let mut stamp = vec![0u32; nv];
+    let mut removed = vec![false; nv];
+    let mut heap = BinaryHeap::new();
+    let push = |heap: &mut BinaryHeap<_>, quadrics: &[Matrix4<F>], stamp: &[u32], from: u32, to: u32| {
+        let q = quadrics[from as usize] + quadrics[to as usize];
+        let p = pos[to as usize].to_homogeneous();
+        let cost = p.dot(&(q * p));
+        if cost <= threshold {
+            heap.push(Collapse { cost, from, to, stamp: (stamp[from as usize], stamp[to as usize]) });
+        }
+    };
+    for &(a, b) in edges.keys() {
+        push(&mut heap, &quadrics, &stamp, a, b);
+        push(&mut heap, &quadrics, &stamp, b, a);
+    }
+    
+    while let Some(Collapse { from, to, stamp: s, .. }) = heap.pop() {
+        let (u, v) = (from as usize, to as usize);
+        if removed[u] || removed[v] || s != (stamp[u], stamp[v]) {
+            continue;
+        }
+        if !can_collapse(pos, &faces, &vfaces, from, to) {
+            continue;
+        }
+        
+        for fi in std::mem::take(&mut vfaces[u]) {
+            let mut f = faces[fi as usize].unwrap();
+            if f.contains(&to) {
+                faces[fi as usize] = None;
+                for &w in f.iter().filter(|&&w| w != from) {
+                    vfaces[w as usize].retain(|&x| x != fi);
+                }
+            } else {
+                for w in f.iter_mut() {
+                    if *w == from {
+                        *w = to;
+                    }
+                }
+                faces[fi as usize] = Some(f);
+                vfaces[v].push(fi);
+            }
+        }
+        removed[u] = true;
+        let q = quadrics[u];
+        quadrics[v] += q;
+        stamp[v] += 1;
+        for w in neighbours(&faces, &vfaces, to) {
+            push(&mut heap, &quadrics, &stamp, to, w);
+            push(&mut heap, &quadrics, &stamp, w, to);
+        }
+    }
+    
+    // compact, dropping unreferenced vertices
+    let mut map = vec![u32::MAX; nv];
+    let mut kept = vec![];
+    let mut indices = vec![];
+    for f in faces.iter().flatten() {
+        let mut t = [0; 3];
+        for i in 0..3 {
+            let v = f[i] as usize;
+            if map[v] == u32::MAX {
+                map[v] = kept.len() as u32;
+                kept.push(v);
+            }
+            t[i] = map[v];
+        }
+        indices.push(Point3::new(t[0], t[1], t[2]));
+    }
+    TerrainMesh::new(
+        kept.iter().map(|&v| pos[v]).collect(),
+        mesh.normals.as_ref().map(|n| kept.iter().map(|&v| n[v]).collect()),
+        mesh.uvs.as_ref().map(|uv| kept.iter().map(|&v| uv[v]).collect()),
+        indices,
+    )
+}
+
+// Non-normalised normal of a face
+fn normal<F: RealField>(pos: &[Point3<F>], f: [u32; 3]) -> Vector3<F> {
+    let (a, b, c) = (pos[f[0] as usize], pos[f[1] as usize], pos[f[2] as usize]);
+    (b - a).cross(&(c - a))
+}
+
+// Quadric of the squared distance to the plane with unit normal `n` through
+// point `p`
+fn plane_quadric<F: RealField>(n: Vector3<F>, p: &Point3<F>) -> Matrix4<F> {
+    let plane = Vector4::new(n.x, n.y, n.z, -n.dot(&p.coords));
+    plane * plane.transpose()
+}
+
+// Vertices adjacent to `v`
+fn neighbours(faces: &[Option<[u32; 3]>], vfaces: &[Vec<u32>], v: u32) -> Vec<u32> {
+    let mut n: Vec<u32> = vfaces[v as usize].iter()
+        .flat_map(|&fi| faces[fi as usize].unwrap().to_vec())
+        .filter(|&w| w != v)
+        .collect();
+    n.sort_unstable();
+    n.dedup();
+    n
+}
+
+// True if edge `a`–`b` belongs to exactly one face
+fn is_boundary_edge(faces: &[Option<[u32; 3]>], vfaces: &[Vec<u32>], a: u32, b: u32) -> bool {
+    vfaces[a as usize].iter().filter(|&&fi| faces[fi as usize].unwrap().contains(&b)).count() == 1
+}
+
+// Check whether collapsing `from` into `to` keeps the mesh manifold without
+// folding any triangle over
+fn can_collapse<F: RealField>(pos: &[Point3<F>], faces: &[Option<[u32; 3]>], vfaces: &[Vec<u32>], from: u32, to: u32)
+    -> bool
+{
+    let nf = neighbours(faces, vfaces, from);
+    let boundary = nf.iter().any(|&w| is_boundary_edge(faces, vfaces, from, w));
+    let edge_boundary = is_boundary_edge(faces, vfaces, from, to);
+    // a boundary vertex may only move along the boundary
+    if boundary && !edge_boundary {
+        return false;
+    }
+    // link condition: shared neighbours are exactly those of the edge's faces
+    let nt = neighbours(faces, vfaces, to);
+    let shared = nf.iter().filter(|w| nt.binary_search(w).is_ok()).count();
+    if shared != if edge_boundary { 1 } else { 2 } {
+        return false;
+    }
+    for &fi in &vfaces[from as usize] {
+        let f = faces[fi as usize].unwrap();
+        if f.contains(&to) {
+            continue;
+        }
+        let old = normal(pos, f);
+        let mut g = f;
+        for w in g.iter_mut() {
+            if *w == from {
+                *w = to;
+            }
+        }
+        let new = normal(pos, g);
+        let min_cos: F = convert(MIN_NORMAL_COS);
+        if new.dot(&old) <= min_cos * new.norm() * old.norm() {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,205 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Realism metrics
+//! 
+//! [`TerrainStats`] summarises the geomorphometry of a heightmap (slope
+//! distribution, hypsometry, drainage density); [`assess`] compares these to
+//! reference statistics of real terrain types ([`REFERENCES`]), giving a
+//! score per statistic and terrain type. This is intended as a guide for
+//! parameter tuning, not a rigorous classification.
+//! 
+//! Heightmaps must be in metres (see [`crate::units`]), with a horizontal
+//! resolution comparable to typical DEMs (roughly 10–100 m): slopes and
+//! drainage density are scale-dependent.
+
+use std::cmp::Ordering;
+use nalgebra::{try_convert, RealField};
+use crate::heightmap::Heightmap;
+use crate::heightmap::drainage::accumulation;
+
+/// Catchment area (m²) above which a vertex is counted as a channel when
+/// computing [`TerrainStats::drainage_density`]
+pub const CHANNEL_AREA: f64 = 1.0e5;
+
+/// Summary statistics of a heightmap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainStats {
+    /// Mean slope (degrees)
+    pub mean_slope: f64,
+    /// Median slope (degrees)
+    pub median_slope: f64,
+    /// 90th percentile of slope (degrees)
+    pub p90_slope: f64,
+    /// Hypsometric integral: `(mean - min) / (max - min)` of heights
+    /// 
+    /// High values (above 0.6) indicate "youthful", little-dissected uplands;
+    /// low values (below 0.35) eroded lowlands with isolated high ground.
+    pub hypsometric_integral: f64,
+    /// Hypsometric curve: the fraction of area above each relative height
+    /// `0, 0.1, …, 1` between minimum and maximum
+    pub hypsometric_curve: [f64; 11],
+    /// Drainage density (km of channel per km²), counting vertices with
+    /// catchment above [`CHANNEL_AREA`] as channels
+    pub drainage_density: f64,
+}
+
+impl TerrainStats {
+    /// Compute statistics of `m`
+    pub fn of<F: RealField>(m: &Heightmap<F>) -> Self {
+        let to_f64 = |x: F| try_convert::<_, f64>(x).unwrap();
+        let dim = m.dim();
+        let n = dim.0 as usize * dim.1 as usize;
+        
+        let mut slopes = Vec::with_capacity(n);
+        let mut heights = Vec::with_capacity(n);
+        for cy in 0..dim.1 {
+            for cx in 0..dim.0 {
+                slopes.push(to_f64(m.slope_at(cx, cy)).atan().to_degrees());
+                heights.push(to_f64(m.get(cx, cy)));
+            }
+        }
+        slopes.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let percentile = |p: f64| slopes[((n - 1) as f64 * p).round() as usize];
+        let mean_slope = slopes.iter().sum::<f64>() / n as f64;
+        
+        let (lo, hi) = (to_f64(m.range().0), to_f64(m.range().1));
+        let mean = heights.iter().sum::<f64>() / n as f64;
+        let relief = hi - lo;
+        let hypsometric_integral = if relief > 0.0 { (mean - lo) / relief } else { 0.5 };
+        let mut hypsometric_curve = [0.0; 11];
+        for (i, frac) in hypsometric_curve.iter_mut().enumerate() {
+            let h = lo + relief * i as f64 / 10.0;
+            *frac = heights.iter().filter(|x| **x >= h).count() as f64 / n as f64;
+        }
+        
+        let cell = m.cell_size();
+        let (cw, ch) = (to_f64(cell.0), to_f64(cell.1));
+        let acc = accumulation(m, false);
+        let channels = acc.data().iter().filter(|a| to_f64(**a) >= CHANNEL_AREA).count();
+        let area = cw * ch * n as f64;
+        let drainage_density = (channels as f64 * (cw + ch) * 0.5 / 1000.0) / (area / 1.0e6);
+        
+        TerrainStats {
+            mean_slope,
+            median_slope: percentile(0.5),
+            p90_slope: percentile(0.9),
+            hypsometric_integral,
+            hypsometric_curve,
+            drainage_density,
+        }
+    }
+}
+
+/// Reference statistics of a real terrain type
+/// 
+/// Each statistic is given as a typical range `(low, high)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reference {
+    /// Name of the terrain type
+    pub name: &'static str,
+    /// Mean slope (degrees)
+    pub mean_slope: (f64, f64),
+    /// 90th percentile of slope (degrees)
+    pub p90_slope: (f64, f64),
+    /// Hypsometric integral
+    pub hypsometric_integral: (f64, f64),
+    /// Drainage density (km/km²)
+    pub drainage_density: (f64, f64),
+}
+
+/// Bundled references
+/// 
+/// Ranges are rough guides for typical examples of each terrain type at
+/// roughly 30 m resolution, not authoritative values; tune or replace them
+/// (e.g. with statistics measured from DEMs via [`TerrainStats::of`]) as
+/// needed.
+pub const REFERENCES: &[Reference] = &[
+    Reference {
+        name: "plains",
+        mean_slope: (0.0, 3.0),
+        p90_slope: (0.0, 6.0),
+        hypsometric_integral: (0.15, 0.5),
+        drainage_density: (0.3, 2.0),
+    },
+    Reference {
+        name: "hills",
+        mean_slope: (3.0, 12.0),
+        p90_slope: (8.0, 22.0),
+        hypsometric_integral: (0.3, 0.55),
+        drainage_density: (1.5, 4.5),
+    },
+    Reference {
+        name: "mountains",
+        mean_slope: (18.0, 35.0),
+        p90_slope: (32.0, 55.0),
+        hypsometric_integral: (0.35, 0.65),
+        drainage_density: (2.0, 6.0),
+    },
+    Reference {
+        name: "plateau",
+        mean_slope: (1.0, 8.0),
+        p90_slope: (5.0, 40.0),
+        hypsometric_integral: (0.6, 0.9),
+        drainage_density: (0.5, 3.0),
+    },
+    Reference {
+        name: "badlands",
+        mean_slope: (15.0, 40.0),
+        p90_slope: (35.0, 65.0),
+        hypsometric_integral: (0.3, 0.6),
+        drainage_density: (5.0, 30.0),
+    },
+    Reference {
+        name: "dunes",
+        mean_slope: (2.0, 12.0),
+        p90_slope: (8.0, 30.0),
+        hypsometric_integral: (0.3, 0.6),
+        drainage_density: (0.0, 0.5),
+    },
+];
+
+/// Realism scores of a heightmap against a [`Reference`]
+/// 
+/// Each score is in `[0, 1]`: 1 within the reference range, decaying
+/// exponentially with distance outside it (relative to the range width).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// Score of the slope distribution (mean and 90th percentile)
+    pub slope: f64,
+    /// Score of the hypsometric integral
+    pub hypsometry: f64,
+    /// Score of the drainage density
+    pub drainage: f64,
+    /// Mean of the above scores
+    pub overall: f64,
+}
+
+impl Reference {
+    /// Score statistics against this reference
+    pub fn score(&self, stats: &TerrainStats) -> Score {
+        let slope = 0.5 * (range_score(stats.mean_slope, self.mean_slope)
+            + range_score(stats.p90_slope, self.p90_slope));
+        let hypsometry = range_score(stats.hypsometric_integral, self.hypsometric_integral);
+        let drainage = range_score(stats.drainage_density, self.drainage_density);
+        Score { slope, hypsometry, drainage, overall: (slope + hypsometry + drainage) / 3.0 }
+    }
+}
+
+/// Score statistics against all [`REFERENCES`], best match first
+pub fn assess(stats: &TerrainStats) -> Vec<(&'static Reference, Score)> {
+    let mut scores: Vec<_> = REFERENCES.iter().map(|r| (r, r.score(stats))).collect();
+    scores.sort_by(|a, b| b.1.overall.partial_cmp(&a.1.overall).unwrap_or(Ordering::Equal));
+    scores
+}
+
+fn range_score(v: f64, range: (f64, f64)) -> f64 {
+    let width = (range.1 - range.0).max(1e-3);
+    let d = (range.0 - v).max(v - range.1).max(0.0);
+    (-d / width).exp()
+}
@@ -0,0 +1,281 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Incremental generation pipelines
+//! 
+//! A [`Pipeline`] is a chain of stages, each transforming the heightmap
+//! produced by the previous stage. Stages declare their tunable parameters
+//! ([`ParamSpec`]); the output of each stage is cached, so that after changing
+//! a parameter, [`Pipeline::run`] only re-executes the stage declaring it and
+//! those after it. This allows responsive parameter sliders in editors.
+//! 
+//! Stage outputs may be tagged as named checkpoints
+//! ([`Pipeline::checkpoint`]), to be retrieved or exported after a run for
+//! inspection.
+//! 
+//! ```
+//! use terr::heightmap::Heightmap;
+//! use terr::pipeline::{ParamSpec, Pipeline};
+//! 
+//! let mut p = Pipeline::new();
+//! p.add_stage("base", vec![ParamSpec::new("height", (0.0, 100.0), 10.0)], |params, _| {
+//!     let mut m = Heightmap::new_flat((16, 16), (100.0, 100.0));
+//!     m.map(|_| params.get("height"));
+//!     m
+//! });
+//! p.checkpoint("after_base");
+//! p.add_stage("scale", vec![ParamSpec::new("factor", (0.0, 2.0), 1.0)], |params, prev| {
+//!     let mut m = prev.unwrap().clone();
+//!     m.map(|h| h * params.get("factor"));
+//!     m
+//! });
+//! assert_eq!(p.run(), 2);
+//! p.set("factor", 0.5);
+//! assert_eq!(p.run(), 1);     // the base stage is not re-run
+//! assert_eq!(p.output().unwrap().get(0, 0), 5.0);
+//! assert_eq!(p.get_checkpoint("after_base").unwrap().get(0, 0), 10.0);
+//! ```
+
+use std::{fmt, io};
+use std::path::{Path, PathBuf};
+use crate::RealField;
+use crate::heightmap::Heightmap;
+use crate::io::write_asc;
+
+/// A tunable parameter declared by a pipeline stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSpec {
+    /// Name (unique within a pipeline)
+    pub name: String,
+    /// Allowed range `(min, max)` (inclusive)
+    pub range: (f64, f64),
+    /// Default value
+    pub default: f64,
+}
+
+impl ParamSpec {
+    /// Construct
+    /// 
+    /// Requires `range.0 <= default <= range.1`.
+    pub fn new(name: &str, range: (f64, f64), default: f64) -> Self {
+        assert!(range.0 <= default && default <= range.1);
+        ParamSpec { name: name.to_string(), range, default }
+    }
+}
+
+/// Parameter values available to a stage
+/// 
+/// Only the parameters declared by the stage are available; this ensures
+/// that the stage is re-run whenever any parameter it reads changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageParams {
+    values: Vec<(String, f64)>,
+}
+
+impl StageParams {
+    /// Get the value of parameter `name`
+    /// 
+    /// Panics if the parameter was not declared by the stage.
+    pub fn get(&self, name: &str) -> f64 {
+        match self.values.iter().find(|(n, _)| n == name) {
+            Some((_, v)) => *v,
+            None => panic!("parameter '{}' not declared by stage", name),
+        }
+    }
+}
+
+type StageFn<F> = Box<dyn FnMut(&StageParams, Option<&Heightmap<F>>) -> Heightmap<F>>;
+
+struct Stage<F: RealField> {
+    name: String,
+    specs: Vec<ParamSpec>,
+    params: StageParams,
+    run: StageFn<F>,
+    output: Option<Heightmap<F>>,
+    tags: Vec<String>,
+}
+
+/// A chain of heightmap generation stages with cached outputs
+/// 
+/// See the [module documentation](self).
+pub struct Pipeline<F: RealField> {
+    stages: Vec<Stage<F>>,
+}
+
+impl<F: RealField> fmt::Debug for Pipeline<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.stages.iter().map(|s| (&s.name, &s.params, s.output.is_some())))
+            .finish()
+    }
+}
+
+impl<F: RealField> Default for Pipeline<F> {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+impl<F: RealField> Pipeline<F> {
+    /// Construct an empty pipeline
+    pub fn new() -> Self {
+        Pipeline { stages: vec![] }
+    }
+    
+    /// Append a stage
+    /// 
+    /// `run(params, input)` is given the values of the declared `params` and
+    /// the output of the previous stage (`None` for the first stage), and
+    /// must return the stage's output. It should be deterministic: cached
+    /// outputs are reused until a parameter of the stage (or an earlier
+    /// stage) changes. Parameter names must be unique within the pipeline.
+    pub fn add_stage<G>(&mut self, name: &str, params: Vec<ParamSpec>, run: G) -> &mut Self
+    where G: FnMut(&StageParams, Option<&Heightmap<F>>) -> Heightmap<F> + 'static
+    {
+        for spec in &params {
+            assert!(self.find(&spec.name).is_none(), "duplicate parameter '{}'", spec.name);
+        }
+        let values = params.iter().map(|s| (s.name.clone(), s.default)).collect();
+        self.stages.push(Stage {
+            name: name.to_string(),
+            specs: params,
+            params: StageParams { values },
+            run: Box::new(run),
+            output: None,
+            tags: vec![],
+        });
+        self
+    }
+    
+    /// Tag the output of the most recently added stage as checkpoint `tag`
+    /// 
+    /// Tags must be unique within the pipeline and should be simple
+    /// identifiers (they are used as file names by
+    /// [`Pipeline::export_checkpoints`]).
+    pub fn checkpoint(&mut self, tag: &str) -> &mut Self {
+        assert!(self.find_checkpoint(tag).is_none(), "duplicate checkpoint '{}'", tag);
+        let stage = self.stages.last_mut().expect("no stage to checkpoint");
+        stage.tags.push(tag.to_string());
+        self
+    }
+    
+    /// Get the output of checkpoint `tag`, if the tag exists and the stage
+    /// has been run since it was last invalidated
+    pub fn get_checkpoint(&self, tag: &str) -> Option<&Heightmap<F>> {
+        self.find_checkpoint(tag).and_then(|s| self.stages[s].output.as_ref())
+    }
+    
+    /// Iterate over `(tag, output)` of all checkpoints, in stage order
+    pub fn checkpoints(&self) -> impl Iterator<Item = (&str, Option<&Heightmap<F>>)> {
+        self.stages.iter().flat_map(|s| s.tags.iter().map(move |t| (t.as_str(), s.output.as_ref())))
+    }
+    
+    /// Write all available checkpoints to `dir` as `<tag>.asc`
+    /// 
+    /// Returns the paths written. Checkpoints whose stage has not been run are
+    /// skipped.
+    pub fn export_checkpoints(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for (tag, output) in self.checkpoints() {
+            if let Some(m) = output {
+                let path = dir.join(format!("{}.asc", tag));
+                write_asc(&path, m, (0.0, 0.0), None)?;
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+    
+    /// Iterate over `(stage name, parameter)` of all declared parameters
+    pub fn params(&self) -> impl Iterator<Item = (&str, &ParamSpec)> {
+        self.stages.iter().flat_map(|s| s.specs.iter().map(move |p| (s.name.as_str(), p)))
+    }
+    
+    /// Get the current value of parameter `name`, if declared
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.find(name).map(|(s, i)| self.stages[s].params.values[i].1)
+    }
+    
+    /// Set parameter `name`, clamped to its range
+    /// 
+    /// If the value changes, the declaring stage and all later stages are
+    /// invalidated. Returns false if the parameter is not declared.
+    pub fn set(&mut self, name: &str, value: f64) -> bool {
+        let (s, i) = match self.find(name) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let range = self.stages[s].specs[i].range;
+        let value = value.max(range.0).min(range.1);
+        if self.stages[s].params.values[i].1 != value {
+            self.stages[s].params.values[i].1 = value;
+            self.invalidate_from(s);
+        }
+        true
+    }
+    
+    /// Invalidate the cached output of stage `name` and all later stages
+    /// 
+    /// This forces re-execution, e.g. after external inputs of the stage
+    /// changed. Returns false if there is no such stage.
+    pub fn invalidate(&mut self, name: &str) -> bool {
+        match self.stages.iter().position(|s| s.name == name) {
+            Some(s) => {
+                self.invalidate_from(s);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Execute all stages without a cached output
+    /// 
+    /// Returns the number of stages executed.
+    pub fn run(&mut self) -> usize {
+        trace_span!("pipeline");
+        let first = match self.stages.iter().position(|s| s.output.is_none()) {
+            Some(s) => s,
+            None => return 0,
+        };
+        for s in first..self.stages.len() {
+            let (done, rest) = self.stages.split_at_mut(s);
+            let input = done.last().and_then(|prev| prev.output.as_ref());
+            let stage = &mut rest[0];
+            trace_span!("stage", name = %stage.name);
+            stage.output = Some((stage.run)(&stage.params, input));
+        }
+        self.stages.len() - first
+    }
+    
+    /// Get the cached output of the last stage
+    pub fn output(&self) -> Option<&Heightmap<F>> {
+        self.stages.last().and_then(|s| s.output.as_ref())
+    }
+    
+    /// Get the cached output of stage `name`
+    pub fn stage_output(&self, name: &str) -> Option<&Heightmap<F>> {
+        self.stages.iter().find(|s| s.name == name).and_then(|s| s.output.as_ref())
+    }
+    
+    // Find (stage, index) of parameter `name`
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        self.stages.iter().enumerate().find_map(|(s, stage)| {
+            stage.specs.iter().position(|p| p.name == name).map(|i| (s, i))
+        })
+    }
+    
+    fn find_checkpoint(&self, tag: &str) -> Option<usize> {
+        self.stages.iter().position(|s| s.tags.iter().any(|t| t == tag))
+    }
+    
+    fn invalidate_from(&mut self, s: usize) {
+        for stage in &mut self.stages[s..] {
+            stage.output = None;
+        }
+    }
+}
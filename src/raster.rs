@@ -0,0 +1,185 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rasterisation of vector features onto grids
+//! 
+//! All functions work in grid coordinates: vertex `(cx, cy)` is at point
+//! `(cx, cy)`. (For a [`Heightmap`](crate::heightmap::Heightmap), divide world
+//! coordinates by [`cell_size`](crate::heightmap::Heightmap::cell_size).)
+//! Output is passed to a `plot(cx, cy, coverage)` callback with coverage in
+//! `(0, 1]`, leaving blending to the caller; vertices outside the grid
+//! dimension `dim` are skipped.
+
+use crate::RealField;
+use crate::grid::Grid;
+use nalgebra::{convert, try_convert};
+
+/// Draw an anti-aliased line from `a` to `b` (Xiaolin Wu's algorithm)
+/// 
+/// Each step along the major axis plots the two vertices straddling the line,
+/// with coverage according to distance.
+pub fn line<F: RealField, P>(dim: (u32, u32), a: (F, F), b: (F, F), mut plot: P)
+where P: FnMut(u32, u32, F)
+{
+    let steep = (b.1 - a.1).abs() > (b.0 - a.0).abs();
+    let (mut a, mut b) = if steep { ((a.1, a.0), (b.1, b.0)) } else { (a, b) };
+    if a.0 > b.0 {
+        std::mem::swap(&mut a, &mut b);
+    }
+    let dx = b.0 - a.0;
+    let gradient = if dx == F::zero() { F::zero() } else { (b.1 - a.1) / dx };
+    
+    let mut put = |u: i64, v: i64, c: F| {
+        let (x, y) = if steep { (v, u) } else { (u, v) };
+        if c > F::zero() && x >= 0 && y >= 0 && x < dim.0 as i64 && y < dim.1 as i64 {
+            plot(x as u32, y as u32, c);
+        }
+    };
+    let x0 = to_i64(a.0.round());
+    let x1 = to_i64(b.0.round());
+    for x in x0..=x1 {
+        let y = a.1 + gradient * (convert::<_, F>(x as f64) - a.0);
+        let fy = y.floor();
+        let t = y - fy;
+        put(x, to_i64(fy), F::one() - t);
+        put(x, to_i64(fy) + 1, t);
+    }
+}
+
+/// Draw a thick polyline with feathered edges
+/// 
+/// Vertices within `width / 2` of the polyline have coverage approximately 1;
+/// coverage falls off linearly over a band of width `feather` centred on the
+/// edge. With `feather = 0` edges are hard.
+pub fn thick_polyline<F: RealField, P>(dim: (u32, u32), points: &[(F, F)], width: F, feather: F, plot: P)
+where P: FnMut(u32, u32, F)
+{
+    let r = width * convert(0.5);
+    let margin = r + feather * convert(0.5);
+    let mut cover = Grid::new(dim, F::zero());
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let bounds = bounds(dim, &[a, b], margin);
+        for_each_in(bounds, |cx, cy| {
+            let p = (convert(cx as f64), convert(cy as f64));
+            let c = coverage(r - segment_distance(p, a, b), feather);
+            if c > cover.get(cx, cy) {
+                cover.set(cx, cy, c);
+            }
+        });
+    }
+    emit(&cover, bounds(dim, points, margin), plot);
+}
+
+/// Fill a polygon with feathered edges
+/// 
+/// Coverage is 1 inside and 0 outside the polygon (even-odd rule), with a
+/// linear transition over a band of width `feather` centred on the boundary.
+/// With `feather = 0` edges are hard.
+pub fn fill_polygon<F: RealField, P>(dim: (u32, u32), polygon: &[(F, F)], feather: F, plot: P)
+where P: FnMut(u32, u32, F)
+{
+    let n = polygon.len();
+    let b = bounds(dim, polygon, feather * convert(0.5));
+    let mut cover = Grid::new(dim, F::zero());
+    for_each_in(b, |cx, cy| {
+        let p = (convert(cx as f64), convert(cy as f64));
+        let edge = (0..n)
+            .map(|i| segment_distance(p, polygon[i], polygon[(i + 1) % n]))
+            .fold(F::max_value(), |a, b| a.min(b));
+        let d = if point_in_polygon(p, polygon) { edge } else { -edge };
+        cover.set(cx, cy, coverage(d, feather));
+    });
+    emit(&cover, b, plot);
+}
+
+/// Test whether point `p` is inside the polygon (even-odd rule)
+pub fn point_in_polygon<F: RealField>(p: (F, F), polygon: &[(F, F)]) -> bool {
+    let mut inside = false;
+    let n = polygon.len();
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + n - 1) % n]);
+        if (a.1 > p.1) != (b.1 > p.1)
+            && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Distance from point `p` to the segment `ab`
+pub fn segment_distance<F: RealField>(p: (F, F), a: (F, F), b: (F, F)) -> F {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 > F::zero() {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).max(F::zero()).min(F::one())
+    } else {
+        F::zero()
+    };
+    let (x, y) = (a.0 + t * dx - p.0, a.1 + t * dy - p.1);
+    (x * x + y * y).sqrt()
+}
+
+// Inclusive vertex bounds ((x0, y0), (x1, y1)), if non-empty
+type Bounds = Option<((u32, u32), (u32, u32))>;
+
+fn to_i64<F: RealField>(x: F) -> i64 {
+    try_convert::<_, f64>(x).unwrap() as i64
+}
+
+// Coverage at signed distance d inside an edge
+fn coverage<F: RealField>(d: F, feather: F) -> F {
+    if feather > F::zero() {
+        (d / feather + convert(0.5)).max(F::zero()).min(F::one())
+    } else if d >= F::zero() {
+        F::one()
+    } else {
+        F::zero()
+    }
+}
+
+// Bounds of points expanded by margin, clamped to the grid
+fn bounds<F: RealField>(dim: (u32, u32), points: &[(F, F)], margin: F) -> Bounds {
+    if points.is_empty() {
+        return None;
+    }
+    let mut lo = points[0];
+    let mut hi = points[0];
+    for p in points {
+        lo = (lo.0.min(p.0), lo.1.min(p.1));
+        hi = (hi.0.max(p.0), hi.1.max(p.1));
+    }
+    let x0 = to_i64((lo.0 - margin).floor()).max(0);
+    let y0 = to_i64((lo.1 - margin).floor()).max(0);
+    let x1 = to_i64((hi.0 + margin).ceil()).min(dim.0 as i64 - 1);
+    let y1 = to_i64((hi.1 + margin).ceil()).min(dim.1 as i64 - 1);
+    if x0 > x1 || y0 > y1 {
+        return None;
+    }
+    Some(((x0 as u32, y0 as u32), (x1 as u32, y1 as u32)))
+}
+
+fn for_each_in<G: FnMut(u32, u32)>(bounds: Bounds, mut f: G) {
+    if let Some((lo, hi)) = bounds {
+        for cy in lo.1..=hi.1 {
+            for cx in lo.0..=hi.0 {
+                f(cx, cy);
+            }
+        }
+    }
+}
+
+fn emit<F: RealField, P: FnMut(u32, u32, F)>(cover: &Grid<F>, bounds: Bounds, mut plot: P) {
+    for_each_in(bounds, |cx, cy| {
+        let c = cover.get(cx, cy);
+        if c > F::zero() {
+            plot(cx, cy, c);
+        }
+    });
+}
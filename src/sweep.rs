@@ -0,0 +1,251 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parameter sweeps
+//! 
+//! A [`Sweep`] runs a user-supplied generation pipeline over every
+//! combination of a set of named parameter values and every seed, collecting
+//! [`TerrainStats`] and realism scores (see [`crate::metrics`]) and optionally
+//! the generated heightmaps and downsampled previews of each run. With the
+//! `rayon` feature, runs are executed in parallel.
+//! 
+//! ```
+//! use terr::heightmap::Heightmap;
+//! use terr::sweep::Sweep;
+//! 
+//! let mut sweep = Sweep::new();
+//! sweep.axis("amplitude", &[10.0, 100.0]).seeds(0..3);
+//! let results = sweep.run(&|params, seed| {
+//!     let mut m = Heightmap::new_flat((32, 32), (1000.0, 1000.0));
+//!     let a = params.get("amplitude").unwrap();
+//!     m.map(|h| h + a * (seed as f64));
+//!     m
+//! });
+//! assert_eq!(results.runs.len(), 6);
+//! ```
+
+use std::fmt::Write;
+use crate::RealField;
+use crate::grid::Grid;
+use crate::heightmap::{Heightmap, Interpolation};
+use crate::metrics::{assess, Score, TerrainStats, REFERENCES};
+
+/// Parameter values of one point of a [`Sweep`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Params {
+    values: Vec<(String, f64)>,
+}
+
+impl Params {
+    /// Get the value of the parameter `name`, if it exists
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+    
+    /// Iterate over `(name, value)` pairs, in axis order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.values.iter().map(|(n, v)| (n.as_str(), *v))
+    }
+}
+
+/// A grid of parameter values and seeds to run a pipeline over
+#[derive(Debug, Clone, Default)]
+pub struct Sweep {
+    axes: Vec<(String, Vec<f64>)>,
+    seeds: Vec<u64>,
+    keep_maps: bool,
+    preview: Option<(u32, u32)>,
+}
+
+impl Sweep {
+    /// Construct an empty sweep (with a single seed, 0)
+    pub fn new() -> Self {
+        Sweep { axes: vec![], seeds: vec![0], keep_maps: false, preview: None }
+    }
+    
+    /// Add a parameter axis
+    /// 
+    /// The `name` should be a simple identifier (it is used as a CSV column
+    /// header).
+    pub fn axis(&mut self, name: &str, values: &[f64]) -> &mut Self {
+        assert!(!values.is_empty());
+        self.axes.push((name.to_string(), values.to_vec()));
+        self
+    }
+    
+    /// Set the seeds to run each parameter combination with
+    pub fn seeds<I: IntoIterator<Item = u64>>(&mut self, seeds: I) -> &mut Self {
+        self.seeds = seeds.into_iter().collect();
+        assert!(!self.seeds.is_empty());
+        self
+    }
+    
+    /// Keep the generated heightmaps in the results (default: false)
+    pub fn keep_maps(&mut self, keep: bool) -> &mut Self {
+        self.keep_maps = keep;
+        self
+    }
+    
+    /// Keep previews of the generated heightmaps, resampled to `dim`
+    pub fn preview(&mut self, dim: (u32, u32)) -> &mut Self {
+        self.preview = Some(dim);
+        self
+    }
+    
+    /// Enumerate all parameter combinations (the last axis varying fastest)
+    pub fn points(&self) -> Vec<Params> {
+        let mut points = vec![Params { values: vec![] }];
+        for (name, values) in &self.axes {
+            points = points.into_iter().flat_map(|p| {
+                values.iter().map(move |v| {
+                    let mut p = p.clone();
+                    p.values.push((name.clone(), *v));
+                    p
+                })
+            }).collect();
+        }
+        points
+    }
+    
+    /// Number of runs: parameter combinations × seeds
+    pub fn len(&self) -> usize {
+        self.axes.iter().map(|(_, v)| v.len()).product::<usize>() * self.seeds.len()
+    }
+    
+    /// True if there are no runs (never: at least one seed is required)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Run `generate(params, seed)` for every parameter combination and seed
+    /// 
+    /// With the `rayon` feature, runs are executed in parallel. Results are in
+    /// the order of [`Sweep::points`], with seeds varying fastest.
+    pub fn run<F: RealField>(&self, generate: &(dyn Fn(&Params, u64) -> Heightmap<F> + Sync)) -> SweepResults<F> {
+        trace_span!("sweep", runs = self.len());
+        let jobs: Vec<(Params, u64)> = self.points().into_iter()
+            .flat_map(|p| self.seeds.iter().map(move |s| (p.clone(), *s)))
+            .collect();
+        let run = |(params, seed): (Params, u64)| {
+            let m = generate(&params, seed);
+            let stats = TerrainStats::of(&m);
+            let scores = assess(&stats).into_iter().map(|(r, s)| (r.name, s)).collect();
+            let preview = self.preview.map(|dim| {
+                let p = m.resample(dim, Interpolation::Bilinear);
+                Grid::from_fn(dim, |cx, cy| p.get(cx, cy))
+            });
+            let map = if self.keep_maps { Some(m) } else { None };
+            SweepRun { params, seed, stats, scores, map, preview }
+        };
+        #[cfg(feature = "rayon")]
+        let runs = {
+            use rayon::prelude::*;
+            jobs.into_par_iter().map(run).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let runs = jobs.into_iter().map(run).collect();
+        SweepResults { runs }
+    }
+}
+
+/// The result of one run of a [`Sweep`]
+#[derive(Debug, Clone)]
+pub struct SweepRun<F: RealField> {
+    /// Parameter values
+    pub params: Params,
+    /// Seed
+    pub seed: u64,
+    /// Statistics of the generated heightmap
+    pub stats: TerrainStats,
+    /// Scores against each reference terrain type, best first
+    pub scores: Vec<(&'static str, Score)>,
+    /// The generated heightmap, if [`Sweep::keep_maps`] was enabled
+    pub map: Option<Heightmap<F>>,
+    /// A preview of the heightmap, if [`Sweep::preview`] was set
+    pub preview: Option<Grid<F>>,
+}
+
+impl<F: RealField> SweepRun<F> {
+    /// Get the score against the reference named `name`, if any
+    pub fn score(&self, name: &str) -> Option<Score> {
+        self.scores.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+    }
+}
+
+/// Results of a [`Sweep`]
+#[derive(Debug, Clone)]
+pub struct SweepResults<F: RealField> {
+    /// All runs, in the order of [`Sweep::points`] with seeds varying fastest
+    pub runs: Vec<SweepRun<F>>,
+}
+
+impl<F: RealField> SweepResults<F> {
+    /// Get the run with the highest overall score against the reference
+    /// named `name`
+    pub fn best_for(&self, name: &str) -> Option<&SweepRun<F>> {
+        let overall = |r: &SweepRun<F>| r.score(name).map(|s| s.overall).unwrap_or(0.0);
+        self.runs.iter().fold(None, |best: Option<&SweepRun<F>>, r| match best {
+            Some(b) if overall(b) >= overall(r) => Some(b),
+            _ => Some(r),
+        })
+    }
+    
+    /// Format statistics and scores of all runs as CSV
+    /// 
+    /// Columns are the parameters, the seed, the statistics, the best-matching
+    /// reference and the overall score against each of
+    /// [`REFERENCES`].
+    pub fn to_csv(&self) -> String {
+        let mut s = String::new();
+        let first = match self.runs.first() {
+            Some(r) => r,
+            None => return s,
+        };
+        for (name, _) in first.params.iter() {
+            write!(s, "{},", name).unwrap();
+        }
+        write!(s, "seed,mean_slope,median_slope,p90_slope,hypsometric_integral,drainage_density,best").unwrap();
+        for r in REFERENCES {
+            write!(s, ",{}", r.name).unwrap();
+        }
+        writeln!(s).unwrap();
+        
+        for run in &self.runs {
+            for (_, v) in run.params.iter() {
+                write!(s, "{},", v).unwrap();
+            }
+            let st = &run.stats;
+            write!(s, "{},{},{},{},{},{},{}", run.seed, st.mean_slope, st.median_slope, st.p90_slope,
+                st.hypsometric_integral, st.drainage_density, run.scores[0].0).unwrap();
+            for r in REFERENCES {
+                write!(s, ",{}", run.score(r.name).unwrap().overall).unwrap();
+            }
+            writeln!(s).unwrap();
+        }
+        s
+    }
+    
+    /// Write previews as 16-bit PNGs named `run_<index>.png` to `dir`
+    /// 
+    /// All previews are normalised over the same height range, for
+    /// comparability. Runs without a preview are skipped.
+    #[cfg(feature = "png")]
+    pub fn write_previews(&self, dir: &std::path::Path) -> std::io::Result<()> {
+        let previews = || self.runs.iter().enumerate().filter_map(|(i, r)| r.preview.as_ref().map(|p| (i, p)));
+        let mut range = (F::max_value(), F::min_value());
+        for (_, p) in previews() {
+            for v in p.data() {
+                range = (range.0.min(*v), range.1.max(*v));
+            }
+        }
+        for (i, p) in previews() {
+            crate::io::write_png16(&dir.join(format!("run_{}.png", i)), p, range)?;
+        }
+        Ok(())
+    }
+}
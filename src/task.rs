@@ -0,0 +1,87 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Frame-budgeted incremental work
+//! 
+//! Long-running terrain work may be advanced a little each frame of a game
+//! loop, within a time budget and without spawning threads, via the
+//! [`IncrementalTask`] interface. Implementations:
+//! 
+//! -   [`ErosionTask`](crate::heightmap::ErosionTask): erosion iterations
+//! -   [`MeshTask`](crate::heightmap::MeshTask): meshing a heightmap, a row
+//!     at a time
+//! -   [`LoadTask`](crate::heightmap::LoadTask): generating chunks of a
+//!     [`TiledHeightmap`](crate::heightmap::TiledHeightmap)
+//! 
+//! ```
+//! use std::time::Duration;
+//! use terr::heightmap::Heightmap;
+//! use terr::mesh::UpAxis;
+//! use terr::task::IncrementalTask;
+//! 
+//! let m = Heightmap::new_flat((65, 65), (100.0, 100.0));
+//! let mut task = m.mesh_task(UpAxis::Y);
+//! while !task.run_for(Duration::from_millis(2)).is_complete() {
+//!     // render a frame
+//! }
+//! let mesh = task.into_mesh();
+//! assert_eq!(mesh.num_triangles(), 2 * 64 * 64);
+//! ```
+
+use std::time::{Duration, Instant};
+
+/// Progress of an [`IncrementalTask`], in task-specific units of work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Units of work completed
+    pub done: u64,
+    /// Total units of work
+    pub total: u64,
+}
+
+impl Progress {
+    /// True if all work is complete
+    pub fn is_complete(self) -> bool {
+        self.done >= self.total
+    }
+    
+    /// Fraction of work completed, in `[0, 1]`
+    pub fn fraction(self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done as f64 / self.total as f64
+        }
+    }
+}
+
+/// A task which may be advanced incrementally
+pub trait IncrementalTask {
+    /// Perform work until complete or until `budget` has elapsed
+    /// 
+    /// Work is done in units (e.g. one iteration or row); the budget is
+    /// checked between units, thus may be exceeded by up to one unit. At
+    /// least one unit is done per call (unless already complete), thus
+    /// progress is guaranteed even with a zero budget.
+    fn run_for(&mut self, budget: Duration) -> Progress;
+    
+    /// Get the current progress
+    fn progress(&self) -> Progress;
+    
+    /// Perform all remaining work
+    fn run_to_completion(&mut self) {
+        while !self.run_for(Duration::from_secs(3600)).is_complete() {}
+    }
+}
+
+// Call `step` until it returns false (no more work) or `budget` has elapsed,
+// calling at least once
+pub(crate) fn budgeted<G: FnMut() -> bool>(budget: Duration, mut step: G) {
+    let start = Instant::now();
+    while step() && start.elapsed() < budget {}
+}
@@ -0,0 +1,171 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Terrain management for interactive applications
+//! 
+//! The [`Pager`] decides which chunks of a
+//! [`TiledHeightmap`](crate::heightmap::TiledHeightmap) should be meshed, and
+//! at which level of detail, as the camera moves.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use nalgebra::{convert, RealField, Translation3};
+use crate::heightmap::{Interpolation, TiledHeightmap};
+use crate::mesh::{TerrainMesh, UpAxis};
+
+/// A change in the set of meshed chunks, reported by [`Pager::update`]
+#[derive(Debug, Clone)]
+pub enum PagerEvent<F: RealField> {
+    /// Chunk `chunk` has been meshed (or re-meshed at a new level of detail)
+    /// 
+    /// The mesh is in world coordinates (oriented per [`Pager::up`]) and
+    /// replaces any previous mesh of the chunk.
+    Load {
+        /// The chunk index
+        chunk: (i32, i32),
+        /// The level of detail
+        lod: usize,
+        /// The chunk mesh
+        mesh: TerrainMesh<F>,
+    },
+    /// Chunk `chunk` should no longer be displayed
+    Unload {
+        /// The chunk index
+        chunk: (i32, i32),
+    },
+}
+
+/// Distance-based chunk loading with levels of detail
+/// 
+/// Level of detail `i` meshes every `2^i`-th vertex of a chunk and is used
+/// for chunks within `lod_distances[i]` of the camera (horizontal distance to
+/// the nearest point of the chunk); chunks further than the last distance are
+/// not meshed. For seamless results `chunk_dim - 1` should be divisible by
+/// `2^(lod_distances.len() - 1)`. Meshes have no skirts, thus small cracks
+/// may be visible between chunks of differing detail.
+/// 
+/// Chunks are generated on demand. Chunks which are no longer meshed are
+/// evicted from the [`TiledHeightmap`], so memory usage is bounded by the
+/// view distance.
+#[derive(Debug, Clone)]
+pub struct Pager<F: RealField> {
+    /// Maximum distance for each level of detail, ascending
+    pub lod_distances: Vec<F>,
+    /// Hysteresis distance: a chunk keeps its level of detail (or stays loaded)
+    /// until the camera moves this far past a threshold
+    pub margin: F,
+    /// Maximum number of chunks meshed per update (minimum one); remaining
+    /// chunks are meshed in later updates, nearest first
+    pub max_loads: usize,
+    /// Vertical axis convention of chunk meshes
+    pub up: UpAxis,
+    loaded: HashMap<(i32, i32), usize>,
+}
+
+impl<F: RealField> Pager<F> {
+    /// Construct with the given level-of-detail distances
+    /// 
+    /// Defaults: `margin` is 10% of the first distance; `max_loads` is unlimited;
+    /// `up` is [`UpAxis::Z`].
+    pub fn new(lod_distances: Vec<F>) -> Self {
+        assert!(!lod_distances.is_empty());
+        let margin = lod_distances[0] * convert(0.1);
+        Pager { lod_distances, margin, max_loads: usize::MAX, up: UpAxis::Z, loaded: HashMap::new() }
+    }
+    
+    /// Iterate over `(chunk, lod)` of meshed chunks (in arbitrary order)
+    pub fn loaded(&self) -> impl Iterator<Item = ((i32, i32), usize)> + '_ {
+        self.loaded.iter().map(|(c, lod)| (*c, *lod))
+    }
+    
+    /// Update for a new camera position `(x, y)`
+    /// 
+    /// Returns unload events followed by load events (nearest chunks first).
+    pub fn update(&mut self, tiles: &mut TiledHeightmap<F>, camera: (F, F)) -> Vec<PagerEvent<F>> {
+        let mut events = Vec::new();
+        let (w, h) = tiles.chunk_size();
+        let distance = |c: (i32, i32)| {
+            let x0 = convert::<_, F>(c.0 as f64) * w;
+            let y0 = convert::<_, F>(c.1 as f64) * h;
+            let dx = (x0 - camera.0).max(camera.0 - (x0 + w)).max(F::zero());
+            let dy = (y0 - camera.1).max(camera.1 - (y0 + h)).max(F::zero());
+            (dx * dx + dy * dy).sqrt()
+        };
+        
+        // Evict chunks beyond range
+        let mut evict = Vec::new();
+        for (&c, &lod) in &self.loaded {
+            let d = distance(c);
+            if self.keep(lod, d).is_none() {
+                evict.push(c);
+            }
+        }
+        for c in evict {
+            self.loaded.remove(&c);
+            tiles.unload(c);
+            events.push(PagerEvent::Unload { chunk: c });
+        }
+        
+        // Find chunks requiring (re-)meshing
+        let range = *self.lod_distances.last().unwrap();
+        let c0 = tiles.chunk_at_coord(camera.0 - range, camera.1 - range);
+        let c1 = tiles.chunk_at_coord(camera.0 + range, camera.1 + range);
+        let mut wanted = Vec::new();
+        for j in c0.1..=c1.1 {
+            for i in c0.0..=c1.0 {
+                let c = (i, j);
+                let d = distance(c);
+                let lod = match self.loaded.get(&c) {
+                    Some(&lod) => match self.keep(lod, d) {
+                        Some(new) if new != lod => new,
+                        _ => continue,
+                    },
+                    None => match self.lod_for(d) {
+                        Some(lod) => lod,
+                        None => continue,
+                    },
+                };
+                wanted.push((d, c, lod));
+            }
+        }
+        wanted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        
+        for (_, c, lod) in wanted.into_iter().take(self.max_loads.max(1)) {
+            let origin = tiles.chunk_origin(c);
+            let chunk = tiles.chunk(c);
+            let stride = 1 << lod;
+            let dim = chunk.dim();
+            let dim = (((dim.0 - 1) / stride).max(1) + 1, ((dim.1 - 1) / stride).max(1) + 1);
+            // translate in the Z-up frame, then orient
+            let mut mesh = chunk.resample(dim, Interpolation::Bilinear).to_trimesh(UpAxis::Z);
+            mesh.translate_by(&Translation3::new(origin.0, origin.1, F::zero()));
+            mesh.orient(self.up);
+            self.loaded.insert(c, lod);
+            events.push(PagerEvent::Load { chunk: c, lod, mesh });
+        }
+        events
+    }
+    
+    // Level of detail for distance d, if in range
+    fn lod_for(&self, d: F) -> Option<usize> {
+        self.lod_distances.iter().position(|max| d <= *max)
+    }
+    
+    // Level of detail for a chunk currently at `lod` and distance `d`, if it
+    // should stay loaded: the current level is kept while within the margin
+    fn keep(&self, lod: usize, d: F) -> Option<usize> {
+        let near = self.lod_for(d - self.margin);
+        let far = self.lod_for(d + self.margin);
+        match (near, far) {
+            (None, _) => None,
+            (Some(n), None) if lod >= n => Some(lod),
+            (Some(n), Some(f)) if n <= lod && lod <= f => Some(lod),
+            _ => self.lod_for(d).or(near),
+        }
+    }
+}
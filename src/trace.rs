@@ -0,0 +1,31 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tracing support (`tracing` feature)
+
+use std::time::Instant;
+use tracing::span::{EnteredSpan, Span};
+
+/// An entered span which emits a timing event when dropped
+pub(crate) struct Timed {
+    _span: EnteredSpan,
+    start: Instant,
+}
+
+impl Timed {
+    pub(crate) fn new(span: Span) -> Self {
+        Timed { _span: span.entered(), start: Instant::now() }
+    }
+}
+
+impl Drop for Timed {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        tracing::debug!(elapsed_ms = elapsed.as_secs_f64() * 1e3, "done");
+    }
+}
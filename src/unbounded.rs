@@ -8,9 +8,25 @@
 
 //! This module concerns surfaces represented by a function `h: ℝ² → ℝ`.
 
+mod blend;
+mod cache;
+mod combinators;
+mod cracks;
 mod perlin;
+mod primitives;
+mod profile;
+mod quadtree;
+mod worley;
 
+pub use blend::{BlendCurve, SurfaceBlend};
+pub use cache::CachedSurface;
+pub use combinators::{Curve, Curved, Terrace, Terraced};
+pub use cracks::Cracks;
 pub use perlin::{Perlin, PerlinError};
+pub use profile::{detail_profile, DetailProfile};
+pub use primitives::{Cone, Dome, Dunes, Plane, Ridge};
+pub use quadtree::SurfaceQuadtree;
+pub use worley::{Worley, WorleyMode};
 
 use crate::RealField;
 
@@ -18,8 +34,50 @@ use crate::RealField;
 pub trait UnboundedSurface<F: RealField> {
     /// Determine the height of the terrain at the given coordinate.
     fn get(&self, x: F, y: F) -> F;
+    
+    /// Determine the height and gradient `[∂h/∂x, ∂h/∂y]` at the given
+    /// coordinate.
+    /// 
+    /// The default implementation uses central finite differences; surfaces
+    /// should override this with an analytic gradient where possible.
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let eps = F::default_epsilon().sqrt();
+        let hx = eps * x.abs().max(F::one());
+        let hy = eps * y.abs().max(F::one());
+        let dx = (self.get(x + hx, y) - self.get(x - hx, y)) / (hx + hx);
+        let dy = (self.get(x, y + hy) - self.get(x, y - hy)) / (hy + hy);
+        (self.get(x, y), [dx, dy])
+    }
 }
 
+impl<F: RealField, S: UnboundedSurface<F> + ?Sized> UnboundedSurface<F> for &S {
+    fn get(&self, x: F, y: F) -> F {
+        (**self).get(x, y)
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        (**self).get_with_gradient(x, y)
+    }
+}
+
+impl<F: RealField, S: UnboundedSurface<F> + ?Sized> UnboundedSurface<F> for Box<S> {
+    fn get(&self, x: F, y: F) -> F {
+        (**self).get(x, y)
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        (**self).get_with_gradient(x, y)
+    }
+}
+
+/// A boxed surface
+/// 
+/// Boxed surfaces are `Send + Sync`, thus composed surfaces (e.g.
+/// [`SurfaceBlend`], [`SurfaceQuadtree`], the source of a
+/// [`TiledHeightmap`](crate::heightmap::TiledHeightmap)) may be shared between
+/// worker threads.
+pub type BoxedSurface<F> = Box<dyn UnboundedSurface<F> + Send + Sync>;
+
 
 /// An infinite, flat surface.
 #[derive(Debug, Clone, Copy, Default)]
@@ -36,4 +94,74 @@ impl<F: RealField> UnboundedSurface<F> for Flat<F> {
     fn get(&self, _: F, _: F) -> F {
         self.0
     }
+    
+    fn get_with_gradient(&self, _: F, _: F) -> (F, [F; 2]) {
+        (self.0, [F::zero(), F::zero()])
+    }
+}
+
+/// A surface defined by a function `f(x, y)`
+/// 
+/// This allows closures to be used where a surface is expected, e.g. as
+/// masks or warps.
+#[derive(Clone, Copy)]
+pub struct FnSurface<G>(G);
+
+impl<G> FnSurface<G> {
+    /// Construct
+    pub fn new(f: G) -> Self {
+        FnSurface(f)
+    }
+}
+
+impl<G> std::fmt::Debug for FnSurface<G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("FnSurface")
+    }
+}
+
+impl<F: RealField, G: Fn(F, F) -> F> UnboundedSurface<F> for FnSurface<G> {
+    fn get(&self, x: F, y: F) -> F {
+        (self.0)(x, y)
+    }
+}
+
+
+// Hash a lattice index to a pseudo-random value (derived from PCG)
+#[inline]
+pub(crate) fn hash(mut x: u64) -> u32 {
+    x = x.wrapping_mul(14647171131086947261);
+    let rot = (x >> 59) as u32;
+    let xsh = (((x >> 18) ^ x) >> 27) as u32;
+    xsh.rotate_right(rot)
+}
+
+// Surfaces must be shareable between worker threads: check that all surface
+// types are `Send + Sync` (given `Send + Sync` components). Consumers which
+// may sample in parallel (`Heightmap::from_surface`, `add_surface` and
+// `add_surface_masked`) require `Sync` whether or not `rayon` is enabled.
+#[allow(dead_code)]
+fn assert_send_sync<F: RealField>() {
+    fn check<T: Send + Sync>() {}
+    check::<BoxedSurface<F>>();
+    check::<CachedSurface<F, Flat<F>>>();
+    check::<Cone<F>>();
+    check::<Cracks<F>>();
+    check::<Curve<F>>();
+    check::<Curved<F, Flat<F>>>();
+    check::<DetailProfile<F>>();
+    check::<Dome<F>>();
+    check::<Dunes<F>>();
+    check::<Flat<F>>();
+    check::<FnSurface<fn(F, F) -> F>>();
+    check::<Perlin<F>>();
+    check::<Plane<F>>();
+    check::<Ridge<F>>();
+    check::<SurfaceBlend<F>>();
+    check::<SurfaceQuadtree<F>>();
+    check::<Terrace<F>>();
+    check::<Terraced<F, Flat<F>>>();
+    check::<Worley<F>>();
+    check::<crate::heightmap::HeightmapSurface<F>>();
+    check::<crate::heightmap::TiledHeightmap<F>>();
 }
@@ -0,0 +1,144 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use nalgebra::convert;
+use super::{BoxedSurface, Curve, RealField, UnboundedSurface};
+
+/// Transfer function from mask value to blend weight
+/// 
+/// Weights are clamped to `[0, 1]` after applying the curve.
+#[derive(Debug, Clone)]
+pub enum BlendCurve<F> {
+    /// The mask value is used directly
+    Linear,
+    /// Smoothstep of the mask value over `[0, 1]`
+    Smooth,
+    /// A smoothed threshold: weight rises from 0 to 1 (smoothstep) as the mask
+    /// increases from `threshold - width / 2` to `threshold + width / 2`
+    Threshold {
+        /// Mask value at which the weight is one half
+        threshold: F,
+        /// Width of the transition (zero for a hard edge)
+        width: F,
+    },
+    /// An arbitrary curve
+    Curve(Curve<F>),
+}
+
+impl<F: RealField> BlendCurve<F> {
+    /// Apply to a mask value, giving a weight in `[0, 1]`
+    pub fn apply(&self, m: F) -> F {
+        let smoothstep = |t: F| {
+            let t = t.max(F::zero()).min(F::one());
+            t * t * (convert::<_, F>(3.0) - t * convert(2.0))
+        };
+        let w = match self {
+            BlendCurve::Linear => m,
+            BlendCurve::Smooth => smoothstep(m),
+            BlendCurve::Threshold { threshold, width } => {
+                if *width <= F::zero() {
+                    if m >= *threshold { F::one() } else { F::zero() }
+                } else {
+                    smoothstep((m - *threshold) / *width + convert(0.5))
+                }
+            }
+            BlendCurve::Curve(curve) => curve.apply(m),
+        };
+        w.max(F::zero()).min(F::one())
+    }
+}
+
+/// A stack of surfaces blended by masks
+/// 
+/// Starting from a base surface, each layer covers the result below it with
+/// weight given by its mask (passed through a [`BlendCurve`]): a layer with
+/// weight 1 fully replaces everything below, while weight 0 leaves it
+/// untouched. Weights are thus normalised: the weight of layer `i` is
+/// `w_i × (1 - w_{i+1}) × … × (1 - w_n)`, and the base takes the remainder.
+/// Surfaces with zero weight are not evaluated.
+/// 
+/// Masks may be any surface, e.g. noise for irregular biome boundaries or a
+/// [`FnSurface`](super::FnSurface) for explicit weight functions.
+/// 
+/// ```
+/// # use terr::unbounded::*;
+/// let mut blend = SurfaceBlend::new(Box::new(Flat::new(0.0)));
+/// let mask = FnSurface::new(|x: f64, _| x / 100.0);
+/// blend.push(Box::new(Flat::new(10.0)), Box::new(mask), BlendCurve::Linear);
+/// assert_eq!(blend.get(25.0, 0.0), 2.5);
+/// assert_eq!(blend.get(200.0, 0.0), 10.0);
+/// ```
+pub struct SurfaceBlend<F: RealField> {
+    base: BoxedSurface<F>,
+    layers: Vec<(BoxedSurface<F>, BoxedSurface<F>, BlendCurve<F>)>,
+}
+
+impl<F: RealField> fmt::Debug for SurfaceBlend<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SurfaceBlend")
+            .field("layers", &self.layers.len())
+            .field("curves", &self.layers.iter().map(|l| &l.2).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<F: RealField> SurfaceBlend<F> {
+    /// Construct with a base surface
+    pub fn new(base: BoxedSurface<F>) -> Self {
+        SurfaceBlend { base, layers: vec![] }
+    }
+    
+    /// Add a layer on top of the stack
+    pub fn push(&mut self, surface: BoxedSurface<F>, mask: BoxedSurface<F>, curve: BlendCurve<F>) -> &mut Self {
+        self.layers.push((surface, mask, curve));
+        self
+    }
+    
+    /// Number of surfaces, including the base
+    pub fn len(&self) -> usize {
+        self.layers.len() + 1
+    }
+    
+    /// True if there are no layers above the base
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+    
+    /// Get the normalised weight of each surface at `(x, y)`
+    /// 
+    /// Index 0 is the base; the weights sum to 1.
+    pub fn weights(&self, x: F, y: F) -> Vec<F> {
+        let mut weights = vec![F::zero(); self.len()];
+        let mut remaining = F::one();
+        for (i, (_, mask, curve)) in self.layers.iter().enumerate().rev() {
+            if remaining <= F::zero() {
+                break;
+            }
+            let w = curve.apply(mask.get(x, y));
+            weights[i + 1] = remaining * w;
+            remaining *= F::one() - w;
+        }
+        weights[0] = remaining;
+        weights
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for SurfaceBlend<F> {
+    fn get(&self, x: F, y: F) -> F {
+        let weights = self.weights(x, y);
+        let mut h = F::zero();
+        for (i, w) in weights.into_iter().enumerate() {
+            if w > F::zero() {
+                let surface = if i == 0 { &self.base } else { &self.layers[i - 1].0 };
+                h += w * surface.get(x, y);
+            }
+        }
+        h
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use nalgebra::{convert, try_convert};
+use super::{RealField, UnboundedSurface};
+use crate::memory::MemoryUsage;
+
+// Maximum number of independently locked shards
+const SHARDS: usize = 16;
+
+/// A surface memoizing tiles of another surface
+/// 
+/// The wrapped surface is sampled on a lattice of the given `spacing`, in
+/// square tiles of `tile_cells × tile_cells` cells, and interpolated
+/// bilinearly. Up to approximately `capacity` tiles are kept, evicting the
+/// least recently used. This speeds up repeated sampling of expensive surfaces
+/// (e.g. deep fBm with warping) at the cost of approximation between lattice
+/// points; `spacing` should thus be no larger than the finest detail of
+/// interest (e.g. the vertex spacing of the heightmaps or meshes being built).
+/// 
+/// The cache may be shared between threads: tiles are distributed over
+/// several independently locked shards, tiles are evaluated without holding
+/// any lock and statistics are updated atomically, thus worker threads
+/// sampling different regions rarely contend.
+#[derive(Debug)]
+pub struct CachedSurface<F: RealField, S> {
+    surface: S,
+    spacing: F,
+    tile_cells: u32,
+    shard_capacity: usize,
+    shards: Vec<Mutex<Cache<F>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// A tile's samples and the clock value of its last use
+type Entry<F> = (Arc<Vec<F>>, u64);
+
+#[derive(Debug)]
+struct Cache<F> {
+    tiles: HashMap<(i64, i64), Entry<F>>,
+    clock: u64,
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> CachedSurface<F, S> {
+    /// Construct
+    /// 
+    /// Requires `spacing > 0`, `tile_cells > 0` and `capacity > 0`.
+    pub fn new(surface: S, spacing: F, tile_cells: u32, capacity: usize) -> Self {
+        assert!(spacing > F::zero() && tile_cells > 0 && capacity > 0);
+        let n = capacity.min(SHARDS);
+        let shards = (0..n).map(|_| Mutex::new(Cache { tiles: HashMap::new(), clock: 0 })).collect();
+        CachedSurface {
+            surface, spacing, tile_cells,
+            shard_capacity: capacity.div_ceil(n),
+            shards,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+    
+    /// Access the wrapped surface
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+    
+    /// Number of tiles currently cached
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.lock(i).tiles.len()).sum()
+    }
+    
+    /// True if no tiles are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Get the number of `(hits, misses)` of tile lookups so far
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+    
+    /// Discard all cached tiles and reset statistics
+    pub fn clear(&self) {
+        for i in 0..self.shards.len() {
+            self.lock(i).tiles.clear();
+        }
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+    
+    fn lock(&self, shard: usize) -> MutexGuard<'_, Cache<F>> {
+        // a panic while holding the lock cannot leave the cache inconsistent
+        self.shards[shard].lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    // Shard holding tile `t`
+    fn shard(&self, t: (i64, i64)) -> usize {
+        let h = (t.0 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (t.1 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        ((h >> 32) as usize) % self.shards.len()
+    }
+    
+    // Get tile `t`, evaluating if not cached
+    fn tile(&self, t: (i64, i64)) -> Arc<Vec<F>> {
+        let shard = self.shard(t);
+        {
+            let mut cache = self.lock(shard);
+            cache.clock += 1;
+            let clock = cache.clock;
+            if let Some(entry) = cache.tiles.get_mut(&t) {
+                entry.1 = clock;
+                let tile = entry.0.clone();
+                drop(cache);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return tile;
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        
+        let n = self.tile_cells as i64;
+        let side = n as usize + 1;
+        let mut data = Vec::with_capacity(side * side);
+        for j in 0..=n {
+            let y = convert::<_, F>((t.1 * n + j) as f64) * self.spacing;
+            for i in 0..=n {
+                let x = convert::<_, F>((t.0 * n + i) as f64) * self.spacing;
+                data.push(self.surface.get(x, y));
+            }
+        }
+        let tile = Arc::new(data);
+        
+        let mut cache = self.lock(shard);
+        if cache.tiles.len() >= self.shard_capacity && !cache.tiles.contains_key(&t) {
+            let oldest = cache.tiles.iter().min_by_key(|(_, v)| v.1).map(|(k, _)| *k);
+            if let Some(k) = oldest {
+                cache.tiles.remove(&k);
+            }
+        }
+        let clock = cache.clock;
+        cache.tiles.insert(t, (tile.clone(), clock));
+        tile
+    }
+}
+
+/// Counts cached tiles and the wrapped surface's inline size (not any heap
+/// memory it owns)
+impl<F: RealField, S: UnboundedSurface<F>> MemoryUsage for CachedSurface<F, S> {
+    fn memory_usage(&self) -> usize {
+        let side = self.tile_cells as usize + 1;
+        // samples, the shared Vec (with Arc counters) and the map entry
+        let tile = side * side * size_of::<F>() + size_of::<Vec<F>>() + 2 * size_of::<usize>()
+            + size_of::<((i64, i64), Entry<F>)>();
+        size_of::<Self>() + self.shards.len() * size_of::<Mutex<Cache<F>>>() + self.len() * tile
+    }
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> UnboundedSurface<F> for CachedSurface<F, S> {
+    fn get(&self, x: F, y: F) -> F {
+        let (fx, fy) = ((x / self.spacing).floor(), (y / self.spacing).floor());
+        let (tx, ty) = (x / self.spacing - fx, y / self.spacing - fy);
+        let to_i64 = |v: F| try_convert::<_, f64>(v).unwrap() as i64;
+        let (cx, cy) = (to_i64(fx), to_i64(fy));
+        let n = self.tile_cells as i64;
+        let t = (cx.div_euclid(n), cy.div_euclid(n));
+        let (i, j) = (cx.rem_euclid(n) as usize, cy.rem_euclid(n) as usize);
+        let tile = self.tile(t);
+        let side = n as usize + 1;
+        let at = |i: usize, j: usize| tile[i + j * side];
+        let h0 = at(i, j) + (at(i + 1, j) - at(i, j)) * tx;
+        let h1 = at(i, j + 1) + (at(i + 1, j + 1) - at(i, j + 1)) * tx;
+        h0 + (h1 - h0) * ty
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::RealField;
+use crate::unbounded::UnboundedSurface;
+use nalgebra::{convert, try_convert};
+
+/// A terracing height transform
+/// 
+/// Heights in the range `min..max` are quantised to `levels` steps. Each step
+/// consists of a flat tread followed by a ramp up to the next level; the ramp
+/// takes the fraction `ramp` (in `(0, 1]`) of the step. With `ramp = 1` the
+/// transform is the identity; small values give near-vertical risers.
+/// 
+/// Heights outside `min..max` continue the same pattern.
+/// 
+/// This may be applied to a surface via [`Terraced`] or to a heightmap via
+/// [`Heightmap::terrace`](crate::heightmap::Heightmap::terrace).
+#[derive(Debug, Clone, Copy)]
+pub struct Terrace<F> {
+    min: F,
+    step: F,
+    ramp: F,
+}
+
+impl<F: RealField> Terrace<F> {
+    /// Construct
+    /// 
+    /// Requires `levels > 0`, `min < max` and `0 < ramp <= 1`.
+    pub fn new(min: F, max: F, levels: u32, ramp: F) -> Self {
+        assert!(levels > 0);
+        assert!(min < max);
+        assert!(F::zero() < ramp && ramp <= F::one());
+        let step = (max - min) / convert(levels as f64);
+        Terrace { min, step, ramp }
+    }
+    
+    /// Apply to a single height
+    pub fn apply(&self, h: F) -> F {
+        self.apply_with_derivative(h).0
+    }
+    
+    // Apply, also returning the derivative with respect to `h`
+    fn apply_with_derivative(&self, h: F) -> (F, F) {
+        let rel = (h - self.min) / self.step;
+        let k = rel.floor();
+        let t = rel - k;
+        let u = (t - (F::one() - self.ramp)) / self.ramp;
+        if u > F::zero() {
+            (self.min + (k + u) * self.step, F::one() / self.ramp)
+        } else {
+            (self.min + k * self.step, F::zero())
+        }
+    }
+    
+    /// Get the step index of a height (`0` for the lowest level in range)
+    pub fn level_of(&self, h: F) -> i64 {
+        try_convert::<_, f64>(((h - self.min) / self.step).floor()).unwrap() as i64
+    }
+}
+
+/// A terraced surface
+/// 
+/// Applies a [`Terrace`] transform to the output of another surface.
+#[derive(Debug, Clone)]
+pub struct Terraced<F, S> {
+    surface: S,
+    terrace: Terrace<F>,
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> Terraced<F, S> {
+    /// Construct
+    pub fn new(surface: S, terrace: Terrace<F>) -> Self {
+        Terraced { surface, terrace }
+    }
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> UnboundedSurface<F> for Terraced<F, S> {
+    fn get(&self, x: F, y: F) -> F {
+        self.terrace.apply(self.surface.get(x, y))
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (h, g) = self.surface.get_with_gradient(x, y);
+        let (h, d) = self.terrace.apply_with_derivative(h);
+        (h, [g[0] * d, g[1] * d])
+    }
+}
+
+/// A height transfer function
+/// 
+/// The curve is defined by control points `(input, output)`, sorted by input,
+/// and is interpolated either linearly or by a monotone cubic spline (which
+/// passes through all control points without overshooting). Inputs outside
+/// the range of control points map to the output of the first or last point.
+/// 
+/// For example, the points `[(0, 0), (0.5, 0.1), (1, 1)]` flatten valleys and
+/// exaggerate peaks of a surface with heights in `0..1`.
+/// 
+/// This may be applied to a surface via [`Curved`] or to a heightmap via
+/// [`Heightmap::remap`](crate::heightmap::Heightmap::remap).
+#[derive(Debug, Clone)]
+pub struct Curve<F> {
+    points: Vec<(F, F)>,
+    // tangent at each point; empty for linear interpolation
+    tangents: Vec<F>,
+}
+
+impl<F: RealField> Curve<F> {
+    /// Construct a piecewise-linear curve
+    /// 
+    /// Requires at least one point; inputs must be strictly increasing.
+    pub fn linear(points: Vec<(F, F)>) -> Self {
+        assert!(!points.is_empty());
+        assert!(points.windows(2).all(|w| w[0].0 < w[1].0));
+        Curve { points, tangents: vec![] }
+    }
+    
+    /// Construct a smooth curve (monotone cubic spline)
+    /// 
+    /// Requires at least one point; inputs must be strictly increasing.
+    pub fn spline(points: Vec<(F, F)>) -> Self {
+        let mut curve = Curve::linear(points);
+        let p = &curve.points;
+        let n = p.len();
+        if n < 2 {
+            return curve;
+        }
+        
+        // Fritsch–Carlson: start with secant averages, then limit tangents
+        // to preserve monotonicity on each interval.
+        let secant: Vec<F> = p.windows(2)
+            .map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0))
+            .collect();
+        let mut t = Vec::with_capacity(n);
+        t.push(secant[0]);
+        for i in 1..n - 1 {
+            if secant[i - 1] * secant[i] <= F::zero() {
+                t.push(F::zero());
+            } else {
+                t.push((secant[i - 1] + secant[i]) * convert(0.5));
+            }
+        }
+        t.push(secant[n - 2]);
+        let three: F = convert(3.0);
+        for (i, d) in secant.iter().enumerate() {
+            if *d == F::zero() {
+                t[i] = F::zero();
+                t[i + 1] = F::zero();
+                continue;
+            }
+            let (a, b) = (t[i] / *d, t[i + 1] / *d);
+            let s = a * a + b * b;
+            if s > three * three {
+                let tau = three / s.sqrt();
+                t[i] = tau * a * *d;
+                t[i + 1] = tau * b * *d;
+            }
+        }
+        curve.tangents = t;
+        curve
+    }
+    
+    /// Apply to a single height
+    pub fn apply(&self, h: F) -> F {
+        self.apply_with_derivative(h).0
+    }
+    
+    // Apply, also returning the derivative with respect to `h`
+    fn apply_with_derivative(&self, h: F) -> (F, F) {
+        let p = &self.points;
+        let n = p.len();
+        if h <= p[0].0 {
+            return (p[0].1, F::zero());
+        } else if h >= p[n - 1].0 {
+            return (p[n - 1].1, F::zero());
+        }
+        // index of the first point with input greater than h; 1 <= i < n
+        let i = p.iter().position(|q| q.0 > h).unwrap();
+        let (a, b) = (p[i - 1], p[i]);
+        let dx = b.0 - a.0;
+        let t = (h - a.0) / dx;
+        if self.tangents.is_empty() {
+            return (a.1 + (b.1 - a.1) * t, (b.1 - a.1) / dx);
+        }
+        // cubic Hermite basis
+        let (two, three, four, six): (F, F, F, F) = (convert(2.0), convert(3.0), convert(4.0), convert(6.0));
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let h00 = two * t3 - three * t2 + F::one();
+        let h10 = t3 - two * t2 + t;
+        let h01 = three * t2 - two * t3;
+        let h11 = t3 - t2;
+        let (ma, mb) = (dx * self.tangents[i - 1], dx * self.tangents[i]);
+        let value = h00 * a.1 + h10 * ma + h01 * b.1 + h11 * mb;
+        // derivatives of the basis with respect to t
+        let d00 = six * (t2 - t);
+        let d10 = three * t2 - four * t + F::one();
+        let d11 = three * t2 - two * t;
+        (value, (d00 * (a.1 - b.1) + d10 * ma + d11 * mb) / dx)
+    }
+}
+
+/// A surface remapped through a [`Curve`]
+#[derive(Debug, Clone)]
+pub struct Curved<F, S> {
+    surface: S,
+    curve: Curve<F>,
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> Curved<F, S> {
+    /// Construct
+    pub fn new(surface: S, curve: Curve<F>) -> Self {
+        Curved { surface, curve }
+    }
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> UnboundedSurface<F> for Curved<F, S> {
+    fn get(&self, x: F, y: F) -> F {
+        self.curve.apply(self.surface.get(x, y))
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (h, g) = self.surface.get_with_gradient(x, y);
+        let (h, d) = self.curve.apply_with_derivative(h);
+        (h, [g[0] * d, g[1] * d])
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::RealField;
+use crate::grid::Grid;
+use crate::unbounded::{UnboundedSurface, Worley, WorleyMode};
+use nalgebra::{convert, Vector3};
+use rand::Rng;
+
+/// Polygonal crack patterns, as formed by frost or thermal contraction
+/// 
+/// Cracks follow the edges of Worley cells (a Voronoi tessellation with one
+/// cell per `cell_size` square, on average), forming the polygonal networks of
+/// dry lakebeds, permafrost and ice sheets. Within `width` of a cell edge the
+/// height is `-depth * (1 - e / width)²` where `e` is the approximate distance
+/// to the edge; elsewhere it is zero. A positive `depth` gives valleys
+/// (cracks); a negative `depth` gives ridges (e.g. pressure ridges).
+/// 
+/// This is a high-frequency detail: apply it to selected regions with
+/// [`Heightmap::add_surface_masked`](crate::heightmap::Heightmap::add_surface_masked),
+/// at mesh generation time via [`MicroDetail`](crate::mesh::MicroDetail), or
+/// bake it to a detail texture ([`Cracks::height_texture`],
+/// [`Cracks::normal_texture`]).
+#[derive(Debug, Clone)]
+pub struct Cracks<F: RealField> {
+    worley: Worley<F>,
+    cell_size: F,
+    width: F,
+    depth: F,
+}
+
+impl<F: RealField> Cracks<F> {
+    /// Construct a crack pattern
+    pub fn new<R: Rng + ?Sized>(cell_size: F, width: F, depth: F, rng: &mut R) -> Self {
+        assert!(cell_size > F::zero() && width > F::zero());
+        let worley = Worley::new(F::one() / cell_size, WorleyMode::F2MinusF1, rng);
+        Cracks { worley, cell_size, width, depth }
+    }
+    
+    /// Approximate distance from `(x, y)` to the nearest crack centre line
+    pub fn edge_distance(&self, x: F, y: F) -> F {
+        let (d1, d2) = self.worley.distances(x, y);
+        (d2 - d1) * self.cell_size * convert(0.5)
+    }
+    
+    /// Sample heights over the rectangle from `start` to `start + size`
+    /// 
+    /// Texels are placed as the vertices of a heightmap: the first and last
+    /// lie on the edges of the rectangle, thus adjacent textures share edges.
+    pub fn height_texture(&self, start: (F, F), size: (F, F), dim: (u32, u32)) -> Grid<F> {
+        let step = texel_step(size, dim);
+        Grid::from_fn(dim, |ix, iy| {
+            let (x, y): (F, F) = (convert(ix as f64), convert(iy as f64));
+            self.get(start.0 + x * step.0, start.1 + y * step.1)
+        })
+    }
+    
+    /// Sample unit normals (Z-up) over the rectangle from `start` to
+    /// `start + size`
+    /// 
+    /// These are the normals of the crack detail alone, for use as a
+    /// tangent-space detail normal map. Texels are placed as for
+    /// [`Cracks::height_texture`].
+    pub fn normal_texture(&self, start: (F, F), size: (F, F), dim: (u32, u32)) -> Grid<Vector3<F>> {
+        let step = texel_step(size, dim);
+        Grid::from_fn(dim, |ix, iy| {
+            let (x, y): (F, F) = (convert(ix as f64), convert(iy as f64));
+            let (_, g) = self.get_with_gradient(start.0 + x * step.0, start.1 + y * step.1);
+            Vector3::new(-g[0], -g[1], F::one()).normalize()
+        })
+    }
+}
+
+fn texel_step<F: RealField>(size: (F, F), dim: (u32, u32)) -> (F, F) {
+    assert!(dim.0 >= 2 && dim.1 >= 2);
+    (size.0 / convert((dim.0 - 1) as f64), size.1 / convert((dim.1 - 1) as f64))
+}
+
+impl<F: RealField> UnboundedSurface<F> for Cracks<F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.get_with_gradient(x, y).0
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let ((d1, g1), (d2, g2)) = self.worley.distances_with_gradients(x, y);
+        let half: F = convert(0.5);
+        let e = (d2 - d1) * self.cell_size * half;
+        if e >= self.width {
+            return (F::zero(), [F::zero(); 2]);
+        }
+        let t = F::one() - e / self.width;
+        // dh/de, then chain through e
+        let dh = self.depth * (t + t) / self.width * self.cell_size * half;
+        (-self.depth * t * t, [dh * (g2[0] - g1[0]), dh * (g2[1] - g1[1])])
+    }
+}
@@ -7,16 +7,17 @@
 // except according to those terms.
 
 use crate::RealField;
-use crate::unbounded::UnboundedSurface;
+use crate::unbounded::{UnboundedSurface, hash};
 use nalgebra::try_convert;
 
 
 /// A Perlin noise generator
 #[derive(Debug, Clone)]
 pub struct Perlin<F: RealField> {
-    scale: F,
-    mask: u32,
-    gradient: Vec<[F; 2]>,  // random unit gradient vectors
+    pub(crate) scale: F,
+    pub(crate) mask: u32,
+    pub(crate) gradient: Vec<[F; 2]>,  // random unit gradient vectors
+    pub(crate) period: Option<(i64, i64)>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -53,12 +54,30 @@ impl<F: RealField> Perlin<F> {
             .map(|_| sampler())
             .collect::<Vec<[F; 2]>>();
         
-        Ok(Perlin { scale, mask: (n - 1) as u32, gradient })
+        Ok(Perlin { scale, mask: (n - 1) as u32, gradient, period: None })
+    }
+    
+    /// Make the noise periodic
+    /// 
+    /// Lattice coordinates are wrapped modulo `period`, thus the noise repeats
+    /// every `period.0 / scale` units along the x-axis and `period.1 / scale`
+    /// along the y-axis. For example, a heightmap of size `(s, s)` sampled
+    /// from noise with `scale = k / s` and `period = (k, k)` tiles seamlessly.
+    /// 
+    /// Both periods must be positive.
+    pub fn with_period(mut self, period: (u32, u32)) -> Self {
+        assert!(period.0 > 0 && period.1 > 0);
+        self.period = Some((period.0 as i64, period.1 as i64));
+        self
     }
 }
 
 impl<F: RealField> UnboundedSurface<F> for Perlin<F> {
     fn get(&self, x: F, y: F) -> F {
+        self.get_with_gradient(x, y).0
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
         let p = (x * self.scale, y * self.scale);
         let p0 = (p.0.floor(), p.1.floor());
         let p1 = (p0.0 + F::one(), p0.1 + F::one());
@@ -67,40 +86,51 @@ impl<F: RealField> UnboundedSurface<F> for Perlin<F> {
         let r1 = (p.0 - p1.0, p.1 - p1.1);
         
         // Get four random indices. This is probably overkill.
-        let to_u64 = |x| -> u64 { try_convert::<_, f64>(x).unwrap() as u64 };
-        let i00 = (to_u64(p0.0)).wrapping_add(to_u64(p0.1) << 32);
-        let i01 = i00.wrapping_add(0x1);
-        let i10 = i00.wrapping_add(0x1_0000_0000);
-        let i11 = i00.wrapping_add(0x1_0000_0001);
+        let to_i64 = |x| -> i64 { try_convert::<_, f64>(x).unwrap() as i64 };
+        let (ix, iy) = (to_i64(p0.0), to_i64(p0.1));
+        let wrap = |x: i64, y: i64| match self.period {
+            Some((px, py)) => (x.rem_euclid(px), y.rem_euclid(py)),
+            None => (x, y),
+        };
         // TODO: use SIMD
         let m = self.mask;
-        let hash = |mut x: u64| {
-            // derived from PCG
-            x = x.wrapping_mul(14647171131086947261);
-            let rot = (x >> 59) as u32;
-            let xsh = (((x >> 18) ^ x) >> 27) as u32;
-            (xsh.rotate_right(rot) & m) as usize
-        };
-        let i00 = hash(i00);
-        let i01 = hash(i01);
-        let i10 = hash(i10);
-        let i11 = hash(i11);
+        let hash = |(x, y): (i64, i64)| (hash((x as u64).wrapping_add((y as u64) << 32)) & m) as usize;
+        let g00 = self.gradient[hash(wrap(ix, iy))];
+        let g01 = self.gradient[hash(wrap(ix + 1, iy))];
+        let g10 = self.gradient[hash(wrap(ix, iy + 1))];
+        let g11 = self.gradient[hash(wrap(ix + 1, iy + 1))];
         
-        let s = |x| x*x*(F::from_f32(3.0).unwrap() - F::from_f32(2.0).unwrap() * x);
+        let (two, three): (F, F) = (F::from_f32(2.0).unwrap(), F::from_f32(3.0).unwrap());
+        let s = |x| x*x*(three - two * x);
+        let ds = |x| (three + three) * x * (F::one() - x);
         let s0 = s(r0.0);
         let s1 = s(r0.1);
         
         let lerp = |t, a, b| a + t * (b - a);
         let dp = |u: (F, F), v: [F; 2]| u.0 * v[0] + u.1 * v[1];
         
-        let u = dp(r0, self.gradient[i00]);
-        let v = dp((r1.0, r0.1), self.gradient[i01]);
+        // The gradient of each corner's dot product is its gradient vector.
+        let u = dp(r0, g00);
+        let v = dp((r1.0, r0.1), g01);
         let a = lerp(s0, u, v);
+        let da = [
+            lerp(s0, g00[0], g01[0]) + ds(r0.0) * (v - u),
+            lerp(s0, g00[1], g01[1]),
+        ];
         
-        let u = dp((r0.0, r1.1), self.gradient[i10]);
-        let v = dp(r1, self.gradient[i11]);
+        let u = dp((r0.0, r1.1), g10);
+        let v = dp(r1, g11);
         let b = lerp(s0, u, v);
+        let db = [
+            lerp(s0, g10[0], g11[0]) + ds(r0.0) * (v - u),
+            lerp(s0, g10[1], g11[1]),
+        ];
         
-        lerp(s1, a, b)
+        let h = lerp(s1, a, b);
+        let dh = [
+            lerp(s1, da[0], db[0]) * self.scale,
+            (lerp(s1, da[1], db[1]) + ds(r0.1) * (b - a)) * self.scale,
+        ];
+        (h, dh)
     }
 }
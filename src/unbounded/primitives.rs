@@ -0,0 +1,195 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::RealField;
+use crate::unbounded::UnboundedSurface;
+
+/// A tilted plane
+/// 
+/// This and the other analytic primitives ([`Dome`], [`Cone`], [`Ridge`],
+/// [`Dunes`]) have exact gradients and are intended for art-directing
+/// large-scale structure, combined with noise via
+/// [`SurfaceBlend`](super::SurfaceBlend) or heightmap layers.
+#[derive(Debug, Clone, Copy)]
+pub struct Plane<F: RealField> {
+    origin: (F, F),
+    height: F,
+    gradient: (F, F),
+}
+
+impl<F: RealField> Plane<F> {
+    /// Construct with the given `height` at `origin` and constant `gradient`
+    /// `(∂h/∂x, ∂h/∂y)`
+    pub fn new(origin: (F, F), height: F, gradient: (F, F)) -> Self {
+        Plane { origin, height, gradient }
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Plane<F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.height + self.gradient.0 * (x - self.origin.0) + self.gradient.1 * (y - self.origin.1)
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        (self.get(x, y), [self.gradient.0, self.gradient.1])
+    }
+}
+
+/// A Gaussian dome
+/// 
+/// The height is `height × exp(-r² / (2 σ²))` at distance `r` from the centre.
+#[derive(Debug, Clone, Copy)]
+pub struct Dome<F: RealField> {
+    centre: (F, F),
+    height: F,
+    sigma: F,
+}
+
+impl<F: RealField> Dome<F> {
+    /// Construct with the given peak `height` and standard deviation `sigma`
+    pub fn new(centre: (F, F), height: F, sigma: F) -> Self {
+        assert!(sigma > F::zero());
+        Dome { centre, height, sigma }
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Dome<F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.get_with_gradient(x, y).0
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+        let s2 = self.sigma * self.sigma;
+        let h = self.height * (-(dx * dx + dy * dy) / (s2 + s2)).exp();
+        (h, [-h * dx / s2, -h * dy / s2])
+    }
+}
+
+/// A cone
+/// 
+/// The height falls linearly from `height` at the centre to zero at `radius`,
+/// and is zero beyond.
+#[derive(Debug, Clone, Copy)]
+pub struct Cone<F: RealField> {
+    centre: (F, F),
+    height: F,
+    radius: F,
+}
+
+impl<F: RealField> Cone<F> {
+    /// Construct
+    pub fn new(centre: (F, F), height: F, radius: F) -> Self {
+        assert!(radius > F::zero());
+        Cone { centre, height, radius }
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Cone<F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.get_with_gradient(x, y).0
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+        let r = (dx * dx + dy * dy).sqrt();
+        tent(self.height, self.radius, r, dx, dy)
+    }
+}
+
+/// A linear ridge
+/// 
+/// The crest runs along the segment from `a` to `b` at `height`; the flanks
+/// fall linearly to zero at horizontal distance `half_width` from the crest
+/// (with rounded ends beyond the segment).
+#[derive(Debug, Clone, Copy)]
+pub struct Ridge<F: RealField> {
+    a: (F, F),
+    b: (F, F),
+    height: F,
+    half_width: F,
+}
+
+impl<F: RealField> Ridge<F> {
+    /// Construct
+    pub fn new(a: (F, F), b: (F, F), height: F, half_width: F) -> Self {
+        assert!(half_width > F::zero());
+        Ridge { a, b, height, half_width }
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Ridge<F> {
+    fn get(&self, x: F, y: F) -> F {
+        self.get_with_gradient(x, y).0
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let (ux, uy) = (self.b.0 - self.a.0, self.b.1 - self.a.1);
+        let len2 = ux * ux + uy * uy;
+        let t = if len2 > F::zero() {
+            (((x - self.a.0) * ux + (y - self.a.1) * uy) / len2).max(F::zero()).min(F::one())
+        } else {
+            F::zero()
+        };
+        let (dx, dy) = (x - (self.a.0 + ux * t), y - (self.a.1 + uy * t));
+        let d = (dx * dx + dy * dy).sqrt();
+        tent(self.height, self.half_width, d, dx, dy)
+    }
+}
+
+/// Sine dunes
+/// 
+/// Parallel crests of height `amplitude` (above and below zero) repeating
+/// every `wavelength` along `direction` (an angle in radians from the x-axis):
+/// `amplitude × sin(2π s / wavelength + phase)` where `s` is the distance
+/// along `direction`.
+#[derive(Debug, Clone, Copy)]
+pub struct Dunes<F: RealField> {
+    dir: (F, F),
+    k: F,
+    amplitude: F,
+    phase: F,
+}
+
+impl<F: RealField> Dunes<F> {
+    /// Construct
+    pub fn new(direction: F, wavelength: F, amplitude: F, phase: F) -> Self {
+        assert!(wavelength > F::zero());
+        let k = F::two_pi() / wavelength;
+        Dunes { dir: (direction.cos(), direction.sin()), k, amplitude, phase }
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Dunes<F> {
+    fn get(&self, x: F, y: F) -> F {
+        let s = x * self.dir.0 + y * self.dir.1;
+        self.amplitude * (self.k * s + self.phase).sin()
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let s = x * self.dir.0 + y * self.dir.1;
+        let (sin, cos) = (self.k * s + self.phase).sin_cos();
+        let g = self.amplitude * self.k * cos;
+        (self.amplitude * sin, [g * self.dir.0, g * self.dir.1])
+    }
+}
+
+// Linear falloff from `height` at distance 0 to zero at `radius`, given the
+// offset (dx, dy) of length d from the nearest crest point
+fn tent<F: RealField>(height: F, radius: F, d: F, dx: F, dy: F) -> (F, [F; 2]) {
+    if d >= radius {
+        return (F::zero(), [F::zero(), F::zero()]);
+    }
+    let h = height * (F::one() - d / radius);
+    if d > F::zero() {
+        let g = -height / (radius * d);
+        (h, [g * dx, g * dy])
+    } else {
+        (h, [F::zero(), F::zero()])
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nalgebra::convert;
+use super::{RealField, UnboundedSurface};
+use crate::grid::Grid;
+
+/// Detail added by successive sampling resolutions of a surface
+/// 
+/// See [`detail_profile`].
+#[derive(Debug, Clone)]
+pub struct DetailProfile<F> {
+    /// Sample spacing `(x, y)` of each level
+    pub spacing: Vec<(F, F)>,
+    /// Root-mean-square detail added at each level (zero at level 0)
+    pub rms: Vec<F>,
+    /// Maximum detail added at each level (zero at level 0)
+    pub max: Vec<F>,
+    /// Maximum detail added at each level within each cell of the level-0
+    /// lattice (zero at level 0)
+    pub cells: Vec<Grid<F>>,
+}
+
+impl<F: RealField> DetailProfile<F> {
+    /// Number of levels
+    pub fn levels(&self) -> usize {
+        self.spacing.len()
+    }
+    
+    /// Get the coarsest level beyond which no level adds detail exceeding
+    /// `tolerance`
+    /// 
+    /// Returns `None` if the finest level still adds such detail, i.e. more
+    /// levels are needed to determine the required resolution.
+    pub fn required_level(&self, tolerance: F) -> Option<usize> {
+        let last = self.max.iter().rposition(|d| *d > tolerance).unwrap_or(0);
+        if last + 1 < self.levels() || self.levels() == 1 { Some(last) } else { None }
+    }
+    
+    /// As [`DetailProfile::required_level`], but for each level-0 cell
+    /// 
+    /// Cells where the finest level adds detail exceeding `tolerance` are
+    /// given `levels()`.
+    pub fn level_map(&self, tolerance: F) -> Grid<u32> {
+        let dim = self.cells[0].dim();
+        Grid::from_fn(dim, |cx, cy| {
+            let last = self.cells.iter().rposition(|g| g.get(cx, cy) > tolerance).unwrap_or(0);
+            if last + 1 < self.levels() { last as u32 } else { self.levels() as u32 }
+        })
+    }
+}
+
+/// Measure how much detail each sampling resolution adds to a surface
+/// 
+/// The surface is sampled over the rectangle from `start` spanning `size`,
+/// first on a lattice of `dim` samples (level 0), then at `levels - 1`
+/// successively doubled resolutions. The detail added by each level is the
+/// difference between its samples and a bilinear interpolation of the
+/// previous level.
+/// 
+/// Where added detail falls below a tolerance (e.g. a fraction of the
+/// vertical precision needed), further resolution is wasted; this guides the
+/// choice of heightmap resolution, and of octave counts (octaves finer than
+/// the required spacing contribute nothing visible). Level `l` requires
+/// `((dim - 1) × 2^l + 1)²` samples.
+pub fn detail_profile<F: RealField, S: UnboundedSurface<F>>(surface: &S, start: (F, F), size: (F, F),
+    dim: (u32, u32), levels: u32) -> DetailProfile<F>
+{
+    assert!(dim.0 >= 2 && dim.1 >= 2 && levels >= 1);
+    let cells = (dim.0 - 1, dim.1 - 1);
+    let sample = |d: (u32, u32)| {
+        let step = (size.0 / convert((d.0 - 1) as f64), size.1 / convert((d.1 - 1) as f64));
+        let grid = Grid::from_fn(d, |cx, cy| {
+            surface.get(start.0 + step.0 * convert(cx as f64), start.1 + step.1 * convert(cy as f64))
+        });
+        (grid, step)
+    };
+    
+    let (mut prev, step) = sample(dim);
+    let mut profile = DetailProfile {
+        spacing: vec![step],
+        rms: vec![F::zero()],
+        max: vec![F::zero()],
+        cells: vec![Grid::new(cells, F::zero())],
+    };
+    for level in 1..levels {
+        let k = 1u32 << level;
+        let d = (cells.0 * k + 1, cells.1 * k + 1);
+        let (grid, step) = sample(d);
+        let mut local = Grid::new(cells, F::zero());
+        let (mut sum2, mut max) = (F::zero(), F::zero());
+        let half: F = convert(0.5);
+        for cy in 0..d.1 {
+            for cx in 0..d.0 {
+                // position in the previous level's lattice: integer or half-way
+                let (px, py) = (cx / 2, cy / 2);
+                let (ox, oy) = (cx % 2 == 1, cy % 2 == 1);
+                let at = |x: u32, y: u32| prev.get(x, y);
+                let coarse = match (ox, oy) {
+                    (false, false) => at(px, py),
+                    (true, false) => (at(px, py) + at(px + 1, py)) * half,
+                    (false, true) => (at(px, py) + at(px, py + 1)) * half,
+                    (true, true) => (at(px, py) + at(px + 1, py) + at(px, py + 1) + at(px + 1, py + 1))
+                        * half * half,
+                };
+                let diff = (grid.get(cx, cy) - coarse).abs();
+                sum2 += diff * diff;
+                max = max.max(diff);
+                let c = ((cx / k).min(cells.0 - 1), (cy / k).min(cells.1 - 1));
+                if diff > local.get(c.0, c.1) {
+                    local.set(c.0, c.1, diff);
+                }
+            }
+        }
+        let n: F = convert(d.0 as f64 * d.1 as f64);
+        profile.spacing.push(step);
+        profile.rms.push((sum2 / n).sqrt());
+        profile.max.push(max);
+        profile.cells.push(local);
+        prev = grid;
+    }
+    profile
+}
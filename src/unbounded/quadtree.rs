@@ -0,0 +1,151 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use nalgebra::convert;
+use super::{BoxedSurface, RealField, UnboundedSurface};
+
+/// A surface composed of different surfaces over the regions of a quadtree
+/// 
+/// The square from `origin` to `origin + (size, size)` is recursively
+/// subdivided into quadrants, each leaf being assigned one of a list of
+/// surfaces. Near region boundaries, surfaces are cross-faded over a band of
+/// width `blend` (smoothstep weights, normalised). Beyond the bounds of the
+/// tree the nearest region applies.
+/// 
+/// For example, one quadrant of a continent may use desert noise while
+/// another uses alpine noise; a sub-quadrant of the latter may differ again.
+/// 
+/// ```
+/// # use terr::unbounded::*;
+/// let mut tree = SurfaceQuadtree::new((0.0, 0.0), 1000.0, Box::new(Flat::new(0.0)), 50.0);
+/// let hills = tree.add_surface(Box::new(Flat::new(10.0)));
+/// tree.assign(1, (1, 0), hills);  // the quadrant with x ≥ 500, y < 500
+/// assert_eq!(tree.get(750.0, 250.0), 10.0);
+/// assert_eq!(tree.get(500.0, 250.0), 5.0);
+/// ```
+pub struct SurfaceQuadtree<F: RealField> {
+    origin: (F, F),
+    size: F,
+    blend: F,
+    surfaces: Vec<BoxedSurface<F>>,
+    root: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf(usize),
+    // Children in order (x0, y0), (x1, y0), (x0, y1), (x1, y1)
+    Split(Box<[Node; 4]>),
+}
+
+impl<F: RealField> fmt::Debug for SurfaceQuadtree<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SurfaceQuadtree")
+            .field("origin", &self.origin)
+            .field("size", &self.size)
+            .field("blend", &self.blend)
+            .field("surfaces", &self.surfaces.len())
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl<F: RealField> SurfaceQuadtree<F> {
+    /// Construct, with the whole area assigned to `default`
+    /// 
+    /// The `default` surface has index 0.
+    pub fn new(origin: (F, F), size: F, default: BoxedSurface<F>, blend: F) -> Self {
+        SurfaceQuadtree { origin, size, blend, surfaces: vec![default], root: Node::Leaf(0) }
+    }
+    
+    /// Add a surface, returning its index
+    pub fn add_surface(&mut self, surface: BoxedSurface<F>) -> usize {
+        self.surfaces.push(surface);
+        self.surfaces.len() - 1
+    }
+    
+    /// Assign surface `index` to a region
+    /// 
+    /// At `depth` the tree has `2^depth × 2^depth` cells; `cell` is the index
+    /// `(i, j)` of a cell at this depth, with `i` along the x-axis. Any
+    /// existing subdivision of the cell is replaced.
+    pub fn assign(&mut self, depth: u32, cell: (u32, u32), index: usize) {
+        assert!(index < self.surfaces.len());
+        assert!(cell.0 < (1 << depth) && cell.1 < (1 << depth));
+        let mut node = &mut self.root;
+        for level in (0..depth).rev() {
+            if let Node::Leaf(i) = *node {
+                let leaf = || Node::Leaf(i);
+                *node = Node::Split(Box::new([leaf(), leaf(), leaf(), leaf()]));
+            }
+            let q = ((cell.0 >> level) & 1) + 2 * ((cell.1 >> level) & 1);
+            node = match node {
+                Node::Split(children) => &mut children[q as usize],
+                Node::Leaf(_) => unreachable!(),
+            };
+        }
+        *node = Node::Leaf(index);
+    }
+    
+    // Visit leaves within blend/2 of (x, y), passing the surface index and weight
+    fn visit<G: FnMut(usize, F)>(&self, node: &Node, min: (F, F), size: F, x: F, y: F, f: &mut G) {
+        let half = self.blend * convert(0.5);
+        if x < min.0 - half || x > min.0 + size + half || y < min.1 - half || y > min.1 + size + half {
+            return;
+        }
+        match node {
+            Node::Leaf(i) => {
+                let wx = self.weight(x, min.0, size, self.origin.0);
+                let wy = self.weight(y, min.1, size, self.origin.1);
+                let w = wx * wy;
+                if w > F::zero() {
+                    f(*i, w);
+                }
+            }
+            Node::Split(children) => {
+                let s = size * convert(0.5);
+                for (q, child) in children.iter().enumerate() {
+                    let cx = if q & 1 == 0 { min.0 } else { min.0 + s };
+                    let cy = if q & 2 == 0 { min.1 } else { min.1 + s };
+                    self.visit(child, (cx, cy), s, x, y, f);
+                }
+            }
+        }
+    }
+    
+    // Weight along one axis of the interval [lo, lo + size] at t; edges on the
+    // tree boundary extend to infinity
+    fn weight(&self, t: F, lo: F, size: F, origin: F) -> F {
+        let hi = lo + size;
+        let ramp = |d: F| {
+            if self.blend <= F::zero() {
+                return if d >= F::zero() { F::one() } else { F::zero() };
+            }
+            let u = (d / self.blend + convert(0.5)).max(F::zero()).min(F::one());
+            u * u * (convert::<_, F>(3.0) - u * convert(2.0))
+        };
+        let low = if lo <= origin { F::one() } else { ramp(t - lo) };
+        let high = if hi >= origin + self.size { F::one() } else { ramp(hi - t) };
+        low * high
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for SurfaceQuadtree<F> {
+    fn get(&self, x: F, y: F) -> F {
+        // Weights are clamped to the tree bounds
+        let cx = x.max(self.origin.0).min(self.origin.0 + self.size);
+        let cy = y.max(self.origin.1).min(self.origin.1 + self.size);
+        let (mut sum, mut total) = (F::zero(), F::zero());
+        self.visit(&self.root, self.origin, self.size, cx, cy, &mut |i, w| {
+            sum += w * self.surfaces[i].get(x, y);
+            total += w;
+        });
+        sum / total
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::RealField;
+use crate::unbounded::{UnboundedSurface, hash};
+use nalgebra::{convert, try_convert};
+use rand::Rng;
+
+// Number of feature points in the table (must be a power of 2)
+const TABLE_LEN: usize = 256;
+
+// Cell offsets of the 5×5 neighbourhood, inner 3×3 first
+const OFFSETS: [(i64, i64); 25] = [
+    (0, 0), (-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1),
+    (-2, -2), (-1, -2), (0, -2), (1, -2), (2, -2), (-2, -1), (2, -1), (-2, 0),
+    (2, 0), (-2, 1), (2, 1), (-2, 2), (-1, 2), (0, 2), (1, 2), (2, 2),
+];
+
+/// Output of a [`Worley`] noise generator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorleyMode {
+    /// Distance to the closest feature point: cells with rounded floors
+    F1,
+    /// Distance to the second-closest feature point
+    F2,
+    /// Difference `F2 - F1`: zero on cell boundaries, producing ridges when
+    /// negated
+    F2MinusF1,
+}
+
+/// A Worley (cellular) noise generator
+/// 
+/// One feature point is placed at a pseudo-random position within each unit
+/// cell of the (scaled) plane; the output is derived from the distances to the
+/// closest feature points (see [`WorleyMode`]). Unlike
+/// [`heightmap::Voronoi`](crate::heightmap::Voronoi), this is unbounded.
+/// 
+/// Distances are measured in scaled units, thus `F1` lies in the range
+/// `[0, √2)`.
+#[derive(Debug, Clone)]
+pub struct Worley<F: RealField> {
+    scale: F,
+    mode: WorleyMode,
+    points: Vec<[F; 2]>,    // random offsets within a unit cell
+}
+
+impl<F: RealField> Worley<F> {
+    /// Construct a Worley noise generator
+    /// 
+    /// The spatial scale can be adjusted via the `scale` parameter. Each
+    /// coordinate is first multiplied by `scale` when sampling.
+    pub fn new<R: Rng + ?Sized>(scale: F, mode: WorleyMode, rng: &mut R) -> Self {
+        let points = (0..TABLE_LEN)
+            .map(|_| [convert(rng.gen::<f64>()), convert(rng.gen::<f64>())])
+            .collect();
+        Worley { scale, mode, points }
+    }
+    
+    /// Get the distances `(F1, F2)` to the two closest feature points
+    pub fn distances(&self, x: F, y: F) -> (F, F) {
+        let (d1, d2) = self.distances_with_gradients(x, y);
+        (d1.0, d2.0)
+    }
+    
+    // Distances to the two closest feature points, each with its gradient
+    // with respect to `(x, y)`
+    pub(crate) fn distances_with_gradients(&self, x: F, y: F) -> ((F, [F; 2]), (F, [F; 2])) {
+        let p = (x * self.scale, y * self.scale);
+        let to_i64 = |x| -> i64 { try_convert::<_, f64>(x).unwrap() as i64 };
+        let c = (to_i64(p.0.floor()), to_i64(p.1.floor()));
+        
+        let mut d1 = (F::max_value(), [F::zero(); 2]);
+        let mut d2 = d1;
+        // F2 < 2, thus both points lie within the 5×5 neighbourhood. Search
+        // the inner 3×3 first, then cells of the outer ring which may hold a
+        // point closer than the current second-closest.
+        let gap = |v: F, c: i64| {
+            let c: F = convert(c as f64);
+            (c - v).max(v - c - F::one()).max(F::zero())
+        };
+        for &(dx, dy) in OFFSETS.iter() {
+            let cx = c.0 + dx;
+            let cy = c.1 + dy;
+            let (gx, gy) = (gap(p.0, cx), gap(p.1, cy));
+            if gx * gx + gy * gy >= d2.0 * d2.0 {
+                continue;
+            }
+            let i = (cx as u64).wrapping_add((cy as u64) << 32);
+            let q = self.points[hash(i) as usize & (TABLE_LEN - 1)];
+            let qx = convert::<_, F>(cx as f64) + q[0];
+            let qy = convert::<_, F>(cy as f64) + q[1];
+            let d = ((qx - p.0).powi(2) + (qy - p.1).powi(2)).sqrt();
+            let g = if d > F::zero() {
+                [(p.0 - qx) / d * self.scale, (p.1 - qy) / d * self.scale]
+            } else {
+                [F::zero(); 2]
+            };
+            if d < d1.0 {
+                d2 = d1;
+                d1 = (d, g);
+            } else if d < d2.0 {
+                d2 = (d, g);
+            }
+        }
+        (d1, d2)
+    }
+}
+
+impl<F: RealField> UnboundedSurface<F> for Worley<F> {
+    fn get(&self, x: F, y: F) -> F {
+        let (d1, d2) = self.distances(x, y);
+        match self.mode {
+            WorleyMode::F1 => d1,
+            WorleyMode::F2 => d2,
+            WorleyMode::F2MinusF1 => d2 - d1,
+        }
+    }
+    
+    fn get_with_gradient(&self, x: F, y: F) -> (F, [F; 2]) {
+        let ((d1, g1), (d2, g2)) = self.distances_with_gradients(x, y);
+        match self.mode {
+            WorleyMode::F1 => (d1, g1),
+            WorleyMode::F2 => (d2, g2),
+            WorleyMode::F2MinusF1 => (d2 - d1, [g2[0] - g1[0], g2[1] - g1[1]]),
+        }
+    }
+}
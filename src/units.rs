@@ -0,0 +1,73 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Units of length
+//! 
+//! This library does not fix a unit of length, however generators and
+//! simulations with physical parameters (e.g. erosion, fluids) assume that
+//! horizontal and vertical units are the same, and importers convert to metres
+//! where the units are known. Use [`Heightmap::convert_units`] to convert data
+//! stored in other units, and [`exaggerate`](crate::mesh::exaggerate) to scale
+//! heights for visualisation only.
+//! 
+//! [`Heightmap::convert_units`]: crate::heightmap::Heightmap::convert_units
+
+/// A unit of length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    /// Metre
+    Metre,
+    /// Kilometre
+    Kilometre,
+    /// Centimetre (e.g. Unreal Engine)
+    Centimetre,
+    /// International foot
+    Foot,
+    /// US survey foot (some US state plane coordinate systems)
+    UsSurveyFoot,
+    /// A custom unit of the given length in metres
+    Custom(f64),
+}
+
+impl Unit {
+    /// Get the length of the unit in metres
+    pub fn metres(self) -> f64 {
+        match self {
+            Unit::Metre => 1.0,
+            Unit::Kilometre => 1000.0,
+            Unit::Centimetre => 0.01,
+            Unit::Foot => 0.3048,
+            Unit::UsSurveyFoot => 1200.0 / 3937.0,
+            Unit::Custom(m) => m,
+        }
+    }
+    
+    /// Get the factor converting a length in units of `self` to units of `to`
+    pub fn factor_to(self, to: Unit) -> f64 {
+        self.metres() / to.metres()
+    }
+}
+
+/// Horizontal and vertical units of a heightmap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Units {
+    /// Unit of the horizontal size
+    pub horizontal: Unit,
+    /// Unit of heights
+    pub vertical: Unit,
+}
+
+impl Units {
+    /// Metres horizontally and vertically
+    pub const METRES: Units = Units { horizontal: Unit::Metre, vertical: Unit::Metre };
+    
+    /// Construct
+    pub fn new(horizontal: Unit, vertical: Unit) -> Self {
+        Units { horizontal, vertical }
+    }
+}
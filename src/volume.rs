@@ -0,0 +1,109 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module concerns volumes represented by a density function
+//! `μ: ℝ³ → ℝ`.
+//! 
+//! By convention, positive density is solid and negative density is empty;
+//! the surface is the level set `μ = 0`. Unlike heightfields, this can express
+//! caves and overhangs.
+
+use crate::RealField;
+use crate::unbounded::{PerlinError, UnboundedSurface, hash};
+use nalgebra::try_convert;
+
+/// A map of 3D coordinate to density
+pub trait Density<F: RealField> {
+    /// Determine the density at the given coordinate.
+    fn density(&self, x: F, y: F, z: F) -> F;
+}
+
+impl<F: RealField, D: Density<F> + ?Sized> Density<F> for &D {
+    fn density(&self, x: F, y: F, z: F) -> F {
+        (**self).density(x, y, z)
+    }
+}
+
+
+/// The volume below a surface
+/// 
+/// Density is the height of the surface above the point, `h(x, y) - z`.
+#[derive(Debug, Clone)]
+pub struct BelowSurface<S>(S);
+
+impl<S> BelowSurface<S> {
+    /// Construct
+    pub fn new(surface: S) -> Self {
+        BelowSurface(surface)
+    }
+}
+
+impl<F: RealField, S: UnboundedSurface<F>> Density<F> for BelowSurface<S> {
+    fn density(&self, x: F, y: F, z: F) -> F {
+        self.0.get(x, y) - z
+    }
+}
+
+
+/// A 3D Perlin noise generator
+/// 
+/// This is the 3D analogue of [`Perlin`](crate::unbounded::Perlin).
+#[derive(Debug, Clone)]
+pub struct Perlin3<F: RealField> {
+    scale: F,
+    mask: u32,
+    gradient: Vec<[F; 3]>,  // random gradient vectors
+}
+
+impl<F: RealField> Perlin3<F> {
+    /// Construct a 3D Perlin noise generator
+    /// 
+    /// Each coordinate is first multiplied by `scale` when sampling. A fixed
+    /// number of gradients, `n`, is sampled immediately via `sampler`, e.g.
+    /// `UnitSphere.sample(rng)`. `n` must be a power of 2.
+    pub fn new<S: FnMut() -> [F; 3]>(scale: F, n: usize, mut sampler: S) -> Result<Self, PerlinError> {
+        if n != 2usize.pow(n.trailing_zeros()) {
+            return Err(PerlinError::NotPowerOf2);
+        }
+        
+        let gradient = (0..n)
+            .map(|_| sampler())
+            .collect::<Vec<[F; 3]>>();
+        
+        Ok(Perlin3 { scale, mask: (n - 1) as u32, gradient })
+    }
+}
+
+impl<F: RealField> Density<F> for Perlin3<F> {
+    fn density(&self, x: F, y: F, z: F) -> F {
+        let p = [x * self.scale, y * self.scale, z * self.scale];
+        let p0 = [p[0].floor(), p[1].floor(), p[2].floor()];
+        let r = [p[0] - p0[0], p[1] - p0[1], p[2] - p0[2]];
+        
+        let to_i64 = |x| -> i64 { try_convert::<_, f64>(x).unwrap() as i64 };
+        let i = [to_i64(p0[0]), to_i64(p0[1]), to_i64(p0[2])];
+        let m = self.mask;
+        // Contribution of the corner at offset (dx, dy, dz)
+        let corner = |dx: i64, dy: i64, dz: i64| {
+            let key = ((i[0] + dx) as u64)
+                .wrapping_add(((i[1] + dy) as u64) << 21)
+                .wrapping_add(((i[2] + dz) as u64) << 42);
+            let g = self.gradient[(hash(key) & m) as usize];
+            let d = |k: usize, o: i64| if o == 0 { r[k] } else { r[k] - F::one() };
+            d(0, dx) * g[0] + d(1, dy) * g[1] + d(2, dz) * g[2]
+        };
+        
+        let s = |x| x*x*(F::from_f32(3.0).unwrap() - F::from_f32(2.0).unwrap() * x);
+        let (sx, sy, sz) = (s(r[0]), s(r[1]), s(r[2]));
+        let lerp = |t, a, b| a + t * (b - a);
+        
+        let a = lerp(sy, lerp(sx, corner(0, 0, 0), corner(1, 0, 0)), lerp(sx, corner(0, 1, 0), corner(1, 1, 0)));
+        let b = lerp(sy, lerp(sx, corner(0, 0, 1), corner(1, 0, 1)), lerp(sx, corner(0, 1, 1), corner(1, 1, 1)));
+        lerp(sz, a, b)
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Serialisation of erosion sessions
+
+use terr::grid::Grid;
+use terr::heightmap::{ErosionParams, ErosionSession, Heightmap};
+
+fn session() -> ErosionSession<f64> {
+    let mut m = Heightmap::<f64>::new_flat((24, 17), (23.0, 32.0));
+    for cy in 0..17 {
+        for cx in 0..24 {
+            let (x, y) = m.coord_of(cx, cy);
+            m.set(cx, cy, (x * 0.3).sin() * 4.0 + (x * y * 0.05).cos() * 2.0 + y * 0.2);
+        }
+    }
+    let params = ErosionParams { rain: 0.01, capacity: 0.5, solubility: 0.1, deposition: 0.2, evaporation: 0.05 };
+    let mut session = ErosionSession::new(m, params);
+    session.set_erodibility(Some(Grid::from_fn((24, 17), |cx, _| if cx < 12 { 0.5 } else { 1.5 })));
+    session
+}
+
+#[test]
+fn resume() {
+    let mut a = session();
+    a.run(10);
+    let mut buf = Vec::new();
+    a.write(&mut buf).unwrap();
+    let mut b = ErosionSession::<f64>::read(&buf[..]).unwrap();
+    assert_eq!(b.iterations(), 10);
+    assert_eq!(b.params(), a.params());
+    assert_eq!(b.erodibility(), a.erodibility());
+    
+    // a resumed session continues exactly as an uninterrupted one
+    a.run(15);
+    b.run(15);
+    assert_eq!(b.iterations(), 25);
+    assert_eq!(b.terrain(), a.terrain());
+    assert_eq!(b.water(), a.water());
+    assert_eq!(b.sediment(), a.sediment());
+    assert_eq!(b.velocity(), a.velocity());
+}
+
+#[test]
+fn invalid() {
+    let mut buf = Vec::new();
+    session().write(&mut buf).unwrap();
+    
+    // a huge dimension must fail on the truncated data, not on allocation
+    let mut huge = buf.clone();
+    huge[8..16].copy_from_slice(&[0xFF; 8]);
+    assert!(ErosionSession::<f64>::read(&huge[..]).is_err());
+    
+    let mut zero = buf.clone();
+    zero[8..12].copy_from_slice(&0u32.to_le_bytes());
+    let err = ErosionSession::<f64>::read(&zero[..]).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    
+    assert!(ErosionSession::<f64>::read(&buf[..buf.len() - 1]).is_err());
+}
@@ -0,0 +1,46 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Round trips through the engine export formats and [`load`]
+
+use std::fs;
+use std::path::PathBuf;
+use terr::grid::Grid;
+use terr::heightmap::Heightmap;
+use terr::io::{load, write_unreal_tiles};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("terr-test-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn unreal_tiles() {
+    // 2×3 tiles of 5 vertices: no resampling is needed
+    let grid = Grid::from_fn((9, 13), |cx, cy| 120.0 + 35.5 * (cx as f64 * 0.7).sin() - 2.5 * cy as f64);
+    let m = Heightmap::from_grid(grid, (16.0, 24.0));
+    let dir = temp_dir("unreal");
+    write_unreal_tiles(&dir, "land", &m, 5).unwrap();
+    
+    let range = m.range();
+    let eps = (range.1 - range.0) / 65535.0;
+    for &(i, j) in &[(0, 0), (1, 2)] {
+        let (t, meta) = load::<f64>(&dir.join(format!("land_x{}_y{}.png", i, j))).unwrap();
+        assert!(!meta.normalised && !meta.unit_spacing);
+        assert_eq!(t.dim(), (5, 5));
+        assert!((t.size().0 - 8.0).abs() < 1e-9 && (t.size().1 - 8.0).abs() < 1e-9, "{:?}", t.size());
+        for cy in 0..5 {
+            for cx in 0..5 {
+                let h = m.get(4 * i + cx, 4 * j + cy);
+                assert!((t.get(cx, cy) - h).abs() <= eps, "tile ({}, {}): {} vs {}", i, j, t.get(cx, cy), h);
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
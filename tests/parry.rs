@@ -0,0 +1,62 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parry ray-casting against heightmaps, compared with `Heightmap::ray_hit`
+
+use parry3d::na::{Point3, Vector3};
+use parry3d::query::{Ray, RayCast};
+use parry3d::shape::FeatureId;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use terr::heightmap::{self, Heightmap};
+
+fn bumpy(dim: (u32, u32), size: (f32, f32)) -> Heightmap<f32> {
+    let mut m = Heightmap::new_flat(dim, size);
+    for cy in 0..dim.1 {
+        for cx in 0..dim.0 {
+            let (x, y) = m.coord_of(cx, cy);
+            m.set(cx, cy, (x * 0.7).sin() * 2.0 + (y * 0.4).cos() * 3.0 + x * 0.1 + (x * y * 0.3).sin());
+        }
+    }
+    m
+}
+
+#[test]
+fn raycast() {
+    let m = bumpy((20, 14), (19.0, 26.0));
+    let n = 2 * 19 * 13;
+    let mut rng = StdRng::seed_from_u64(4);
+    let mut num_hits = 0;
+    for _ in 0..1000 {
+        let o = (rng.gen_range(-10.0, 30.0), rng.gen_range(-10.0, 35.0), rng.gen_range(-20.0, 20.0));
+        let d = (rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+        let ray = Ray::new(Point3::new(o.0, o.1, o.2), Vector3::new(d.0, d.1, d.2));
+        let local = heightmap::Ray::new(nalgebra::Point3::new(o.0, o.1, o.2), nalgebra::Vector3::new(d.0, d.1, d.2));
+        let inter = m.cast_local_ray_and_get_normal(&ray, 50.0, true);
+        match (inter, m.ray_hit(&local, 50.0)) {
+            (Some(inter), Some(hit)) => {
+                num_hits += 1;
+                assert!((inter.time_of_impact - hit.toi).abs() < 1e-4, "{:?}: {:?} vs {:?}", ray, inter, hit);
+                let normal = Vector3::new(hit.normal.x, hit.normal.y, hit.normal.z);
+                assert!((inter.normal - normal).norm() < 1e-4);
+                let face = if hit.from_below { hit.triangle + n } else { hit.triangle };
+                assert_eq!(inter.feature, FeatureId::Face(face as u32));
+            }
+            (None, None) => {}
+            (a, b) => panic!("{:?}: {:?} vs {:?}", ray, a, b),
+        }
+    }
+    assert!(num_hits > 50, "{} hits", num_hits);
+    
+    // translated and rotated
+    let iso = parry3d::na::Isometry3::new(Vector3::new(3.0, -2.0, 1.0), Vector3::new(0.3, -0.2, 1.1));
+    let ray = Ray::new(Point3::new(4.0, 5.0, 50.0), Vector3::new(0.0, 0.0, -1.0));
+    let hit = m.cast_local_ray(&ray, f32::MAX, true).unwrap();
+    let world = ray.transform_by(&iso);
+    assert!((m.cast_ray(&iso, &world, f32::MAX, true).unwrap() - hit).abs() < 1e-4);
+}
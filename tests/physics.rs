@@ -0,0 +1,45 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Agreement of rapier colliders with heightmap meshes
+
+use rapier3d::na::Point3;
+use rapier3d::parry::query::PointQuery;
+use terr::heightmap::Heightmap;
+use terr::mesh::UpAxis;
+
+fn bumpy(dim: (u32, u32), size: (f32, f32)) -> Heightmap<f32> {
+    let mut m = Heightmap::new_flat(dim, size);
+    for cy in 0..dim.1 {
+        for cx in 0..dim.0 {
+            let (x, y) = m.coord_of(cx, cy);
+            // the product term makes cells non-planar, so that the
+            // triangulation matters
+            m.set(cx, cy, (x * 0.7).sin() * 2.0 + (y * 0.4).cos() * 3.0 + x * 0.1 + (x * y * 0.3).sin());
+        }
+    }
+    m
+}
+
+#[test]
+fn collider_matches_mesh() {
+    // Non-square cells and an asymmetric surface: any mirroring, offset or
+    // mismatched triangulation moves points off the collider
+    let m = bumpy((13, 9), (12.0, 20.0));
+    let collider = m.to_rapier_collider().build();
+    let mesh = m.to_trimesh(UpAxis::Y);
+    for tri in &mesh.indices {
+        let (a, b, c) = (mesh.positions[tri.x as usize], mesh.positions[tri.y as usize], mesh.positions[tri.z as usize]);
+        let centroid = (a.coords + b.coords + c.coords) / 3.0;
+        // mesh points use the crate's nalgebra version
+        for p in &[Point3::new(a.x, a.y, a.z), Point3::new(centroid.x, centroid.y, centroid.z)] {
+            let dist = collider.shape().distance_to_point(collider.position(), p, false);
+            assert!(dist < 1e-4, "{:?}: distance {}", p, dist);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ray-casting against heightmaps, compared with brute-force tests of every
+//! triangle
+
+use nalgebra::{Isometry3, Point3, Vector3};
+use ncollide3d::query::{Ray, RayCast};
+use ncollide3d::shape::Triangle;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use terr::heightmap::Heightmap;
+
+const EPS: f64 = 1e-9;
+
+fn bumpy(dim: (u32, u32), size: (f64, f64)) -> Heightmap<f64> {
+    let mut m = Heightmap::new_flat(dim, size);
+    for cy in 0..dim.1 {
+        for cx in 0..dim.0 {
+            let (x, y) = m.coord_of(cx, cy);
+            m.set(cx, cy, (x * 0.7).sin() * 2.0 + (y * 0.4).cos() * 3.0 + x * 0.1);
+        }
+    }
+    m
+}
+
+// First time of impact over all triangles
+fn brute_force(m: &Heightmap<f64>, iso: &Isometry3<f64>, ray: &Ray<f64>) -> Option<f64> {
+    let dim = m.dim();
+    let point = |cx, cy| {
+        let (x, y) = m.coord_of(cx, cy);
+        Point3::new(x, y, m.get(cx, cy))
+    };
+    let mut best: Option<f64> = None;
+    for cy in 0..dim.1 - 1 {
+        for cx in 0..dim.0 - 1 {
+            let (p00, p11) = (point(cx, cy), point(cx + 1, cy + 1));
+            let tris = [
+                Triangle::new(point(cx + 1, cy), p00, p11),
+                Triangle::new(p00, point(cx, cy + 1), p11),
+            ];
+            for tri in &tris {
+                if let Some(toi) = tri.toi_with_ray(iso, ray, true) {
+                    best = Some(best.map_or(toi, |b| b.min(toi)));
+                }
+            }
+        }
+    }
+    best
+}
+
+fn check(m: &Heightmap<f64>, iso: &Isometry3<f64>, ray: &Ray<f64>) {
+    let expected = brute_force(m, iso, ray);
+    let toi = m.toi_with_ray(iso, ray, true);
+    match (toi, expected) {
+        (Some(a), Some(b)) => assert!((a - b).abs() < EPS, "{:?}: {} != {}", ray, a, b),
+        (None, None) => {}
+        _ => panic!("{:?}: {:?} != {:?}", ray, toi, expected),
+    }
+}
+
+#[test]
+fn vertical() {
+    let m = bumpy((17, 9), (16.0, 8.0));
+    let iso = Isometry3::identity();
+    for &(x, y) in &[(0.0, 0.0), (3.3, 4.7), (5.0, 2.0), (16.0, 8.0), (15.99, 0.01), (7.5, 7.5)] {
+        let ray = Ray::new(Point3::new(x, y, 100.0), Vector3::new(0.0, 0.0, -1.0));
+        let toi = m.toi_with_ray(&iso, &ray, true).unwrap();
+        assert!((100.0 - toi - m.height_at(x, y).unwrap()).abs() < EPS);
+    }
+}
+
+#[test]
+fn axis_aligned() {
+    // Rays parallel to an axis (zero x or y direction) must terminate
+    let m = bumpy((9, 9), (8.0, 8.0));
+    let iso = Isometry3::identity();
+    for &dir in &[(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+        for &z in &[-10.0, 0.0, 2.0, 10.0] {
+            let origin = Point3::new(4.0 - dir.0 * 10.0, 4.0 - dir.1 * 10.0, z);
+            check(&m, &iso, &Ray::new(origin, Vector3::new(dir.0, dir.1, -0.05)));
+            check(&m, &iso, &Ray::new(origin, Vector3::new(dir.0, dir.1, 0.0)));
+        }
+    }
+}
+
+#[test]
+fn non_square_cells() {
+    // Cell width and height differ
+    let m = bumpy((33, 5), (8.0, 20.0));
+    let iso = Isometry3::identity();
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..500 {
+        let origin = Point3::new(rng.gen_range(-5.0, 13.0), rng.gen_range(-5.0, 25.0), rng.gen_range(-10.0, 10.0));
+        let dir = Vector3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 0.2));
+        check(&m, &iso, &Ray::new(origin, dir));
+    }
+}
+
+#[test]
+fn random() {
+    let m = bumpy((20, 14), (19.0, 13.0));
+    let mut rng = StdRng::seed_from_u64(2);
+    for i in 0..1000 {
+        let iso = if i % 2 == 0 {
+            Isometry3::identity()
+        } else {
+            Isometry3::new(Vector3::new(3.0, -2.0, 1.0), Vector3::new(0.3, -0.2, 1.1))
+        };
+        let origin = Point3::new(rng.gen_range(-10.0, 30.0), rng.gen_range(-10.0, 25.0), rng.gen_range(-20.0, 20.0));
+        let dir = Vector3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+        check(&m, &iso, &Ray::new(origin, dir));
+    }
+}
+
+#[test]
+fn through_vertices() {
+    // Diagonal rays passing exactly through grid vertices
+    let m = bumpy((9, 9), (8.0, 8.0));
+    let iso = Isometry3::identity();
+    for &z in &[-2.0, 0.5, 3.0] {
+        check(&m, &iso, &Ray::new(Point3::new(-1.0, -1.0, z), Vector3::new(1.0, 1.0, -0.1)));
+        check(&m, &iso, &Ray::new(Point3::new(9.0, -1.0, z), Vector3::new(-1.0, 1.0, -0.1)));
+    }
+}
+
+#[test]
+fn miss() {
+    let m = bumpy((9, 9), (8.0, 8.0));
+    let iso = Isometry3::identity();
+    let up = Ray::new(Point3::new(4.0, 4.0, 100.0), Vector3::new(0.0, 0.0, 1.0));
+    assert!(m.toi_with_ray(&iso, &up, true).is_none());
+    let outside = Ray::new(Point3::new(-1.0, 4.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    assert!(m.toi_with_ray(&iso, &outside, true).is_none());
+    let above = Ray::new(Point3::new(-5.0, 4.0, 50.0), Vector3::new(1.0, 0.0, 0.0));
+    assert!(m.toi_with_ray(&iso, &above, true).is_none());
+}
+
+#[test]
+fn many() {
+    let m = bumpy((20, 14), (19.0, 13.0));
+    let iso = Isometry3::identity();
+    let mut rng = StdRng::seed_from_u64(3);
+    let rays: Vec<_> = (0..200).map(|_| {
+        let origin = Point3::new(rng.gen_range(-10.0, 30.0), rng.gen_range(-10.0, 25.0), rng.gen_range(-20.0, 20.0));
+        let dir = Vector3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+        Ray::new(origin, dir)
+    }).collect();
+    let results = m.raycast_many(&rays);
+    assert_eq!(results.len(), rays.len());
+    for (ray, inter) in rays.iter().zip(results) {
+        let single = m.toi_and_normal_with_ray(&iso, ray, true);
+        match (inter, single) {
+            (Some(a), Some(b)) => {
+                assert!((a.toi - b.toi).abs() < EPS, "{:?}: {} != {}", ray, a.toi, b.toi);
+                assert!((a.normal - b.normal).norm() < EPS);
+                assert_eq!(a.feature, b.feature);
+            }
+            (None, None) => {}
+            (a, b) => panic!("{:?}: {:?} != {:?}", ray, a.map(|i| i.toi), b.map(|i| i.toi)),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2019 Diggory Hardy
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Traversal of the cells crossed by a ray, compared with brute-force tests
+//! of every cell, and ray-casting built on it
+
+use nalgebra::{Point3, Vector3};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use terr::heightmap::{Heightmap, Ray};
+
+const EPS: f64 = 1e-9;
+
+// Parameter range over which the ray is within the cell's rectangle, if any
+fn clip(m: &Heightmap<f64>, c: (u32, u32), o: (f64, f64), d: (f64, f64), max_t: f64) -> Option<(f64, f64)> {
+    let (x0, y0) = m.coord_of(c.0, c.1);
+    let (x1, y1) = m.coord_of(c.0 + 1, c.1 + 1);
+    let mut range = (0.0, max_t);
+    for &(o, d, a, b) in &[(o.0, d.0, x0, x1), (o.1, d.1, y0, y1)] {
+        if d == 0.0 {
+            if o < a || o > b {
+                return None;
+            }
+        } else {
+            let (t0, t1) = ((a - o) / d, (b - o) / d);
+            range = (f64::max(range.0, t0.min(t1)), f64::min(range.1, t0.max(t1)));
+        }
+    }
+    if range.0 <= range.1 { Some(range) } else { None }
+}
+
+fn check(m: &Heightmap<f64>, o: (f64, f64), d: (f64, f64), max_t: f64) -> Vec<(u32, u32)> {
+    let cells: Vec<_> = m.ray_cells(o, d, max_t).take(10_000).collect();
+    let dim = m.dim();
+    assert!(cells.len() < (dim.0 + dim.1) as usize, "{:?} {:?}: too many cells", o, d);
+    
+    let mut prev: Option<((u32, u32), f64)> = None;
+    for &c in &cells {
+        assert!(c.0 + 1 < dim.0 && c.1 + 1 < dim.1);
+        // each cell is touched by the ray, in order, and adjacent to the last
+        let (t0, _) = clip(m, c, o, d, max_t).unwrap_or_else(|| panic!("{:?} {:?}: {:?} missed", o, d, c));
+        if let Some((p, pt)) = prev {
+            let step = (c.0 as i64 - p.0 as i64).abs() + (c.1 as i64 - p.1 as i64).abs();
+            assert_eq!(step, 1, "{:?} {:?}: {:?} after {:?}", o, d, c, p);
+            assert!(t0 + EPS >= pt);
+        }
+        prev = Some((c, t0));
+    }
+    
+    // every cell whose interior is crossed is yielded
+    let len = (d.0 * d.0 + d.1 * d.1).sqrt();
+    for cy in 0..dim.1 - 1 {
+        for cx in 0..dim.0 - 1 {
+            if let Some((t0, t1)) = clip(m, (cx, cy), o, d, max_t) {
+                let mid = (t0 + t1) / 2.0;
+                let p = (o.0 + d.0 * mid, o.1 + d.1 * mid);
+                let (x0, y0) = m.coord_of(cx, cy);
+                let (x1, y1) = m.coord_of(cx + 1, cy + 1);
+                let interior = (t1 - t0) * len > EPS
+                    && p.0 > x0 + EPS && p.0 < x1 - EPS && p.1 > y0 + EPS && p.1 < y1 - EPS;
+                if interior {
+                    assert!(cells.contains(&(cx, cy)), "{:?} {:?}: {:?} not yielded", o, d, (cx, cy));
+                }
+            }
+        }
+    }
+    cells
+}
+
+#[test]
+fn random() {
+    // Including rays starting outside the map and non-square cells
+    let m = Heightmap::new_flat((20, 14), (19.0, 26.0));
+    let mut rng = StdRng::seed_from_u64(1);
+    for i in 0..2000 {
+        let o = (rng.gen_range(-10.0, 30.0), rng.gen_range(-10.0, 35.0));
+        let d = (rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+        let max_t = if i % 2 == 0 { f64::MAX } else { rng.gen_range(0.0, 40.0) };
+        check(&m, o, d, max_t);
+    }
+}
+
+#[test]
+fn axis_parallel() {
+    let m = Heightmap::new_flat((9, 9), (8.0, 8.0));
+    for &d in &[(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+        for &o in &[3.5, 0.0, 4.0, 8.0] {
+            let origin = (4.0 - d.0 * 10.0 + d.1 * (o - 4.0), 4.0 - d.1 * 10.0 + d.0 * (o - 4.0));
+            let cells = check(&m, origin, d, f64::MAX);
+            assert_eq!(cells.len(), 8, "{:?} {:?}", origin, d);
+        }
+    }
+}
+
+#[test]
+fn along_edges() {
+    // Rays exactly along cell boundaries, including the map edges
+    let m = Heightmap::new_flat((9, 5), (16.0, 4.0));
+    for cy in 0..5 {
+        let y = cy as f64;
+        assert_eq!(check(&m, (0.0, y), (2.0, 0.0), f64::MAX).len(), 8);
+        assert_eq!(check(&m, (16.0, y), (-1.0, 0.0), f64::MAX).len(), 8);
+    }
+    for cx in 0..9 {
+        let x = cx as f64 * 2.0;
+        assert_eq!(check(&m, (x, -3.0), (0.0, 1.0), f64::MAX).len(), 4);
+        assert_eq!(check(&m, (x, 4.0), (0.0, -0.5), f64::MAX).len(), 4);
+    }
+}
+
+#[test]
+fn through_vertices() {
+    // Diagonal rays passing exactly through vertices
+    let m = Heightmap::new_flat((9, 9), (8.0, 8.0));
+    check(&m, (-1.0, -1.0), (1.0, 1.0), f64::MAX);
+    check(&m, (9.0, -1.0), (-1.0, 1.0), f64::MAX);
+    check(&m, (0.0, 2.0), (1.0, 1.0), f64::MAX);
+    check(&m, (8.0, 8.0), (-2.0, -2.0), f64::MAX);
+}
+
+#[test]
+fn vertical() {
+    let m = Heightmap::new_flat((9, 9), (8.0, 8.0));
+    let cells: Vec<_> = m.ray_cells((3.5, 4.5), (0.0, 0.0), f64::MAX).collect();
+    assert_eq!(cells, vec![(3, 4)]);
+    let cells: Vec<_> = m.ray_cells((8.0, 8.0), (0.0, 0.0), f64::MAX).collect();
+    assert_eq!(cells, vec![(7, 7)]);
+    assert_eq!(m.ray_cells((-0.5, 4.0), (0.0, 0.0), f64::MAX).count(), 0);
+}
+
+#[test]
+fn outside() {
+    let m = Heightmap::new_flat((9, 9), (8.0, 8.0));
+    // pointing away, passing beside, or too short to reach the map
+    assert_eq!(m.ray_cells((-1.0, 4.0), (-1.0, 0.0), f64::MAX).count(), 0);
+    assert_eq!(m.ray_cells((-1.0, 9.0), (1.0, 0.0), f64::MAX).count(), 0);
+    assert_eq!(m.ray_cells((-1.0, -5.0), (1.0, 0.5), f64::MAX).count(), 0);
+    assert_eq!(m.ray_cells((-5.0, 4.0), (1.0, 0.0), 4.0).count(), 0);
+    // entering from each side
+    assert_eq!(m.ray_cells((-5.0, 4.5), (1.0, 0.0), 7.5).collect::<Vec<_>>(), vec![(0, 4), (1, 4), (2, 4)]);
+    assert_eq!(m.ray_cells((4.5, 20.0), (0.0, -2.0), 7.0).collect::<Vec<_>>(), vec![(4, 7), (4, 6), (4, 5)]);
+}
+
+fn bumpy(dim: (u32, u32), size: (f64, f64)) -> Heightmap<f64> {
+    let mut m = Heightmap::new_flat(dim, size);
+    for cy in 0..dim.1 {
+        for cx in 0..dim.0 {
+            let (x, y) = m.coord_of(cx, cy);
+            m.set(cx, cy, (x * 0.7).sin() * 2.0 + (y * 0.4).cos() * 3.0 + x * 0.1);
+        }
+    }
+    m
+}
+
+// Height of the ray above the surface at `t`, if over the map
+fn above(m: &Heightmap<f64>, ray: &Ray<f64>, t: f64) -> Option<f64> {
+    let p = ray.point_at(t);
+    m.height_at(p.x, p.y).map(|h| p.z - h)
+}
+
+#[test]
+fn hits() {
+    let m = bumpy((20, 14), (19.0, 26.0));
+    let mut rng = StdRng::seed_from_u64(3);
+    let rays: Vec<_> = (0..1000).map(|_| {
+        let origin = Point3::new(rng.gen_range(-10.0, 30.0), rng.gen_range(-10.0, 35.0), rng.gen_range(-20.0, 20.0));
+        let dir = Vector3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+        Ray::new(origin, dir)
+    }).collect();
+    let hits = m.ray_hits(&rays, 100.0);
+    assert_eq!(hits.len(), rays.len());
+    let mut num_hits = 0;
+    for (ray, hit) in rays.iter().zip(hits) {
+        assert_eq!(hit, m.ray_hit(ray, 100.0));
+        // the sign of the height above the surface does not change before the hit
+        let end = hit.map_or(100.0, |hit| hit.toi);
+        let samples: Vec<f64> = (0..1000).filter_map(|i| above(&m, ray, end * i as f64 / 1000.0)).collect();
+        assert!(samples.iter().all(|h| *h >= -EPS) || samples.iter().all(|h| *h <= EPS), "{:?}: missed hit", ray);
+        if let Some(hit) = hit {
+            num_hits += 1;
+            assert!(above(&m, ray, hit.toi).unwrap().abs() < 1e-6, "{:?}: {:?} not on surface", ray, hit);
+            assert!(ray.dir.dot(&hit.normal) <= 0.0);
+            assert_eq!(hit.from_below, hit.normal.z < 0.0);
+            assert_eq!(hit.from_below, samples.iter().any(|h| *h < -EPS));
+        }
+    }
+    assert!(num_hits > 100, "{} hits", num_hits);
+}
+
+#[test]
+fn hit_vertical() {
+    let m = bumpy((17, 9), (16.0, 8.0));
+    for &(x, y) in &[(0.0, 0.0), (3.3, 4.7), (5.0, 2.0), (16.0, 8.0), (15.99, 0.01), (7.5, 7.5)] {
+        let h = m.height_at(x, y).unwrap();
+        let down = Ray::new(Point3::new(x, y, 100.0), Vector3::new(0.0, 0.0, -1.0));
+        let hit = m.ray_hit(&down, f64::MAX).unwrap();
+        assert!((100.0 - hit.toi - h).abs() < EPS);
+        assert!(!hit.from_below);
+        let up = Ray::new(Point3::new(x, y, -100.0), Vector3::new(0.0, 0.0, 2.0));
+        let hit = m.ray_hit(&up, f64::MAX).unwrap();
+        assert!((hit.toi * 2.0 - 100.0 - h).abs() < EPS);
+        assert!(hit.from_below);
+        assert!(m.ray_hit(&up, 45.0).is_none());
+    }
+}